@@ -0,0 +1,104 @@
+//! Protocol debugging tool.
+//!
+//! Usage:
+//! - `agent-proto inspect [OPTIONS] <FILE>`: list every frame in a capture with its offset,
+//!   declared length, CRC status, payload sizes, compression ratio, message type and decoded
+//!   fields, then a summary. Frames whose payload does not decode (CRC mismatch, encryption,
+//!   unknown dictionary) are listed with their envelope; corrupt regions are reported and
+//!   skipped.
+//!
+//! Options:
+//! - `--agent <ID>`: only frames from this agent (repeatable)
+//! - `--type <TYPE>`: only frames of this message type, e.g. `Snapshot` (repeatable)
+//! - `--since <TIME>` / `--until <TIME>`: only frames with an envelope timestamp in the window
+//!   (inclusive); `TIME` is UTC epoch milliseconds or RFC 3339
+//! - `--layout legacy|spec`: frame layout of the file; detected unless given
+//! - `--top <N>`: number of largest frames in the summary (default 5)
+
+use agent::inspect::{inspect, FrameFilter, DEFAULT_TOP_FRAMES};
+use agent::protocol::{FrameLayout, MessageType};
+use chrono::DateTime;
+use std::process::ExitCode;
+
+const USAGE: &str = "usage: agent-proto inspect [--agent <ID>]... [--type <TYPE>]... [--since <TIME>] [--until <TIME>] [--layout legacy|spec] [--top <N>] <FILE>";
+
+struct Args {
+    filter: FrameFilter,
+    layout: Option<FrameLayout>,
+    top: usize,
+    input: String,
+}
+
+/// Parse UTC epoch milliseconds or an RFC 3339 time.
+fn parse_time(value: &str) -> Result<i64, String> {
+    value.parse::<i64>().or_else(|_| {
+        DateTime::parse_from_rfc3339(value)
+            .map(|time| time.timestamp_millis())
+            .map_err(|_| format!("invalid time {value:?}"))
+    })
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Args, String> {
+    match args.next().as_deref() {
+        Some("inspect") => {}
+        Some(other) => return Err(format!("unknown command {other:?}")),
+        None => return Err("no command given".to_string()),
+    }
+    let (mut filter, mut layout, mut top, mut paths) =
+        (FrameFilter::default(), None, DEFAULT_TOP_FRAMES, vec![]);
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("{arg} needs a value"));
+        match arg.as_str() {
+            "--agent" => filter.agents.push(value()?),
+            "--type" => {
+                let name = value()?;
+                filter.types.push(
+                    MessageType::from_name(&name)
+                        .ok_or(format!("unknown message type {name:?}"))?,
+                )
+            }
+            "--since" => filter.since_ms = Some(parse_time(&value()?)?),
+            "--until" => filter.until_ms = Some(parse_time(&value()?)?),
+            "--layout" => {
+                layout = Some(match value()?.as_str() {
+                    "legacy" => FrameLayout::Legacy,
+                    "spec" => FrameLayout::Spec,
+                    other => return Err(format!("invalid layout {other:?}")),
+                })
+            }
+            "--top" => {
+                let n = value()?;
+                top = n.parse().map_err(|_| format!("invalid count {n:?}"))?
+            }
+            _ if arg.starts_with("--") => return Err(format!("unknown option {arg}")),
+            _ => paths.push(arg),
+        }
+    }
+    let mut paths = paths.into_iter();
+    let input = paths.next().ok_or("no input file given")?;
+    if paths.next().is_some() {
+        return Err("too many arguments".to_string());
+    }
+    Ok(Args {
+        filter,
+        layout,
+        top,
+        input,
+    })
+}
+
+fn run(args: Args) -> Result<(), String> {
+    let data = std::fs::read(&args.input).map_err(|e| format!("{}: {e}", args.input))?;
+    print!("{}", inspect(&data, args.layout, &args.filter, args.top));
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    match parse_args(std::env::args().skip(1)).and_then(run) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(message) => {
+            eprintln!("agent-proto: {message}\n{USAGE}");
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,70 @@
+//! Demo producer: writes protocol frames to a file for the .NET demo consumer.
+//!
+//! Usage: `demo_protocol_producer --out <path> --count <n> [--type <type>] [--compress]
+//! [--processes <n> [--seed <n>]] [--corrupt <kind>]`. See `ProducerArgs::usage`.
+//!
+//! With no options it writes one fixed, uncompressed Snapshot frame to `tmp/demo-protocol.bin`;
+//! an existing file is kept and the output goes to a version-stamped name instead.
+
+use agent::demo_protocol::{
+    build_demo_messages, corrupt_legacy_frame, encode_demo_frame_bytes, format_message_for_console,
+    resolve_path_for_logging, version_stamp_if_exists, ProducerArgs, EXIT_USAGE,
+};
+use agent::protocol::OsType;
+use std::process::ExitCode;
+
+fn run(args: ProducerArgs) -> Result<(), String> {
+    let platform = if cfg!(windows) {
+        OsType::Windows
+    } else {
+        OsType::Linux
+    };
+    let messages = build_demo_messages(&args, platform);
+
+    // Encode everything first, so a frame that does not fit fails before any output
+    let mut frames = messages
+        .iter()
+        .map(encode_demo_frame_bytes)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("Encode failed: {e}"))?;
+    for (i, message) in messages.iter().enumerate() {
+        print!("{}", format_message_for_console(message, i + 1));
+    }
+    if let (Some(corruption), Some(last)) = (args.corruption, frames.last_mut()) {
+        corrupt_legacy_frame(last, corruption).map_err(|e| format!("Corrupt failed: {e}"))?;
+        println!("Corrupted frame {} ({})", frames.len(), corruption.name());
+    }
+
+    if let Some(parent) = args.out_path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        std::fs::create_dir_all(parent).map_err(|e| format!("{}: {e}", parent.display()))?;
+    }
+    let out_path = version_stamp_if_exists(&args.out_path)
+        .map_err(|e| format!("{}: {e}", args.out_path.display()))?;
+    std::fs::write(&out_path, frames.concat())
+        .map_err(|e| format!("{}: {e}", out_path.display()))?;
+
+    let logged = resolve_path_for_logging(&out_path).unwrap_or(out_path);
+    println!("Wrote {} frame(s) to {}", frames.len(), logged.display());
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    let args = match ProducerArgs::parse_from_env() {
+        Ok(args) => args,
+        Err(message) if message == "help" => {
+            print!("{}", ProducerArgs::usage());
+            return ExitCode::SUCCESS;
+        }
+        Err(message) => {
+            eprintln!("UsageError: {message}\n\n{}", ProducerArgs::usage());
+            return ExitCode::from(EXIT_USAGE as u8);
+        }
+    };
+    match run(args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(message) => {
+            eprintln!("demo_protocol_producer: {message}");
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,104 @@
+//! Convert frame files to canonical JSON Lines and back.
+//!
+//! Usage:
+//! - `frame-json to-json [--rfc3339] [--layout legacy|spec] <FRAMES> [OUT]`: one canonical JSON
+//!   message per line, to `OUT` or stdout. The layout is detected unless given.
+//! - `frame-json to-frames [--layout legacy|spec] <JSONL> <OUT>`: encode every line into a
+//!   frame (legacy layout unless given); payloads with `"compressed": true` are compressed.
+//!
+//! See `Message::to_json` for the JSON form.
+
+use agent::protocol::{frames_to_json_lines, json_lines_to_frames, FrameLayout, JsonOptions};
+use std::process::ExitCode;
+
+const USAGE: &str = "usage: frame-json to-json [--rfc3339] [--layout legacy|spec] <FRAMES> [OUT]\n       frame-json to-frames [--layout legacy|spec] <JSONL> <OUT>";
+
+enum Direction {
+    ToJson,
+    ToFrames,
+}
+
+struct Args {
+    direction: Direction,
+    options: JsonOptions,
+    layout: Option<FrameLayout>,
+    input: String,
+    output: Option<String>,
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Args, String> {
+    let direction = match args.next().as_deref() {
+        Some("to-json") => Direction::ToJson,
+        Some("to-frames") => Direction::ToFrames,
+        Some(other) => return Err(format!("unknown command {other:?}")),
+        None => return Err("no command given".to_string()),
+    };
+    let (mut options, mut layout, mut paths) = (JsonOptions::default(), None, vec![]);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--rfc3339" => options = options.with_rfc3339_timestamps(),
+            "--layout" => {
+                layout = Some(match args.next().as_deref() {
+                    Some("legacy") => FrameLayout::Legacy,
+                    Some("spec") => FrameLayout::Spec,
+                    other => return Err(format!("invalid layout {other:?}")),
+                })
+            }
+            _ if arg.starts_with("--") => return Err(format!("unknown option {arg}")),
+            _ => paths.push(arg),
+        }
+    }
+    let mut paths = paths.into_iter();
+    let input = paths.next().ok_or("no input file given")?;
+    let output = paths.next();
+    if paths.next().is_some() {
+        return Err("too many arguments".to_string());
+    }
+    if matches!(direction, Direction::ToFrames) && output.is_none() {
+        return Err("to-frames needs an output file".to_string());
+    }
+    Ok(Args {
+        direction,
+        options,
+        layout,
+        input,
+        output,
+    })
+}
+
+fn run(args: Args) -> Result<(), String> {
+    let input = std::fs::read(&args.input).map_err(|e| format!("{}: {e}", args.input))?;
+    let output = match args.direction {
+        Direction::ToJson => {
+            let layout = args
+                .layout
+                .or_else(|| FrameLayout::detect(&input))
+                .unwrap_or_default();
+            frames_to_json_lines(&input, layout, &args.options)
+                .map_err(|e| format!("{}: {e}", args.input))?
+                .into_bytes()
+        }
+        Direction::ToFrames => {
+            let jsonl = String::from_utf8(input).map_err(|e| format!("{}: {e}", args.input))?;
+            json_lines_to_frames(&jsonl, args.layout.unwrap_or_default())
+                .map_err(|e| format!("{}: {e}", args.input))?
+        }
+    };
+    match &args.output {
+        Some(path) => std::fs::write(path, output).map_err(|e| format!("{path}: {e}")),
+        None => {
+            print!("{}", String::from_utf8_lossy(&output));
+            Ok(())
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    match parse_args(std::env::args().skip(1)).and_then(run) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(message) => {
+            eprintln!("frame-json: {message}\n{USAGE}");
+            ExitCode::FAILURE
+        }
+    }
+}
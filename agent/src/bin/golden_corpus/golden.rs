@@ -0,0 +1,723 @@
+//! Golden test-vector corpus: frames of every message type plus the values they decode to.
+//!
+//! A corpus directory holds one legacy-layout frame per `<name>.bin` file and a `manifest.json`
+//! listing, for each frame, its file, message type, compression flag, length, CRC32 and the
+//! expected decoded message. [`generate`] writes the corpus for [`CORPUS_VERSION`]; the
+//! checked-in copy under `agent/tests/data/golden/v<N>/` is the one other implementations
+//! decode. Never edit a released corpus version; add cases in a new version instead.
+//!
+//! To check an implementation, decode every frame in the manifest and write one JSON object
+//! per line:
+//!
+//! ```text
+//! {"name":"snapshot","message":{...}}           decoded message, same shape as `expected`
+//! {"name":"snapshot_max_size","error":"..."}    frame failed to decode
+//! ```
+//!
+//! then compare the output with [`verify`] (`golden-corpus verify <DIR> <DECODED.jsonl>`).
+//! `expected` is the canonical JSON form of the message (`Message::to_json`: hex ids, variant
+//! names, Unix timestamps). Numbers with a fractional part compare with a relative tolerance
+//! of [`FLOAT_TOLERANCE`], so an implementation may print `f32` fields widened to `f64`.
+//!
+//! The cases are built with a deterministic `MessageBuilder`, so regenerating a corpus version
+//! reproduces its frames byte for byte.
+
+use agent::protocol::{
+    AgentIdentity, BackpressureSignal, DecodeMode, DecodeOptions, ErrorCode, FixedClock,
+    FrameCodec, FrameLayout, HandshakeAckPayload, Message, MessageAck, MessageBuilder,
+    MessagePayload, OsType, ProcessChange, ProcessSample, ProtocolError, ProtocolVersion,
+    SnapshotDeltaPayload, SnapshotPartPayload, SnapshotPayload, VersionRange, MAX_FRAME_SIZE,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{Number, Value};
+use std::fmt;
+use std::io::{self, Cursor};
+use std::path::{Path, PathBuf};
+
+/// Version of the corpus written by [`generate`].
+pub const CORPUS_VERSION: u32 = 1;
+
+/// Name of the manifest file in a corpus directory.
+pub const MANIFEST_FILE: &str = "manifest.json";
+
+/// Relative tolerance for numbers with a fractional part.
+pub const FLOAT_TOLERANCE: f64 = 1e-6;
+
+/// Seed of the message ids of the cases.
+const CASE_SEED: u64 = 1;
+
+/// Envelope timestamp of the first case; the clock advances one second per reading.
+const CASE_START_MS: i64 = 1703174405000;
+
+/// Errors reading, writing or decoding a corpus.
+#[derive(Debug, thiserror::Error)]
+pub enum GoldenError {
+    #[error("{}: {source}", .path.display())]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    #[error("invalid JSON: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("failed to write canonical JSON: {0}")]
+    Canonical(#[source] ProtocolError),
+    #[error("failed to encode case {name}: {source}")]
+    Encode {
+        name: String,
+        #[source]
+        source: ProtocolError,
+    },
+    #[error("corpus version {found} is newer than the supported version {supported}")]
+    UnsupportedCorpusVersion { found: u32, supported: u32 },
+    #[error("{file} does not match the manifest: {detail}")]
+    CorruptCorpus { file: String, detail: String },
+}
+
+/// One message of the corpus.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GoldenCase {
+    /// Unique case name; the frame is written to `<name>.bin`
+    pub name: String,
+    /// What the case covers
+    pub description: String,
+    /// Message encoded into the frame
+    pub message: Message,
+}
+
+/// Corpus manifest (`manifest.json`).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Manifest {
+    /// Corpus version; bumped whenever cases change
+    pub corpus_version: u32,
+    /// Protocol version the frames were encoded with
+    pub protocol_version: ProtocolVersion,
+    /// One entry per frame
+    pub entries: Vec<ManifestEntry>,
+}
+
+/// One frame of the corpus with its expected decode result.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    /// Case name
+    pub name: String,
+    /// Frame file, relative to the corpus directory
+    pub file: String,
+    /// What the case covers
+    pub description: String,
+    /// `MessageType` name (e.g. `Snapshot`)
+    pub message_type: String,
+    /// True if the payload is zstd-compressed
+    pub compressed: bool,
+    /// Length of the frame file in bytes
+    pub frame_len: usize,
+    /// CRC32 of the whole frame file (guards the corpus itself against corruption)
+    pub file_crc32: u32,
+    /// Expected decoded message
+    pub expected: Value,
+}
+
+/// Decode output of an implementation for one frame (one JSON Lines record).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DecodedFrame {
+    /// Case name from the manifest
+    pub name: String,
+    /// Decoded message, if the frame decoded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<Value>,
+    /// Decode error, if the frame failed to decode
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// A difference between an implementation's decode output and the manifest.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mismatch {
+    /// Case name
+    pub name: String,
+    /// What differs, e.g. `message.payload.Snapshot.processes[2].pid: expected 1003, got 1004`
+    pub detail: String,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.name, self.detail)
+    }
+}
+
+/// Every case of the current corpus version.
+///
+/// Each base case appears twice: uncompressed under its name and with a zstd-compressed
+/// payload under `<name>_compressed`.
+pub fn cases() -> Result<Vec<GoldenCase>, GoldenError> {
+    let mut cases = Vec::new();
+    for (name, description, message) in base_cases()? {
+        let mut compressed = message.clone();
+        compressed.envelope.compressed = true;
+        cases.push(GoldenCase {
+            name: name.to_string(),
+            description: description.to_string(),
+            message,
+        });
+        cases.push(GoldenCase {
+            name: format!("{name}_compressed"),
+            description: format!("{description} (zstd-compressed payload)"),
+            message: compressed,
+        });
+    }
+    Ok(cases)
+}
+
+/// Encode every case into a frame and describe it in a manifest entry.
+pub fn encode_cases() -> Result<Vec<(ManifestEntry, Vec<u8>)>, GoldenError> {
+    cases()?
+        .into_iter()
+        .map(|case| {
+            let frame =
+                FrameCodec::encode(&case.message).map_err(|source| GoldenError::Encode {
+                    name: case.name.clone(),
+                    source,
+                })?;
+            let entry = ManifestEntry {
+                file: format!("{}.bin", case.name),
+                name: case.name,
+                description: case.description,
+                message_type: format!("{:?}", case.message.envelope.message_type),
+                compressed: case.message.envelope.compressed,
+                frame_len: frame.len(),
+                file_crc32: crc32fast::hash(&frame),
+                expected: canonical_json(&case.message)?,
+            };
+            Ok((entry, frame))
+        })
+        .collect()
+}
+
+/// Write the corpus (frames plus manifest) to `dir`, creating it if needed.
+pub fn generate(dir: &Path) -> Result<Manifest, GoldenError> {
+    std::fs::create_dir_all(dir).map_err(|source| io_error(dir, source))?;
+    let mut entries = Vec::new();
+    for (entry, frame) in encode_cases()? {
+        let path = dir.join(&entry.file);
+        std::fs::write(&path, frame).map_err(|source| io_error(&path, source))?;
+        entries.push(entry);
+    }
+    let manifest = Manifest {
+        corpus_version: CORPUS_VERSION,
+        protocol_version: ProtocolVersion::CURRENT,
+        entries,
+    };
+    let path = dir.join(MANIFEST_FILE);
+    std::fs::write(&path, manifest.to_json()?).map_err(|source| io_error(&path, source))?;
+    Ok(manifest)
+}
+
+impl Manifest {
+    /// Manifest JSON with one compact entry per line, so diffs stay readable while large
+    /// cases stay small.
+    pub fn to_json(&self) -> Result<String, GoldenError> {
+        let entries = self
+            .entries
+            .iter()
+            .map(serde_json::to_string)
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(format!(
+            "{{\"corpus_version\":{},\"protocol_version\":{},\"entries\":[\n{}\n]}}\n",
+            self.corpus_version,
+            serde_json::to_string(&self.protocol_version)?,
+            entries.join(",\n")
+        ))
+    }
+
+    /// Read `manifest.json` from the corpus directory `dir`.
+    pub fn load(dir: &Path) -> Result<Self, GoldenError> {
+        let path = dir.join(MANIFEST_FILE);
+        let json = std::fs::read_to_string(&path).map_err(|source| io_error(&path, source))?;
+        let manifest: Manifest = serde_json::from_str(&json)?;
+        if manifest.corpus_version > CORPUS_VERSION {
+            return Err(GoldenError::UnsupportedCorpusVersion {
+                found: manifest.corpus_version,
+                supported: CORPUS_VERSION,
+            });
+        }
+        Ok(manifest)
+    }
+}
+
+impl ManifestEntry {
+    /// Read the frame file from the corpus directory `dir`, checking its length and CRC32.
+    pub fn read_frame(&self, dir: &Path) -> Result<Vec<u8>, GoldenError> {
+        let path = dir.join(&self.file);
+        let frame = std::fs::read(&path).map_err(|source| io_error(&path, source))?;
+        let corrupt = |detail| GoldenError::CorruptCorpus {
+            file: self.file.clone(),
+            detail,
+        };
+        if frame.len() != self.frame_len {
+            return Err(corrupt(format!(
+                "{} bytes, expected {}",
+                frame.len(),
+                self.frame_len
+            )));
+        }
+        let crc = crc32fast::hash(&frame);
+        if crc != self.file_crc32 {
+            return Err(corrupt(format!(
+                "CRC32 {crc:#010x}, expected {:#010x}",
+                self.file_crc32
+            )));
+        }
+        Ok(frame)
+    }
+}
+
+/// Decode every frame of the corpus in `dir` with this implementation (strict mode).
+///
+/// The reference output for [`verify`]; fails only if the corpus itself is unreadable.
+pub fn decode_corpus(dir: &Path, manifest: &Manifest) -> Result<Vec<DecodedFrame>, GoldenError> {
+    manifest
+        .entries
+        .iter()
+        .map(|entry| {
+            let frame = entry.read_frame(dir)?;
+            let decoded = FrameCodec::decode_with_options(
+                &mut Cursor::new(frame),
+                &mut DecodeOptions::new().with_mode(DecodeMode::Strict),
+            );
+            Ok(match decoded {
+                Ok(message) => DecodedFrame {
+                    name: entry.name.clone(),
+                    message: Some(canonical_json(&message)?),
+                    error: None,
+                },
+                Err(err) => DecodedFrame {
+                    name: entry.name.clone(),
+                    message: None,
+                    error: Some(err.to_string()),
+                },
+            })
+        })
+        .collect()
+}
+
+/// Parse decode output in JSON Lines form; blank lines are skipped.
+pub fn parse_decoded(jsonl: &str) -> Result<Vec<DecodedFrame>, GoldenError> {
+    jsonl
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| Ok(serde_json::from_str(line)?))
+        .collect()
+}
+
+/// Compare decode output with the manifest; returns every mismatch (empty if all frames match).
+///
+/// Reports frames that failed to decode, decoded to different values, are missing from the
+/// output or appear more than once, and output for names the manifest does not list.
+pub fn verify(manifest: &Manifest, decoded: &[DecodedFrame]) -> Vec<Mismatch> {
+    let mut mismatches = Vec::new();
+    let mut report = |name: &str, detail: String| {
+        mismatches.push(Mismatch {
+            name: name.to_string(),
+            detail,
+        })
+    };
+
+    for entry in &manifest.entries {
+        let mut outputs = decoded.iter().filter(|frame| frame.name == entry.name);
+        let Some(frame) = outputs.next() else {
+            report(&entry.name, "no decode output".to_string());
+            continue;
+        };
+        if outputs.next().is_some() {
+            report(&entry.name, "decoded more than once".to_string());
+        }
+        match (&frame.message, &frame.error) {
+            (Some(message), None) => {
+                if let Some(detail) = diff(&entry.expected, message, "message") {
+                    report(&entry.name, detail);
+                }
+            }
+            (None, Some(error)) => report(&entry.name, format!("failed to decode: {error}")),
+            _ => report(
+                &entry.name,
+                "output needs exactly one of `message` and `error`".to_string(),
+            ),
+        }
+    }
+
+    for frame in decoded {
+        if !manifest
+            .entries
+            .iter()
+            .any(|entry| entry.name == frame.name)
+        {
+            report(&frame.name, "not in the manifest".to_string());
+        }
+    }
+    mismatches
+}
+
+/// First difference between `expected` and `actual`, located by its path below `path`.
+fn diff(expected: &Value, actual: &Value, path: &str) -> Option<String> {
+    match (expected, actual) {
+        (Value::Number(e), Value::Number(a)) if numbers_match(e, a) => None,
+        (Value::Array(e), Value::Array(a)) => {
+            if e.len() != a.len() {
+                return Some(format!(
+                    "{path}: expected {} elements, got {}",
+                    e.len(),
+                    a.len()
+                ));
+            }
+            e.iter()
+                .zip(a)
+                .enumerate()
+                .find_map(|(i, (e, a))| diff(e, a, &format!("{path}[{i}]")))
+        }
+        (Value::Object(e), Value::Object(a)) => e
+            .iter()
+            .find_map(|(key, e)| match a.get(key) {
+                Some(a) => diff(e, a, &format!("{path}.{key}")),
+                None => Some(format!("{path}.{key}: missing")),
+            })
+            .or_else(|| {
+                a.keys()
+                    .find(|key| !e.contains_key(*key))
+                    .map(|key| format!("{path}.{key}: unexpected field"))
+            }),
+        _ if expected == actual => None,
+        _ => Some(format!("{path}: expected {expected}, got {actual}")),
+    }
+}
+
+/// Integers compare exactly; numbers with a fractional part within `FLOAT_TOLERANCE`.
+fn numbers_match(expected: &Number, actual: &Number) -> bool {
+    if expected.is_f64() || actual.is_f64() {
+        match (expected.as_f64(), actual.as_f64()) {
+            (Some(e), Some(a)) => (e - a).abs() <= FLOAT_TOLERANCE * e.abs().max(a.abs()),
+            _ => false,
+        }
+    } else {
+        expected == actual
+    }
+}
+
+/// Canonical JSON form of `message` as a value, for comparing field by field.
+fn canonical_json(message: &Message) -> Result<Value, GoldenError> {
+    let json = message.to_json().map_err(GoldenError::Canonical)?;
+    Ok(serde_json::from_str(&json)?)
+}
+
+fn io_error(path: &Path, source: io::Error) -> GoldenError {
+    GoldenError::Io {
+        path: path.to_path_buf(),
+        source,
+    }
+}
+
+fn snapshot(processes: Vec<ProcessSample>) -> SnapshotPayload {
+    SnapshotPayload {
+        window_start_secs: 1703174400,
+        window_end_secs: 1703174410,
+        total_cpu_percent: 75.5,
+        memory_used_bytes: 15_000_000_000,
+        memory_total_bytes: 16_000_000_000,
+        processes,
+        truncated: false,
+    }
+}
+
+fn process(pid: u32, name: &str, cpu_percent: f32, cmdline: Option<&str>) -> ProcessSample {
+    ProcessSample {
+        pid,
+        name: name.to_string(),
+        cpu_percent,
+        memory_percent: 5.0,
+        memory_bytes: 800_000_000,
+        cmdline: cmdline.map(str::to_string),
+    }
+}
+
+fn base_cases() -> Result<Vec<(&'static str, &'static str, Message)>, GoldenError> {
+    let identity = AgentIdentity {
+        instance_id: "golden-agent".to_string(),
+        os_type: OsType::Windows,
+        agent_version: "1.4.2".to_string(),
+        supported_versions: VersionRange::CURRENT,
+        capabilities: AgentIdentity::CAP_ALL_PROCESS
+            | AgentIdentity::CAP_COMPRESSION
+            | AgentIdentity::CAP_SNAPSHOT_DELTA
+            | AgentIdentity::CAP_ENCRYPTION
+            | AgentIdentity::CAP_SPEC_FRAMING
+            | AgentIdentity::dictionary_capabilities(7)
+            | AgentIdentity::compression_level_capabilities(5),
+    };
+    let mut builder = MessageBuilder::deterministic(
+        identity,
+        CASE_SEED,
+        FixedClock::stepping(CASE_START_MS, 1000),
+    )
+    .with_platform(OsType::Linux);
+
+    let mut handshake = builder.handshake();
+    handshake.envelope.platform = OsType::Windows;
+
+    let mut handshake_empty_strings = builder.build(MessagePayload::Handshake(AgentIdentity {
+        instance_id: String::new(),
+        os_type: OsType::Linux,
+        agent_version: String::new(),
+        supported_versions: VersionRange::CURRENT,
+        capabilities: 0,
+    }));
+    handshake_empty_strings.envelope.agent_id = String::new();
+
+    let handshake_ack = builder.build(MessagePayload::HandshakeAck(HandshakeAckPayload {
+        negotiated_version: ProtocolVersion::CURRENT,
+        negotiated_capabilities: AgentIdentity::CAP_ALL_PROCESS | AgentIdentity::CAP_COMPRESSION,
+    }));
+    let heartbeat = builder.build(MessagePayload::Heartbeat);
+
+    let mut heartbeat_extensions = builder.build(MessagePayload::Heartbeat);
+    heartbeat_extensions.envelope.set_sequence_number(7);
+    heartbeat_extensions
+        .payload_extensions
+        .insert(0x8001, b"experimental".to_vec());
+
+    let snapshot_message = builder.build(MessagePayload::Snapshot(snapshot(vec![
+        ProcessSample {
+            pid: 1001,
+            name: "chrome".to_string(),
+            cpu_percent: 45.0,
+            memory_percent: 12.5,
+            memory_bytes: 2_000_000_000,
+            cmdline: Some(
+                "/usr/bin/chrome --user-data-dir=/home/user/.config/google-chrome".to_string(),
+            ),
+        },
+        ProcessSample {
+            pid: 1002,
+            name: "firefox".to_string(),
+            cpu_percent: 20.0,
+            memory_percent: 9.375,
+            memory_bytes: 1_500_000_000,
+            cmdline: Some("/usr/bin/firefox".to_string()),
+        },
+        process(1003, "code", 10.5, None),
+    ])));
+    // Acks and the delta refer to this snapshot
+    let snapshot_id = snapshot_message.envelope.message_id;
+
+    let zero_processes = builder.build(MessagePayload::Snapshot(snapshot(Vec::new())));
+
+    let mut non_ascii = builder.build(MessagePayload::Snapshot(snapshot(vec![
+        process(2001, "Дискорд", 12.0, Some("/opt/Дискорд/дискорд --тихо")),
+        process(2002, "微信", 8.0, Some("C:\\Program Files\\微信\\微信.exe")),
+        process(2003, "café ☕", 4.0, Some("café --menu=crème-brûlée")),
+        process(2004, "🚀 launcher", 2.0, None),
+    ])));
+    non_ascii.envelope.agent_id = "agent-ñ-東京".to_string();
+
+    let mut empty_strings_snapshot = snapshot(vec![process(3001, "", 1.0, Some(""))]);
+    empty_strings_snapshot.truncated = true;
+    let mut empty_strings = builder.build(MessagePayload::Snapshot(empty_strings_snapshot));
+    empty_strings.envelope.agent_id = String::new();
+
+    let max_size = max_size_snapshot(&mut builder)?;
+
+    let ack = builder.build(MessagePayload::Ack(MessageAck {
+        message_id: snapshot_id,
+        success: true,
+        error_code: None,
+    }));
+    let ack_error = builder.build(MessagePayload::Ack(MessageAck::failed(
+        snapshot_id,
+        ErrorCode::CrcMismatch,
+    )));
+    let backpressure = builder.build(MessagePayload::Backpressure(BackpressureSignal {
+        throttle_delay_ms: 5_000,
+        reason: Some("server buffer 90% full".to_string()),
+    }));
+    let backpressure_no_reason = builder.build(MessagePayload::Backpressure(BackpressureSignal {
+        throttle_delay_ms: 0,
+        reason: None,
+    }));
+    let error = builder.build(MessagePayload::Error {
+        code: ErrorCode::MalformedPayload.to_u32(),
+        message: "snapshot.processes[0].name: string too long".to_string(),
+    });
+    let error_empty_message = builder.build(MessagePayload::Error {
+        code: ErrorCode::Internal.to_u32(),
+        message: String::new(),
+    });
+
+    let part_snapshot_id = builder.next_message_id();
+    let snapshot_part = builder.build(MessagePayload::SnapshotPart(SnapshotPartPayload {
+        snapshot_id: part_snapshot_id,
+        part_index: 1,
+        part_count: 3,
+        snapshot: snapshot(vec![
+            process(4001, "worker", 30.0, Some("worker --id=1")),
+            process(4002, "worker", 25.0, Some("worker --id=2")),
+        ]),
+    }));
+    let snapshot_delta = builder.build(MessagePayload::SnapshotDelta(Box::new(
+        SnapshotDeltaPayload {
+            base_message_id: snapshot_id,
+            window_start_secs: 1703174410,
+            window_end_secs: 1703174420,
+            total_cpu_percent: 60.25,
+            memory_used_bytes: 14_500_000_000,
+            memory_total_bytes: 16_000_000_000,
+            added: vec![process(1004, "cargo", 15.0, Some("cargo test"))],
+            changed: vec![ProcessChange {
+                pid: 1002,
+                cpu_percent: 22.5,
+                memory_percent: 10.0,
+                memory_bytes: 1_600_000_000,
+            }],
+            removed: vec![1003],
+            truncated: false,
+        },
+    )));
+
+    Ok(vec![
+        (
+            "handshake",
+            "Handshake with every capability, a dictionary id and a zstd level",
+            handshake,
+        ),
+        (
+            "handshake_empty_strings",
+            "Handshake with empty instance id, agent version and agent id",
+            handshake_empty_strings,
+        ),
+        (
+            "handshake_ack",
+            "HandshakeAck enabling all-process mode and compression",
+            handshake_ack,
+        ),
+        ("heartbeat", "Heartbeat (empty payload)", heartbeat),
+        (
+            "heartbeat_extensions",
+            "Heartbeat with an envelope sequence number and an unknown payload extension",
+            heartbeat_extensions,
+        ),
+        (
+            "snapshot",
+            "Snapshot with three processes, one without a command line",
+            snapshot_message,
+        ),
+        (
+            "snapshot_zero_processes",
+            "Snapshot with an empty process list",
+            zero_processes,
+        ),
+        (
+            "snapshot_non_ascii",
+            "Snapshot with non-ASCII agent id, process names and command lines",
+            non_ascii,
+        ),
+        (
+            "snapshot_empty_strings",
+            "Truncated snapshot with empty agent id, process name and command line",
+            empty_strings,
+        ),
+        (
+            "snapshot_max_size",
+            "Snapshot whose uncompressed frame body is exactly MAX_FRAME_SIZE bytes",
+            max_size,
+        ),
+        (
+            "ack",
+            "Positive Ack of the snapshot case without an error code",
+            ack,
+        ),
+        (
+            "ack_error",
+            "Negative Ack of the snapshot case with error code 1003 (CRC mismatch)",
+            ack_error,
+        ),
+        (
+            "backpressure",
+            "Backpressure with a delay and a reason",
+            backpressure,
+        ),
+        (
+            "backpressure_no_reason",
+            "Backpressure lifting the throttle, without a reason",
+            backpressure_no_reason,
+        ),
+        (
+            "error",
+            "Error with code 1004 (malformed payload) and a message",
+            error,
+        ),
+        (
+            "error_empty_message",
+            "Error with code 5001 (internal) and an empty message",
+            error_empty_message,
+        ),
+        (
+            "snapshot_part",
+            "Second of three parts of a segmented snapshot",
+            snapshot_part,
+        ),
+        (
+            "snapshot_delta",
+            "Snapshot delta against the snapshot case with an added, a changed and a removed process",
+            snapshot_delta,
+        ),
+    ])
+}
+
+/// Snapshot whose uncompressed frame body is exactly `MAX_FRAME_SIZE` bytes.
+///
+/// Every process has the same encoded size, so the process count follows from the sizes of
+/// the frames with zero and one process; the last command line absorbs the remainder. Names
+/// and command lines are pseudo-random so the compressed twin stays under the decoder's
+/// compression ratio limit.
+fn max_size_snapshot(builder: &mut MessageBuilder) -> Result<Message, GoldenError> {
+    let mut message = builder.build(MessagePayload::Snapshot(snapshot(Vec::new())));
+    let mut state = 0x9E37_79B9_7F4A_7C15u64;
+    let mut sample = |pid: u32| ProcessSample {
+        pid,
+        name: pseudo_random_text(&mut state, 16),
+        cpu_percent: 0.1,
+        memory_percent: 0.05,
+        memory_bytes: 8_000_000,
+        cmdline: Some(pseudo_random_text(&mut state, 240)),
+    };
+
+    let mut body_len = |processes: Vec<ProcessSample>| -> Result<usize, GoldenError> {
+        message.payload = MessagePayload::Snapshot(snapshot(processes));
+        let frame = FrameCodec::encode(&message).map_err(|source| GoldenError::Encode {
+            name: "snapshot_max_size".to_string(),
+            source,
+        })?;
+        Ok(frame.len() - FrameLayout::Legacy.frame_len(0))
+    };
+    let empty = body_len(Vec::new())?;
+    let per_process = body_len(vec![sample(0)])? - empty;
+    let count = (MAX_FRAME_SIZE - empty) / per_process;
+    let padding = (MAX_FRAME_SIZE - empty) % per_process;
+
+    let mut processes: Vec<ProcessSample> = (0..count as u32).map(|i| sample(10_000 + i)).collect();
+    if let Some(cmdline) = processes.last_mut().and_then(|p| p.cmdline.as_mut()) {
+        cmdline.push_str(&pseudo_random_text(&mut state, padding));
+    }
+    message.payload = MessagePayload::Snapshot(snapshot(processes));
+    Ok(message)
+}
+
+/// `len` characters of deterministic xorshift64 noise drawn from an ASCII alphabet.
+fn pseudo_random_text(state: &mut u64, len: usize) -> String {
+    const ALPHABET: &[u8] = b"abcdefghijklmnopqrstuvwxyz0123456789-_/.";
+    (0..len)
+        .map(|_| {
+            *state ^= *state << 13;
+            *state ^= *state >> 7;
+            *state ^= *state << 17;
+            ALPHABET[(*state % ALPHABET.len() as u64) as usize] as char
+        })
+        .collect()
+}
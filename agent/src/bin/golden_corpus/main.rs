@@ -0,0 +1,93 @@
+//! Generate, decode and verify the golden test-vector corpus.
+//!
+//! Usage:
+//! - `golden-corpus generate <DIR>`: write the frames and `manifest.json` to `<DIR>`
+//! - `golden-corpus decode <DIR>`: decode every frame with this implementation and print the
+//!   result as JSON Lines (the format `verify` expects from any implementation)
+//! - `golden-corpus verify <DIR> [DECODED.jsonl]`: compare decode output with the manifest;
+//!   without a file, checks this implementation's own decoder
+//!
+//! See the `golden` module for the corpus layout and the decode output format.
+
+mod golden;
+
+use golden::Manifest;
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+const USAGE: &str = "usage: golden-corpus generate <DIR>\n       golden-corpus decode <DIR>\n       golden-corpus verify <DIR> [DECODED.jsonl]";
+
+enum Command {
+    Generate(PathBuf),
+    Decode(PathBuf),
+    Verify(PathBuf, Option<PathBuf>),
+}
+
+fn parse_args(args: impl Iterator<Item = String>) -> Result<Command, String> {
+    let args: Vec<String> = args.collect();
+    match args.iter().map(String::as_str).collect::<Vec<_>>()[..] {
+        ["generate", dir] => Ok(Command::Generate(dir.into())),
+        ["decode", dir] => Ok(Command::Decode(dir.into())),
+        ["verify", dir] => Ok(Command::Verify(dir.into(), None)),
+        ["verify", dir, decoded] => Ok(Command::Verify(dir.into(), Some(decoded.into()))),
+        [] => Err("no command given".to_string()),
+        [command, ..] => Err(format!("invalid arguments for {command:?}")),
+    }
+}
+
+fn run(command: Command) -> Result<(), String> {
+    match command {
+        Command::Generate(dir) => {
+            let manifest = golden::generate(&dir).map_err(|e| e.to_string())?;
+            println!(
+                "wrote {} frames (corpus version {}) to {}",
+                manifest.entries.len(),
+                manifest.corpus_version,
+                dir.display()
+            );
+        }
+        Command::Decode(dir) => {
+            let manifest = Manifest::load(&dir).map_err(|e| e.to_string())?;
+            for frame in golden::decode_corpus(&dir, &manifest).map_err(|e| e.to_string())? {
+                println!(
+                    "{}",
+                    serde_json::to_string(&frame).map_err(|e| e.to_string())?
+                );
+            }
+        }
+        Command::Verify(dir, decoded) => {
+            let manifest = Manifest::load(&dir).map_err(|e| e.to_string())?;
+            let decoded = match decoded {
+                Some(path) => {
+                    let jsonl = std::fs::read_to_string(&path)
+                        .map_err(|e| format!("{}: {e}", path.display()))?;
+                    golden::parse_decoded(&jsonl).map_err(|e| e.to_string())?
+                }
+                None => golden::decode_corpus(&dir, &manifest).map_err(|e| e.to_string())?,
+            };
+            let mismatches = golden::verify(&manifest, &decoded);
+            for mismatch in &mismatches {
+                println!("MISMATCH {mismatch}");
+            }
+            if !mismatches.is_empty() {
+                return Err(format!(
+                    "{} of {} frames do not match",
+                    mismatches.len(),
+                    manifest.entries.len()
+                ));
+            }
+            println!("all {} frames match", manifest.entries.len());
+        }
+    }
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    match parse_args(std::env::args().skip(1)).and_then(run) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(message) => {
+            eprintln!("golden-corpus: {message}\n{USAGE}");
+            ExitCode::FAILURE
+        }
+    }
+}
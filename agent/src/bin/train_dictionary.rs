@@ -0,0 +1,79 @@
+//! Train a zstd compression dictionary from captured frame streams.
+//!
+//! Usage: `train-dictionary --id <ID> [--max-size <BYTES>] --out <FILE> <FRAMES>...`
+//!
+//! Each input file holds concatenated frames (e.g. a capture of an agent's snapshot stream).
+//! The dictionary is written to `--out`; load it with `CompressionDictionary::from_bytes` and
+//! advertise it with `AgentIdentity::dictionary_capabilities`.
+
+use agent::protocol::{CompressionDictionary, DEFAULT_DICTIONARY_SIZE};
+use std::process::ExitCode;
+
+const USAGE: &str =
+    "usage: train-dictionary --id <ID> [--max-size <BYTES>] --out <FILE> <FRAMES>...";
+
+struct Args {
+    id: u16,
+    max_size: usize,
+    out: String,
+    inputs: Vec<String>,
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Args, String> {
+    let (mut id, mut max_size, mut out, mut inputs) = (None, DEFAULT_DICTIONARY_SIZE, None, vec![]);
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| args.next().ok_or(format!("{name} needs a value"));
+        match arg.as_str() {
+            "--id" => {
+                let value = value("--id")?;
+                id = Some(value.parse().map_err(|_| format!("invalid id {value:?}"))?);
+            }
+            "--max-size" => {
+                let value = value("--max-size")?;
+                max_size = value
+                    .parse()
+                    .map_err(|_| format!("invalid size {value:?}"))?;
+            }
+            "--out" => out = Some(value("--out")?),
+            _ if arg.starts_with("--") => return Err(format!("unknown option {arg}")),
+            _ => inputs.push(arg),
+        }
+    }
+    if inputs.is_empty() {
+        return Err("no frame files given".to_string());
+    }
+    Ok(Args {
+        id: id.ok_or("--id is required")?,
+        max_size,
+        out: out.ok_or("--out is required")?,
+        inputs,
+    })
+}
+
+fn run(args: Args) -> Result<(), String> {
+    let files = args
+        .inputs
+        .iter()
+        .map(|path| std::fs::read(path).map_err(|e| format!("{path}: {e}")))
+        .collect::<Result<Vec<_>, _>>()?;
+    let dictionary = CompressionDictionary::train_from_frames(args.id, &files, args.max_size)
+        .map_err(|e| format!("training failed: {e}"))?;
+    std::fs::write(&args.out, dictionary.as_bytes()).map_err(|e| format!("{}: {e}", args.out))?;
+    println!(
+        "wrote dictionary {} ({} bytes) to {}",
+        dictionary.id(),
+        dictionary.as_bytes().len(),
+        args.out
+    );
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    match parse_args(std::env::args().skip(1)).and_then(run) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(message) => {
+            eprintln!("train-dictionary: {message}\n{USAGE}");
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -1,6 +1,8 @@
 use crate::protocol::{
-    Envelope, FrameCodec, Message, MessagePayload, MessageType, OsType, ProcessSample,
-    ProtocolVersion, SnapshotPayload,
+    AgentIdentity, BackpressureSignal, Envelope, ErrorCode, Extensions, FrameCodec,
+    HandshakeAckPayload, Message, MessageAck, MessagePayload, MessageType, OsType, ProcessChange,
+    ProcessSample, ProtocolError, ProtocolVersion, SnapshotDeltaPayload, SnapshotPartPayload,
+    SnapshotPayload, SplitMix64, VersionRange, MAX_FRAME_SIZE,
 };
 use std::fmt::Write as _;
 use std::io;
@@ -9,36 +11,157 @@ use std::path::{Path, PathBuf};
 pub const DEFAULT_DEMO_PATH: &str = "tmp/demo-protocol.bin";
 pub const MAX_FRAME_COUNT: u32 = 1000;
 
+/// Largest `--processes` value, which bounds what a run allocates.
+///
+/// It does not promise a frame that fits in `MAX_FRAME_SIZE`: uncompressed snapshots stop
+/// fitting at about 4,400 processes, compressed ones later. The producer encodes every frame
+/// before it prints or writes anything, so an oversized snapshot fails up front.
+pub const MAX_PROCESS_COUNT: u32 = 10_000;
+
+/// Seed of random snapshots when `--seed` is not given.
+pub const DEFAULT_SEED: u64 = 1;
+
 pub const EXIT_USAGE: i32 = 2;
 
+/// Deliberate damage applied to the last frame of a demo file, for negative testing of
+/// consumers. Each kind maps to one decoder failure.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Corruption {
+    /// Flip a bit in the CRC32 trailer (CRC mismatch)
+    Crc,
+    /// Declare a body longer than `MAX_FRAME_SIZE` (frame too large)
+    Length,
+    /// Cut the frame in half (incomplete frame at end of file)
+    Truncate,
+    /// Append bytes that cannot hold a frame (trailing bytes)
+    Trailing,
+    /// Bump the major version, with a valid CRC (unsupported version)
+    Version,
+    /// Use an unknown message type byte, with a valid CRC (invalid frame)
+    Type,
+}
+
+impl Corruption {
+    /// Every kind, in the order `--help` lists them.
+    pub const ALL: [Corruption; 6] = [
+        Corruption::Crc,
+        Corruption::Length,
+        Corruption::Truncate,
+        Corruption::Trailing,
+        Corruption::Version,
+        Corruption::Type,
+    ];
+
+    /// Name accepted by `--corrupt`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Corruption::Crc => "crc",
+            Corruption::Length => "length",
+            Corruption::Truncate => "truncate",
+            Corruption::Trailing => "trailing",
+            Corruption::Version => "version",
+            Corruption::Type => "type",
+        }
+    }
+
+    /// Kind whose `name` is `name`, if any.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|kind| kind.name() == name)
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct ProducerArgs {
     pub out_path: PathBuf,
     pub count: u32,
+    /// Type of every message in the file
+    pub message_type: MessageType,
+    /// Set the compressed flag, so payloads are written zstd-compressed
+    pub compressed: bool,
+    /// Replace the fixed demo snapshot with a seeded random one of this many processes
+    pub processes: Option<u32>,
+    /// Seed for random snapshots; frame `i` uses `seed + i`
+    pub seed: u64,
+    /// Damage the last frame
+    pub corruption: Option<Corruption>,
+}
+
+impl Default for ProducerArgs {
+    fn default() -> Self {
+        Self {
+            out_path: DEFAULT_DEMO_PATH.into(),
+            count: 1,
+            message_type: MessageType::Snapshot,
+            compressed: false,
+            processes: None,
+            seed: DEFAULT_SEED,
+            corruption: None,
+        }
+    }
 }
 
 impl ProducerArgs {
     pub fn parse_from_env() -> Result<Self, String> {
-        let mut out_path: PathBuf = DEFAULT_DEMO_PATH.into();
-        let mut count: u32 = 1;
+        Self::parse_from(std::env::args().skip(1))
+    }
+
+    /// Parse arguments (without the program name).
+    pub fn parse_from(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut parsed = Self::default();
 
-        let mut args = std::env::args().skip(1);
         while let Some(arg) = args.next() {
             match arg.as_str() {
                 "--out" => {
                     let value = args
                         .next()
                         .ok_or_else(|| "--out requires a <path> value".to_string())?;
-                    out_path = PathBuf::from(value);
+                    parsed.out_path = PathBuf::from(value);
                 }
                 "--count" => {
                     let value = args
                         .next()
                         .ok_or_else(|| "--count requires a <n> value".to_string())?;
-                    count = value
+                    parsed.count = value
                         .parse::<u32>()
                         .map_err(|_| format!("Invalid --count value: {value}"))?;
                 }
+                "--type" => {
+                    let value = args
+                        .next()
+                        .ok_or_else(|| "--type requires a <type> value".to_string())?;
+                    parsed.message_type = MessageType::from_name(&value)
+                        .ok_or_else(|| format!("Invalid --type value: {value}"))?;
+                }
+                "--compress" => {
+                    parsed.compressed = true;
+                }
+                "--processes" => {
+                    let value = args
+                        .next()
+                        .ok_or_else(|| "--processes requires a <n> value".to_string())?;
+                    parsed.processes = Some(
+                        value
+                            .parse::<u32>()
+                            .map_err(|_| format!("Invalid --processes value: {value}"))?,
+                    );
+                }
+                "--seed" => {
+                    let value = args
+                        .next()
+                        .ok_or_else(|| "--seed requires a <n> value".to_string())?;
+                    parsed.seed = value
+                        .parse::<u64>()
+                        .map_err(|_| format!("Invalid --seed value: {value}"))?;
+                }
+                "--corrupt" => {
+                    let value = args
+                        .next()
+                        .ok_or_else(|| "--corrupt requires a <kind> value".to_string())?;
+                    parsed.corruption = Some(
+                        Corruption::from_name(&value)
+                            .ok_or_else(|| format!("Invalid --corrupt value: {value}"))?,
+                    );
+                }
                 "-h" | "--help" => {
                     return Err("help".to_string());
                 }
@@ -48,34 +171,200 @@ impl ProducerArgs {
             }
         }
 
+        let count = parsed.count;
         if !(1..=MAX_FRAME_COUNT).contains(&count) {
             return Err(format!(
                 "--count must be in 1..={MAX_FRAME_COUNT} (got {count})"
             ));
         }
+        if let Some(processes) = parsed.processes.filter(|&n| n > MAX_PROCESS_COUNT) {
+            return Err(format!(
+                "--processes must be at most {MAX_PROCESS_COUNT} (got {processes})"
+            ));
+        }
+        if parsed.processes.is_some() && !carries_snapshot(parsed.message_type) {
+            return Err(format!(
+                "--processes needs a Snapshot, SnapshotPart or SnapshotDelta --type (got {})",
+                parsed.message_type.name()
+            ));
+        }
 
-        Ok(Self { out_path, count })
+        Ok(parsed)
     }
 
     pub fn usage() -> String {
+        let kinds: Vec<&str> = Corruption::ALL.iter().map(|kind| kind.name()).collect();
         format!(
-            "demo_protocol_producer --out <path> --count <n>\n\nDefaults:\n  --out   {DEFAULT_DEMO_PATH}\n  --count 1\n\nConstraints:\n  1 <= --count <= {MAX_FRAME_COUNT}\n"
+            "demo_protocol_producer --out <path> --count <n> [--type <type>] [--compress] [--processes <n> [--seed <n>]] [--corrupt <kind>]\n\nDefaults:\n  --out   {DEFAULT_DEMO_PATH}\n  --count 1\n  --type  Snapshot\n  --seed  {DEFAULT_SEED}\n\nConstraints:\n  1 <= --count <= {MAX_FRAME_COUNT}\n  --processes <= {MAX_PROCESS_COUNT}\n  --type is a message type name: Handshake, HandshakeAck, Heartbeat, Snapshot, Ack, Backpressure, Error, SnapshotPart, SnapshotDelta\n  --processes needs --type Snapshot, SnapshotPart or SnapshotDelta\n  --corrupt damages the last frame: {}\n",
+            kinds.join(", ")
         )
     }
 }
 
+fn carries_snapshot(message_type: MessageType) -> bool {
+    matches!(
+        message_type,
+        MessageType::Snapshot | MessageType::SnapshotPart | MessageType::SnapshotDelta
+    )
+}
+
+/// The fixed demo Snapshot message sent from `platform`.
 pub fn build_demo_message(platform: OsType) -> Message {
+    demo_message(MessagePayload::Snapshot(demo_snapshot()), platform)
+}
+
+/// Build the messages of a demo file: `args.count` messages of `args.message_type`.
+///
+/// Without `--processes` every message is the same fixed sample; the default arguments give
+/// `build_demo_message` repeated.
+pub fn build_demo_messages(args: &ProducerArgs, platform: OsType) -> Vec<Message> {
+    (0..args.count)
+        .map(|i| {
+            let snapshot = match args.processes {
+                Some(process_count) => {
+                    build_random_snapshot(process_count, args.seed.wrapping_add(u64::from(i)))
+                }
+                None => demo_snapshot(),
+            };
+            let mut message = demo_message(demo_payload(args.message_type, snapshot), platform);
+            message.envelope.compressed = args.compressed;
+            message
+        })
+        .collect()
+}
+
+fn demo_message(payload: MessagePayload, platform: OsType) -> Message {
     let envelope = Envelope {
         version: ProtocolVersion::CURRENT,
-        message_type: MessageType::Snapshot,
+        message_type: payload.message_type(),
         message_id: demo_message_id(),
         timestamp_utc_ms: 1703174410000,
         agent_id: "demo-agent-ž".to_string(),
         platform,
         compressed: false,
+        extensions: Extensions::new(),
     };
 
-    let payload = SnapshotPayload {
+    Message {
+        envelope,
+        payload,
+        payload_extensions: Extensions::new(),
+    }
+}
+
+/// Fixed sample payload of `message_type`; types that carry processes take them from `snapshot`.
+fn demo_payload(message_type: MessageType, snapshot: SnapshotPayload) -> MessagePayload {
+    match message_type {
+        MessageType::Handshake => MessagePayload::Handshake(AgentIdentity {
+            instance_id: "demo-agent-ž".to_string(),
+            os_type: OsType::Linux,
+            agent_version: env!("CARGO_PKG_VERSION").to_string(),
+            supported_versions: VersionRange::CURRENT,
+            capabilities: AgentIdentity::CAP_ALL_PROCESS | AgentIdentity::CAP_COMPRESSION,
+        }),
+        MessageType::HandshakeAck => MessagePayload::HandshakeAck(HandshakeAckPayload {
+            negotiated_version: ProtocolVersion::CURRENT,
+            negotiated_capabilities: AgentIdentity::CAP_COMPRESSION,
+        }),
+        MessageType::Heartbeat => MessagePayload::Heartbeat,
+        MessageType::Snapshot => MessagePayload::Snapshot(snapshot),
+        MessageType::Ack => MessagePayload::Ack(MessageAck {
+            message_id: demo_message_id(),
+            success: true,
+            error_code: None,
+        }),
+        MessageType::Backpressure => MessagePayload::Backpressure(BackpressureSignal {
+            throttle_delay_ms: 5_000,
+            reason: Some("demo: server buffer full".to_string()),
+        }),
+        MessageType::Error => MessagePayload::Error {
+            code: ErrorCode::MalformedPayload.to_u32(),
+            message: "demo: payload could not be decoded".to_string(),
+        },
+        MessageType::SnapshotPart => MessagePayload::SnapshotPart(SnapshotPartPayload {
+            snapshot_id: demo_message_id(),
+            part_index: 0,
+            part_count: 1,
+            snapshot,
+        }),
+        MessageType::SnapshotDelta => {
+            // First half of the processes are new, the rest changed; one base process is gone
+            let mut added = snapshot.processes;
+            let changed = added
+                .split_off(added.len() / 2)
+                .into_iter()
+                .map(|p| ProcessChange {
+                    pid: p.pid,
+                    cpu_percent: p.cpu_percent,
+                    memory_percent: p.memory_percent,
+                    memory_bytes: p.memory_bytes,
+                })
+                .collect();
+            MessagePayload::SnapshotDelta(Box::new(SnapshotDeltaPayload {
+                base_message_id: demo_message_id(),
+                window_start_secs: snapshot.window_start_secs,
+                window_end_secs: snapshot.window_end_secs,
+                total_cpu_percent: snapshot.total_cpu_percent,
+                memory_used_bytes: snapshot.memory_used_bytes,
+                memory_total_bytes: snapshot.memory_total_bytes,
+                added,
+                changed,
+                removed: vec![4321],
+                truncated: snapshot.truncated,
+            }))
+        }
+    }
+}
+
+/// Random but reproducible snapshot of `process_count` processes: the same seed always gives
+/// the same snapshot.
+pub fn build_random_snapshot(process_count: u32, seed: u64) -> SnapshotPayload {
+    const NAMES: [&str; 8] = [
+        "svchost", "worker", "chrome", "postgres", "nginx", "python3", "java", "code",
+    ];
+    const MEMORY_TOTAL_BYTES: u64 = 16_000_000_000;
+
+    let mut rng = SplitMix64::new(seed);
+    let first_pid = 100 + rng.below(10_000) as u32;
+    let processes: Vec<ProcessSample> = (0..process_count)
+        .map(|i| {
+            let name = NAMES[rng.below(NAMES.len() as u64) as usize];
+            let memory_bytes = 1_000_000 + rng.below(2_000_000_000);
+            ProcessSample {
+                pid: first_pid.wrapping_add(i),
+                name: name.to_string(),
+                cpu_percent: rng.below(25_000) as f32 / 1000.0,
+                memory_percent: (memory_bytes as f64 * 100.0 / MEMORY_TOTAL_BYTES as f64) as f32,
+                memory_bytes,
+                // Some processes hide their command line
+                cmdline: (rng.below(4) != 0).then(|| format!("/usr/bin/{name} --id={i}")),
+            }
+        })
+        .collect();
+
+    let total_cpu_percent = processes
+        .iter()
+        .map(|p| p.cpu_percent)
+        .sum::<f32>()
+        .min(100.0);
+    let memory_used_bytes = processes
+        .iter()
+        .map(|p| p.memory_bytes)
+        .sum::<u64>()
+        .min(MEMORY_TOTAL_BYTES);
+    SnapshotPayload {
+        window_start_secs: 1703174400,
+        window_end_secs: 1703174410,
+        total_cpu_percent,
+        memory_used_bytes,
+        memory_total_bytes: MEMORY_TOTAL_BYTES,
+        processes,
+        truncated: false,
+    }
+}
+
+fn demo_snapshot() -> SnapshotPayload {
+    SnapshotPayload {
         window_start_secs: 1703174400,
         window_end_secs: 1703174410,
         total_cpu_percent: 12.345_f32,
@@ -100,11 +389,6 @@ pub fn build_demo_message(platform: OsType) -> Message {
             },
         ],
         truncated: false,
-    };
-
-    Message {
-        envelope,
-        payload: MessagePayload::Snapshot(payload),
     }
 }
 
@@ -122,6 +406,46 @@ pub fn encode_demo_frame_bytes(
     FrameCodec::encode(message)
 }
 
+/// Damage a legacy-layout frame (`[len:u32 BE][body][crc32:u32 LE]`), as produced by
+/// `encode_demo_frame_bytes`, as `corruption` describes.
+///
+/// Fails with `ProtocolError::InvalidFrameLength` and leaves `frame` untouched when its length
+/// prefix does not match its size or the body is too short to hold a version and message type.
+pub fn corrupt_legacy_frame(
+    frame: &mut Vec<u8>,
+    corruption: Corruption,
+) -> Result<(), ProtocolError> {
+    // Length prefix, version (2 bytes), message type and CRC32 trailer
+    if frame.len() < 4 + 3 + 4
+        || u32::from_be_bytes([frame[0], frame[1], frame[2], frame[3]]) as usize != frame.len() - 8
+    {
+        return Err(ProtocolError::InvalidFrameLength(frame.len()));
+    }
+    let body_end = frame.len() - 4;
+    let recompute_crc = |frame: &mut Vec<u8>| {
+        let crc = crc32fast::hash(&frame[4..body_end]);
+        frame[body_end..].copy_from_slice(&crc.to_le_bytes());
+    };
+    match corruption {
+        Corruption::Crc => frame[body_end] ^= 0x01,
+        Corruption::Length => {
+            frame[..4].copy_from_slice(&(MAX_FRAME_SIZE as u32 + 1).to_be_bytes());
+        }
+        Corruption::Truncate => frame.truncate(frame.len() / 2),
+        // Fewer bytes than a length prefix
+        Corruption::Trailing => frame.extend_from_slice(&[0xDE, 0xAD, 0xBE]),
+        Corruption::Version => {
+            frame[4] = frame[4].wrapping_add(1);
+            recompute_crc(frame);
+        }
+        Corruption::Type => {
+            frame[6] = 0xFF;
+            recompute_crc(frame);
+        }
+    }
+    Ok(())
+}
+
 pub fn format_message_for_console(message: &Message, frame_index_1_based: usize) -> String {
     let mut out = String::new();
 
@@ -200,6 +524,16 @@ pub fn format_message_for_console(message: &Message, frame_index_1_based: usize)
         }
 
         let _ = writeln!(&mut out, "truncated={}", bool_to_lower(snapshot.truncated));
+    } else {
+        // Other payloads: the whole message in its canonical JSON form
+        match message.to_json() {
+            Ok(json) => {
+                let _ = writeln!(&mut out, "json={json}");
+            }
+            Err(err) => {
+                let _ = writeln!(&mut out, "json=<{err}>");
+            }
+        }
     }
 
     out
@@ -222,6 +556,8 @@ fn format_message_type(t: MessageType) -> &'static str {
         MessageType::Ack => "Ack",
         MessageType::Backpressure => "Backpressure",
         MessageType::Error => "Error",
+        MessageType::SnapshotPart => "SnapshotPart",
+        MessageType::SnapshotDelta => "SnapshotDelta",
     }
 }
 
@@ -0,0 +1,512 @@
+//! Frame file inspection for `agent-proto inspect`.
+//!
+//! [`inspect`] walks a capture with a [`FrameScanner`], so a corrupt or truncated region is
+//! reported and skipped instead of ending the listing. For every frame that passes the
+//! [`FrameFilter`] it records the offset, declared body length, sizes of the payload on the
+//! wire and after decompression, and the decoded message; [`Inspection`] renders the listing
+//! followed by a summary (counts per type and per agent, time range and the largest frames).
+//!
+//! A frame whose envelope can be read but whose payload cannot (CRC mismatch, encrypted
+//! payload, unknown compression dictionary) is still listed, with its envelope and a
+//! [`FrameStatus`] saying why it did not decode.
+
+use crate::protocol::{
+    DecodeOptions, Envelope, EnvelopeRef, FrameLayout, FrameScanner, JsonOptions, Message,
+    MessageRef, MessageType, ProtocolError, ScanItem, SkipReason, SkippedRange,
+};
+use chrono::{DateTime, SecondsFormat};
+use std::collections::BTreeMap;
+use std::fmt::{self, Write as _};
+
+/// Number of largest frames listed in the summary unless configured otherwise.
+pub const DEFAULT_TOP_FRAMES: usize = 5;
+
+/// Selects the frames to list. An empty filter matches every frame.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FrameFilter {
+    /// Agent ids to keep; empty keeps all agents
+    pub agents: Vec<String>,
+    /// Message types to keep; empty keeps all types
+    pub types: Vec<MessageType>,
+    /// Earliest envelope timestamp to keep (UTC epoch ms, inclusive)
+    pub since_ms: Option<i64>,
+    /// Latest envelope timestamp to keep (UTC epoch ms, inclusive)
+    pub until_ms: Option<i64>,
+}
+
+impl FrameFilter {
+    /// True if a frame with `envelope` passes every configured criterion.
+    pub fn matches(&self, envelope: &Envelope) -> bool {
+        (self.agents.is_empty() || self.agents.contains(&envelope.agent_id))
+            && (self.types.is_empty() || self.types.contains(&envelope.message_type))
+            && self
+                .since_ms
+                .map_or(true, |since| envelope.timestamp_utc_ms >= since)
+            && self
+                .until_ms
+                .map_or(true, |until| envelope.timestamp_utc_ms <= until)
+    }
+}
+
+/// A frame that passed the filter.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FrameInfo {
+    /// Byte offset of the frame's length prefix
+    pub offset: usize,
+    /// Total frame length in bytes (length prefix + CRC32 + body)
+    pub frame_len: usize,
+    /// Body length declared by the length prefix
+    pub declared_len: usize,
+    /// Decoded message, or the envelope and why the rest did not decode
+    pub status: FrameStatus,
+}
+
+impl FrameInfo {
+    /// Envelope of the frame, whether or not its payload decoded.
+    pub fn envelope(&self) -> &Envelope {
+        match &self.status {
+            FrameStatus::Decoded { message, .. } => &message.envelope,
+            FrameStatus::CrcMismatch { envelope, .. }
+            | FrameStatus::Encrypted { envelope }
+            | FrameStatus::UnknownDictionary { envelope, .. }
+            | FrameStatus::Malformed { envelope, .. } => envelope,
+        }
+    }
+
+    /// Decoded message, if the frame decoded.
+    pub fn message(&self) -> Option<&Message> {
+        match &self.status {
+            FrameStatus::Decoded { message, .. } => Some(message),
+            _ => None,
+        }
+    }
+}
+
+/// How far a listed frame could be read.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FrameStatus {
+    /// CRC ok and the message decoded
+    Decoded {
+        message: Message,
+        /// Payload sizes, or why they could not be measured
+        sizes: Result<PayloadSizes, String>,
+    },
+    /// The body does not match the stored CRC32, so the envelope may be damaged as well
+    CrcMismatch {
+        envelope: Envelope,
+        expected: u32,
+        actual: u32,
+    },
+    /// CRC ok but the payload is encrypted
+    Encrypted { envelope: Envelope },
+    /// CRC ok but the payload needs compression dictionary `dictionary_id`
+    UnknownDictionary {
+        envelope: Envelope,
+        dictionary_id: u32,
+    },
+    /// CRC ok but the payload could not be decoded
+    Malformed { envelope: Envelope, detail: String },
+}
+
+/// Payload size of a decoded frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PayloadSizes {
+    /// Size in the frame body (compressed if the envelope says so)
+    pub encoded: usize,
+    /// Size after decompression
+    pub decoded: usize,
+}
+
+impl PayloadSizes {
+    /// Uncompressed payload size divided by its size on the wire (1.0 for an empty payload).
+    pub fn compression_ratio(&self) -> f64 {
+        if self.encoded == 0 {
+            1.0
+        } else {
+            self.decoded as f64 / self.encoded as f64
+        }
+    }
+}
+
+/// A byte range that held no readable frame.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CorruptRegion {
+    /// Skipped bytes and the reason the frame at their start was rejected
+    pub range: SkippedRange,
+    /// Body length declared at the start of the range, if a length prefix fits there
+    pub declared_len: Option<usize>,
+}
+
+/// Item of an [`Inspection`], in file order.
+#[derive(Debug, Clone, PartialEq)]
+pub enum InspectItem {
+    Frame(FrameInfo),
+    Corrupt(CorruptRegion),
+}
+
+/// Totals over the frames that passed the filter.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Summary {
+    /// Frames in the file with a readable envelope, matching the filter or not
+    pub total_frames: usize,
+    /// Frames that passed the filter
+    pub matched_frames: usize,
+    /// Matched frames whose payload did not decode
+    pub undecoded_frames: usize,
+    /// Number of skipped regions
+    pub corrupt_regions: usize,
+    /// Total bytes in skipped regions
+    pub corrupt_bytes: usize,
+    /// Matched frames per message type
+    pub per_type: BTreeMap<MessageType, usize>,
+    /// Matched frames per agent id
+    pub per_agent: BTreeMap<String, usize>,
+    /// Earliest and latest envelope timestamp of the matched frames (UTC epoch ms)
+    pub time_range: Option<(i64, i64)>,
+    /// Offset, length and type of the largest matched frames, largest first
+    pub largest: Vec<(usize, usize, MessageType)>,
+}
+
+/// Result of [`inspect`]. `Display` renders the frame listing and the summary.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Inspection {
+    /// Frame layout of the data
+    pub layout: FrameLayout,
+    /// Matched frames and every corrupt region, in file order
+    pub items: Vec<InspectItem>,
+    /// Totals over the matched frames
+    pub summary: Summary,
+}
+
+/// Inspect a buffer of concatenated frames in `layout` (detected if `None`), keeping the frames
+/// that pass `filter` and listing the `top` largest of them in the summary.
+pub fn inspect(
+    data: &[u8],
+    layout: Option<FrameLayout>,
+    filter: &FrameFilter,
+    top: usize,
+) -> Inspection {
+    let scanner = match layout {
+        Some(layout) => FrameScanner::with_layout(data, layout),
+        None => FrameScanner::new(data),
+    };
+    let layout = scanner.layout();
+    let mut items = Vec::new();
+    let mut summary = Summary::default();
+
+    for item in scanner {
+        match item {
+            ScanItem::Frame(frame) => {
+                summary.total_frames += 1;
+                if !filter.matches(&frame.message.envelope) {
+                    continue;
+                }
+                let sizes = payload_sizes(&data[frame.offset..frame.offset + frame.len], layout)
+                    .map_err(|e| e.to_string());
+                items.push(InspectItem::Frame(FrameInfo {
+                    offset: frame.offset,
+                    frame_len: frame.len,
+                    declared_len: frame.len - layout.frame_len(0),
+                    status: FrameStatus::Decoded {
+                        message: frame.message,
+                        sizes,
+                    },
+                }));
+            }
+            ScanItem::Skipped(range) => {
+                for item in split_skipped(data, range, layout) {
+                    match item {
+                        InspectItem::Frame(frame) => {
+                            summary.total_frames += 1;
+                            if filter.matches(frame.envelope()) {
+                                items.push(InspectItem::Frame(frame));
+                            }
+                        }
+                        InspectItem::Corrupt(region) => {
+                            summary.corrupt_regions += 1;
+                            summary.corrupt_bytes += region.range.len();
+                            items.push(InspectItem::Corrupt(region));
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    let mut frames: Vec<&FrameInfo> = items
+        .iter()
+        .filter_map(|item| match item {
+            InspectItem::Frame(frame) => Some(frame),
+            InspectItem::Corrupt(_) => None,
+        })
+        .collect();
+    summary.matched_frames = frames.len();
+    for frame in &frames {
+        if frame.message().is_none() {
+            summary.undecoded_frames += 1;
+        }
+        let envelope = frame.envelope();
+        *summary.per_type.entry(envelope.message_type).or_default() += 1;
+        *summary
+            .per_agent
+            .entry(envelope.agent_id.clone())
+            .or_default() += 1;
+        let ts = envelope.timestamp_utc_ms;
+        summary.time_range = Some(match summary.time_range {
+            Some((first, last)) => (first.min(ts), last.max(ts)),
+            None => (ts, ts),
+        });
+    }
+    // Stable sort: equal sizes stay in file order
+    frames.sort_by_key(|frame| std::cmp::Reverse(frame.frame_len));
+    summary.largest = frames
+        .iter()
+        .take(top)
+        .map(|frame| (frame.offset, frame.frame_len, frame.envelope().message_type))
+        .collect();
+
+    Inspection {
+        layout,
+        items,
+        summary,
+    }
+}
+
+/// Payload size on the wire and after decompression for a frame the scanner already accepted.
+fn payload_sizes(frame: &[u8], layout: FrameLayout) -> Result<PayloadSizes, ProtocolError> {
+    let (view, _) =
+        MessageRef::from_frame_with_options(frame, &mut DecodeOptions::new().with_layout(layout))?;
+    Ok(PayloadSizes {
+        encoded: view.encoded_payload_len(),
+        decoded: view.payload_bytes().len(),
+    })
+}
+
+/// Split a skipped range into the frames at its start whose envelope can still be read and the
+/// corrupt region after them.
+fn split_skipped(data: &[u8], mut range: SkippedRange, layout: FrameLayout) -> Vec<InspectItem> {
+    let mut items = Vec::new();
+    while let Some(frame) = undecoded_frame(data, &range, layout) {
+        let end = frame.offset + frame.frame_len;
+        items.push(InspectItem::Frame(frame));
+        if end == range.end {
+            return items;
+        }
+        // No valid frame starts in the rest of the range; rescan it for the reason its first
+        // byte was rejected
+        range = match FrameScanner::with_layout(&data[end..range.end], layout).next() {
+            Some(ScanItem::Skipped(rest)) => SkippedRange {
+                start: end,
+                end: range.end,
+                reason: rest.reason,
+            },
+            _ => SkippedRange {
+                start: end,
+                ..range
+            },
+        };
+    }
+    let declared_len = data
+        .get(range.start..range.start + layout.header_len())
+        .and_then(|header| layout.body_len(header).ok());
+    items.push(InspectItem::Corrupt(CorruptRegion {
+        range,
+        declared_len,
+    }));
+    items
+}
+
+/// The frame at the start of `range`, if it lies within the range and its envelope can be
+/// read.
+fn undecoded_frame(data: &[u8], range: &SkippedRange, layout: FrameLayout) -> Option<FrameInfo> {
+    let header_len = layout.header_len();
+    let header = data.get(range.start..range.start + header_len)?;
+    let declared_len = layout.body_len(header).ok()?;
+    let frame_len = layout.frame_len(declared_len);
+    if frame_len > range.len() {
+        return None;
+    }
+    let body_start = range.start + header_len;
+    let body = data.get(body_start..body_start + declared_len)?;
+    let envelope = EnvelopeRef::from_body(body).ok()?.to_owned().ok()?;
+    let status = match &range.reason {
+        SkipReason::Crc32Mismatch { expected, actual } => FrameStatus::CrcMismatch {
+            envelope,
+            expected: *expected,
+            actual: *actual,
+        },
+        SkipReason::Encrypted { .. } => FrameStatus::Encrypted { envelope },
+        SkipReason::UnknownDictionary { dictionary_id, .. } => FrameStatus::UnknownDictionary {
+            envelope,
+            dictionary_id: *dictionary_id,
+        },
+        SkipReason::Malformed(detail) => FrameStatus::Malformed {
+            envelope,
+            detail: detail.clone(),
+        },
+        SkipReason::InvalidLength(_)
+        | SkipReason::Truncated { .. }
+        | SkipReason::InvalidMessageType(_) => return None,
+    };
+    Some(FrameInfo {
+        offset: range.start,
+        frame_len,
+        declared_len,
+        status,
+    })
+}
+
+fn format_time(ms: i64) -> String {
+    DateTime::from_timestamp_millis(ms).map_or_else(
+        || format!("{ms} ms"),
+        |time| time.to_rfc3339_opts(SecondsFormat::Millis, true),
+    )
+}
+
+fn describe(reason: &SkipReason) -> String {
+    match reason {
+        SkipReason::InvalidLength(len) => format!("invalid body length {len}"),
+        SkipReason::Truncated {
+            declared,
+            available,
+        } => format!("truncated: frame needs {declared} bytes, {available} available"),
+        SkipReason::InvalidMessageType(byte) => format!("invalid message type {byte}"),
+        SkipReason::Crc32Mismatch { expected, actual } => {
+            format!("CRC mismatch: stored 0x{expected:08X}, computed 0x{actual:08X}")
+        }
+        SkipReason::Malformed(detail) => format!("CRC ok, body malformed: {detail}"),
+        SkipReason::UnknownDictionary { dictionary_id, .. } => {
+            format!("CRC ok, unknown dictionary id {dictionary_id}")
+        }
+        SkipReason::Encrypted { .. } => "CRC ok, payload encrypted".to_string(),
+    }
+}
+
+fn describe_status(frame: &FrameInfo) -> String {
+    match &frame.status {
+        FrameStatus::Decoded {
+            sizes: Ok(sizes), ..
+        } if frame.envelope().compressed => format!(
+            "CRC ok, payload compressed {} -> {} bytes (ratio {:.2})",
+            sizes.encoded,
+            sizes.decoded,
+            sizes.compression_ratio()
+        ),
+        FrameStatus::Decoded {
+            sizes: Ok(sizes), ..
+        } => format!("CRC ok, payload uncompressed {} bytes", sizes.decoded),
+        FrameStatus::Decoded { sizes: Err(e), .. } => format!("CRC ok, payload size unknown: {e}"),
+        FrameStatus::CrcMismatch {
+            expected, actual, ..
+        } => format!("CRC bad: stored 0x{expected:08X}, computed 0x{actual:08X}"),
+        FrameStatus::Encrypted { .. } => "CRC ok, payload encrypted".to_string(),
+        FrameStatus::UnknownDictionary { dictionary_id, .. } => {
+            format!("CRC ok, unknown dictionary id {dictionary_id}")
+        }
+        FrameStatus::Malformed { detail, .. } => format!("CRC ok, payload malformed: {detail}"),
+    }
+}
+
+/// Envelope fields of a frame whose payload did not decode, on one line.
+fn describe_envelope(envelope: &Envelope) -> String {
+    let message_id = envelope
+        .message_id
+        .iter()
+        .fold(String::new(), |mut hex, byte| {
+            let _ = write!(hex, "{byte:02x}");
+            hex
+        });
+    format!(
+        "version {}.{}, message id {message_id}, time {}, agent {:?}, platform {:?}, {}",
+        envelope.version.major,
+        envelope.version.minor,
+        format_time(envelope.timestamp_utc_ms),
+        envelope.agent_id,
+        envelope.platform,
+        if envelope.compressed {
+            "compressed"
+        } else {
+            "uncompressed"
+        }
+    )
+}
+
+impl fmt::Display for Inspection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let json = JsonOptions::default().with_rfc3339_timestamps();
+        writeln!(f, "layout: {:?}", self.layout)?;
+        for item in &self.items {
+            match item {
+                InspectItem::Frame(frame) => {
+                    writeln!(
+                        f,
+                        "@{} {} frame {} bytes, declared body {}, {}",
+                        frame.offset,
+                        frame.envelope().message_type.name(),
+                        frame.frame_len,
+                        frame.declared_len,
+                        describe_status(frame)
+                    )?;
+                    match frame.message().map(|message| message.to_json_with(&json)) {
+                        Some(Ok(json)) => writeln!(f, "    {json}")?,
+                        Some(Err(err)) => writeln!(f, "    {err}")?,
+                        None => writeln!(f, "    {}", describe_envelope(frame.envelope()))?,
+                    }
+                }
+                InspectItem::Corrupt(region) => {
+                    let declared = region
+                        .declared_len
+                        .map_or_else(String::new, |len| format!(", declared body {len}"));
+                    writeln!(
+                        f,
+                        "@{} corrupt region {} bytes{declared}, {}",
+                        region.range.start,
+                        region.range.len(),
+                        describe(&region.range.reason)
+                    )?;
+                }
+            }
+        }
+
+        let summary = &self.summary;
+        writeln!(f, "summary:")?;
+        writeln!(
+            f,
+            "  frames: {} of {} matched ({} not decoded); {} corrupt regions ({} bytes)",
+            summary.matched_frames,
+            summary.total_frames,
+            summary.undecoded_frames,
+            summary.corrupt_regions,
+            summary.corrupt_bytes
+        )?;
+        let per_type: Vec<String> = summary
+            .per_type
+            .iter()
+            .map(|(message_type, count)| format!("{} {count}", message_type.name()))
+            .collect();
+        writeln!(f, "  types: {}", per_type.join(", "))?;
+        let per_agent: Vec<String> = summary
+            .per_agent
+            .iter()
+            .map(|(agent, count)| format!("{agent:?} {count}"))
+            .collect();
+        writeln!(f, "  agents: {}", per_agent.join(", "))?;
+        match summary.time_range {
+            Some((first, last)) => writeln!(
+                f,
+                "  time range: {} .. {}",
+                format_time(first),
+                format_time(last)
+            )?,
+            None => writeln!(f, "  time range: none")?,
+        }
+        let largest: Vec<String> = summary
+            .largest
+            .iter()
+            .map(|(offset, len, message_type)| {
+                format!("@{offset} {} {len} bytes", message_type.name())
+            })
+            .collect();
+        writeln!(f, "  largest: {}", largest.join(", "))
+    }
+}
@@ -1,10 +1,14 @@
+#[cfg(feature = "json")]
 pub mod demo_protocol;
+#[cfg(feature = "json")]
+pub mod inspect;
 /// Agent library exports
 ///
 /// Provides protocol encoding, framing, and core monitoring agent functionality.
 pub mod protocol;
 
 pub use protocol::{
-    AgentIdentity, BackpressureSignal, Message, MessageAck, MessageType, ProcessSample,
-    ProtocolError, ProtocolVersion, SnapshotPayload,
+    AgentIdentity, BackpressureSignal, HandshakeAckPayload, Message, MessageAck, MessageType,
+    ProcessSample, ProtocolError, ProtocolVersion, SnapshotDeltaPayload, SnapshotPartPayload,
+    SnapshotPayload, VersionRange,
 };
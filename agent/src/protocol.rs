@@ -8,13 +8,72 @@ use crc32fast::Hasher;
 /// - At-least-once delivery semantics with retry and de-duplication
 use serde::de::Error as SerdeError;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
-use std::io::{self, Cursor, Read, Write};
+use std::io::{self, Read, Write};
+
+mod auth;
+mod builder;
+mod compression;
+mod decoder;
+mod delta;
+mod dictionary;
+mod encoder;
+mod encryption;
+mod error_code;
+mod extensions;
+mod framing;
+#[cfg(feature = "json")]
+mod json;
+mod layout;
+mod limits;
+mod options;
+mod reader;
+mod scanner;
+mod segmentation;
+#[cfg(feature = "tokio")]
+mod tokio_codec;
+mod truncation;
+mod validation;
+mod view;
+
+pub use auth::{AuthKey, KeyRing, KeyStore, AUTH_TAG_SIZE};
+#[cfg(feature = "json")]
+pub(crate) use builder::SplitMix64;
+pub use builder::{message_id_timestamp_ms, Clock, FixedClock, MessageBuilder, SystemClock};
+pub use compression::CompressionPolicy;
+pub use decoder::{DecodedFrames, FrameDecoder};
+pub use delta::{SnapshotBaseStore, SnapshotDeltaEncoder, DEFAULT_DELTA_BASES};
+pub use dictionary::{CompressionDictionary, DEFAULT_DICTIONARY_SIZE};
+pub use encoder::FrameEncoder;
+pub use encryption::{PayloadCipher, SessionKeys, SessionRole, SESSION_NONCE_SIZE};
+pub use error_code::{ErrorAction, ErrorCategory, ErrorCode};
+pub use extensions::{tags, Extensions};
+pub use framing::FrameLayout;
+#[cfg(feature = "json")]
+pub use json::{frames_to_json_lines, json_lines_to_frames, JsonOptions, TimestampFormat};
+pub use limits::{DecodeLimits, DecodeMode};
+pub use options::DecodeOptions;
+pub use scanner::{
+    scan_frames, FrameScanner, RecoveredFrame, ScanItem, ScanReport, SkipReason, SkippedRange,
+};
+pub use segmentation::{split_snapshot, ReassemblyLimits, SnapshotReassembler, MAX_SNAPSHOT_PARTS};
+#[cfg(feature = "tokio")]
+pub use tokio_codec::{read_message, read_message_with_options, write_message, MessageCodec};
+pub use truncation::{sort_processes, truncate_snapshot, DEFAULT_TOP_N};
+pub use validation::{ValidationContext, Violation, ViolationKind};
+pub use view::{EnvelopeRef, MessageRef, PayloadRef, ProcessIter, ProcessSampleRef, SnapshotRef};
+
+use dictionary::dictionary_for;
+use extensions::{read_extensions, write_extensions};
+use layout::{check_decodable, check_encodable, decode_payload};
+use reader::FieldReader;
 
 /// Protocol version (MAJOR.MINOR format).
 ///
 /// - MAJOR: Breaking changes to message structure
 /// - MINOR: Backward-compatible additions (optional fields)
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+///
+/// Versions are ordered by major, then minor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub struct ProtocolVersion {
     pub major: u8,
     pub minor: u8,
@@ -32,8 +91,47 @@ impl ProtocolVersion {
     }
 }
 
+/// Inclusive range of protocol versions supported by one side of a session.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VersionRange {
+    /// Lowest supported version
+    pub min: ProtocolVersion,
+    /// Highest supported version
+    pub max: ProtocolVersion,
+}
+
+impl VersionRange {
+    /// Range containing only the current protocol version.
+    pub const CURRENT: Self = Self::single(ProtocolVersion::CURRENT);
+
+    /// Create a range; returns `None` if `min > max`.
+    pub fn new(min: ProtocolVersion, max: ProtocolVersion) -> Option<Self> {
+        (min <= max).then_some(Self { min, max })
+    }
+
+    /// Range containing exactly one version.
+    pub const fn single(version: ProtocolVersion) -> Self {
+        Self {
+            min: version,
+            max: version,
+        }
+    }
+
+    /// Check if a version lies within this range.
+    pub fn contains(&self, version: ProtocolVersion) -> bool {
+        self.min <= version && version <= self.max
+    }
+
+    /// Highest version supported by both ranges, if they overlap.
+    pub fn highest_common(&self, other: &Self) -> Option<ProtocolVersion> {
+        let low = self.min.max(other.min);
+        let high = self.max.min(other.max);
+        (low <= high).then_some(high)
+    }
+}
+
 /// Message types in the protocol.
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 #[repr(u8)]
 pub enum MessageType {
     /// Initial handshake from agent to server
@@ -50,6 +148,10 @@ pub enum MessageType {
     Backpressure = 6,
     /// Error notification
     Error = 7,
+    /// One segment of a snapshot too large for a single frame
+    SnapshotPart = 8,
+    /// Snapshot encoded as changes against an acknowledged base snapshot
+    SnapshotDelta = 9,
 }
 
 impl MessageType {
@@ -63,6 +165,8 @@ impl MessageType {
             5 => Ok(MessageType::Ack),
             6 => Ok(MessageType::Backpressure),
             7 => Ok(MessageType::Error),
+            8 => Ok(MessageType::SnapshotPart),
+            9 => Ok(MessageType::SnapshotDelta),
             _ => Err(ProtocolError::InvalidMessageType(value)),
         }
     }
@@ -71,6 +175,28 @@ impl MessageType {
     pub fn to_u8(self) -> u8 {
         self as u8
     }
+
+    /// Variant name (e.g. `"SnapshotDelta"`), as used by the canonical JSON form
+    pub fn name(self) -> &'static str {
+        match self {
+            MessageType::Handshake => "Handshake",
+            MessageType::HandshakeAck => "HandshakeAck",
+            MessageType::Heartbeat => "Heartbeat",
+            MessageType::Snapshot => "Snapshot",
+            MessageType::Ack => "Ack",
+            MessageType::Backpressure => "Backpressure",
+            MessageType::Error => "Error",
+            MessageType::SnapshotPart => "SnapshotPart",
+            MessageType::SnapshotDelta => "SnapshotDelta",
+        }
+    }
+
+    /// Convert from a variant name
+    pub fn from_name(name: &str) -> Option<Self> {
+        (1..=9)
+            .filter_map(|value| MessageType::from_u8(value).ok())
+            .find(|message_type| message_type.name() == name)
+    }
 }
 
 impl Serialize for MessageType {
@@ -100,9 +226,29 @@ pub enum OsType {
     Linux = 2,
 }
 
+impl OsType {
+    /// Platform of the build target.
+    pub const fn current() -> Self {
+        if cfg!(windows) {
+            OsType::Windows
+        } else {
+            OsType::Linux
+        }
+    }
+
+    /// Convert from u8 discriminant
+    pub fn from_u8(value: u8) -> Result<Self, ProtocolError> {
+        match value {
+            1 => Ok(OsType::Windows),
+            2 => Ok(OsType::Linux),
+            _ => Err(ProtocolError::InvalidPlatform(value)),
+        }
+    }
+}
+
 /// Agent identity information sent during handshake.
 ///
-/// Includes instance ID, OS type, version, and capability flags.
+/// Includes instance ID, OS type, version range, and capability flags.
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct AgentIdentity {
     /// Unique instance identifier for this agent
@@ -111,9 +257,11 @@ pub struct AgentIdentity {
     pub os_type: OsType,
     /// Agent version string
     pub agent_version: String,
-    /// Protocol version supported by this agent
-    pub protocol_version: ProtocolVersion,
-    /// Capability flags (bit 0: supports all-process mode, bit 1: compression)
+    /// Range of protocol versions supported by this agent
+    pub supported_versions: VersionRange,
+    /// Capability flags (bit 0: supports all-process mode, bit 1: compression, bit 2: snapshot deltas,
+    /// bit 3: compression dictionary, whose id is in bits 16..=31; bit 4: payload encryption;
+    /// bit 5: spec frame layout; bits 8..=15: preferred zstd level)
     pub capabilities: u32,
 }
 
@@ -122,6 +270,28 @@ impl AgentIdentity {
     pub const CAP_ALL_PROCESS: u32 = 0x01;
     /// Capability flag: supports zstd compression
     pub const CAP_COMPRESSION: u32 = 0x02;
+    /// Capability flag: supports `SnapshotDelta` messages
+    pub const CAP_SNAPSHOT_DELTA: u32 = 0x04;
+    /// Capability flag: holds the compression dictionary whose id is in `DICTIONARY_ID_MASK`
+    pub const CAP_COMPRESSION_DICTIONARY: u32 = 0x08;
+    /// Capability flag: holds a pre-shared key for payload encryption
+    pub const CAP_ENCRYPTION: u32 = 0x10;
+    /// Capability flag: reads and writes `FrameLayout::Spec` frames after the handshake
+    pub const CAP_SPEC_FRAMING: u32 = 0x20;
+    /// Capability bits carrying the compression dictionary id
+    pub const DICTIONARY_ID_MASK: u32 = 0xFFFF_0000;
+    /// Capability bits carrying the preferred zstd level (0: no preference)
+    pub const COMPRESSION_LEVEL_MASK: u32 = 0x0000_FF00;
+
+    /// Capability bits stating a preferred zstd `level`.
+    pub const fn compression_level_capabilities(level: u8) -> u32 {
+        (level as u32) << 8
+    }
+
+    /// Capability bits advertising the compression dictionary `id`.
+    pub const fn dictionary_capabilities(id: u16) -> u32 {
+        Self::CAP_COMPRESSION_DICTIONARY | (id as u32) << 16
+    }
 
     /// Check if agent supports all-process mode
     pub fn supports_all_process(&self) -> bool {
@@ -132,11 +302,147 @@ impl AgentIdentity {
     pub fn supports_compression(&self) -> bool {
         (self.capabilities & Self::CAP_COMPRESSION) != 0
     }
+
+    /// Check if agent supports snapshot deltas
+    pub fn supports_snapshot_delta(&self) -> bool {
+        (self.capabilities & Self::CAP_SNAPSHOT_DELTA) != 0
+    }
+
+    /// Check if agent supports payload encryption
+    pub fn supports_encryption(&self) -> bool {
+        (self.capabilities & Self::CAP_ENCRYPTION) != 0
+    }
+
+    /// Check if agent supports the spec frame layout
+    pub fn supports_spec_framing(&self) -> bool {
+        (self.capabilities & Self::CAP_SPEC_FRAMING) != 0
+    }
+
+    /// Id of the compression dictionary the agent holds, if any
+    pub fn dictionary_id(&self) -> Option<u16> {
+        dictionary_id(self.capabilities)
+    }
+
+    /// zstd level the agent prefers, if stated
+    pub fn compression_level(&self) -> Option<u8> {
+        compression_level(self.capabilities)
+    }
+}
+
+/// Server reply to a handshake with the parameters chosen for the session.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HandshakeAckPayload {
+    /// Protocol version chosen for the session (highest mutually supported)
+    pub negotiated_version: ProtocolVersion,
+    /// Capability flags enabled for the session (supported by both sides)
+    pub negotiated_capabilities: u32,
+}
+
+impl HandshakeAckPayload {
+    /// Check if all-process mode was negotiated
+    pub fn all_process_enabled(&self) -> bool {
+        (self.negotiated_capabilities & AgentIdentity::CAP_ALL_PROCESS) != 0
+    }
+
+    /// Check if compression was negotiated
+    pub fn compression_enabled(&self) -> bool {
+        (self.negotiated_capabilities & AgentIdentity::CAP_COMPRESSION) != 0
+    }
+
+    /// Check if snapshot deltas were negotiated
+    pub fn snapshot_delta_enabled(&self) -> bool {
+        (self.negotiated_capabilities & AgentIdentity::CAP_SNAPSHOT_DELTA) != 0
+    }
+
+    /// Check if payload encryption was negotiated
+    pub fn encryption_enabled(&self) -> bool {
+        (self.negotiated_capabilities & AgentIdentity::CAP_ENCRYPTION) != 0
+    }
+
+    /// Frame layout of every frame after the handshake ack
+    pub fn frame_layout(&self) -> FrameLayout {
+        if self.negotiated_capabilities & AgentIdentity::CAP_SPEC_FRAMING != 0 {
+            FrameLayout::Spec
+        } else {
+            FrameLayout::Legacy
+        }
+    }
+
+    /// Id of the compression dictionary both sides use, if one was negotiated
+    pub fn dictionary_id(&self) -> Option<u16> {
+        dictionary_id(self.negotiated_capabilities)
+    }
+
+    /// zstd level for the session, if either side stated a preference
+    pub fn compression_level(&self) -> Option<u8> {
+        compression_level(self.negotiated_capabilities)
+    }
+}
+
+fn dictionary_id(capabilities: u32) -> Option<u16> {
+    let id = (capabilities >> 16) as u16;
+    (capabilities & AgentIdentity::CAP_COMPRESSION_DICTIONARY != 0 && id != 0).then_some(id)
+}
+
+fn compression_level(capabilities: u32) -> Option<u8> {
+    let level = ((capabilities & AgentIdentity::COMPRESSION_LEVEL_MASK) >> 8) as u8;
+    (level != 0).then_some(level)
+}
+
+/// Negotiate session parameters from an agent handshake (FR-003).
+///
+/// Chooses the highest protocol version inside both the agent's and the server's supported
+/// ranges, and the intersection of both capability sets. A compression dictionary is only
+/// negotiated if both sides advertise the same dictionary id and compression; the compression
+/// level is the lower of both preferred levels.
+///
+/// Returns `ProtocolError::IncompatibleVersion` if the version ranges do not overlap.
+pub fn negotiate(
+    agent: &AgentIdentity,
+    server_versions: VersionRange,
+    server_capabilities: u32,
+) -> Result<HandshakeAckPayload, ProtocolError> {
+    let negotiated_version = agent
+        .supported_versions
+        .highest_common(&server_versions)
+        .ok_or(ProtocolError::IncompatibleVersion)?;
+
+    let flags = agent.capabilities
+        & server_capabilities
+        & !(AgentIdentity::CAP_COMPRESSION_DICTIONARY
+            | AgentIdentity::DICTIONARY_ID_MASK
+            | AgentIdentity::COMPRESSION_LEVEL_MASK);
+    let mut negotiated_capabilities = flags;
+    if flags & AgentIdentity::CAP_COMPRESSION != 0 {
+        if let (Some(agent_id), Some(server_id)) =
+            (agent.dictionary_id(), dictionary_id(server_capabilities))
+        {
+            if agent_id == server_id {
+                negotiated_capabilities |= AgentIdentity::dictionary_capabilities(agent_id);
+            }
+        }
+        let level = match (
+            agent.compression_level(),
+            compression_level(server_capabilities),
+        ) {
+            (Some(agent_level), Some(server_level)) => Some(agent_level.min(server_level)),
+            (agent_level, server_level) => agent_level.or(server_level),
+        };
+        if let Some(level) = level {
+            negotiated_capabilities |= AgentIdentity::compression_level_capabilities(level);
+        }
+    }
+
+    Ok(HandshakeAckPayload {
+        negotiated_version,
+        negotiated_capabilities,
+    })
 }
 
 /// Single process sample in a snapshot.
 ///
-/// Ordered by CPU usage (descending) when truncation is applied.
+/// Ordered by CPU usage (descending, then pid ascending) when truncation is applied;
+/// see [`truncate_snapshot`].
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ProcessSample {
     /// Process ID
@@ -174,6 +480,65 @@ pub struct SnapshotPayload {
     pub truncated: bool,
 }
 
+/// One part of a segmented snapshot (FR-009).
+///
+/// All parts of a snapshot share `snapshot_id` and carry the same aggregate fields; each part
+/// holds a contiguous slice of the process list. Parts are reassembled in `part_index` order.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SnapshotPartPayload {
+    /// Identifier shared by all parts of the snapshot (opaque 16-byte identifier)
+    pub snapshot_id: [u8; 16],
+    /// Zero-based index of this part
+    pub part_index: u32,
+    /// Total number of parts in the snapshot
+    pub part_count: u32,
+    /// Aggregates plus this part's slice of process samples
+    pub snapshot: SnapshotPayload,
+}
+
+/// Snapshot encoded as the changes against an earlier, acknowledged snapshot.
+///
+/// Aggregates are always sent in full. Processes are matched by pid: a process whose name or
+/// command line changed is sent in `added`, one whose numbers changed in `changed`. Build with
+/// [`SnapshotDeltaPayload::diff`] and rebuild the full snapshot with
+/// [`SnapshotDeltaPayload::apply`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SnapshotDeltaPayload {
+    /// Message id of the base snapshot (a `Snapshot` or `SnapshotDelta` message)
+    pub base_message_id: [u8; 16],
+    /// Sampling window start timestamp (Unix epoch seconds)
+    pub window_start_secs: i64,
+    /// Sampling window end timestamp (Unix epoch seconds)
+    pub window_end_secs: i64,
+    /// Aggregate CPU usage percentage (0.0 - 100.0)
+    pub total_cpu_percent: f32,
+    /// Memory currently in use (bytes)
+    pub memory_used_bytes: u64,
+    /// Total system memory (bytes)
+    pub memory_total_bytes: u64,
+    /// Processes not in the base snapshot (or whose name or command line changed)
+    pub added: Vec<ProcessSample>,
+    /// New measurements for processes in the base snapshot
+    pub changed: Vec<ProcessChange>,
+    /// Pids of base snapshot processes that are gone
+    pub removed: Vec<u32>,
+    /// True if process list was truncated to fit size cap
+    pub truncated: bool,
+}
+
+/// New measurements for a process already present in a delta's base snapshot.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ProcessChange {
+    /// Process ID
+    pub pid: u32,
+    /// CPU usage percentage (0.0 - 100.0)
+    pub cpu_percent: f32,
+    /// Memory usage percentage of total system memory (0.0 - 100.0)
+    pub memory_percent: f32,
+    /// Memory usage in bytes (RSS)
+    pub memory_bytes: u64,
+}
+
 /// Backpressure signal from server to agent.
 ///
 /// Instructs agent to throttle its send rate by applying a delay.
@@ -216,6 +581,8 @@ pub struct Envelope {
     pub platform: OsType,
     /// True if payload is zstd-compressed
     pub compressed: bool,
+    /// Optional envelope fields (written only when non-empty)
+    pub extensions: Extensions,
 }
 
 /// Top-level message container.
@@ -227,18 +594,39 @@ pub struct Message {
     pub envelope: Envelope,
     /// Message payload (varies by type)
     pub payload: MessagePayload,
+    /// Optional payload fields appended after the fixed payload layout (written only when non-empty)
+    pub payload_extensions: Extensions,
 }
 
 /// Message payload variants.
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum MessagePayload {
     Handshake(AgentIdentity),
-    HandshakeAck,
+    HandshakeAck(HandshakeAckPayload),
     Heartbeat,
     Snapshot(SnapshotPayload),
     Ack(MessageAck),
     Backpressure(BackpressureSignal),
     Error { code: u32, message: String },
+    SnapshotPart(SnapshotPartPayload),
+    SnapshotDelta(Box<SnapshotDeltaPayload>),
+}
+
+impl MessagePayload {
+    /// Message type whose layout carries this payload.
+    pub fn message_type(&self) -> MessageType {
+        match self {
+            MessagePayload::Handshake(_) => MessageType::Handshake,
+            MessagePayload::HandshakeAck(_) => MessageType::HandshakeAck,
+            MessagePayload::Heartbeat => MessageType::Heartbeat,
+            MessagePayload::Snapshot(_) => MessageType::Snapshot,
+            MessagePayload::Ack(_) => MessageType::Ack,
+            MessagePayload::Backpressure(_) => MessageType::Backpressure,
+            MessagePayload::Error { .. } => MessageType::Error,
+            MessagePayload::SnapshotPart(_) => MessageType::SnapshotPart,
+            MessagePayload::SnapshotDelta(_) => MessageType::SnapshotDelta,
+        }
+    }
 }
 
 /// Protocol errors.
@@ -250,6 +638,8 @@ pub enum ProtocolError {
     IncompatibleVersion,
     #[error("Frame too large: {0} bytes (max {1})")]
     FrameTooLarge(usize, usize),
+    #[error("Invalid frame length prefix: {0}")]
+    InvalidFrameLength(usize),
     #[error("CRC32 checksum mismatch: expected {expected:#010x}, got {actual:#010x}")]
     Crc32Mismatch { expected: u32, actual: u32 },
     #[error("IO error: {0}")]
@@ -258,6 +648,105 @@ pub enum ProtocolError {
     Serialization(String),
     #[error("Compression error: {0}")]
     Compression(String),
+    #[error("Invalid extension field {tag:#06x}: {reason}")]
+    InvalidExtension { tag: u16, reason: String },
+    #[error("String length {len} exceeds limit {max}")]
+    StringTooLong { len: u64, max: usize },
+    #[error("Process count {count} exceeds limit {max}")]
+    TooManyProcesses { count: u64, max: usize },
+    #[error("Decompressed payload exceeds limit of {max} bytes")]
+    DecompressedSizeExceeded { max: usize },
+    #[error("Payload of {compressed} compressed bytes exceeds decompression ratio {max_ratio}")]
+    CompressionRatioExceeded { compressed: usize, max_ratio: usize },
+    #[error("Invalid snapshot part: {0}")]
+    InvalidSnapshotPart(String),
+    #[error("Invalid snapshot delta: {0}")]
+    InvalidSnapshotDelta(String),
+    #[error("Unknown delta base snapshot {0:02x?}")]
+    UnknownDeltaBase([u8; 16]),
+    #[error("Encryption error: {0}")]
+    Encryption(String),
+    #[error("Frame is not encrypted but a payload cipher is configured")]
+    UnencryptedFrame,
+    #[error("Invalid compression dictionary: {0}")]
+    InvalidDictionary(String),
+    #[error("Payload compressed with unknown zstd dictionary {0}")]
+    UnknownDictionary(u32),
+    #[error("Frame authentication failed: {0}")]
+    AuthenticationFailed(String),
+    #[error("Invalid platform: {0}")]
+    InvalidPlatform(u8),
+    #[error("Invalid boolean value: {0} (expected 0 or 1)")]
+    InvalidBool(u8),
+    #[error("Unknown envelope flags: {0:#04x}")]
+    InvalidFlags(u8),
+    #[error("{0} trailing bytes after payload")]
+    TrailingBytes(usize),
+    #[error("Invalid JSON message: {0}")]
+    InvalidJson(String),
+    #[error("Envelope message type {envelope:?} does not match payload type {payload:?}")]
+    MessageTypeMismatch {
+        envelope: MessageType,
+        payload: MessageType,
+    },
+    /// Decode error located at a field of the frame body.
+    ///
+    /// `offset` is relative to the start of the frame body, except inside a compressed payload,
+    /// where it is relative to the start of the decompressed payload.
+    #[error("{source} (field `{path}` at byte {offset})")]
+    Field {
+        path: String,
+        offset: usize,
+        #[source]
+        source: Box<ProtocolError>,
+    },
+}
+
+impl ProtocolError {
+    /// The underlying error, without field location.
+    pub fn root_cause(&self) -> &ProtocolError {
+        match self {
+            ProtocolError::Field { source, .. } => source.root_cause(),
+            other => other,
+        }
+    }
+
+    /// Path of the field that failed to decode, e.g. `snapshot.processes[3].name`.
+    pub fn field_path(&self) -> Option<&str> {
+        match self {
+            ProtocolError::Field { path, .. } => Some(path),
+            _ => None,
+        }
+    }
+
+    /// Byte offset of the field that failed to decode.
+    pub fn byte_offset(&self) -> Option<usize> {
+        match self {
+            ProtocolError::Field { offset, .. } => Some(*offset),
+            _ => None,
+        }
+    }
+
+    /// Locate this error at `path`; an already located error keeps its offset and gains
+    /// `path` as a prefix.
+    fn at(self, path: String, offset: usize) -> Self {
+        match self {
+            ProtocolError::Field {
+                path: inner,
+                offset,
+                source,
+            } => ProtocolError::Field {
+                path: format!("{path}.{inner}"),
+                offset,
+                source,
+            },
+            source => ProtocolError::Field {
+                path,
+                offset,
+                source: Box::new(source),
+            },
+        }
+    }
 }
 
 impl From<bincode::Error> for ProtocolError {
@@ -272,14 +761,35 @@ impl From<bincode::Error> for ProtocolError {
 /// `FrameCodec.MaxFrameSize` constant in the C# implementation.
 /// If you change this limit here, update the C# value as well to avoid
 /// protocol incompatibilities between agent and server.
-const MAX_FRAME_SIZE: usize = 256 * 1024;
+pub const MAX_FRAME_SIZE: usize = 256 * 1024;
 
 /// Target compressed frame size (64 KB)
 pub const TARGET_FRAME_SIZE: usize = 64 * 1024;
 
+/// Size of the frame length prefix.
+const LENGTH_PREFIX_SIZE: usize = 4;
+
+/// Size of the little-endian CRC32 of the frame body.
+const CRC_SIZE: usize = 4;
+
+/// zstd compression level for payloads
+const ZSTD_LEVEL: i32 = 3;
+
+/// Envelope flags byte: payload is zstd-compressed
+const FLAG_COMPRESSED: u8 = 0x01;
+/// Envelope flags byte: an envelope extension area follows the flags byte
+const FLAG_EXTENSIONS: u8 = 0x02;
+/// Envelope flags byte: the frame body ends with an HMAC-SHA256 tag (see `auth`)
+const FLAG_AUTHENTICATED: u8 = 0x04;
+/// Envelope flags byte: the payload is AEAD-encrypted (see `encryption`)
+const FLAG_ENCRYPTED: u8 = 0x08;
+/// Every envelope flag this implementation understands
+const KNOWN_FLAGS: u8 = FLAG_COMPRESSED | FLAG_EXTENSIONS | FLAG_AUTHENTICATED | FLAG_ENCRYPTED;
+
 /// Wire format framing and encoding.
 ///
-/// Framing: [length: u32 big-endian][message bytes][crc32: u32 little-endian]
+/// Framing: [length: u32 big-endian][message bytes][crc32: u32 little-endian], or the layout
+/// passed to the `*_with_layout` functions (see [`FrameLayout`])
 /// Message bytes: custom-encoded Message, optionally zstd-compressed
 pub struct FrameCodec;
 
@@ -294,96 +804,77 @@ impl FrameCodec {
     ///
     /// Returns: framed bytes ready to write to socket
     pub fn encode(message: &Message) -> Result<Vec<u8>, ProtocolError> {
-        let mut body = Vec::with_capacity(128);
-
-        // Envelope header layout: multi-byte envelope fields (message_id, timestamp_utc_ms) are encoded in
-        // little-endian; single-byte fields (version bytes, message type, compressed flag) have no
-        // endianness. The 4-byte frame length prefix and CRC32 suffix use big-endian and little-endian
-        // respectively. The .NET FrameCodec must read/write the same layout.
-        body.push(message.envelope.version.major);
-        body.push(message.envelope.version.minor);
-        body.push(message.envelope.message_type.to_u8());
-        body.extend_from_slice(&message.envelope.message_id);
-        body.extend_from_slice(&message.envelope.timestamp_utc_ms.to_le_bytes());
-        write_string(&mut body, &message.envelope.agent_id);
-        body.push(message.envelope.platform as u8);
-        body.push(if message.envelope.compressed { 1 } else { 0 });
-
-        // Serialize payload based on message type
+        Self::encode_frame(message, None, FrameLayout::Legacy)
+    }
+
+    /// Encode a message into a frame with the given `layout`.
+    pub fn encode_with_layout(
+        message: &Message,
+        layout: FrameLayout,
+    ) -> Result<Vec<u8>, ProtocolError> {
+        Self::encode_frame(message, None, layout)
+    }
+
+    /// Encode a message, encrypting its payload (after compression) with `cipher`.
+    ///
+    /// Only use a cipher from the `SessionKeys` of a session that negotiated `CAP_ENCRYPTION`.
+    pub fn encode_encrypted(
+        message: &Message,
+        cipher: &mut PayloadCipher,
+    ) -> Result<Vec<u8>, ProtocolError> {
+        Self::encode_frame(message, Some(cipher), FrameLayout::Legacy)
+    }
+
+    fn encode_frame(
+        message: &Message,
+        cipher: Option<&mut PayloadCipher>,
+        layout: FrameLayout,
+    ) -> Result<Vec<u8>, ProtocolError> {
+        check_message(message)?;
+        let mut frame = Vec::with_capacity(128);
+        let body_start = layout.begin_frame(&mut frame);
+        let flags_position = write_envelope(
+            &mut frame,
+            &message.envelope,
+            message.envelope.compressed,
+            None,
+        )?;
+
         let mut payload_bytes = Vec::with_capacity(256);
-        match &message.payload {
-            MessagePayload::Handshake(identity) => {
-                write_string(&mut payload_bytes, &identity.instance_id);
-                payload_bytes.push(identity.os_type as u8);
-                write_string(&mut payload_bytes, &identity.agent_version);
-                payload_bytes.push(identity.protocol_version.major);
-                payload_bytes.push(identity.protocol_version.minor);
-                payload_bytes.extend_from_slice(&identity.capabilities.to_le_bytes());
-            }
-            MessagePayload::HandshakeAck => {}
-            MessagePayload::Heartbeat => {}
-            MessagePayload::Snapshot(snapshot) => {
-                payload_bytes.extend_from_slice(&snapshot.window_start_secs.to_le_bytes());
-                payload_bytes.extend_from_slice(&snapshot.window_end_secs.to_le_bytes());
-                payload_bytes.extend_from_slice(&snapshot.total_cpu_percent.to_le_bytes());
-                payload_bytes.extend_from_slice(&snapshot.memory_used_bytes.to_le_bytes());
-                payload_bytes.extend_from_slice(&snapshot.memory_total_bytes.to_le_bytes());
-
-                let count = snapshot.processes.len() as u64;
-                payload_bytes.extend_from_slice(&count.to_le_bytes());
-                for process in &snapshot.processes {
-                    payload_bytes.extend_from_slice(&process.pid.to_le_bytes());
-                    write_string(&mut payload_bytes, &process.name);
-                    payload_bytes.extend_from_slice(&process.cpu_percent.to_le_bytes());
-                    payload_bytes.extend_from_slice(&process.memory_percent.to_le_bytes());
-                    payload_bytes.extend_from_slice(&process.memory_bytes.to_le_bytes());
-                    write_optional_string(&mut payload_bytes, process.cmdline.as_deref());
-                }
-
-                payload_bytes.push(if snapshot.truncated { 1 } else { 0 });
-            }
-            MessagePayload::Ack(ack) => {
-                payload_bytes.extend_from_slice(&ack.message_id);
-                payload_bytes.push(if ack.success { 1 } else { 0 });
-                write_optional_u32(&mut payload_bytes, ack.error_code);
-            }
-            MessagePayload::Backpressure(bp) => {
-                payload_bytes.extend_from_slice(&bp.throttle_delay_ms.to_le_bytes());
-                write_optional_string(&mut payload_bytes, bp.reason.as_deref());
-            }
-            MessagePayload::Error { code, message } => {
-                payload_bytes.extend_from_slice(&code.to_le_bytes());
-                write_string(&mut payload_bytes, message);
-            }
-        }
+        write_payload(&mut payload_bytes, message)?;
 
         // Compress payload bytes if requested; envelope stays uncompressed
         let encoded_payload = if message.envelope.compressed {
-            zstd::encode_all(payload_bytes.as_slice(), 3)
+            zstd::encode_all(payload_bytes.as_slice(), ZSTD_LEVEL)
                 .map_err(|e| ProtocolError::Compression(e.to_string()))?
         } else {
             payload_bytes
         };
 
-        // Final buffer = envelope + payload (compressed or not)
-        body.extend_from_slice(&encoded_payload);
-
-        // Check size constraints
-        if body.len() > MAX_FRAME_SIZE {
-            return Err(ProtocolError::FrameTooLarge(body.len(), MAX_FRAME_SIZE));
+        // Body = envelope + payload (compressed or not, then optionally encrypted)
+        let payload_start = frame.len();
+        frame.extend_from_slice(&encoded_payload);
+        if let Some(cipher) = cipher {
+            frame[flags_position] |= FLAG_ENCRYPTED;
+            cipher.seal(&mut frame, body_start, payload_start)?;
         }
 
-        // Compute CRC32 checksum of body
-        let mut hasher = Hasher::new();
-        hasher.update(&body);
-        let checksum = hasher.finalize();
-
-        // Build frame: [length:u32 BE][body][crc32:u32 LE]
-        let mut frame = Vec::with_capacity(4 + body.len() + 4);
-        frame.extend_from_slice(&(body.len() as u32).to_be_bytes());
-        frame.extend_from_slice(&body);
-        frame.extend_from_slice(&checksum.to_le_bytes());
+        // Check the body size, then fill in length prefix and CRC32
+        layout.finish_frame(&mut frame, 0)?;
+        Ok(frame)
+    }
 
+    /// Encode a message, compressing its payload with `dictionary` if `envelope.compressed`.
+    ///
+    /// Only use a dictionary the peer acknowledged in `HandshakeAckPayload::dictionary_id`.
+    pub fn encode_with_dictionary(
+        message: &Message,
+        dictionary: &CompressionDictionary,
+    ) -> Result<Vec<u8>, ProtocolError> {
+        let mut frame = Vec::new();
+        FrameEncoder::new()
+            .with_dictionary(dictionary.clone())
+            .encode_into(message, &mut frame)?;
         Ok(frame)
     }
 
@@ -398,178 +889,22 @@ impl FrameCodec {
     ///
     /// Returns: decoded Message
     pub fn decode<R: Read>(reader: &mut R) -> Result<Message, ProtocolError> {
-        // Read length prefix
-        let mut len_buf = [0u8; 4];
-        reader.read_exact(&mut len_buf)?;
-        let payload_len = u32::from_be_bytes(len_buf) as usize;
-
-        // Validate length
-        if payload_len > MAX_FRAME_SIZE {
-            return Err(ProtocolError::FrameTooLarge(payload_len, MAX_FRAME_SIZE));
-        }
-
-        // Read payload
-        let mut payload = vec![0u8; payload_len];
-        reader.read_exact(&mut payload)?;
-
-        // Read CRC32 checksum
-        let mut crc_buf = [0u8; 4];
-        reader.read_exact(&mut crc_buf)?;
-        let expected_crc = u32::from_le_bytes(crc_buf);
-
-        // Validate CRC32
-        let mut hasher = Hasher::new();
-        hasher.update(&payload);
-        let actual_crc = hasher.finalize();
-        if actual_crc != expected_crc {
-            return Err(ProtocolError::Crc32Mismatch {
-                expected: expected_crc,
-                actual: actual_crc,
-            });
-        }
-
-        let mut cursor = Cursor::new(&payload);
-
-        // Envelope
-        let mut version_buf = [0u8; 1];
-        cursor.read_exact(&mut version_buf)?;
-        let major = version_buf[0];
-        cursor.read_exact(&mut version_buf)?;
-        let minor = version_buf[0];
-
-        let mut mt_buf = [0u8; 1];
-        cursor.read_exact(&mut mt_buf)?;
-        let message_type = MessageType::from_u8(mt_buf[0])?;
-
-        let mut message_id = [0u8; 16];
-        cursor.read_exact(&mut message_id)?;
-        let timestamp_utc_ms = read_i64_le(&mut cursor)?;
-        let agent_id = read_string(&mut cursor)?;
-        let platform_raw = read_u8(&mut cursor)?;
-        let platform = match platform_raw {
-            1 => OsType::Windows,
-            2 => OsType::Linux,
-            other => return Err(ProtocolError::InvalidMessageType(other)),
-        };
-        let mut bool_buf = [0u8; 1];
-        cursor.read_exact(&mut bool_buf)?;
-        let compressed = bool_buf[0] != 0;
-
-        // Remaining bytes are payload (possibly compressed)
-        let remaining = payload[cursor.position() as usize..].to_vec();
-        let payload_bytes = if compressed {
-            zstd::decode_all(remaining.as_slice())
-                .map_err(|e| ProtocolError::Compression(e.to_string()))?
-        } else {
-            remaining
-        };
-
-        let mut payload_cursor = Cursor::new(&payload_bytes);
-        let payload = match message_type {
-            MessageType::Handshake => {
-                let instance_id = read_string(&mut payload_cursor)?;
-                let os_type_raw = read_u8(&mut payload_cursor)?;
-                let agent_version = read_string(&mut payload_cursor)?;
-                let proto_major = read_u8(&mut payload_cursor)?;
-                let proto_minor = read_u8(&mut payload_cursor)?;
-                let capabilities = read_u32_le(&mut payload_cursor)?;
-
-                MessagePayload::Handshake(AgentIdentity {
-                    instance_id,
-                    os_type: match os_type_raw {
-                        1 => OsType::Windows,
-                        2 => OsType::Linux,
-                        other => return Err(ProtocolError::InvalidMessageType(other)),
-                    },
-                    agent_version,
-                    protocol_version: ProtocolVersion {
-                        major: proto_major,
-                        minor: proto_minor,
-                    },
-                    capabilities,
-                })
-            }
-            MessageType::HandshakeAck => MessagePayload::HandshakeAck,
-            MessageType::Heartbeat => MessagePayload::Heartbeat,
-            MessageType::Snapshot => {
-                let window_start_secs = read_i64_le(&mut payload_cursor)?;
-                let window_end_secs = read_i64_le(&mut payload_cursor)?;
-                let total_cpu_percent = read_f32_le(&mut payload_cursor)?;
-                let memory_used_bytes = read_u64_le(&mut payload_cursor)?;
-                let memory_total_bytes = read_u64_le(&mut payload_cursor)?;
-
-                let process_count = read_u64_le(&mut payload_cursor)?;
-                let mut processes = Vec::with_capacity(process_count as usize);
-                for _ in 0..process_count {
-                    let pid = read_u32_le(&mut payload_cursor)?;
-                    let name = read_string(&mut payload_cursor)?;
-                    let cpu_percent = read_f32_le(&mut payload_cursor)?;
-                    let memory_percent = read_f32_le(&mut payload_cursor)?;
-                    let memory_bytes = read_u64_le(&mut payload_cursor)?;
-                    let cmdline = read_optional_string(&mut payload_cursor)?;
-
-                    processes.push(ProcessSample {
-                        pid,
-                        name,
-                        cpu_percent,
-                        memory_percent,
-                        memory_bytes,
-                        cmdline,
-                    });
-                }
-
-                let truncated = read_bool(&mut payload_cursor)?;
-
-                MessagePayload::Snapshot(SnapshotPayload {
-                    window_start_secs,
-                    window_end_secs,
-                    total_cpu_percent,
-                    memory_used_bytes,
-                    memory_total_bytes,
-                    processes,
-                    truncated,
-                })
-            }
-            MessageType::Ack => {
-                let mut ack_message_id = [0u8; 16];
-                payload_cursor.read_exact(&mut ack_message_id)?;
-                let success = read_bool(&mut payload_cursor)?;
-                let error_code = read_optional_u32(&mut payload_cursor)?;
-
-                MessagePayload::Ack(MessageAck {
-                    message_id: ack_message_id,
-                    success,
-                    error_code,
-                })
-            }
-            MessageType::Backpressure => {
-                let throttle_delay_ms = read_u32_le(&mut payload_cursor)?;
-                let reason = read_optional_string(&mut payload_cursor)?;
-
-                MessagePayload::Backpressure(BackpressureSignal {
-                    throttle_delay_ms,
-                    reason,
-                })
-            }
-            MessageType::Error => {
-                let code = read_u32_le(&mut payload_cursor)?;
-                let message = read_string(&mut payload_cursor)?;
-                MessagePayload::Error { code, message }
-            }
-        };
+        Self::decode_with_options(reader, &mut DecodeOptions::default())
+    }
 
-        Ok(Message {
-            envelope: Envelope {
-                version: ProtocolVersion { major, minor },
-                message_type,
-                message_id,
-                timestamp_utc_ms,
-                agent_id,
-                platform,
-                compressed,
-            },
-            payload,
-        })
+    /// Decode a message from a reader with `options`: limits, validation mode, frame layout,
+    /// and the dictionary, key store and cipher negotiated for the session.
+    ///
+    /// Fails with `ProtocolError::AuthenticationFailed` if a key store is set and the frame is
+    /// not authenticated or its tag does not verify, with `ProtocolError::UnencryptedFrame` if a
+    /// cipher is set and the frame is not encrypted, and with `ProtocolError::UnknownDictionary`
+    /// if the payload was compressed with a dictionary `options` does not hold.
+    pub fn decode_with_options<R: Read>(
+        reader: &mut R,
+        options: &mut DecodeOptions<'_>,
+    ) -> Result<Message, ProtocolError> {
+        let body = read_frame_body(reader, options.layout)?;
+        decode_body(&body, options)
     }
 
     /// Write a framed message to a writer.
@@ -583,92 +918,237 @@ impl FrameCodec {
     }
 }
 
-fn write_string(buf: &mut Vec<u8>, value: &str) {
-    let bytes = value.as_bytes();
-    buf.extend_from_slice(&(bytes.len() as u64).to_le_bytes());
-    buf.extend_from_slice(bytes);
+/// Read one frame in `layout` from `reader` and return its CRC-validated body.
+fn read_frame_body<R: Read>(reader: &mut R, layout: FrameLayout) -> Result<Vec<u8>, ProtocolError> {
+    // Read and validate the length prefix (and, in the spec layout, the CRC32 behind it)
+    let mut header_buf = [0u8; LENGTH_PREFIX_SIZE + CRC_SIZE];
+    let header = &mut header_buf[..layout.header_len()];
+    reader.read_exact(header)?;
+    let body_len = layout.body_len(header)?;
+
+    // Read body and trailer
+    let mut body = vec![0u8; body_len + layout.trailer_len()];
+    reader.read_exact(&mut body)?;
+    let expected_crc = layout.stored_crc(header, &body[body_len..])?;
+    body.truncate(body_len);
+
+    verify_crc32(&body, expected_crc)?;
+    Ok(body)
 }
 
-fn write_optional_string(buf: &mut Vec<u8>, value: Option<&str>) {
-    match value {
-        Some(v) => {
-            buf.push(1);
-            write_string(buf, v);
-        }
-        None => buf.push(0),
+/// Reject messages that would be encoded into a frame decoders misread.
+fn check_message(message: &Message) -> Result<(), ProtocolError> {
+    check_encodable(message.envelope.version)?;
+    let payload = message.payload.message_type();
+    if message.envelope.message_type != payload {
+        return Err(ProtocolError::MessageTypeMismatch {
+            envelope: message.envelope.message_type,
+            payload,
+        });
     }
+    Ok(())
 }
 
-fn write_optional_u32(buf: &mut Vec<u8>, value: Option<u32>) {
-    match value {
-        Some(v) => {
-            buf.push(1);
-            buf.extend_from_slice(&v.to_le_bytes());
-        }
-        None => buf.push(0),
+/// Validate the CRC32 checksum of a frame body.
+fn verify_crc32(body: &[u8], expected_crc: u32) -> Result<(), ProtocolError> {
+    let mut hasher = Hasher::new();
+    hasher.update(body);
+    let actual_crc = hasher.finalize();
+    if actual_crc != expected_crc {
+        return Err(ProtocolError::Crc32Mismatch {
+            expected: expected_crc,
+            actual: actual_crc,
+        });
     }
+    Ok(())
 }
 
-fn read_u8<R: Read>(reader: &mut R) -> Result<u8, ProtocolError> {
-    let mut buf = [0u8; 1];
-    reader.read_exact(&mut buf)?;
-    Ok(buf[0])
+/// Decode a CRC-validated frame body (envelope + payload) into a message.
+///
+/// With a key store in `options`, the frame must be authenticated and its tag is verified before
+/// the payload is decrypted (with a cipher, which requires the frame to be encrypted) and
+/// decompressed.
+fn decode_body(body: &[u8], options: &mut DecodeOptions<'_>) -> Result<Message, ProtocolError> {
+    // A frame that decrypts but whose payload does not decode must not use up its counter
+    let (message, opened) = MessageRef::parse_body_uncommitted(body, options)?;
+    let message = message.to_owned()?;
+    if let (Some(cipher), Some(counter)) = (options.cipher.as_deref_mut(), opened) {
+        cipher.commit_opened(counter);
+    }
+    Ok(message)
 }
 
-fn read_bool<R: Read>(reader: &mut R) -> Result<bool, ProtocolError> {
-    Ok(read_u8(reader)? != 0)
+/// Append the envelope header (including any extension area) to `buf`.
+///
+/// `compressed` sets the compressed flag; `key_id` marks the frame as authenticated and adds
+/// `tags::KEY_ID` to the extensions. Returns the position of the flags byte in `buf`.
+fn write_envelope(
+    buf: &mut Vec<u8>,
+    envelope: &Envelope,
+    compressed: bool,
+    key_id: Option<u32>,
+) -> Result<usize, ProtocolError> {
+    // Envelope header layout: multi-byte envelope fields (message_id, timestamp_utc_ms) are encoded in
+    // little-endian; single-byte fields (version bytes, message type, compressed flag) have no
+    // endianness. The framing around the body is described in `framing`. The .NET FrameCodec must
+    // read/write the same layout.
+    buf.push(envelope.version.major);
+    buf.push(envelope.version.minor);
+    buf.push(envelope.message_type.to_u8());
+    buf.extend_from_slice(&envelope.message_id);
+    buf.extend_from_slice(&envelope.timestamp_utc_ms.to_le_bytes());
+    write_string(buf, &envelope.agent_id);
+    buf.push(envelope.platform as u8);
+    // The compressed flag byte doubles as a flags byte; bit 1 announces envelope extensions.
+    let mut flags = if compressed { FLAG_COMPRESSED } else { 0 };
+    let signed_extensions;
+    let extensions = match key_id {
+        Some(key_id) => {
+            flags |= FLAG_AUTHENTICATED;
+            let mut extensions = envelope.extensions.clone();
+            extensions.insert_u32(tags::KEY_ID, key_id);
+            signed_extensions = extensions;
+            &signed_extensions
+        }
+        None => &envelope.extensions,
+    };
+    if !extensions.is_empty() {
+        flags |= FLAG_EXTENSIONS;
+    }
+    let flags_position = buf.len();
+    buf.push(flags);
+    if !extensions.is_empty() {
+        write_extensions(buf, extensions)?;
+    }
+    Ok(flags_position)
 }
 
-fn read_u32_le<R: Read>(reader: &mut R) -> Result<u32, ProtocolError> {
-    let mut buf = [0u8; 4];
-    reader.read_exact(&mut buf)?;
-    Ok(u32::from_le_bytes(buf))
+/// Append the payload (fixed fields, then any extension trailer) to `buf`.
+fn write_payload(buf: &mut Vec<u8>, message: &Message) -> Result<(), ProtocolError> {
+    // Serialize payload based on message type
+    match &message.payload {
+        MessagePayload::Handshake(identity) => {
+            write_string(buf, &identity.instance_id);
+            buf.push(identity.os_type as u8);
+            write_string(buf, &identity.agent_version);
+            // The max version sits where v1.0 placed the single protocol version; the min
+            // version is appended so decoders that predate version ranges still work.
+            buf.push(identity.supported_versions.max.major);
+            buf.push(identity.supported_versions.max.minor);
+            buf.extend_from_slice(&identity.capabilities.to_le_bytes());
+            buf.push(identity.supported_versions.min.major);
+            buf.push(identity.supported_versions.min.minor);
+        }
+        MessagePayload::HandshakeAck(ack) => {
+            buf.push(ack.negotiated_version.major);
+            buf.push(ack.negotiated_version.minor);
+            buf.extend_from_slice(&ack.negotiated_capabilities.to_le_bytes());
+        }
+        MessagePayload::Heartbeat => {}
+        MessagePayload::Snapshot(snapshot) => write_snapshot(buf, snapshot),
+        MessagePayload::SnapshotPart(part) => {
+            buf.extend_from_slice(&part.snapshot_id);
+            buf.extend_from_slice(&part.part_index.to_le_bytes());
+            buf.extend_from_slice(&part.part_count.to_le_bytes());
+            write_snapshot(buf, &part.snapshot);
+        }
+        MessagePayload::SnapshotDelta(delta) => {
+            buf.extend_from_slice(&delta.base_message_id);
+            buf.extend_from_slice(&delta.window_start_secs.to_le_bytes());
+            buf.extend_from_slice(&delta.window_end_secs.to_le_bytes());
+            buf.extend_from_slice(&delta.total_cpu_percent.to_le_bytes());
+            buf.extend_from_slice(&delta.memory_used_bytes.to_le_bytes());
+            buf.extend_from_slice(&delta.memory_total_bytes.to_le_bytes());
+            buf.extend_from_slice(&(delta.added.len() as u64).to_le_bytes());
+            for process in &delta.added {
+                write_process(buf, process);
+            }
+            buf.extend_from_slice(&(delta.changed.len() as u64).to_le_bytes());
+            for change in &delta.changed {
+                buf.extend_from_slice(&change.pid.to_le_bytes());
+                buf.extend_from_slice(&change.cpu_percent.to_le_bytes());
+                buf.extend_from_slice(&change.memory_percent.to_le_bytes());
+                buf.extend_from_slice(&change.memory_bytes.to_le_bytes());
+            }
+            buf.extend_from_slice(&(delta.removed.len() as u64).to_le_bytes());
+            for pid in &delta.removed {
+                buf.extend_from_slice(&pid.to_le_bytes());
+            }
+            buf.push(if delta.truncated { 1 } else { 0 });
+        }
+        MessagePayload::Ack(ack) => {
+            buf.extend_from_slice(&ack.message_id);
+            buf.push(if ack.success { 1 } else { 0 });
+            write_optional_u32(buf, ack.error_code);
+        }
+        MessagePayload::Backpressure(bp) => {
+            buf.extend_from_slice(&bp.throttle_delay_ms.to_le_bytes());
+            write_optional_string(buf, bp.reason.as_deref());
+        }
+        MessagePayload::Error { code, message } => {
+            buf.extend_from_slice(&code.to_le_bytes());
+            write_string(buf, message);
+        }
+    }
+    if !message.payload_extensions.is_empty() {
+        write_extensions(buf, &message.payload_extensions)?;
+    }
+    Ok(())
 }
 
-fn read_u64_le<R: Read>(reader: &mut R) -> Result<u64, ProtocolError> {
-    let mut buf = [0u8; 8];
-    reader.read_exact(&mut buf)?;
-    Ok(u64::from_le_bytes(buf))
-}
+fn write_snapshot(buf: &mut Vec<u8>, snapshot: &SnapshotPayload) {
+    buf.extend_from_slice(&snapshot.window_start_secs.to_le_bytes());
+    buf.extend_from_slice(&snapshot.window_end_secs.to_le_bytes());
+    buf.extend_from_slice(&snapshot.total_cpu_percent.to_le_bytes());
+    buf.extend_from_slice(&snapshot.memory_used_bytes.to_le_bytes());
+    buf.extend_from_slice(&snapshot.memory_total_bytes.to_le_bytes());
+
+    let count = snapshot.processes.len() as u64;
+    buf.extend_from_slice(&count.to_le_bytes());
+    for process in &snapshot.processes {
+        write_process(buf, process);
+    }
 
-fn read_i64_le<R: Read>(reader: &mut R) -> Result<i64, ProtocolError> {
-    let mut buf = [0u8; 8];
-    reader.read_exact(&mut buf)?;
-    Ok(i64::from_le_bytes(buf))
+    buf.push(if snapshot.truncated { 1 } else { 0 });
 }
 
-fn read_f32_le<R: Read>(reader: &mut R) -> Result<f32, ProtocolError> {
-    let mut buf = [0u8; 4];
-    reader.read_exact(&mut buf)?;
-    Ok(f32::from_le_bytes(buf))
+fn write_process(buf: &mut Vec<u8>, process: &ProcessSample) {
+    buf.extend_from_slice(&process.pid.to_le_bytes());
+    write_string(buf, &process.name);
+    buf.extend_from_slice(&process.cpu_percent.to_le_bytes());
+    buf.extend_from_slice(&process.memory_percent.to_le_bytes());
+    buf.extend_from_slice(&process.memory_bytes.to_le_bytes());
+    write_optional_string(buf, process.cmdline.as_deref());
 }
 
-fn read_string<R: Read>(reader: &mut R) -> Result<String, ProtocolError> {
-    let len = read_u64_le(reader)? as usize;
-    let mut buf = vec![0u8; len];
-    reader.read_exact(&mut buf)?;
-    String::from_utf8(buf).map_err(|e| ProtocolError::Serialization(e.to_string()))
+fn write_string(buf: &mut Vec<u8>, value: &str) {
+    let bytes = value.as_bytes();
+    buf.extend_from_slice(&(bytes.len() as u64).to_le_bytes());
+    buf.extend_from_slice(bytes);
 }
 
-fn read_optional_string<R: Read>(reader: &mut R) -> Result<Option<String>, ProtocolError> {
-    let has_value = read_bool(reader)?;
-    if has_value {
-        Ok(Some(read_string(reader)?))
-    } else {
-        Ok(None)
+fn write_optional_string(buf: &mut Vec<u8>, value: Option<&str>) {
+    match value {
+        Some(v) => {
+            buf.push(1);
+            write_string(buf, v);
+        }
+        None => buf.push(0),
     }
 }
 
-fn read_optional_u32<R: Read>(reader: &mut R) -> Result<Option<u32>, ProtocolError> {
-    let has_value = read_bool(reader)?;
-    if has_value {
-        Ok(Some(read_u32_le(reader)?))
-    } else {
-        Ok(None)
+fn write_optional_u32(buf: &mut Vec<u8>, value: Option<u32>) {
+    match value {
+        Some(v) => {
+            buf.push(1);
+            buf.extend_from_slice(&v.to_le_bytes());
+        }
+        None => buf.push(0),
     }
 }
 
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
 mod tests {
     use super::*;
     use std::io::Cursor;
@@ -707,7 +1187,7 @@ mod tests {
             instance_id: "test-001".to_string(),
             os_type: OsType::Linux,
             agent_version: "0.1.0".to_string(),
-            protocol_version: ProtocolVersion::CURRENT,
+            supported_versions: VersionRange::CURRENT,
             capabilities: AgentIdentity::CAP_ALL_PROCESS | AgentIdentity::CAP_COMPRESSION,
         };
 
@@ -736,7 +1216,7 @@ mod tests {
             instance_id: "agent-123".to_string(),
             os_type: OsType::Windows,
             agent_version: "0.1.0".to_string(),
-            protocol_version: ProtocolVersion::CURRENT,
+            supported_versions: VersionRange::CURRENT,
             capabilities: AgentIdentity::CAP_COMPRESSION,
         };
 
@@ -749,8 +1229,10 @@ mod tests {
                 agent_id: "agent-123".to_string(),
                 platform: OsType::Windows,
                 compressed: false,
+                extensions: Extensions::new(),
             },
             payload: MessagePayload::Handshake(identity.clone()),
+            payload_extensions: Extensions::new(),
         };
 
         // Encode
@@ -813,8 +1295,10 @@ mod tests {
                 agent_id: "test-agent".to_string(),
                 platform: OsType::Linux,
                 compressed: false,
+                extensions: Extensions::new(),
             },
             payload: MessagePayload::Snapshot(snapshot.clone()),
+            payload_extensions: Extensions::new(),
         };
 
         // Encode and decode
@@ -868,8 +1352,10 @@ mod tests {
                 agent_id: "test-agent".to_string(),
                 platform: OsType::Linux,
                 compressed: true, // Enable compression
+                extensions: Extensions::new(),
             },
             payload: MessagePayload::Snapshot(snapshot.clone()),
+            payload_extensions: Extensions::new(),
         };
 
         // Encode (should compress)
@@ -882,6 +1368,7 @@ mod tests {
                 ..message.envelope.clone()
             },
             payload: message.payload.clone(),
+            payload_extensions: Extensions::new(),
         };
         let uncompressed_frame = FrameCodec::encode(&uncompressed_message).unwrap();
         assert!(
@@ -915,11 +1402,13 @@ mod tests {
                 agent_id: "test-agent".to_string(),
                 platform: OsType::Linux,
                 compressed: false,
+                extensions: Extensions::new(),
             },
             payload: MessagePayload::Backpressure(BackpressureSignal {
                 throttle_delay_ms: 5000,
                 reason: Some("Server buffer threshold exceeded".to_string()),
             }),
+            payload_extensions: Extensions::new(),
         };
 
         let frame = FrameCodec::encode(&message).unwrap();
@@ -950,12 +1439,14 @@ mod tests {
                 agent_id: "test-agent".to_string(),
                 platform: OsType::Linux,
                 compressed: false,
+                extensions: Extensions::new(),
             },
             payload: MessagePayload::Ack(MessageAck {
                 message_id: test_message_id(42),
                 success: false,
                 error_code: Some(1001),
             }),
+            payload_extensions: Extensions::new(),
         };
 
         let frame = FrameCodec::encode(&message).unwrap();
@@ -1007,8 +1498,10 @@ mod tests {
                 agent_id: "test-agent".to_string(),
                 platform: OsType::Linux,
                 compressed: false,
+                extensions: Extensions::new(),
             },
             payload: MessagePayload::Snapshot(large_snapshot),
+            payload_extensions: Extensions::new(),
         };
 
         // Should fail to encode due to size
@@ -1081,8 +1574,10 @@ mod tests {
                 agent_id: "test-agent".to_string(),
                 platform: OsType::Linux,
                 compressed: false,
+                extensions: Extensions::new(),
             },
             payload: MessagePayload::Snapshot(snapshot),
+            payload_extensions: Extensions::new(),
         };
 
         // Encode the message
@@ -0,0 +1,186 @@
+//! Frame authentication with HMAC-SHA256.
+//!
+//! The CRC32 trailer detects accidental corruption only; anyone who can reach the server can
+//! forge a frame with a valid checksum. An authenticated frame proves it was produced by a
+//! holder of a pre-shared key for its `agent_id`:
+//! - the envelope flags byte has `FLAG_AUTHENTICATED` set and the envelope extension
+//!   `tags::KEY_ID` names the key (u32 little-endian);
+//! - the last `AUTH_TAG_SIZE` bytes of the frame body are an HMAC-SHA256 tag over every body
+//!   byte before them (envelope and payload as transmitted, i.e. after compression).
+//!
+//! Keys come from a [`KeyStore`]. A key signs and verifies frames whose envelope timestamp lies
+//! in its validity window, so both sides can rotate keys with overlapping windows: the encoder
+//! signs with the newest valid key while the decoder still accepts frames signed with the
+//! previous one. To revoke a key, remove it from the store.
+
+use super::{tags, EnvelopeRef, ProtocolError};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::fmt;
+
+/// Size of the HMAC-SHA256 tag at the end of an authenticated frame body.
+pub const AUTH_TAG_SIZE: usize = 32;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// A pre-shared frame authentication key.
+#[derive(Clone, PartialEq, Eq)]
+pub struct AuthKey {
+    key_id: u32,
+    secret: Vec<u8>,
+    /// First envelope timestamp (UTC ms, inclusive) the key is valid for
+    not_before_ms: i64,
+    /// Envelope timestamp (UTC ms, exclusive) from which the key is no longer valid
+    not_after_ms: i64,
+}
+
+impl fmt::Debug for AuthKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AuthKey")
+            .field("key_id", &self.key_id)
+            .field("not_before_ms", &self.not_before_ms)
+            .field("not_after_ms", &self.not_after_ms)
+            .finish_non_exhaustive()
+    }
+}
+
+impl AuthKey {
+    /// Key `key_id` with `secret`, valid for any timestamp.
+    pub fn new(key_id: u32, secret: impl Into<Vec<u8>>) -> Self {
+        Self {
+            key_id,
+            secret: secret.into(),
+            not_before_ms: i64::MIN,
+            not_after_ms: i64::MAX,
+        }
+    }
+
+    /// Restrict the key to frames timestamped at or after `not_before_ms`.
+    pub fn valid_from(self, not_before_ms: i64) -> Self {
+        Self {
+            not_before_ms,
+            ..self
+        }
+    }
+
+    /// Restrict the key to frames timestamped before `not_after_ms`.
+    pub fn valid_until(self, not_after_ms: i64) -> Self {
+        Self {
+            not_after_ms,
+            ..self
+        }
+    }
+
+    /// Key id, as carried in `tags::KEY_ID`.
+    pub fn key_id(&self) -> u32 {
+        self.key_id
+    }
+
+    /// True if the key may sign a frame timestamped `timestamp_utc_ms`.
+    pub fn is_valid_at(&self, timestamp_utc_ms: i64) -> bool {
+        self.not_before_ms <= timestamp_utc_ms && timestamp_utc_ms < self.not_after_ms
+    }
+
+    /// HMAC-SHA256 tag over `data`.
+    pub(super) fn tag(&self, data: &[u8]) -> Result<[u8; AUTH_TAG_SIZE], ProtocolError> {
+        Ok(self.mac(data)?.finalize().into_bytes().into())
+    }
+
+    fn mac(&self, data: &[u8]) -> Result<HmacSha256, ProtocolError> {
+        let mut mac = HmacSha256::new_from_slice(&self.secret).map_err(|e| {
+            ProtocolError::AuthenticationFailed(format!("key {} is unusable: {e}", self.key_id))
+        })?;
+        mac.update(data);
+        Ok(mac)
+    }
+}
+
+/// Source of frame authentication keys.
+///
+/// Implement this to look keys up in a database or secret manager; [`KeyRing`] is an in-memory
+/// implementation.
+pub trait KeyStore: fmt::Debug + Send + Sync {
+    /// Key `key_id` of `agent_id`, regardless of its validity window.
+    fn key(&self, agent_id: &str, key_id: u32) -> Option<&AuthKey>;
+
+    /// Key to sign a frame of `agent_id` timestamped `timestamp_utc_ms`.
+    fn signing_key(&self, agent_id: &str, timestamp_utc_ms: i64) -> Option<&AuthKey>;
+}
+
+/// In-memory key store holding any number of keys per agent.
+///
+/// The signing key is the valid key with the latest `valid_from`, so a new key takes over as
+/// soon as its window opens while the old one keeps verifying until its window closes.
+#[derive(Debug, Clone, Default)]
+pub struct KeyRing {
+    keys: HashMap<String, Vec<AuthKey>>,
+}
+
+impl KeyRing {
+    /// Create an empty key ring.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a key for `agent_id`, replacing any key with the same id.
+    pub fn insert(&mut self, agent_id: impl Into<String>, key: AuthKey) {
+        let keys = self.keys.entry(agent_id.into()).or_default();
+        keys.retain(|k| k.key_id != key.key_id);
+        keys.push(key);
+    }
+
+    /// Remove (revoke) key `key_id` of `agent_id`.
+    pub fn remove(&mut self, agent_id: &str, key_id: u32) -> Option<AuthKey> {
+        let keys = self.keys.get_mut(agent_id)?;
+        let index = keys.iter().position(|k| k.key_id == key_id)?;
+        Some(keys.remove(index))
+    }
+}
+
+impl KeyStore for KeyRing {
+    fn key(&self, agent_id: &str, key_id: u32) -> Option<&AuthKey> {
+        self.keys.get(agent_id)?.iter().find(|k| k.key_id == key_id)
+    }
+
+    fn signing_key(&self, agent_id: &str, timestamp_utc_ms: i64) -> Option<&AuthKey> {
+        self.keys
+            .get(agent_id)?
+            .iter()
+            .filter(|k| k.is_valid_at(timestamp_utc_ms))
+            .max_by_key(|k| k.not_before_ms)
+    }
+}
+
+/// Check the tag of an authenticated frame.
+///
+/// `signed` is the frame body without the tag; `tag` is `None` for a frame without
+/// `FLAG_AUTHENTICATED`.
+pub(super) fn verify(
+    envelope: &EnvelopeRef<'_>,
+    signed: &[u8],
+    tag: Option<&[u8]>,
+    keys: &dyn KeyStore,
+) -> Result<(), ProtocolError> {
+    let failed = ProtocolError::AuthenticationFailed;
+    let tag = tag.ok_or_else(|| failed("frame is not authenticated".to_string()))?;
+    let key_id = envelope
+        .extensions()?
+        .get_u32(tags::KEY_ID)?
+        .ok_or_else(|| failed("authenticated frame has no key id".to_string()))?;
+    let key = keys.key(envelope.agent_id, key_id).ok_or_else(|| {
+        failed(format!(
+            "unknown key {key_id} for agent {}",
+            envelope.agent_id
+        ))
+    })?;
+    if !key.is_valid_at(envelope.timestamp_utc_ms) {
+        return Err(failed(format!(
+            "key {key_id} is not valid at {}",
+            envelope.timestamp_utc_ms
+        )));
+    }
+    key.mac(signed)?
+        .verify_slice(tag)
+        .map_err(|_| failed("tag mismatch".to_string()))
+}
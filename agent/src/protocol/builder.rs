@@ -0,0 +1,267 @@
+//! Message construction with generated ids and timestamps.
+//!
+//! A `MessageBuilder` is bound to one agent identity and fills every envelope field:
+//! - `message_id`: time-ordered unique id in the UUIDv7 layout (RFC 9562): 48-bit Unix
+//!   millisecond timestamp, version 7, a 12-bit counter that keeps ids from the same
+//!   millisecond ordered, the variant bits and 62 random bits;
+//! - `timestamp_utc_ms`: from an injectable [`Clock`];
+//! - `platform`: the build target (`OsType::current()`);
+//! - `message_type`: from the payload;
+//! - `compressed`: from the [`CompressionPolicy`] and the encoded payload size.
+//!
+//! With a fixed seed and a [`FixedClock`] the builder is deterministic, so golden frames can be
+//! regenerated byte for byte.
+
+use super::{
+    write_payload, AgentIdentity, CompressionPolicy, Envelope, Extensions, Message, MessagePayload,
+    OsType, ProtocolVersion,
+};
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Source of envelope timestamps (UTC Unix epoch milliseconds).
+pub trait Clock: fmt::Debug + Send {
+    /// Current time in UTC milliseconds.
+    fn now_utc_ms(&mut self) -> i64;
+}
+
+/// Wall clock.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now_utc_ms(&mut self) -> i64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_millis() as i64)
+    }
+}
+
+/// Clock that starts at a fixed time and advances by a fixed step on every reading.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FixedClock {
+    next_ms: i64,
+    step_ms: i64,
+}
+
+impl FixedClock {
+    /// Clock that always reads `now_ms`.
+    pub fn new(now_ms: i64) -> Self {
+        Self::stepping(now_ms, 0)
+    }
+
+    /// Clock that reads `start_ms`, then `start_ms + step_ms`, and so on.
+    pub fn stepping(start_ms: i64, step_ms: i64) -> Self {
+        Self {
+            next_ms: start_ms,
+            step_ms,
+        }
+    }
+}
+
+impl Clock for FixedClock {
+    fn now_utc_ms(&mut self) -> i64 {
+        let now = self.next_ms;
+        self.next_ms = now.saturating_add(self.step_ms);
+        now
+    }
+}
+
+/// Unix millisecond timestamp of a UUIDv7-style message id, or `None` for other ids.
+pub fn message_id_timestamp_ms(message_id: &[u8; 16]) -> Option<i64> {
+    if message_id[6] >> 4 != 7 || message_id[8] >> 6 != 0b10 {
+        return None;
+    }
+    let mut millis = [0u8; 8];
+    millis[2..].copy_from_slice(&message_id[..6]);
+    Some(i64::from_be_bytes(millis))
+}
+
+/// Builds messages for one agent.
+#[derive(Debug)]
+pub struct MessageBuilder {
+    identity: AgentIdentity,
+    version: ProtocolVersion,
+    platform: OsType,
+    policy: CompressionPolicy,
+    clock: Box<dyn Clock>,
+    ids: IdGenerator,
+    /// Scratch buffer for sizing payloads against the compression policy
+    payload: Vec<u8>,
+}
+
+impl MessageBuilder {
+    /// Builder for `identity` with the system clock, random ids, the current protocol version
+    /// and compression disabled (until the handshake negotiates it).
+    pub fn new(identity: AgentIdentity) -> Self {
+        Self {
+            identity,
+            version: ProtocolVersion::CURRENT,
+            platform: OsType::current(),
+            policy: CompressionPolicy::disabled(),
+            clock: Box::new(SystemClock),
+            ids: IdGenerator::new(os_seed()),
+            payload: Vec::new(),
+        }
+    }
+
+    /// Deterministic builder: ids from `seed`, timestamps from `clock`.
+    pub fn deterministic(identity: AgentIdentity, seed: u64, clock: FixedClock) -> Self {
+        Self::new(identity).with_seed(seed).with_clock(clock)
+    }
+
+    /// Take timestamps from `clock`.
+    pub fn with_clock(self, clock: impl Clock + 'static) -> Self {
+        Self {
+            clock: Box::new(clock),
+            ..self
+        }
+    }
+
+    /// Derive the random part of message ids from `seed` instead of OS randomness.
+    pub fn with_seed(self, seed: u64) -> Self {
+        Self {
+            ids: IdGenerator::new(seed),
+            ..self
+        }
+    }
+
+    /// Stamp envelopes with `version` (the one negotiated in the handshake).
+    pub fn with_version(self, version: ProtocolVersion) -> Self {
+        Self { version, ..self }
+    }
+
+    /// Set `Envelope.compressed` from `policy` (e.g. `CompressionPolicy::negotiated`).
+    pub fn with_policy(self, policy: CompressionPolicy) -> Self {
+        Self { policy, ..self }
+    }
+
+    /// Override the platform, e.g. to produce identical golden frames on every target.
+    pub fn with_platform(self, platform: OsType) -> Self {
+        Self { platform, ..self }
+    }
+
+    /// Identity the builder is bound to.
+    pub fn identity(&self) -> &AgentIdentity {
+        &self.identity
+    }
+
+    /// Generate the next message id.
+    pub fn next_message_id(&mut self) -> [u8; 16] {
+        let now_ms = self.clock.now_utc_ms();
+        self.ids.next(now_ms)
+    }
+
+    /// Build a message carrying `payload`.
+    pub fn build(&mut self, payload: MessagePayload) -> Message {
+        let timestamp_utc_ms = self.clock.now_utc_ms();
+        let message_id = self.ids.next(timestamp_utc_ms);
+        let mut message = Message {
+            envelope: Envelope {
+                version: self.version,
+                message_type: payload.message_type(),
+                message_id,
+                timestamp_utc_ms,
+                agent_id: self.identity.instance_id.clone(),
+                platform: self.platform,
+                compressed: false,
+                extensions: Extensions::new(),
+            },
+            payload,
+            payload_extensions: Extensions::new(),
+        };
+        self.payload.clear();
+        // A built message has no extensions, so writing its payload cannot fail
+        if self.policy.enabled && write_payload(&mut self.payload, &message).is_ok() {
+            message.envelope.compressed = self.policy.should_compress(self.payload.len());
+        }
+        message
+    }
+
+    /// Build the handshake announcing the bound identity.
+    pub fn handshake(&mut self) -> Message {
+        self.build(MessagePayload::Handshake(self.identity.clone()))
+    }
+}
+
+/// Seed for the random part of message ids: OS randomness, or the system time if that is
+/// unavailable (ids must be unique, not secret).
+fn os_seed() -> u64 {
+    let mut seed = [0u8; 8];
+    match getrandom::getrandom(&mut seed) {
+        Ok(()) => u64::from_le_bytes(seed),
+        Err(_) => SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_nanos() as u64),
+    }
+}
+
+/// UUIDv7-style id generator with a per-millisecond counter.
+#[derive(Debug, Clone)]
+struct IdGenerator {
+    /// Source of the random bits
+    rng: SplitMix64,
+    /// Millisecond of the last id; never decreases
+    last_ms: i64,
+    /// Counter of the last id within `last_ms`
+    counter: u16,
+}
+
+/// Largest value of the 12-bit counter.
+const COUNTER_MAX: u16 = 0x0FFF;
+
+impl IdGenerator {
+    fn new(seed: u64) -> Self {
+        Self {
+            rng: SplitMix64::new(seed),
+            last_ms: i64::MIN,
+            counter: 0,
+        }
+    }
+
+    fn next(&mut self, now_ms: i64) -> [u8; 16] {
+        let now_ms = now_ms.clamp(0, (1 << 48) - 1);
+        if now_ms > self.last_ms {
+            // Start each millisecond in the lower half so the counter rarely overflows
+            self.last_ms = now_ms;
+            self.counter = (self.rng.next_u64() & 0x07FF) as u16;
+        } else if self.counter < COUNTER_MAX {
+            // Same millisecond, or the clock went back: stay ordered after the last id
+            self.counter += 1;
+        } else {
+            self.last_ms += 1;
+            self.counter = 0;
+        }
+
+        let mut id = [0u8; 16];
+        id[..6].copy_from_slice(&self.last_ms.to_be_bytes()[2..]);
+        id[6..8].copy_from_slice(&(0x7000 | self.counter).to_be_bytes());
+        id[8..].copy_from_slice(&self.rng.next_u64().to_be_bytes());
+        id[8] = 0x80 | (id[8] & 0x3F);
+        id
+    }
+}
+
+/// SplitMix64: small, seedable and stable across platforms and releases.
+#[derive(Debug, Clone)]
+pub(crate) struct SplitMix64(u64);
+
+impl SplitMix64 {
+    pub(crate) fn new(seed: u64) -> Self {
+        Self(seed)
+    }
+
+    pub(crate) fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Next value in `0..bound`.
+    #[cfg(feature = "json")]
+    pub(crate) fn below(&mut self, bound: u64) -> u64 {
+        self.next_u64() % bound
+    }
+}
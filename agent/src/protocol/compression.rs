@@ -0,0 +1,67 @@
+//! Per-frame compression decisions.
+//!
+//! Compressing a small payload (a heartbeat, an ack) costs CPU and usually makes the frame
+//! larger. A `CompressionPolicy` on the `FrameEncoder` decides per frame whether to compress,
+//! sets the envelope's compressed flag accordingly and picks the zstd level. The policy for a
+//! session follows from the handshake: nothing is compressed unless `CAP_COMPRESSION` was
+//! negotiated, and the level is the lower of both sides' preferred levels.
+
+use super::{HandshakeAckPayload, ZSTD_LEVEL};
+
+/// When and how the encoder compresses payloads.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompressionPolicy {
+    /// Compress at all (false once the handshake did not negotiate compression)
+    pub enabled: bool,
+    /// Smallest uncompressed payload, in bytes, worth compressing
+    pub min_payload_size: usize,
+    /// zstd compression level
+    pub level: i32,
+    /// Send the payload uncompressed if compressing did not make it smaller
+    pub only_if_smaller: bool,
+}
+
+impl CompressionPolicy {
+    /// Default minimum payload size (512 bytes).
+    pub const DEFAULT_MIN_PAYLOAD_SIZE: usize = 512;
+    /// Default zstd level.
+    pub const DEFAULT_LEVEL: i32 = ZSTD_LEVEL;
+
+    /// Policy that never compresses.
+    pub fn disabled() -> Self {
+        Self {
+            enabled: false,
+            ..Self::default()
+        }
+    }
+
+    /// Policy for a session: the default policy at the negotiated level, or `disabled()` if
+    /// compression was not negotiated.
+    pub fn negotiated(ack: &HandshakeAckPayload) -> Self {
+        if !ack.compression_enabled() {
+            return Self::disabled();
+        }
+        Self {
+            level: ack
+                .compression_level()
+                .map_or(Self::DEFAULT_LEVEL, i32::from),
+            ..Self::default()
+        }
+    }
+
+    /// True if a payload of `payload_len` bytes should be compressed.
+    pub fn should_compress(&self, payload_len: usize) -> bool {
+        self.enabled && payload_len >= self.min_payload_size
+    }
+}
+
+impl Default for CompressionPolicy {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            min_payload_size: Self::DEFAULT_MIN_PAYLOAD_SIZE,
+            level: Self::DEFAULT_LEVEL,
+            only_if_smaller: true,
+        }
+    }
+}
@@ -0,0 +1,208 @@
+//! Push-based incremental frame decoding.
+//!
+//! `FrameCodec::decode` pulls bytes from a blocking `Read`. Event-loop based ingestion
+//! (non-blocking sockets, partial reads) instead receives arbitrary chunks of bytes that may
+//! contain several frames, a fraction of a frame, or both. `FrameDecoder` accepts those chunks,
+//! keeps any partial frame between calls, and yields every complete message.
+
+use super::{
+    decode_body, CompressionDictionary, DecodeLimits, DecodeMode, DecodeOptions, FrameLayout,
+    KeyStore, Message, PayloadCipher, ProtocolError,
+};
+use std::sync::Arc;
+
+/// Result of feeding a chunk into a [`FrameDecoder`].
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct DecodedFrames {
+    /// Complete messages decoded from the chunk (in stream order)
+    pub messages: Vec<Message>,
+    /// Number of bytes taken from the chunk (complete frames plus any buffered partial frame)
+    pub consumed: usize,
+}
+
+/// Stateful, push-based frame decoder.
+///
+/// Bytes are supplied with [`FrameDecoder::decode`]. The decoder buffers at most one partial
+/// frame (bounded by `MAX_FRAME_SIZE` plus framing overhead) between calls.
+///
+/// Error handling: if a frame fails validation after other messages were already decoded from
+/// the same chunk, those messages are returned and `consumed` stops at the start of the failing
+/// frame. Feeding the unconsumed remainder again reports the error. Once an error is returned the
+/// buffered bytes are discarded; the stream should be treated as desynchronized.
+/// [`FrameDecoder::error_consumed`] tells how much of the chunk the failing call took.
+///
+/// With a cipher, a frame only counts as opened once its payload decodes, so a frame that
+/// decrypts but is rejected afterwards does not advance the replay counter.
+#[derive(Debug, Default)]
+pub struct FrameDecoder {
+    buffer: Vec<u8>,
+    limits: DecodeLimits,
+    mode: DecodeMode,
+    dictionary: Option<CompressionDictionary>,
+    keys: Option<Arc<dyn KeyStore>>,
+    cipher: Option<PayloadCipher>,
+    layout: FrameLayout,
+    /// Bytes of the chunk taken by the last `decode` call that returned an error
+    error_consumed: usize,
+}
+
+impl FrameDecoder {
+    /// Create an empty decoder.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create an empty decoder that enforces `limits` on every frame.
+    pub fn with_limits(limits: DecodeLimits) -> Self {
+        Self::with_options(limits, DecodeMode::Lenient)
+    }
+
+    /// Create an empty decoder that enforces `limits` and the rules of `mode` on every frame.
+    pub fn with_options(limits: DecodeLimits, mode: DecodeMode) -> Self {
+        Self {
+            buffer: Vec::new(),
+            limits,
+            mode,
+            dictionary: None,
+            keys: None,
+            cipher: None,
+            layout: FrameLayout::Legacy,
+            error_consumed: 0,
+        }
+    }
+
+    /// Decompress payloads that reference `dictionary` (the one negotiated for the session).
+    pub fn with_dictionary(self, dictionary: CompressionDictionary) -> Self {
+        Self {
+            dictionary: Some(dictionary),
+            ..self
+        }
+    }
+
+    /// Require every frame to be authenticated with a key from `keys`.
+    ///
+    /// Frames that fail verification are reported as `ProtocolError::AuthenticationFailed`.
+    pub fn with_key_store(self, keys: Arc<dyn KeyStore>) -> Self {
+        Self {
+            keys: Some(keys),
+            ..self
+        }
+    }
+
+    /// Decrypt encrypted payloads with `cipher` (the session opener for this direction).
+    ///
+    /// Every frame must then be encrypted; plaintext frames fail with
+    /// `ProtocolError::UnencryptedFrame`.
+    pub fn with_cipher(self, cipher: PayloadCipher) -> Self {
+        Self {
+            cipher: Some(cipher),
+            ..self
+        }
+    }
+
+    /// Read frames in `layout` (configured for the session, or `HandshakeAckPayload::frame_layout`).
+    ///
+    /// Switch layouts only at a frame boundary.
+    pub fn with_layout(self, layout: FrameLayout) -> Self {
+        Self { layout, ..self }
+    }
+
+    /// Number of bytes currently buffered for an incomplete frame.
+    pub fn buffered_len(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// True if no partial frame is buffered (i.e. the stream is at a frame boundary).
+    pub fn is_at_frame_boundary(&self) -> bool {
+        self.buffer.is_empty()
+    }
+
+    /// Number of bytes the last `decode` call that returned an error took from its chunk.
+    ///
+    /// Covers the frames before the failing one and the failing frame up to the point its
+    /// error was detected (its whole length once the length prefix was read and accepted).
+    pub fn error_consumed(&self) -> usize {
+        self.error_consumed
+    }
+
+    /// Discard any buffered partial frame.
+    pub fn reset(&mut self) {
+        self.buffer.clear();
+    }
+
+    /// Feed a chunk of bytes and decode every frame it completes.
+    ///
+    /// Returns zero or more messages along with the number of bytes consumed from `chunk`.
+    pub fn decode(&mut self, chunk: &[u8]) -> Result<DecodedFrames, ProtocolError> {
+        let mut output = DecodedFrames::default();
+
+        loop {
+            let frame_start = output.consumed;
+            let buffered_at_start = self.buffer.len();
+
+            match self.next_frame(chunk, &mut output.consumed) {
+                Ok(Some(message)) => output.messages.push(message),
+                Ok(None) => return Ok(output),
+                Err(_) if !output.messages.is_empty() => {
+                    // Report the good messages first; leave the failing frame unconsumed.
+                    self.buffer.truncate(buffered_at_start);
+                    output.consumed = frame_start;
+                    return Ok(output);
+                }
+                Err(err) => {
+                    self.buffer.clear();
+                    self.error_consumed = output.consumed;
+                    return Err(err);
+                }
+            }
+        }
+    }
+
+    /// Pull bytes from `chunk[*consumed..]` until a frame is complete.
+    ///
+    /// Returns `Ok(None)` when the chunk is exhausted before the frame is complete.
+    fn next_frame(
+        &mut self,
+        chunk: &[u8],
+        consumed: &mut usize,
+    ) -> Result<Option<Message>, ProtocolError> {
+        let header_len = self.layout.header_len();
+        if !self.fill_to(header_len, chunk, consumed) {
+            return Ok(None);
+        }
+
+        let body_len = self.layout.body_len(&self.buffer[..header_len])?;
+        let frame_len = self.layout.frame_len(body_len);
+        if !self.fill_to(frame_len, chunk, consumed) {
+            return Ok(None);
+        }
+
+        let body = self.layout.verified_body(&self.buffer, body_len)?;
+        let mut options = DecodeOptions {
+            limits: self.limits,
+            mode: self.mode,
+            layout: self.layout,
+            dictionary: self.dictionary.as_ref(),
+            keys: self.keys.as_deref(),
+            cipher: self.cipher.as_mut(),
+        };
+        let message = decode_body(body, &mut options)?;
+
+        self.buffer.clear();
+        Ok(Some(message))
+    }
+
+    /// Move bytes from the chunk into the buffer until it holds `target` bytes.
+    ///
+    /// Returns true once the buffer holds at least `target` bytes.
+    fn fill_to(&mut self, target: usize, chunk: &[u8], consumed: &mut usize) -> bool {
+        if self.buffer.len() < target {
+            let available = chunk.len() - *consumed;
+            let take = (target - self.buffer.len()).min(available);
+            self.buffer
+                .extend_from_slice(&chunk[*consumed..*consumed + take]);
+            *consumed += take;
+        }
+        self.buffer.len() >= target
+    }
+}
@@ -0,0 +1,302 @@
+//! Delta-encoded snapshots (`SnapshotDelta`).
+//!
+//! Consecutive snapshots mostly report the same processes with slightly different numbers.
+//! Once the server has acknowledged a snapshot, the agent may send the next one as the changes
+//! against it, leaving out every unchanged name and command line. Both sides keep the snapshot
+//! a delta refers to:
+//! - the agent keeps a [`SnapshotDeltaEncoder`], which picks a delta or a full snapshot and
+//!   tracks which snapshot the server acknowledged;
+//! - the server keeps a [`SnapshotBaseStore`] per session, which rebuilds full snapshots.
+//!
+//! A delta whose base the server does not have fails with `ProtocolError::UnknownDeltaBase`.
+//! The server answers it with a negative `Ack`, and the agent falls back to a full snapshot.
+//! Deltas are only sent when `AgentIdentity::CAP_SNAPSHOT_DELTA` was negotiated.
+//!
+//! [`sort_processes`] order is canonical: the encoder sends every snapshot in it and the store
+//! rebuilds snapshots in it, so both sides hold the same process list.
+
+use super::{
+    sort_processes, Message, MessageAck, MessagePayload, ProcessChange, ProcessSample,
+    ProtocolError, SnapshotDeltaPayload, SnapshotPayload,
+};
+use std::collections::{HashMap, HashSet, VecDeque};
+
+/// Number of sent but unacknowledged snapshots an encoder remembers.
+const MAX_PENDING_SNAPSHOTS: usize = 8;
+
+/// Default number of base snapshots a [`SnapshotBaseStore`] keeps.
+pub const DEFAULT_DELTA_BASES: usize = 8;
+
+impl SnapshotDeltaPayload {
+    /// Compute the changes that turn `base` into `target`.
+    ///
+    /// `apply(diff(base, target))` equals `target` when `target`'s processes are in
+    /// [`sort_processes`] order and its pids are unique.
+    pub fn diff(
+        base_message_id: [u8; 16],
+        base: &SnapshotPayload,
+        target: &SnapshotPayload,
+    ) -> Self {
+        let base_by_pid: HashMap<u32, &ProcessSample> =
+            base.processes.iter().map(|p| (p.pid, p)).collect();
+        let mut added = Vec::new();
+        let mut changed = Vec::new();
+        for process in &target.processes {
+            match base_by_pid.get(&process.pid) {
+                Some(old) if old.name == process.name && old.cmdline == process.cmdline => {
+                    if !same_measurements(old, process) {
+                        changed.push(ProcessChange {
+                            pid: process.pid,
+                            cpu_percent: process.cpu_percent,
+                            memory_percent: process.memory_percent,
+                            memory_bytes: process.memory_bytes,
+                        });
+                    }
+                }
+                _ => added.push(process.clone()),
+            }
+        }
+        let target_pids: HashSet<u32> = target.processes.iter().map(|p| p.pid).collect();
+        let removed = base
+            .processes
+            .iter()
+            .map(|p| p.pid)
+            .filter(|pid| !target_pids.contains(pid))
+            .collect();
+
+        Self {
+            base_message_id,
+            window_start_secs: target.window_start_secs,
+            window_end_secs: target.window_end_secs,
+            total_cpu_percent: target.total_cpu_percent,
+            memory_used_bytes: target.memory_used_bytes,
+            memory_total_bytes: target.memory_total_bytes,
+            added,
+            changed,
+            removed,
+            truncated: target.truncated,
+        }
+    }
+
+    /// Rebuild the full snapshot from its base.
+    ///
+    /// Processes come back in [`sort_processes`] order. Fails with
+    /// `ProtocolError::InvalidSnapshotDelta` if a removed or changed pid is not in `base`.
+    pub fn apply(&self, base: &SnapshotPayload) -> Result<SnapshotPayload, ProtocolError> {
+        let mut processes: HashMap<u32, ProcessSample> =
+            base.processes.iter().map(|p| (p.pid, p.clone())).collect();
+        for pid in &self.removed {
+            if processes.remove(pid).is_none() {
+                return Err(ProtocolError::InvalidSnapshotDelta(format!(
+                    "removed pid {pid} is not in the base snapshot"
+                )));
+            }
+        }
+        for change in &self.changed {
+            let process = processes.get_mut(&change.pid).ok_or_else(|| {
+                ProtocolError::InvalidSnapshotDelta(format!(
+                    "changed pid {} is not in the base snapshot",
+                    change.pid
+                ))
+            })?;
+            process.cpu_percent = change.cpu_percent;
+            process.memory_percent = change.memory_percent;
+            process.memory_bytes = change.memory_bytes;
+        }
+        for process in &self.added {
+            processes.insert(process.pid, process.clone());
+        }
+
+        let mut processes: Vec<ProcessSample> = processes.into_values().collect();
+        sort_processes(&mut processes);
+        Ok(SnapshotPayload {
+            window_start_secs: self.window_start_secs,
+            window_end_secs: self.window_end_secs,
+            total_cpu_percent: self.total_cpu_percent,
+            memory_used_bytes: self.memory_used_bytes,
+            memory_total_bytes: self.memory_total_bytes,
+            processes,
+            truncated: self.truncated,
+        })
+    }
+}
+
+/// Agent-side choice between delta and full snapshots.
+///
+/// Call [`SnapshotDeltaEncoder::payload`] for every snapshot sent and
+/// [`SnapshotDeltaEncoder::handle_ack`] for every `Ack` received. Until the server acknowledges
+/// a snapshot, and after it rejects one, full snapshots are sent.
+#[derive(Debug, Default)]
+pub struct SnapshotDeltaEncoder {
+    /// Last snapshot the server acknowledged
+    base: Option<([u8; 16], SnapshotPayload)>,
+    /// Sent but unacknowledged snapshots, oldest first
+    pending: VecDeque<([u8; 16], SnapshotPayload)>,
+}
+
+impl SnapshotDeltaEncoder {
+    /// Create an encoder without a base; the first snapshot is sent in full.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Payload to send `snapshot` in the message with id `message_id`.
+    ///
+    /// Returns a `SnapshotDelta` against the acknowledged base if that is smaller than the full
+    /// snapshot, and a `Snapshot` otherwise. Processes are put in [`sort_processes`] order
+    /// first, the order `apply` rebuilds them in.
+    pub fn payload(
+        &mut self,
+        message_id: [u8; 16],
+        mut snapshot: SnapshotPayload,
+    ) -> MessagePayload {
+        sort_processes(&mut snapshot.processes);
+        let payload = match &self.base {
+            Some((base_id, base)) => {
+                let delta = SnapshotDeltaPayload::diff(*base_id, base, &snapshot);
+                if delta_len(&delta) < snapshot_len(&snapshot) {
+                    MessagePayload::SnapshotDelta(Box::new(delta))
+                } else {
+                    MessagePayload::Snapshot(snapshot.clone())
+                }
+            }
+            None => MessagePayload::Snapshot(snapshot.clone()),
+        };
+
+        if self.pending.len() == MAX_PENDING_SNAPSHOTS {
+            self.pending.pop_front();
+        }
+        self.pending.push_back((message_id, snapshot));
+        payload
+    }
+
+    /// Record the server's answer to a snapshot message.
+    ///
+    /// A positive ack makes that snapshot the new base. A negative ack (e.g. for a delta whose
+    /// base the server lost) drops the base, so the next snapshot is sent in full.
+    pub fn handle_ack(&mut self, ack: &MessageAck) {
+        let Some(index) = self
+            .pending
+            .iter()
+            .position(|(id, _)| *id == ack.message_id)
+        else {
+            return;
+        };
+        if let Some((message_id, snapshot)) = self.pending.remove(index) {
+            if ack.success {
+                // Older snapshots can no longer become the base.
+                self.pending.drain(..index);
+                self.base = Some((message_id, snapshot));
+            } else {
+                self.base = None;
+            }
+        }
+    }
+
+    /// Forget the base and all pending snapshots (e.g. after reconnecting).
+    pub fn reset(&mut self) {
+        self.base = None;
+        self.pending.clear();
+    }
+
+    /// Message id of the current base snapshot.
+    pub fn base_message_id(&self) -> Option<&[u8; 16]> {
+        self.base.as_ref().map(|(id, _)| id)
+    }
+}
+
+/// Server-side store of recent snapshots that deltas may refer to.
+///
+/// Keep one store per session; message ids are only unique per agent.
+#[derive(Debug)]
+pub struct SnapshotBaseStore {
+    capacity: usize,
+    /// Most recent snapshots, oldest first
+    snapshots: VecDeque<([u8; 16], SnapshotPayload)>,
+}
+
+impl Default for SnapshotBaseStore {
+    fn default() -> Self {
+        Self::with_capacity(DEFAULT_DELTA_BASES)
+    }
+}
+
+impl SnapshotBaseStore {
+    /// Create a store keeping [`DEFAULT_DELTA_BASES`] snapshots.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a store keeping the `capacity` most recent snapshots (at least one).
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            snapshots: VecDeque::new(),
+        }
+    }
+
+    /// Full snapshot carried by a `Snapshot` or `SnapshotDelta` message.
+    ///
+    /// The snapshot is remembered under the message id as a base for later deltas. Returns
+    /// `None` for other message types and `ProtocolError::UnknownDeltaBase` for a delta whose
+    /// base is not in the store.
+    pub fn resolve(&mut self, message: &Message) -> Result<Option<SnapshotPayload>, ProtocolError> {
+        let snapshot = match &message.payload {
+            MessagePayload::Snapshot(snapshot) => snapshot.clone(),
+            MessagePayload::SnapshotDelta(delta) => {
+                let base = self
+                    .get(&delta.base_message_id)
+                    .ok_or(ProtocolError::UnknownDeltaBase(delta.base_message_id))?;
+                delta.apply(base)?
+            }
+            _ => return Ok(None),
+        };
+
+        if self.snapshots.len() == self.capacity {
+            self.snapshots.pop_front();
+        }
+        self.snapshots
+            .push_back((message.envelope.message_id, snapshot.clone()));
+        Ok(Some(snapshot))
+    }
+
+    /// Stored snapshot with the given message id.
+    pub fn get(&self, message_id: &[u8; 16]) -> Option<&SnapshotPayload> {
+        self.snapshots
+            .iter()
+            .rev()
+            .find(|(id, _)| id == message_id)
+            .map(|(_, snapshot)| snapshot)
+    }
+}
+
+fn same_measurements(a: &ProcessSample, b: &ProcessSample) -> bool {
+    a.cpu_percent.to_bits() == b.cpu_percent.to_bits()
+        && a.memory_percent.to_bits() == b.memory_percent.to_bits()
+        && a.memory_bytes == b.memory_bytes
+}
+
+/// Encoded size of a process sample.
+fn process_len(process: &ProcessSample) -> usize {
+    let cmdline_len = process.cmdline.as_ref().map_or(0, |c| 8 + c.len());
+    4 + 8 + process.name.len() + 4 + 4 + 8 + 1 + cmdline_len
+}
+
+/// Encoded size of a snapshot payload (without payload extensions).
+fn snapshot_len(snapshot: &SnapshotPayload) -> usize {
+    8 + 8 + 4 + 8 + 8 + 8 + snapshot.processes.iter().map(process_len).sum::<usize>() + 1
+}
+
+/// Encoded size of a snapshot delta payload (without payload extensions).
+fn delta_len(delta: &SnapshotDeltaPayload) -> usize {
+    let added: usize = delta.added.iter().map(process_len).sum();
+    16 + 8
+        + 8
+        + 4
+        + 8
+        + 8
+        + (8 + added)
+        + (8 + 20 * delta.changed.len())
+        + (8 + 4 * delta.removed.len())
+        + 1
+}
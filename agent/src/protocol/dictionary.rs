@@ -0,0 +1,144 @@
+//! Trained zstd dictionaries for payload compression.
+//!
+//! Payloads are a few KB of repetitive structure, too little for zstd to learn much from within
+//! a single frame. A dictionary trained on earlier frames primes the compressor with that
+//! structure. Both peers must hold the same dictionary:
+//! - the agent advertises the dictionary id in `AgentIdentity.capabilities`
+//!   (`CAP_COMPRESSION_DICTIONARY`, id in bits 16..=31), and the server acknowledges it in
+//!   `HandshakeAckPayload.negotiated_capabilities` only if it holds the same dictionary;
+//! - a compressed payload references its dictionary through the dictionary id zstd writes into
+//!   the frame header, so the envelope layout is unchanged.
+//!
+//! Decoding a payload compressed with a dictionary the decoder does not hold fails with
+//! `ProtocolError::UnknownDictionary`.
+
+use super::{MessageRef, ProtocolError};
+use std::fmt;
+use std::sync::Arc;
+use zstd::dict::DecoderDictionary;
+use zstd::zstd_safe;
+
+/// Default maximum size of a trained dictionary (16 KiB).
+pub const DEFAULT_DICTIONARY_SIZE: usize = 16 * 1024;
+
+/// Magic number at the start of a zstd dictionary (RFC 8878, section 5).
+const DICTIONARY_MAGIC: [u8; 4] = 0xEC30A437u32.to_le_bytes();
+
+/// A zstd dictionary shared by the encoders and decoders of a session.
+///
+/// Cloning is cheap; the dictionary is prepared for decompression once.
+#[derive(Clone)]
+pub struct CompressionDictionary {
+    inner: Arc<Prepared>,
+}
+
+struct Prepared {
+    id: u16,
+    bytes: Vec<u8>,
+    decoder: DecoderDictionary<'static>,
+}
+
+impl fmt::Debug for CompressionDictionary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CompressionDictionary")
+            .field("id", &self.inner.id)
+            .field("len", &self.inner.bytes.len())
+            .finish()
+    }
+}
+
+impl CompressionDictionary {
+    /// Load a dictionary written by [`CompressionDictionary::as_bytes`].
+    ///
+    /// The id is read from the dictionary header and must be in `1..=65535`.
+    pub fn from_bytes(bytes: Vec<u8>) -> Result<Self, ProtocolError> {
+        if bytes.len() < 8 || bytes[..4] != DICTIONARY_MAGIC {
+            return Err(ProtocolError::InvalidDictionary(
+                "missing zstd dictionary header".to_string(),
+            ));
+        }
+        let id = zstd_safe::get_dict_id_from_dict(&bytes)
+            .and_then(|id| u16::try_from(id.get()).ok())
+            .ok_or_else(|| {
+                ProtocolError::InvalidDictionary("dictionary id must be in 1..=65535".to_string())
+            })?;
+
+        Ok(Self {
+            inner: Arc::new(Prepared {
+                id,
+                decoder: DecoderDictionary::copy(&bytes),
+                bytes,
+            }),
+        })
+    }
+
+    /// Train a dictionary with id `id` (nonzero) from uncompressed payload samples.
+    pub fn train<S: AsRef<[u8]>>(
+        id: u16,
+        samples: &[S],
+        max_size: usize,
+    ) -> Result<Self, ProtocolError> {
+        if id == 0 {
+            return Err(ProtocolError::InvalidDictionary(
+                "dictionary id 0 is reserved".to_string(),
+            ));
+        }
+        let mut bytes = zstd::dict::from_samples(samples, max_size)
+            .map_err(|e| ProtocolError::InvalidDictionary(e.to_string()))?;
+        // Header: [magic: u32 LE][dictionary id: u32 LE]. zstd derives the id from the content;
+        // replace it with ours so frame headers carry the negotiated id.
+        bytes[4..8].copy_from_slice(&u32::from(id).to_le_bytes());
+        Self::from_bytes(bytes)
+    }
+
+    /// Train a dictionary from captured frame streams (files of concatenated frames).
+    ///
+    /// Every frame's payload, decompressed if necessary, is one training sample.
+    pub fn train_from_frames<F: AsRef<[u8]>>(
+        id: u16,
+        frame_files: &[F],
+        max_size: usize,
+    ) -> Result<Self, ProtocolError> {
+        let mut samples = Vec::new();
+        for file in frame_files {
+            let mut data = file.as_ref();
+            while !data.is_empty() {
+                let (view, len) = MessageRef::from_frame(data)?;
+                samples.push(view.payload_bytes().to_vec());
+                data = &data[len..];
+            }
+        }
+        Self::train(id, &samples, max_size)
+    }
+
+    /// Dictionary id, as advertised in the handshake.
+    pub fn id(&self) -> u16 {
+        self.inner.id
+    }
+
+    /// Raw dictionary, e.g. to store it next to the agent configuration.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.inner.bytes
+    }
+
+    pub(super) fn decoder(&self) -> &DecoderDictionary<'static> {
+        &self.inner.decoder
+    }
+}
+
+/// Dictionary needed to decompress `compressed`, if any.
+///
+/// Fails with `ProtocolError::UnknownDictionary` if the zstd frame header names a dictionary
+/// other than `dictionary`.
+pub(super) fn dictionary_for<'d>(
+    compressed: &[u8],
+    dictionary: Option<&'d CompressionDictionary>,
+) -> Result<Option<&'d CompressionDictionary>, ProtocolError> {
+    match zstd_safe::get_dict_id_from_frame(compressed) {
+        None => Ok(None),
+        Some(id) => match dictionary {
+            Some(dictionary) if u32::from(dictionary.id()) == id.get() => Ok(Some(dictionary)),
+            _ => Err(ProtocolError::UnknownDictionary(id.get())),
+        },
+    }
+}
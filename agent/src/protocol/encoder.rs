@@ -0,0 +1,285 @@
+//! Allocation-reusing frame encoder.
+//!
+//! `FrameCodec::encode` allocates fresh body, payload and frame buffers on every call, and
+//! `zstd::encode_all` creates (and frees) a compression context each time. `FrameEncoder`
+//! keeps a payload scratch buffer and one zstd compression context for its whole lifetime and
+//! writes frames straight into a caller-provided buffer. Its output is byte-identical to
+//! `FrameCodec::encode`.
+//!
+//! With a [`CompressionPolicy`] the encoder decides per frame whether to compress, instead of
+//! following `Envelope.compressed`. With a [`PayloadCipher`] it encrypts every payload after
+//! compression, and with a [`KeyStore`] it authenticates every frame. Frames use the legacy
+//! layout unless another [`FrameLayout`] is set.
+
+use super::{
+    check_message, write_envelope, write_payload, CompressionDictionary, CompressionPolicy,
+    FrameLayout, KeyStore, Message, PayloadCipher, ProtocolError, CRC_SIZE, FLAG_COMPRESSED,
+    FLAG_ENCRYPTED, LENGTH_PREFIX_SIZE, ZSTD_LEVEL,
+};
+use std::fmt;
+use std::io::Write;
+use std::sync::Arc;
+use zstd::zstd_safe::{self, CCtx, CParameter, InBuffer, OutBuffer, ResetDirective};
+
+/// Reusable frame encoder with persistent scratch buffers and zstd context.
+///
+/// The zstd context is created on the first compressed message. A `FrameEncoder` is `Send`;
+/// use one per writer thread.
+#[derive(Default)]
+pub struct FrameEncoder {
+    /// Uncompressed payload of the message being encoded
+    payload: Vec<u8>,
+    /// Frame scratch buffer for [`FrameEncoder::write_to`]
+    frame: Vec<u8>,
+    /// Compression context, kept across messages
+    compressor: Option<CCtx<'static>>,
+    /// Dictionary loaded into `compressor`
+    dictionary: Option<CompressionDictionary>,
+    /// Per-frame compression decision; `None` follows `Envelope.compressed` at level 3
+    policy: Option<CompressionPolicy>,
+    /// Signing keys; `None` sends unauthenticated frames
+    keys: Option<Arc<dyn KeyStore>>,
+    /// Payload cipher; `None` sends plaintext payloads
+    cipher: Option<PayloadCipher>,
+    /// Arrangement of length prefix and CRC32 around each body
+    layout: FrameLayout,
+}
+
+impl fmt::Debug for FrameEncoder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FrameEncoder")
+            .field("payload_capacity", &self.payload.capacity())
+            .field("frame_capacity", &self.frame.capacity())
+            .field("has_compressor", &self.compressor.is_some())
+            .field("dictionary", &self.dictionary)
+            .field("policy", &self.policy)
+            .field("keys", &self.keys)
+            .field("cipher", &self.cipher)
+            .field("layout", &self.layout)
+            .finish()
+    }
+}
+
+impl FrameEncoder {
+    /// Create an encoder; buffers grow to the largest message encoded.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Compress payloads with `dictionary` (the one the peer acknowledged in the handshake).
+    pub fn with_dictionary(self, dictionary: CompressionDictionary) -> Self {
+        Self {
+            compressor: None,
+            dictionary: Some(dictionary),
+            ..self
+        }
+    }
+
+    /// Decide compression per frame with `policy`, ignoring `Envelope.compressed`.
+    ///
+    /// The compressed flag of each frame reflects whether its payload was actually compressed.
+    pub fn with_policy(self, policy: CompressionPolicy) -> Self {
+        Self {
+            compressor: None,
+            policy: Some(policy),
+            ..self
+        }
+    }
+
+    /// Authenticate every frame with the signing key `keys` holds for its agent and timestamp.
+    ///
+    /// Encoding fails with `ProtocolError::AuthenticationFailed` if there is no such key.
+    pub fn with_key_store(self, keys: Arc<dyn KeyStore>) -> Self {
+        Self {
+            keys: Some(keys),
+            ..self
+        }
+    }
+
+    /// Encrypt every payload with `cipher` (the session sealer for this direction).
+    pub fn with_cipher(self, cipher: PayloadCipher) -> Self {
+        Self {
+            cipher: Some(cipher),
+            ..self
+        }
+    }
+
+    /// Write frames in `layout` (configured for the session, or `HandshakeAckPayload::frame_layout`).
+    pub fn with_layout(self, layout: FrameLayout) -> Self {
+        Self { layout, ..self }
+    }
+
+    /// Append one framed message to `out`.
+    ///
+    /// On error `out` is left as it was before the call.
+    pub fn encode_into(
+        &mut self,
+        message: &Message,
+        out: &mut Vec<u8>,
+    ) -> Result<(), ProtocolError> {
+        let start = out.len();
+        let result = self.encode_frame(message, out, start);
+        if result.is_err() {
+            out.truncate(start);
+        }
+        result
+    }
+
+    /// Encode one message and write the frame to `writer`, then flush.
+    ///
+    /// Equivalent to `FrameCodec::write`, reusing the encoder's frame buffer.
+    pub fn write_to<W: Write>(
+        &mut self,
+        writer: &mut W,
+        message: &Message,
+    ) -> Result<(), ProtocolError> {
+        let mut frame = std::mem::take(&mut self.frame);
+        frame.clear();
+        let result = self.encode_into(message, &mut frame).and_then(|()| {
+            writer.write_all(&frame)?;
+            writer.flush()?;
+            Ok(())
+        });
+        self.frame = frame;
+        result
+    }
+
+    /// Length of the frame `encode_into` would write for `message`.
+    ///
+    /// Frames over `MAX_FRAME_SIZE` report their length instead of failing. The payload is not
+    /// sealed, so sizing does not spend a frame counter; encryption adds a fixed overhead.
+    pub(super) fn frame_len(&mut self, message: &Message) -> Result<usize, ProtocolError> {
+        let cipher = self.cipher.take();
+        let mut frame = std::mem::take(&mut self.frame);
+        frame.clear();
+        let result = self.encode_into(message, &mut frame);
+        let len = frame.len();
+        self.frame = frame;
+        let overhead = if cipher.is_some() {
+            PayloadCipher::OVERHEAD
+        } else {
+            0
+        };
+        self.cipher = cipher;
+        match result {
+            Ok(()) => Ok(len + overhead),
+            Err(ProtocolError::FrameTooLarge(body_len, _)) => {
+                Ok(LENGTH_PREFIX_SIZE + body_len + CRC_SIZE + overhead)
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    fn encode_frame(
+        &mut self,
+        message: &Message,
+        out: &mut Vec<u8>,
+        start: usize,
+    ) -> Result<(), ProtocolError> {
+        check_message(message)?;
+        let keys = self.keys.clone();
+        let signing_key = match &keys {
+            Some(keys) => Some(
+                keys.signing_key(
+                    &message.envelope.agent_id,
+                    message.envelope.timestamp_utc_ms,
+                )
+                .ok_or_else(|| {
+                    ProtocolError::AuthenticationFailed(format!(
+                        "no signing key for agent {} at {}",
+                        message.envelope.agent_id, message.envelope.timestamp_utc_ms
+                    ))
+                })?,
+            ),
+            None => None,
+        };
+        self.payload.clear();
+        write_payload(&mut self.payload, message)?;
+        let compress = match &self.policy {
+            Some(policy) => policy.should_compress(self.payload.len()),
+            None => message.envelope.compressed,
+        };
+
+        // Framing placeholder, patched once the body is complete
+        let body_start = self.layout.begin_frame(out);
+        let flags_position = write_envelope(
+            out,
+            &message.envelope,
+            compress,
+            signing_key.map(|key| key.key_id()),
+        )?;
+        let payload_start = out.len();
+        if compress {
+            self.compress_payload(out)?;
+            let only_if_smaller = self.policy.is_some_and(|policy| policy.only_if_smaller);
+            if only_if_smaller && out.len() - payload_start >= self.payload.len() {
+                out.truncate(payload_start);
+                out.extend_from_slice(&self.payload);
+                out[flags_position] &= !FLAG_COMPRESSED;
+            }
+        } else {
+            out.extend_from_slice(&self.payload);
+        }
+
+        if let Some(cipher) = &mut self.cipher {
+            out[flags_position] |= FLAG_ENCRYPTED;
+            cipher.seal(out, body_start, payload_start)?;
+        }
+        if let Some(key) = signing_key {
+            let tag = key.tag(&out[body_start..])?;
+            out.extend_from_slice(&tag);
+        }
+        self.layout.finish_frame(out, start)
+    }
+
+    /// Compress the payload scratch buffer onto the end of `out`.
+    fn compress_payload(&mut self, out: &mut Vec<u8>) -> Result<(), ProtocolError> {
+        let cctx = match &mut self.compressor {
+            Some(cctx) => cctx,
+            None => {
+                let level = self.policy.map_or(ZSTD_LEVEL, |policy| policy.level);
+                self.compressor
+                    .insert(new_compressor(level, self.dictionary.as_ref())?)
+            }
+        };
+        cctx.reset(ResetDirective::SessionOnly)
+            .map_err(zstd_error)?;
+
+        // Feed all input before ending the frame, as `zstd::encode_all` does. Ending in the
+        // first call would pledge the input size, which writes a content-size field and picks
+        // different parameters, so the frame would no longer match `FrameCodec::encode`.
+        let mut input = InBuffer::around(&self.payload);
+        while input.pos() < self.payload.len() {
+            out.reserve(CCtx::out_size());
+            let mut output = OutBuffer::around_pos(out, out.len());
+            cctx.compress_stream(&mut output, &mut input)
+                .map_err(zstd_error)?;
+        }
+        loop {
+            out.reserve(CCtx::out_size());
+            let mut output = OutBuffer::around_pos(out, out.len());
+            if cctx.end_stream(&mut output).map_err(zstd_error)? == 0 {
+                return Ok(());
+            }
+        }
+    }
+}
+
+fn new_compressor(
+    level: i32,
+    dictionary: Option<&CompressionDictionary>,
+) -> Result<CCtx<'static>, ProtocolError> {
+    let mut cctx = CCtx::create();
+    cctx.set_parameter(CParameter::CompressionLevel(level))
+        .map_err(zstd_error)?;
+    if let Some(dictionary) = dictionary {
+        // Loaded after the level so the dictionary is digested with the same parameters.
+        cctx.load_dictionary(dictionary.as_bytes())
+            .map_err(zstd_error)?;
+    }
+    Ok(cctx)
+}
+
+fn zstd_error(code: zstd_safe::ErrorCode) -> ProtocolError {
+    ProtocolError::Compression(zstd_safe::get_error_name(code).to_string())
+}
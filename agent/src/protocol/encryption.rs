@@ -0,0 +1,219 @@
+//! AEAD payload encryption (ChaCha20-Poly1305) for transports without TLS.
+//!
+//! An encrypted frame has `FLAG_ENCRYPTED` set in the envelope flags byte and carries
+//! `[counter: u64 LE][ciphertext][tag: 16 bytes]` in place of the payload. The payload is
+//! encrypted after compression; the envelope and the counter are associated data, so they
+//! cannot be altered without failing decryption.
+//!
+//! Keys are per session and per direction. Agent and server each put a fresh random
+//! `tags::SESSION_NONCE` into the handshake payloads, and both derive the keys with HKDF-SHA256
+//! from a pre-shared key and the two nonces ([`SessionKeys::derive`]). The AEAD nonce is the
+//! frame counter, which a [`PayloadCipher`] increments on every frame it seals, so a nonce is
+//! never used twice with the same key. The receiving cipher only accepts counters above the
+//! highest one it has opened, so a recorded frame cannot be replayed. Encrypt only if
+//! `CAP_ENCRYPTION` was negotiated.
+
+use super::ProtocolError;
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{AeadInPlace, KeyInit, OsRng};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce, Tag};
+use hkdf::Hkdf;
+use sha2::Sha256;
+use std::fmt;
+
+/// Size of a handshake session nonce.
+pub const SESSION_NONCE_SIZE: usize = 32;
+
+/// Size of the frame counter in front of the ciphertext.
+const COUNTER_SIZE: usize = 8;
+
+/// Size of the Poly1305 tag after the ciphertext.
+const TAG_SIZE: usize = 16;
+
+/// HKDF info labels, one per direction.
+const AGENT_TO_SERVER: &[u8] = b"clientmonitoring payload key: agent to server";
+const SERVER_TO_AGENT: &[u8] = b"clientmonitoring payload key: server to agent";
+
+/// Side of the session a key set is used by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionRole {
+    Agent,
+    Server,
+}
+
+/// Payload keys of one session, derived during the handshake.
+pub struct SessionKeys {
+    agent_to_server: Key,
+    server_to_agent: Key,
+}
+
+impl fmt::Debug for SessionKeys {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SessionKeys").finish_non_exhaustive()
+    }
+}
+
+impl SessionKeys {
+    /// Fresh random nonce for `tags::SESSION_NONCE`.
+    pub fn random_nonce() -> [u8; SESSION_NONCE_SIZE] {
+        let mut nonce = [0u8; SESSION_NONCE_SIZE];
+        OsRng.fill_bytes(&mut nonce);
+        nonce
+    }
+
+    /// Derive the session keys from the pre-shared key and both handshake nonces.
+    pub fn derive(
+        pre_shared_key: &[u8],
+        agent_nonce: &[u8; SESSION_NONCE_SIZE],
+        server_nonce: &[u8; SESSION_NONCE_SIZE],
+    ) -> Result<Self, ProtocolError> {
+        let mut salt = [0u8; 2 * SESSION_NONCE_SIZE];
+        salt[..SESSION_NONCE_SIZE].copy_from_slice(agent_nonce);
+        salt[SESSION_NONCE_SIZE..].copy_from_slice(server_nonce);
+        let hkdf = Hkdf::<Sha256>::new(Some(&salt), pre_shared_key);
+        let expand = |info: &[u8]| -> Result<Key, ProtocolError> {
+            let mut key = Key::default();
+            hkdf.expand(info, &mut key)
+                .map_err(|e| ProtocolError::Encryption(format!("key derivation failed: {e}")))?;
+            Ok(key)
+        };
+        Ok(Self {
+            agent_to_server: expand(AGENT_TO_SERVER)?,
+            server_to_agent: expand(SERVER_TO_AGENT)?,
+        })
+    }
+
+    /// Cipher for the frames `role` sends.
+    pub fn sealer(&self, role: SessionRole) -> PayloadCipher {
+        PayloadCipher::from_key(match role {
+            SessionRole::Agent => &self.agent_to_server,
+            SessionRole::Server => &self.server_to_agent,
+        })
+    }
+
+    /// Cipher for the frames `role` receives.
+    pub fn opener(&self, role: SessionRole) -> PayloadCipher {
+        PayloadCipher::from_key(match role {
+            SessionRole::Agent => &self.server_to_agent,
+            SessionRole::Server => &self.agent_to_server,
+        })
+    }
+}
+
+/// Payload cipher for one direction of a session.
+///
+/// Not `Clone`: two copies sealing with the same key would reuse counters, and with them nonces.
+pub struct PayloadCipher {
+    aead: ChaCha20Poly1305,
+    /// Counter (and nonce) of the next sealed frame
+    next_counter: u64,
+    /// Highest counter of an opened frame; lower or equal counters are replays
+    last_opened: Option<u64>,
+}
+
+impl fmt::Debug for PayloadCipher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PayloadCipher")
+            .field("next_counter", &self.next_counter)
+            .field("last_opened", &self.last_opened)
+            .finish_non_exhaustive()
+    }
+}
+
+impl PayloadCipher {
+    /// Bytes sealing adds to a payload: the frame counter and the tag.
+    pub(super) const OVERHEAD: usize = COUNTER_SIZE + TAG_SIZE;
+
+    /// Cipher with an externally managed 256-bit key.
+    ///
+    /// Prefer [`SessionKeys`]; a key used here must not seal frames in any other cipher.
+    pub fn new(key: &[u8; 32]) -> Self {
+        Self::from_key(Key::from_slice(key))
+    }
+
+    fn from_key(key: &Key) -> Self {
+        Self {
+            aead: ChaCha20Poly1305::new(key),
+            next_counter: 0,
+            last_opened: None,
+        }
+    }
+
+    /// Encrypt `buf[payload_start..]` in place, with `buf[aad_start..payload_start]` (the
+    /// envelope) as associated data.
+    ///
+    /// Inserts the frame counter before the ciphertext and appends the tag.
+    pub(super) fn seal(
+        &mut self,
+        buf: &mut Vec<u8>,
+        aad_start: usize,
+        payload_start: usize,
+    ) -> Result<(), ProtocolError> {
+        let counter = self.next_counter;
+        self.next_counter = counter
+            .checked_add(1)
+            .ok_or_else(|| ProtocolError::Encryption("frame counter exhausted".to_string()))?;
+
+        buf.splice(payload_start..payload_start, counter.to_le_bytes());
+        let (aad, payload) = buf.split_at_mut(payload_start + COUNTER_SIZE);
+        let tag = self
+            .aead
+            .encrypt_in_place_detached(&nonce(counter), &aad[aad_start..], payload)
+            .map_err(|_| ProtocolError::Encryption("payload too large".to_string()))?;
+        buf.extend_from_slice(&tag);
+        Ok(())
+    }
+
+    /// Decrypt the payload of a frame body whose payload starts at `payload_offset`.
+    ///
+    /// Frames must arrive with increasing counters; a counter at or below the highest one
+    /// opened so far is rejected as a replay. Returns the payload and the frame counter, which
+    /// counts as opened only once passed to [`PayloadCipher::commit_opened`], so a frame that
+    /// is rejected after decryption does not advance it.
+    pub(super) fn open(
+        &self,
+        body: &[u8],
+        payload_offset: usize,
+    ) -> Result<(Vec<u8>, u64), ProtocolError> {
+        let too_short = || ProtocolError::Encryption("encrypted payload too short".to_string());
+        let ciphertext_start = payload_offset + COUNTER_SIZE;
+        if body.len() < ciphertext_start + TAG_SIZE {
+            return Err(too_short());
+        }
+        let counter = body
+            .get(payload_offset..ciphertext_start)
+            .and_then(|bytes| <[u8; COUNTER_SIZE]>::try_from(bytes).ok())
+            .map(u64::from_le_bytes)
+            .ok_or_else(too_short)?;
+        if self.last_opened.is_some_and(|last| counter <= last) {
+            return Err(ProtocolError::Encryption(format!(
+                "replayed frame counter {counter}"
+            )));
+        }
+        let (ciphertext, tag) =
+            body[ciphertext_start..].split_at(body.len() - ciphertext_start - TAG_SIZE);
+
+        let mut payload = ciphertext.to_vec();
+        self.aead
+            .decrypt_in_place_detached(
+                &nonce(counter),
+                &body[..ciphertext_start],
+                &mut payload,
+                Tag::from_slice(tag),
+            )
+            .map_err(|_| ProtocolError::Encryption("payload authentication failed".to_string()))?;
+        Ok((payload, counter))
+    }
+
+    /// Record frame `counter` as opened, so it and every lower counter are replays from now on.
+    pub(super) fn commit_opened(&mut self, counter: u64) {
+        self.last_opened = Some(self.last_opened.map_or(counter, |last| last.max(counter)));
+    }
+}
+
+/// 96-bit AEAD nonce for frame `counter`.
+fn nonce(counter: u64) -> Nonce {
+    let mut nonce = Nonce::default();
+    nonce[..COUNTER_SIZE].copy_from_slice(&counter.to_le_bytes());
+    nonce
+}
@@ -0,0 +1,259 @@
+//! Wire error codes for `MessagePayload::Error` and `MessageAck.error_code`.
+//!
+//! Codes are grouped in ranges by category, so a peer can still classify a code added by a
+//! newer version:
+//!
+//! | Range       | Category      | Examples                                   |
+//! |-------------|---------------|--------------------------------------------|
+//! | 1000..=1999 | `Protocol`    | unsupported version, CRC mismatch          |
+//! | 2000..=2999 | `Security`    | unauthenticated                            |
+//! | 3000..=3999 | `FlowControl` | throttled                                  |
+//! | 5000..=5999 | `Server`      | internal error                             |
+//!
+//! Each code states whether resending the message can succeed (`is_retryable`) and whether the
+//! session is unusable afterwards (`is_fatal`); [`ErrorCode::action`] combines both into what
+//! the agent should do next. Unknown codes round-trip unchanged and take their semantics from
+//! their category.
+
+use super::{MessageAck, MessagePayload, ProtocolError};
+use std::io;
+
+/// Category of an error code, given by its range.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorCategory {
+    /// Framing, versioning and payload errors (1000..=1999)
+    Protocol,
+    /// Authentication and encryption errors (2000..=2999)
+    Security,
+    /// Load shedding and rate limits (3000..=3999)
+    FlowControl,
+    /// Server-side failures unrelated to the message (5000..=5999)
+    Server,
+    /// Outside every assigned range
+    Unassigned,
+}
+
+impl ErrorCategory {
+    /// Category of the raw code `code`.
+    pub fn of(code: u32) -> Self {
+        match code {
+            1000..=1999 => ErrorCategory::Protocol,
+            2000..=2999 => ErrorCategory::Security,
+            3000..=3999 => ErrorCategory::FlowControl,
+            5000..=5999 => ErrorCategory::Server,
+            _ => ErrorCategory::Unassigned,
+        }
+    }
+}
+
+/// What the agent should do after receiving an error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorAction {
+    /// Resend the message on the same session (after the usual backoff)
+    Retry,
+    /// Drop the message and carry on; resending it would fail the same way
+    Drop,
+    /// Close the session, reconnect and resend
+    Reconnect,
+    /// Stop sending; the problem needs configuration or an upgrade
+    GiveUp,
+    /// Send the rejected delta's data again as a full snapshot; the same delta would fail again
+    SendFullSnapshot,
+}
+
+/// Typed wire error code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorCode {
+    /// No protocol version in common (1001)
+    UnsupportedVersion,
+    /// Frame or decompressed payload over the size limit (1002)
+    FrameTooLarge,
+    /// Frame failed its CRC32 check; the stream may be desynchronized (1003)
+    CrcMismatch,
+    /// Payload could not be decoded or failed validation (1004)
+    MalformedPayload,
+    /// Snapshot delta referenced a base the server does not hold; send a full snapshot (1005)
+    UnknownDeltaBase,
+    /// Frame not authenticated, or its tag or encryption did not verify (2001)
+    Unauthenticated,
+    /// Server is shedding load; resend after the backpressure delay (3001)
+    Throttled,
+    /// Server failed to process a valid message (5001)
+    Internal,
+    /// Code this implementation does not know
+    Unknown(u32),
+}
+
+impl ErrorCode {
+    /// Every known code, in code order.
+    pub const KNOWN: [ErrorCode; 8] = [
+        ErrorCode::UnsupportedVersion,
+        ErrorCode::FrameTooLarge,
+        ErrorCode::CrcMismatch,
+        ErrorCode::MalformedPayload,
+        ErrorCode::UnknownDeltaBase,
+        ErrorCode::Unauthenticated,
+        ErrorCode::Throttled,
+        ErrorCode::Internal,
+    ];
+
+    /// Decode a wire code; unknown values are kept as `Unknown`.
+    pub fn from_u32(code: u32) -> Self {
+        match code {
+            1001 => ErrorCode::UnsupportedVersion,
+            1002 => ErrorCode::FrameTooLarge,
+            1003 => ErrorCode::CrcMismatch,
+            1004 => ErrorCode::MalformedPayload,
+            1005 => ErrorCode::UnknownDeltaBase,
+            2001 => ErrorCode::Unauthenticated,
+            3001 => ErrorCode::Throttled,
+            5001 => ErrorCode::Internal,
+            other => ErrorCode::Unknown(other),
+        }
+    }
+
+    /// Wire value.
+    pub fn to_u32(self) -> u32 {
+        match self {
+            ErrorCode::UnsupportedVersion => 1001,
+            ErrorCode::FrameTooLarge => 1002,
+            ErrorCode::CrcMismatch => 1003,
+            ErrorCode::MalformedPayload => 1004,
+            ErrorCode::UnknownDeltaBase => 1005,
+            ErrorCode::Unauthenticated => 2001,
+            ErrorCode::Throttled => 3001,
+            ErrorCode::Internal => 5001,
+            ErrorCode::Unknown(code) => code,
+        }
+    }
+
+    /// Category, from the code range.
+    pub fn category(self) -> ErrorCategory {
+        ErrorCategory::of(self.to_u32())
+    }
+
+    /// True if resending the message can succeed.
+    pub fn is_retryable(self) -> bool {
+        match self {
+            ErrorCode::CrcMismatch | ErrorCode::Throttled | ErrorCode::Internal => true,
+            ErrorCode::UnsupportedVersion
+            | ErrorCode::FrameTooLarge
+            | ErrorCode::MalformedPayload
+            | ErrorCode::UnknownDeltaBase
+            | ErrorCode::Unauthenticated => false,
+            ErrorCode::Unknown(_) => matches!(
+                self.category(),
+                ErrorCategory::FlowControl | ErrorCategory::Server
+            ),
+        }
+    }
+
+    /// True if the session cannot be used after this error.
+    pub fn is_fatal(self) -> bool {
+        match self {
+            ErrorCode::UnsupportedVersion | ErrorCode::CrcMismatch | ErrorCode::Unauthenticated => {
+                true
+            }
+            ErrorCode::FrameTooLarge
+            | ErrorCode::MalformedPayload
+            | ErrorCode::UnknownDeltaBase
+            | ErrorCode::Throttled
+            | ErrorCode::Internal => false,
+            ErrorCode::Unknown(_) => self.category() == ErrorCategory::Security,
+        }
+    }
+
+    /// What the agent should do next.
+    pub fn action(self) -> ErrorAction {
+        if self == ErrorCode::UnknownDeltaBase {
+            return ErrorAction::SendFullSnapshot;
+        }
+        match (self.is_retryable(), self.is_fatal()) {
+            (true, false) => ErrorAction::Retry,
+            (false, false) => ErrorAction::Drop,
+            (true, true) => ErrorAction::Reconnect,
+            (false, true) => ErrorAction::GiveUp,
+        }
+    }
+}
+
+impl From<u32> for ErrorCode {
+    fn from(code: u32) -> Self {
+        ErrorCode::from_u32(code)
+    }
+}
+
+impl From<ErrorCode> for u32 {
+    fn from(code: ErrorCode) -> Self {
+        code.to_u32()
+    }
+}
+
+impl From<&ProtocolError> for ErrorCode {
+    /// Code to report for a frame that failed to decode with `err`.
+    fn from(err: &ProtocolError) -> Self {
+        match err.root_cause() {
+            ProtocolError::IncompatibleVersion => ErrorCode::UnsupportedVersion,
+            ProtocolError::FrameTooLarge(..) | ProtocolError::DecompressedSizeExceeded { .. } => {
+                ErrorCode::FrameTooLarge
+            }
+            ProtocolError::Crc32Mismatch { .. } => ErrorCode::CrcMismatch,
+            ProtocolError::UnknownDeltaBase(_) => ErrorCode::UnknownDeltaBase,
+            ProtocolError::AuthenticationFailed(_)
+            | ProtocolError::Encryption(_)
+            | ProtocolError::UnencryptedFrame => ErrorCode::Unauthenticated,
+            // A truncated or unreadable field is the sender's fault; resending it fails again
+            ProtocolError::Io(err)
+                if matches!(
+                    err.kind(),
+                    io::ErrorKind::UnexpectedEof | io::ErrorKind::InvalidData
+                ) =>
+            {
+                ErrorCode::MalformedPayload
+            }
+            ProtocolError::Io(_) => ErrorCode::Internal,
+            _ => ErrorCode::MalformedPayload,
+        }
+    }
+}
+
+impl ProtocolError {
+    /// Wire error code for this error (see [`ErrorCode`]).
+    pub fn error_code(&self) -> ErrorCode {
+        ErrorCode::from(self)
+    }
+
+    /// `MessagePayload::Error` reporting this error to the peer.
+    pub fn to_wire_error(&self) -> MessagePayload {
+        MessagePayload::Error {
+            code: self.error_code().to_u32(),
+            message: self.to_string(),
+        }
+    }
+}
+
+impl MessagePayload {
+    /// Typed code of an `Error` payload.
+    pub fn error_code(&self) -> Option<ErrorCode> {
+        match self {
+            MessagePayload::Error { code, .. } => Some(ErrorCode::from_u32(*code)),
+            _ => None,
+        }
+    }
+}
+
+impl MessageAck {
+    /// Negative acknowledgment of `message_id` with `code`.
+    pub fn failed(message_id: [u8; 16], code: ErrorCode) -> Self {
+        Self {
+            message_id,
+            success: false,
+            error_code: Some(code.to_u32()),
+        }
+    }
+
+    /// Typed error code of a negative acknowledgment.
+    pub fn code(&self) -> Option<ErrorCode> {
+        self.error_code.map(ErrorCode::from_u32)
+    }
+}
@@ -0,0 +1,229 @@
+//! Forward-compatible extension fields (tag-length-value areas).
+//!
+//! The envelope and payload layouts are fixed sequences, so a minor version cannot add a field
+//! without breaking older decoders. Extension areas carry optional fields as
+//! `[count: u16 LE]` followed by `count` entries of `[tag: u16 LE][len: u32 LE][value]`.
+//!
+//! - Envelope: present only when the envelope flags byte has `FLAG_EXTENSIONS` set; the area
+//!   follows the flags byte and is never compressed.
+//! - Payload: appended after the fixed payload fields (inside the compressed region). Decoders
+//!   that predate extensions ignore trailing payload bytes, so adding fields stays compatible.
+//!   In `DecodeMode::Lenient` a trailer that is not a valid area is ignored the same way.
+//!
+//! Decoders keep every entry, including tags they do not understand, so a message can be
+//! re-encoded without losing fields added by newer peers. Known tags are exposed through
+//! typed accessors.
+
+use super::{Envelope, FieldReader, Message, ProtocolError, SESSION_NONCE_SIZE};
+use serde::{Deserialize, Serialize};
+
+/// Extension tags understood by this implementation.
+///
+/// Tags `0x8000..=0xFFFF` are reserved for private or experimental use.
+pub mod tags {
+    /// Envelope: per-session message sequence number (u64 little-endian)
+    pub const SEQUENCE_NUMBER: u16 = 0x0001;
+    /// Envelope: id of the key that authenticated the frame (u32 little-endian)
+    pub const KEY_ID: u16 = 0x0002;
+    /// Snapshot payload: number of processes observed before truncation (u64 little-endian)
+    pub const TOTAL_PROCESS_COUNT: u16 = 0x0101;
+    /// Handshake and HandshakeAck payloads: session key derivation nonce (32 bytes)
+    pub const SESSION_NONCE: u16 = 0x0201;
+}
+
+/// Ordered set of tagged optional fields.
+///
+/// Each tag appears at most once; entries keep their insertion (or decode) order.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct Extensions {
+    entries: Vec<(u16, Vec<u8>)>,
+}
+
+impl Extensions {
+    /// Create an empty extension area.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// True if no fields are present.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Number of fields present.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Raw value of a field, if present.
+    pub fn get(&self, tag: u16) -> Option<&[u8]> {
+        self.entries
+            .iter()
+            .find(|(t, _)| *t == tag)
+            .map(|(_, value)| value.as_slice())
+    }
+
+    /// Check if a field is present.
+    pub fn contains(&self, tag: u16) -> bool {
+        self.get(tag).is_some()
+    }
+
+    /// Set a field, replacing any existing value for the tag.
+    pub fn insert(&mut self, tag: u16, value: Vec<u8>) {
+        match self.entries.iter_mut().find(|(t, _)| *t == tag) {
+            Some(entry) => entry.1 = value,
+            None => self.entries.push((tag, value)),
+        }
+    }
+
+    /// Remove a field, returning its raw value.
+    pub fn remove(&mut self, tag: u16) -> Option<Vec<u8>> {
+        let index = self.entries.iter().position(|(t, _)| *t == tag)?;
+        Some(self.entries.remove(index).1)
+    }
+
+    /// Iterate over `(tag, value)` pairs in order.
+    pub fn iter(&self) -> impl Iterator<Item = (u16, &[u8])> {
+        self.entries
+            .iter()
+            .map(|(tag, value)| (*tag, value.as_slice()))
+    }
+
+    /// Read a `u64` field; errors if the field is present with the wrong size.
+    pub fn get_u64(&self, tag: u16) -> Result<Option<u64>, ProtocolError> {
+        self.get_array::<8>(tag).map(|v| v.map(u64::from_le_bytes))
+    }
+
+    /// Set a `u64` field.
+    pub fn insert_u64(&mut self, tag: u16, value: u64) {
+        self.insert(tag, value.to_le_bytes().to_vec());
+    }
+
+    /// Read a `u32` field; errors if the field is present with the wrong size.
+    pub fn get_u32(&self, tag: u16) -> Result<Option<u32>, ProtocolError> {
+        self.get_array::<4>(tag).map(|v| v.map(u32::from_le_bytes))
+    }
+
+    /// Set a `u32` field.
+    pub fn insert_u32(&mut self, tag: u16, value: u32) {
+        self.insert(tag, value.to_le_bytes().to_vec());
+    }
+
+    /// Read a UTF-8 string field.
+    pub fn get_str(&self, tag: u16) -> Result<Option<&str>, ProtocolError> {
+        self.get(tag)
+            .map(|value| {
+                std::str::from_utf8(value).map_err(|e| ProtocolError::InvalidExtension {
+                    tag,
+                    reason: e.to_string(),
+                })
+            })
+            .transpose()
+    }
+
+    /// Set a UTF-8 string field.
+    pub fn insert_str(&mut self, tag: u16, value: &str) {
+        self.insert(tag, value.as_bytes().to_vec());
+    }
+
+    /// Read a fixed-size field.
+    pub fn get_array<const N: usize>(&self, tag: u16) -> Result<Option<[u8; N]>, ProtocolError> {
+        self.get(tag)
+            .map(|value| {
+                value
+                    .try_into()
+                    .map_err(|_| ProtocolError::InvalidExtension {
+                        tag,
+                        reason: format!("expected {N} bytes, got {}", value.len()),
+                    })
+            })
+            .transpose()
+    }
+}
+
+impl Envelope {
+    /// Per-session sequence number (`tags::SEQUENCE_NUMBER`), if present.
+    pub fn sequence_number(&self) -> Result<Option<u64>, ProtocolError> {
+        self.extensions.get_u64(tags::SEQUENCE_NUMBER)
+    }
+
+    /// Set the per-session sequence number.
+    pub fn set_sequence_number(&mut self, sequence: u64) {
+        self.extensions.insert_u64(tags::SEQUENCE_NUMBER, sequence);
+    }
+
+    /// Id of the key that authenticated the frame (`tags::KEY_ID`), if present.
+    ///
+    /// Set by `FrameEncoder` when it signs a frame; only meaningful on a message decoded with
+    /// tag verification.
+    pub fn key_id(&self) -> Result<Option<u32>, ProtocolError> {
+        self.extensions.get_u32(tags::KEY_ID)
+    }
+}
+
+impl Message {
+    /// Snapshot process count before truncation (`tags::TOTAL_PROCESS_COUNT`), if present.
+    pub fn total_process_count(&self) -> Result<Option<u64>, ProtocolError> {
+        self.payload_extensions.get_u64(tags::TOTAL_PROCESS_COUNT)
+    }
+
+    /// Set the snapshot process count before truncation.
+    pub fn set_total_process_count(&mut self, count: u64) {
+        self.payload_extensions
+            .insert_u64(tags::TOTAL_PROCESS_COUNT, count);
+    }
+
+    /// Session key derivation nonce of a handshake payload (`tags::SESSION_NONCE`), if present.
+    pub fn session_nonce(&self) -> Result<Option<[u8; SESSION_NONCE_SIZE]>, ProtocolError> {
+        self.payload_extensions.get_array(tags::SESSION_NONCE)
+    }
+
+    /// Set the session key derivation nonce (see `SessionKeys::random_nonce`).
+    pub fn set_session_nonce(&mut self, nonce: &[u8; SESSION_NONCE_SIZE]) {
+        self.payload_extensions
+            .insert(tags::SESSION_NONCE, nonce.to_vec());
+    }
+}
+
+/// Append an extension area to `buf`.
+///
+/// Returns `ProtocolError::InvalidExtension` if the area has more entries than the `u16` count
+/// or a value longer than the `u32` length can describe.
+pub(super) fn write_extensions(
+    buf: &mut Vec<u8>,
+    extensions: &Extensions,
+) -> Result<(), ProtocolError> {
+    let count =
+        u16::try_from(extensions.entries.len()).map_err(|_| ProtocolError::InvalidExtension {
+            tag: extensions.entries[usize::from(u16::MAX)].0,
+            reason: format!(
+                "{} entries exceed the maximum of {}",
+                extensions.entries.len(),
+                u16::MAX
+            ),
+        })?;
+    buf.extend_from_slice(&count.to_le_bytes());
+    for (tag, value) in &extensions.entries {
+        let len = u32::try_from(value.len()).map_err(|_| ProtocolError::InvalidExtension {
+            tag: *tag,
+            reason: format!("value of {} bytes exceeds the u32 length", value.len()),
+        })?;
+        buf.extend_from_slice(&tag.to_le_bytes());
+        buf.extend_from_slice(&len.to_le_bytes());
+        buf.extend_from_slice(value);
+    }
+    Ok(())
+}
+
+/// Read an extension area written by [`write_extensions`].
+pub(super) fn read_extensions(reader: &mut FieldReader<'_>) -> Result<Extensions, ProtocolError> {
+    let area = reader.extension_area()?;
+    let mut entries = FieldReader::new(area, 0, *reader.limits(), reader.mode());
+    let mut extensions = Extensions::new();
+    for _ in 0..entries.u16()? {
+        let tag = entries.u16()?;
+        let len = entries.u32()? as usize;
+        extensions.entries.push((tag, entries.bytes(len)?.to_vec()));
+    }
+    Ok(extensions)
+}
@@ -0,0 +1,162 @@
+//! Frame layouts: where the length prefix and the CRC32 sit around a frame body.
+//!
+//! - [`FrameLayout::Legacy`]: `[length: u32 BE][body][crc32: u32 LE]`, the length counting the
+//!   body. The layout this codec has always written, and the default.
+//! - [`FrameLayout::Spec`]: `[length: u32 LE][crc32: u32 LE][body]`, the length counting every
+//!   byte after the prefix (CRC32 and body), as specified by FR-002.
+//!
+//! Both layouts carry the same body with a CRC-32/ISO-HDLC checksum over it and the same body
+//! limit (`MAX_FRAME_SIZE`), so converting a frame only moves the framing bytes. A session uses
+//! one layout for every frame, either configured on both ends (`with_layout` on the encoder and
+//! decoder) or negotiated with `CAP_SPEC_FRAMING`: the handshake and its ack use the legacy
+//! layout and [`HandshakeAckPayload::frame_layout`] applies from the next frame on. Captures
+//! may be in either layout; [`FrameLayout::detect`] tells which.
+//!
+//! [`HandshakeAckPayload::frame_layout`]: super::HandshakeAckPayload::frame_layout
+
+use super::{
+    scanner, verify_crc32, ProtocolError, SkipReason, CRC_SIZE, LENGTH_PREFIX_SIZE, MAX_FRAME_SIZE,
+};
+use std::io;
+
+/// Arrangement of length prefix, CRC32 and body in a frame.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum FrameLayout {
+    /// `[length: u32 BE][body][crc32: u32 LE]`; length = body size
+    #[default]
+    Legacy,
+    /// `[length: u32 LE][crc32: u32 LE][body]`; length = CRC32 + body size (FR-002)
+    Spec,
+}
+
+impl FrameLayout {
+    /// Every layout, in detection order.
+    pub const ALL: [FrameLayout; 2] = [FrameLayout::Legacy, FrameLayout::Spec];
+
+    /// Number of framing bytes in front of the body.
+    pub const fn header_len(self) -> usize {
+        match self {
+            FrameLayout::Legacy => LENGTH_PREFIX_SIZE,
+            FrameLayout::Spec => LENGTH_PREFIX_SIZE + CRC_SIZE,
+        }
+    }
+
+    /// Number of framing bytes after the body.
+    pub const fn trailer_len(self) -> usize {
+        match self {
+            FrameLayout::Legacy => CRC_SIZE,
+            FrameLayout::Spec => 0,
+        }
+    }
+
+    /// Total length of a frame with a body of `body_len` bytes.
+    pub const fn frame_len(self, body_len: usize) -> usize {
+        self.header_len() + body_len + self.trailer_len()
+    }
+
+    /// Layout of a capture of concatenated frames, detected from its first frame.
+    ///
+    /// Returns the layout in which `data` starts with a complete, CRC-valid and decodable frame
+    /// (or an intact one whose payload is encrypted or compressed with an unknown dictionary),
+    /// or `None` if it does in neither layout (leading garbage or a damaged first frame). Such
+    /// a capture needs its layout from the caller.
+    pub fn detect(data: &[u8]) -> Option<FrameLayout> {
+        Self::ALL.into_iter().find(|&layout| {
+            matches!(
+                scanner::try_frame_at(data, 0, layout, None),
+                Ok(_) | Err(SkipReason::Encrypted { .. } | SkipReason::UnknownDictionary { .. })
+            )
+        })
+    }
+
+    /// Body length declared by `header` (the first `header_len()` bytes of a frame).
+    ///
+    /// Fails if the length prefix cannot describe a frame or the body exceeds `MAX_FRAME_SIZE`.
+    pub fn body_len(self, header: &[u8]) -> Result<usize, ProtocolError> {
+        let prefix = word_at(header, 0)?;
+        let body_len = match self {
+            FrameLayout::Legacy => u32::from_be_bytes(prefix) as usize,
+            FrameLayout::Spec => {
+                let len = u32::from_le_bytes(prefix) as usize;
+                len.checked_sub(CRC_SIZE)
+                    .ok_or(ProtocolError::InvalidFrameLength(len))?
+            }
+        };
+        if body_len > MAX_FRAME_SIZE {
+            return Err(ProtocolError::FrameTooLarge(body_len, MAX_FRAME_SIZE));
+        }
+        Ok(body_len)
+    }
+
+    /// CRC32 stored in a frame with `header` in front of its body and `trailer` after it.
+    pub(super) fn stored_crc(self, header: &[u8], trailer: &[u8]) -> Result<u32, ProtocolError> {
+        let word = match self {
+            FrameLayout::Legacy => word_at(trailer, 0),
+            FrameLayout::Spec => word_at(header, LENGTH_PREFIX_SIZE),
+        }?;
+        Ok(u32::from_le_bytes(word))
+    }
+
+    /// Verify the CRC32 of a complete `frame` with a body of `body_len` bytes; returns the body.
+    pub(super) fn verified_body(
+        self,
+        frame: &[u8],
+        body_len: usize,
+    ) -> Result<&[u8], ProtocolError> {
+        let (header, rest) = frame.split_at(self.header_len());
+        let (body, trailer) = rest.split_at(body_len);
+        verify_crc32(body, self.stored_crc(header, trailer)?)?;
+        Ok(body)
+    }
+
+    /// Verify the frame at the start of `data`; returns its body and total length.
+    pub(super) fn split_frame(self, data: &[u8]) -> Result<(&[u8], usize), ProtocolError> {
+        let header = data.get(..self.header_len()).ok_or_else(unexpected_eof)?;
+        let body_len = self.body_len(header)?;
+        let frame_len = self.frame_len(body_len);
+        let frame = data.get(..frame_len).ok_or_else(unexpected_eof)?;
+        Ok((self.verified_body(frame, body_len)?, frame_len))
+    }
+
+    /// Start a frame at the end of `out` with a placeholder header; returns the body start.
+    pub(super) fn begin_frame(self, out: &mut Vec<u8>) -> usize {
+        out.resize(out.len() + self.header_len(), 0);
+        out.len()
+    }
+
+    /// Complete the frame begun at `start`: check the body size, then fill in length and CRC32.
+    pub(super) fn finish_frame(self, out: &mut Vec<u8>, start: usize) -> Result<(), ProtocolError> {
+        let body_start = start + self.header_len();
+        let body_len = out.len() - body_start;
+        if body_len > MAX_FRAME_SIZE {
+            return Err(ProtocolError::FrameTooLarge(body_len, MAX_FRAME_SIZE));
+        }
+
+        let checksum = crc32fast::hash(&out[body_start..]).to_le_bytes();
+        let prefix = &mut out[start..start + LENGTH_PREFIX_SIZE];
+        match self {
+            FrameLayout::Legacy => {
+                prefix.copy_from_slice(&(body_len as u32).to_be_bytes());
+                out.extend_from_slice(&checksum);
+            }
+            FrameLayout::Spec => {
+                prefix.copy_from_slice(&((CRC_SIZE + body_len) as u32).to_le_bytes());
+                out[start + LENGTH_PREFIX_SIZE..body_start].copy_from_slice(&checksum);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The four bytes at `offset` of `bytes`; fails if `bytes` ends before them.
+fn word_at(bytes: &[u8], offset: usize) -> Result<[u8; 4], ProtocolError> {
+    bytes
+        .get(offset..)
+        .and_then(|rest| rest.get(..4))
+        .and_then(|word| <[u8; 4]>::try_from(word).ok())
+        .ok_or_else(unexpected_eof)
+}
+
+fn unexpected_eof() -> ProtocolError {
+    ProtocolError::Io(io::ErrorKind::UnexpectedEof.into())
+}
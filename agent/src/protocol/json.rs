@@ -0,0 +1,759 @@
+//! Canonical JSON form of a [`Message`], for inspecting and hand-crafting frames.
+//!
+//! ```json
+//! {
+//!   "version": "1.0",
+//!   "type": "Ack",
+//!   "message_id": "2a000000000000000000000000000000",
+//!   "timestamp": "2023-12-21T16:00:05.000Z",
+//!   "agent_id": "test-agent-001",
+//!   "platform": "Linux",
+//!   "compressed": false,
+//!   "extensions": [{ "tag": 1, "value": "0700000000000000" }],
+//!   "payload": { "message_id": "01000000000000000000000000000000", "success": true, "error_code": null }
+//! }
+//! ```
+//!
+//! - Byte ids (`message_id`, `snapshot_id`, `base_message_id`) and extension values are
+//!   lowercase hex strings; either case is accepted.
+//! - `type`, `platform` and `os_type` are variant names; versions are `"major.minor"`.
+//! - Timestamps (`timestamp` in milliseconds, `window_start` and `window_end` in seconds) are
+//!   Unix integers, or RFC 3339 strings with [`TimestampFormat::Rfc3339`]; both are accepted.
+//! - `payload` holds the fields of the payload of `type` (`{}` for `Heartbeat`). Optional
+//!   fields are written as `null` and may be omitted; `extensions` and `payload_extensions`
+//!   are omitted when empty.
+//! - Unknown fields are rejected, so a typo in a hand-written message does not go unnoticed.
+//!
+//! The full schema is `specs/001-protocol-messaging/contracts/message.schema.json`. JSON has
+//! no non-finite numbers: a NaN or infinite percentage is written as `null`, which
+//! [`Message::from_json`] rejects.
+
+use super::{
+    AgentIdentity, BackpressureSignal, DecodeOptions, Envelope, Extensions, FrameCodec,
+    FrameLayout, HandshakeAckPayload, Message, MessageAck, MessagePayload, MessageType, OsType,
+    ProcessChange, ProcessSample, ProtocolError, ProtocolVersion, SnapshotDeltaPayload,
+    SnapshotPartPayload, SnapshotPayload, VersionRange,
+};
+use chrono::{DateTime, SecondsFormat};
+use serde::de::{DeserializeOwned, Error as SerdeError};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::Value;
+use std::fmt::Write as _;
+use std::io::Cursor;
+
+/// How timestamps are written.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TimestampFormat {
+    /// Unix epoch integers, exactly as on the wire
+    #[default]
+    UnixEpoch,
+    /// RFC 3339 UTC strings (`2023-12-21T16:00:05.000Z`)
+    Rfc3339,
+}
+
+/// Options for writing canonical JSON.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct JsonOptions {
+    /// Format of every timestamp field
+    pub timestamps: TimestampFormat,
+    /// Indent the output (otherwise one line, as in JSON Lines)
+    pub pretty: bool,
+}
+
+impl JsonOptions {
+    /// Write timestamps as RFC 3339 strings.
+    pub fn with_rfc3339_timestamps(self) -> Self {
+        Self {
+            timestamps: TimestampFormat::Rfc3339,
+            ..self
+        }
+    }
+
+    /// Indent the output.
+    pub fn with_pretty(self) -> Self {
+        Self {
+            pretty: true,
+            ..self
+        }
+    }
+}
+
+impl Message {
+    /// Canonical JSON on one line, with Unix timestamps.
+    pub fn to_json(&self) -> Result<String, ProtocolError> {
+        self.to_json_with(&JsonOptions::default())
+    }
+
+    /// Canonical JSON written with `options`.
+    pub fn to_json_with(&self, options: &JsonOptions) -> Result<String, ProtocolError> {
+        let message = JsonMessage::from_message(self, options.timestamps);
+        if options.pretty {
+            serde_json::to_string_pretty(&message).map_err(invalid_json)
+        } else {
+            serde_json::to_string(&message).map_err(invalid_json)
+        }
+    }
+
+    /// Parse canonical JSON (see the module documentation).
+    ///
+    /// Only the JSON form is checked; use [`Message::validate`] for the semantic invariants.
+    pub fn from_json(json: &str) -> Result<Message, ProtocolError> {
+        let message: JsonMessage<Value> = serde_json::from_str(json).map_err(invalid_json)?;
+        message.into_message()
+    }
+}
+
+/// Convert a capture of concatenated frames in `layout` to JSON Lines, one message per line.
+///
+/// Frames are decoded leniently with default limits; the first frame that fails to decode
+/// fails the conversion (use `FrameScanner` to skip corrupt regions).
+pub fn frames_to_json_lines(
+    data: &[u8],
+    layout: FrameLayout,
+    options: &JsonOptions,
+) -> Result<String, ProtocolError> {
+    let options = JsonOptions {
+        pretty: false,
+        ..*options
+    };
+    let mut reader = Cursor::new(data);
+    let mut lines = String::new();
+    while (reader.position() as usize) < data.len() {
+        let message = FrameCodec::decode_with_options(
+            &mut reader,
+            &mut DecodeOptions::new().with_layout(layout),
+        )?;
+        let _ = writeln!(lines, "{}", message.to_json_with(&options)?);
+    }
+    Ok(lines)
+}
+
+/// Encode JSON Lines (one canonical JSON message per line) into concatenated frames in
+/// `layout`. Blank lines are skipped; errors name the line they occurred on.
+pub fn json_lines_to_frames(jsonl: &str, layout: FrameLayout) -> Result<Vec<u8>, ProtocolError> {
+    let mut frames = Vec::new();
+    for (index, line) in jsonl.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let message = Message::from_json(line).map_err(|err| match err {
+            ProtocolError::InvalidJson(reason) => {
+                ProtocolError::InvalidJson(format!("line {}: {reason}", index + 1))
+            }
+            other => other,
+        })?;
+        frames.extend(FrameCodec::encode_with_layout(&message, layout)?);
+    }
+    Ok(frames)
+}
+
+fn invalid_json(err: impl ToString) -> ProtocolError {
+    ProtocolError::InvalidJson(err.to_string())
+}
+
+/// Message with its payload as `P`: typed when writing, a JSON value when reading (its shape
+/// depends on `type`).
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct JsonMessage<P> {
+    version: Version,
+    #[serde(rename = "type")]
+    message_type: TypeName,
+    message_id: Hex16,
+    timestamp: Timestamp,
+    agent_id: String,
+    platform: OsType,
+    compressed: bool,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    extensions: Vec<JsonExtension>,
+    payload: P,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    payload_extensions: Vec<JsonExtension>,
+}
+
+impl JsonMessage<JsonPayload> {
+    fn from_message(message: &Message, format: TimestampFormat) -> Self {
+        let envelope = &message.envelope;
+        Self {
+            version: Version(envelope.version),
+            message_type: TypeName(envelope.message_type),
+            message_id: Hex16(envelope.message_id),
+            timestamp: Timestamp::new(envelope.timestamp_utc_ms, Precision::Millis, format),
+            agent_id: envelope.agent_id.clone(),
+            platform: envelope.platform,
+            compressed: envelope.compressed,
+            extensions: JsonExtension::list(&envelope.extensions),
+            payload: JsonPayload::new(&message.payload, format),
+            payload_extensions: JsonExtension::list(&message.payload_extensions),
+        }
+    }
+}
+
+impl JsonMessage<Value> {
+    fn into_message(self) -> Result<Message, ProtocolError> {
+        let message_type = self.message_type.0;
+        Ok(Message {
+            envelope: Envelope {
+                version: self.version.0,
+                message_type,
+                message_id: self.message_id.0,
+                timestamp_utc_ms: self.timestamp.value(Precision::Millis, "timestamp")?,
+                agent_id: self.agent_id,
+                platform: self.platform,
+                compressed: self.compressed,
+                extensions: JsonExtension::collect(self.extensions)?,
+            },
+            payload: parse_payload(message_type, self.payload)?,
+            payload_extensions: JsonExtension::collect(self.payload_extensions)?,
+        })
+    }
+}
+
+/// Payload fields, written without a tag (the message `type` names the variant).
+#[derive(Serialize)]
+#[serde(untagged)]
+enum JsonPayload {
+    Handshake(JsonIdentity),
+    HandshakeAck(JsonHandshakeAck),
+    Heartbeat(JsonEmpty),
+    Snapshot(JsonSnapshot),
+    Ack(JsonAck),
+    Backpressure(JsonBackpressure),
+    Error(JsonError),
+    SnapshotPart(JsonSnapshotPart),
+    SnapshotDelta(JsonSnapshotDelta),
+}
+
+impl JsonPayload {
+    fn new(payload: &MessagePayload, format: TimestampFormat) -> Self {
+        match payload {
+            MessagePayload::Handshake(identity) => JsonPayload::Handshake(JsonIdentity {
+                instance_id: identity.instance_id.clone(),
+                os_type: identity.os_type,
+                agent_version: identity.agent_version.clone(),
+                supported_versions: JsonVersionRange {
+                    min: Version(identity.supported_versions.min),
+                    max: Version(identity.supported_versions.max),
+                },
+                capabilities: identity.capabilities,
+            }),
+            MessagePayload::HandshakeAck(ack) => JsonPayload::HandshakeAck(JsonHandshakeAck {
+                negotiated_version: Version(ack.negotiated_version),
+                negotiated_capabilities: ack.negotiated_capabilities,
+            }),
+            MessagePayload::Heartbeat => JsonPayload::Heartbeat(JsonEmpty {}),
+            MessagePayload::Snapshot(snapshot) => {
+                JsonPayload::Snapshot(JsonSnapshot::new(snapshot, format))
+            }
+            MessagePayload::Ack(ack) => JsonPayload::Ack(JsonAck {
+                message_id: Hex16(ack.message_id),
+                success: ack.success,
+                error_code: ack.error_code,
+            }),
+            MessagePayload::Backpressure(signal) => JsonPayload::Backpressure(JsonBackpressure {
+                throttle_delay_ms: signal.throttle_delay_ms,
+                reason: signal.reason.clone(),
+            }),
+            MessagePayload::Error { code, message } => JsonPayload::Error(JsonError {
+                code: *code,
+                message: message.clone(),
+            }),
+            MessagePayload::SnapshotPart(part) => JsonPayload::SnapshotPart(JsonSnapshotPart {
+                snapshot_id: Hex16(part.snapshot_id),
+                part_index: part.part_index,
+                part_count: part.part_count,
+                snapshot: JsonSnapshot::new(&part.snapshot, format),
+            }),
+            MessagePayload::SnapshotDelta(delta) => JsonPayload::SnapshotDelta(JsonSnapshotDelta {
+                base_message_id: Hex16(delta.base_message_id),
+                window_start: Timestamp::new(delta.window_start_secs, Precision::Seconds, format),
+                window_end: Timestamp::new(delta.window_end_secs, Precision::Seconds, format),
+                total_cpu_percent: delta.total_cpu_percent,
+                memory_used_bytes: delta.memory_used_bytes,
+                memory_total_bytes: delta.memory_total_bytes,
+                added: delta.added.iter().map(JsonProcess::from).collect(),
+                changed: delta.changed.iter().map(JsonProcessChange::from).collect(),
+                removed: delta.removed.clone(),
+                truncated: delta.truncated,
+            }),
+        }
+    }
+}
+
+/// Parse the payload fields of a `message_type` message.
+fn parse_payload(
+    message_type: MessageType,
+    payload: Value,
+) -> Result<MessagePayload, ProtocolError> {
+    fn parse<T: DeserializeOwned>(payload: Value) -> Result<T, ProtocolError> {
+        serde_json::from_value(payload).map_err(|err| invalid_json(format!("payload: {err}")))
+    }
+
+    Ok(match message_type {
+        MessageType::Handshake => {
+            let identity: JsonIdentity = parse(payload)?;
+            MessagePayload::Handshake(AgentIdentity {
+                instance_id: identity.instance_id,
+                os_type: identity.os_type,
+                agent_version: identity.agent_version,
+                supported_versions: VersionRange {
+                    min: identity.supported_versions.min.0,
+                    max: identity.supported_versions.max.0,
+                },
+                capabilities: identity.capabilities,
+            })
+        }
+        MessageType::HandshakeAck => {
+            let ack: JsonHandshakeAck = parse(payload)?;
+            MessagePayload::HandshakeAck(HandshakeAckPayload {
+                negotiated_version: ack.negotiated_version.0,
+                negotiated_capabilities: ack.negotiated_capabilities,
+            })
+        }
+        MessageType::Heartbeat => {
+            parse::<JsonEmpty>(payload)?;
+            MessagePayload::Heartbeat
+        }
+        MessageType::Snapshot => {
+            MessagePayload::Snapshot(parse::<JsonSnapshot>(payload)?.into_snapshot("payload")?)
+        }
+        MessageType::Ack => {
+            let ack: JsonAck = parse(payload)?;
+            MessagePayload::Ack(MessageAck {
+                message_id: ack.message_id.0,
+                success: ack.success,
+                error_code: ack.error_code,
+            })
+        }
+        MessageType::Backpressure => {
+            let signal: JsonBackpressure = parse(payload)?;
+            MessagePayload::Backpressure(BackpressureSignal {
+                throttle_delay_ms: signal.throttle_delay_ms,
+                reason: signal.reason,
+            })
+        }
+        MessageType::Error => {
+            let error: JsonError = parse(payload)?;
+            MessagePayload::Error {
+                code: error.code,
+                message: error.message,
+            }
+        }
+        MessageType::SnapshotPart => {
+            let part: JsonSnapshotPart = parse(payload)?;
+            MessagePayload::SnapshotPart(SnapshotPartPayload {
+                snapshot_id: part.snapshot_id.0,
+                part_index: part.part_index,
+                part_count: part.part_count,
+                snapshot: part.snapshot.into_snapshot("payload.snapshot")?,
+            })
+        }
+        MessageType::SnapshotDelta => {
+            let delta: JsonSnapshotDelta = parse(payload)?;
+            MessagePayload::SnapshotDelta(Box::new(SnapshotDeltaPayload {
+                base_message_id: delta.base_message_id.0,
+                window_start_secs: delta
+                    .window_start
+                    .value(Precision::Seconds, "payload.window_start")?,
+                window_end_secs: delta
+                    .window_end
+                    .value(Precision::Seconds, "payload.window_end")?,
+                total_cpu_percent: delta.total_cpu_percent,
+                memory_used_bytes: delta.memory_used_bytes,
+                memory_total_bytes: delta.memory_total_bytes,
+                added: delta.added.into_iter().map(ProcessSample::from).collect(),
+                changed: delta.changed.into_iter().map(ProcessChange::from).collect(),
+                removed: delta.removed,
+                truncated: delta.truncated,
+            }))
+        }
+    })
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct JsonEmpty {}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct JsonIdentity {
+    instance_id: String,
+    os_type: OsType,
+    agent_version: String,
+    supported_versions: JsonVersionRange,
+    capabilities: u32,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct JsonVersionRange {
+    min: Version,
+    max: Version,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct JsonHandshakeAck {
+    negotiated_version: Version,
+    negotiated_capabilities: u32,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct JsonSnapshot {
+    window_start: Timestamp,
+    window_end: Timestamp,
+    total_cpu_percent: f32,
+    memory_used_bytes: u64,
+    memory_total_bytes: u64,
+    processes: Vec<JsonProcess>,
+    truncated: bool,
+}
+
+impl JsonSnapshot {
+    fn new(snapshot: &SnapshotPayload, format: TimestampFormat) -> Self {
+        Self {
+            window_start: Timestamp::new(snapshot.window_start_secs, Precision::Seconds, format),
+            window_end: Timestamp::new(snapshot.window_end_secs, Precision::Seconds, format),
+            total_cpu_percent: snapshot.total_cpu_percent,
+            memory_used_bytes: snapshot.memory_used_bytes,
+            memory_total_bytes: snapshot.memory_total_bytes,
+            processes: snapshot.processes.iter().map(JsonProcess::from).collect(),
+            truncated: snapshot.truncated,
+        }
+    }
+
+    fn into_snapshot(self, path: &str) -> Result<SnapshotPayload, ProtocolError> {
+        Ok(SnapshotPayload {
+            window_start_secs: self
+                .window_start
+                .value(Precision::Seconds, &format!("{path}.window_start"))?,
+            window_end_secs: self
+                .window_end
+                .value(Precision::Seconds, &format!("{path}.window_end"))?,
+            total_cpu_percent: self.total_cpu_percent,
+            memory_used_bytes: self.memory_used_bytes,
+            memory_total_bytes: self.memory_total_bytes,
+            processes: self
+                .processes
+                .into_iter()
+                .map(ProcessSample::from)
+                .collect(),
+            truncated: self.truncated,
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct JsonProcess {
+    pid: u32,
+    name: String,
+    cpu_percent: f32,
+    memory_percent: f32,
+    memory_bytes: u64,
+    cmdline: Option<String>,
+}
+
+impl From<&ProcessSample> for JsonProcess {
+    fn from(process: &ProcessSample) -> Self {
+        Self {
+            pid: process.pid,
+            name: process.name.clone(),
+            cpu_percent: process.cpu_percent,
+            memory_percent: process.memory_percent,
+            memory_bytes: process.memory_bytes,
+            cmdline: process.cmdline.clone(),
+        }
+    }
+}
+
+impl From<JsonProcess> for ProcessSample {
+    fn from(process: JsonProcess) -> Self {
+        Self {
+            pid: process.pid,
+            name: process.name,
+            cpu_percent: process.cpu_percent,
+            memory_percent: process.memory_percent,
+            memory_bytes: process.memory_bytes,
+            cmdline: process.cmdline,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct JsonProcessChange {
+    pid: u32,
+    cpu_percent: f32,
+    memory_percent: f32,
+    memory_bytes: u64,
+}
+
+impl From<&ProcessChange> for JsonProcessChange {
+    fn from(change: &ProcessChange) -> Self {
+        Self {
+            pid: change.pid,
+            cpu_percent: change.cpu_percent,
+            memory_percent: change.memory_percent,
+            memory_bytes: change.memory_bytes,
+        }
+    }
+}
+
+impl From<JsonProcessChange> for ProcessChange {
+    fn from(change: JsonProcessChange) -> Self {
+        Self {
+            pid: change.pid,
+            cpu_percent: change.cpu_percent,
+            memory_percent: change.memory_percent,
+            memory_bytes: change.memory_bytes,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct JsonAck {
+    message_id: Hex16,
+    success: bool,
+    error_code: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct JsonBackpressure {
+    throttle_delay_ms: u32,
+    reason: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct JsonError {
+    code: u32,
+    message: String,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct JsonSnapshotPart {
+    snapshot_id: Hex16,
+    part_index: u32,
+    part_count: u32,
+    snapshot: JsonSnapshot,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct JsonSnapshotDelta {
+    base_message_id: Hex16,
+    window_start: Timestamp,
+    window_end: Timestamp,
+    total_cpu_percent: f32,
+    memory_used_bytes: u64,
+    memory_total_bytes: u64,
+    added: Vec<JsonProcess>,
+    changed: Vec<JsonProcessChange>,
+    removed: Vec<u32>,
+    truncated: bool,
+}
+
+/// Extension entry; an array of these keeps the entry order of `Extensions`.
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct JsonExtension {
+    tag: u16,
+    value: HexBytes,
+}
+
+impl JsonExtension {
+    fn list(extensions: &Extensions) -> Vec<Self> {
+        extensions
+            .iter()
+            .map(|(tag, value)| JsonExtension {
+                tag,
+                value: HexBytes(value.to_vec()),
+            })
+            .collect()
+    }
+
+    fn collect(entries: Vec<Self>) -> Result<Extensions, ProtocolError> {
+        let mut extensions = Extensions::new();
+        for entry in entries {
+            if extensions.contains(entry.tag) {
+                return Err(invalid_json(format!(
+                    "extension tag {:#06x} appears more than once",
+                    entry.tag
+                )));
+            }
+            extensions.insert(entry.tag, entry.value.0);
+        }
+        Ok(extensions)
+    }
+}
+
+/// Protocol version as `"major.minor"`.
+struct Version(ProtocolVersion);
+
+impl Serialize for Version {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(&format_args!("{}.{}", self.0.major, self.0.minor))
+    }
+}
+
+impl<'de> Deserialize<'de> for Version {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        text.split_once('.')
+            .and_then(|(major, minor)| {
+                Some(Version(ProtocolVersion {
+                    major: major.parse().ok()?,
+                    minor: minor.parse().ok()?,
+                }))
+            })
+            .ok_or_else(|| D::Error::custom(format!("invalid version {text:?} (expected \"1.0\")")))
+    }
+}
+
+/// `MessageType` as its variant name.
+struct TypeName(MessageType);
+
+impl Serialize for TypeName {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.0.name())
+    }
+}
+
+impl<'de> Deserialize<'de> for TypeName {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        MessageType::from_name(&name)
+            .map(TypeName)
+            .ok_or_else(|| D::Error::custom(format!("unknown message type {name:?}")))
+    }
+}
+
+/// 16-byte id as 32 hex digits.
+struct Hex16([u8; 16]);
+
+impl Serialize for Hex16 {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&to_hex(&self.0))
+    }
+}
+
+impl<'de> Deserialize<'de> for Hex16 {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let HexBytes(bytes) = HexBytes::deserialize(deserializer)?;
+        let len = bytes.len();
+        bytes.try_into().map(Hex16).map_err(|_| {
+            D::Error::custom(format!(
+                "expected a 16-byte id (32 hex digits), got {len} bytes"
+            ))
+        })
+    }
+}
+
+/// Bytes as lowercase hex.
+struct HexBytes(Vec<u8>);
+
+impl Serialize for HexBytes {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&to_hex(&self.0))
+    }
+}
+
+impl<'de> Deserialize<'de> for HexBytes {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        text.as_bytes()
+            .chunks(2)
+            .map(|pair| match pair {
+                [high, low] => Some(hex_digit(*high)? << 4 | hex_digit(*low)?),
+                _ => None,
+            })
+            .collect::<Option<Vec<u8>>>()
+            .map(HexBytes)
+            .ok_or_else(|| D::Error::custom(format!("invalid hex string {text:?}")))
+    }
+}
+
+/// Value of one hex digit (either case).
+fn hex_digit(digit: u8) -> Option<u8> {
+    char::from(digit).to_digit(16).map(|value| value as u8)
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut hex, byte| {
+        let _ = write!(hex, "{byte:02x}");
+        hex
+    })
+}
+
+#[derive(Clone, Copy)]
+enum Precision {
+    Seconds,
+    Millis,
+}
+
+/// Unix timestamp, as an integer or an RFC 3339 string.
+#[derive(Serialize)]
+#[serde(untagged)]
+enum Timestamp {
+    Unix(i64),
+    Rfc3339(String),
+}
+
+impl Timestamp {
+    fn new(value: i64, precision: Precision, format: TimestampFormat) -> Self {
+        let (datetime, seconds_format) = match precision {
+            Precision::Seconds => (DateTime::from_timestamp(value, 0), SecondsFormat::Secs),
+            Precision::Millis => (
+                DateTime::from_timestamp_millis(value),
+                SecondsFormat::Millis,
+            ),
+        };
+        match (format, datetime) {
+            (TimestampFormat::Rfc3339, Some(datetime)) => {
+                Timestamp::Rfc3339(datetime.to_rfc3339_opts(seconds_format, true))
+            }
+            // Out of chrono's range: keep the exact integer
+            _ => Timestamp::Unix(value),
+        }
+    }
+
+    fn value(&self, precision: Precision, field: &str) -> Result<i64, ProtocolError> {
+        let text = match self {
+            Timestamp::Unix(value) => return Ok(*value),
+            Timestamp::Rfc3339(text) => text,
+        };
+        let datetime = DateTime::parse_from_rfc3339(text).map_err(|err| {
+            invalid_json(format!("{field}: invalid RFC 3339 time {text:?}: {err}"))
+        })?;
+        let (value, unit_nanos) = match precision {
+            Precision::Seconds => (datetime.timestamp(), 1_000_000_000),
+            Precision::Millis => (datetime.timestamp_millis(), 1_000_000),
+        };
+        if datetime.timestamp_subsec_nanos() % unit_nanos != 0 {
+            return Err(invalid_json(format!(
+                "{field}: {text:?} is more precise than the field"
+            )));
+        }
+        Ok(value)
+    }
+}
+
+impl<'de> Deserialize<'de> for Timestamp {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        match Value::deserialize(deserializer)? {
+            Value::Number(number) => number
+                .as_i64()
+                .map(Timestamp::Unix)
+                .ok_or_else(|| D::Error::custom(format!("timestamp {number} is not an integer"))),
+            Value::String(text) => Ok(Timestamp::Rfc3339(text)),
+            other => Err(D::Error::custom(format!(
+                "expected a Unix timestamp or an RFC 3339 string, got {other}"
+            ))),
+        }
+    }
+}
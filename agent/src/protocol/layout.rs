@@ -0,0 +1,253 @@
+//! Version-aware payload layout dispatch.
+//!
+//! The envelope layout is fixed for a major version; the payload layout is chosen from the
+//! envelope version through [`LAYOUTS`]. A frame whose major version has no layout is rejected
+//! with `ProtocolError::IncompatibleVersion` while the envelope is read, before any payload
+//! byte is looked at.
+//!
+//! Minor versions only append payload fields:
+//! - a frame from an older minor version lacks the newer fields, which take their defaults;
+//! - a frame from a newer minor version is decoded up to the fields this layout knows and the
+//!   rest of the payload is skipped. Its payload extension area sits behind the unknown fields,
+//!   so it cannot be located and is dropped as well.
+
+use super::{
+    read_extensions, AgentIdentity, BackpressureSignal, DecodeMode, Extensions, FieldReader,
+    HandshakeAckPayload, MessageAck, MessagePayload, MessageType, OsType, ProcessChange,
+    ProcessSampleRef, ProtocolError, ProtocolVersion, SnapshotDeltaPayload, SnapshotPartPayload,
+    SnapshotRef, VersionRange,
+};
+
+/// Smallest encoded process sample: pid, empty name, cpu, memory percent, memory bytes, no cmdline.
+const MIN_PROCESS_LEN: usize = 4 + 8 + 4 + 4 + 8 + 1;
+/// Encoded process change: pid, cpu, memory percent, memory bytes.
+const CHANGE_LEN: usize = 4 + 4 + 4 + 8;
+
+/// Payload layout of one major version.
+struct Layout {
+    /// Major version decoded by this layout
+    major: u8,
+    /// Highest minor version whose fields this layout knows
+    known_minor: u8,
+    /// Decode the fixed payload fields of a message type
+    decode: fn(
+        MessageType,
+        ProtocolVersion,
+        &mut FieldReader<'_>,
+    ) -> Result<MessagePayload, ProtocolError>,
+}
+
+/// Layouts by major version.
+const LAYOUTS: &[Layout] = &[Layout {
+    major: 1,
+    known_minor: ProtocolVersion::CURRENT.minor,
+    decode: decode_v1,
+}];
+
+fn layout_for(version: ProtocolVersion) -> Result<&'static Layout, ProtocolError> {
+    LAYOUTS
+        .iter()
+        .find(|layout| layout.major == version.major)
+        .ok_or(ProtocolError::IncompatibleVersion)
+}
+
+/// Check that frames of `version` can be decoded.
+pub(super) fn check_decodable(version: ProtocolVersion) -> Result<(), ProtocolError> {
+    layout_for(version).map(|_| ())
+}
+
+/// Check that frames of `version` can be encoded.
+///
+/// The encoder only writes fields this implementation knows, so it cannot label a frame with a
+/// newer minor version than its layout.
+pub(super) fn check_encodable(version: ProtocolVersion) -> Result<(), ProtocolError> {
+    match layout_for(version)? {
+        layout if version.minor <= layout.known_minor => Ok(()),
+        _ => Err(ProtocolError::IncompatibleVersion),
+    }
+}
+
+/// Decode payload bytes (already decompressed) for a message type and envelope version.
+///
+/// Returns the payload and the trailing payload extension area (empty if absent). Strict mode
+/// rejects any bytes left after the extension area.
+pub(super) fn decode_payload(
+    message_type: MessageType,
+    version: ProtocolVersion,
+    mut reader: FieldReader<'_>,
+) -> Result<(MessagePayload, Extensions), ProtocolError> {
+    let layout = layout_for(version)?;
+    let r = &mut reader;
+    let payload = (layout.decode)(message_type, version, r)?;
+    if version.minor > layout.known_minor {
+        r.rest();
+        return Ok((payload, Extensions::new()));
+    }
+
+    // Payloads from peers that predate extensions end at the last fixed field. Nothing marks
+    // a trailer as an extension area, so lenient mode ignores one that does not parse as such,
+    // as decoders did before extensions existed.
+    let payload_extensions = if !r.has_remaining() {
+        Extensions::new()
+    } else {
+        match r.field("payload_extensions", read_extensions) {
+            Ok(extensions) => extensions,
+            Err(_) if r.mode() == DecodeMode::Lenient => {
+                r.rest();
+                Extensions::new()
+            }
+            Err(err) => return Err(err),
+        }
+    };
+    if r.mode() == DecodeMode::Strict && r.has_remaining() {
+        let error = ProtocolError::TrailingBytes(r.remaining());
+        return Err(error.at("payload".to_string(), r.offset()));
+    }
+
+    Ok((payload, payload_extensions))
+}
+
+/// Version 1.x payload layout.
+///
+/// Every field here belongs to 1.0. A field added in minor version N must be read only when
+/// `version.minor >= N` and take its default otherwise.
+fn decode_v1(
+    message_type: MessageType,
+    version: ProtocolVersion,
+    r: &mut FieldReader<'_>,
+) -> Result<MessagePayload, ProtocolError> {
+    let payload = match message_type {
+        MessageType::Handshake => r.field("handshake", |r| {
+            let instance_id = r.field("instance_id", FieldReader::str)?.to_string();
+            let os_type = r.field("os_type", |r| OsType::from_u8(r.u8()?))?;
+            let agent_version = r.field("agent_version", FieldReader::str)?.to_string();
+            let max_version = r.field("max_version", read_version)?;
+            let capabilities = r.field("capabilities", FieldReader::u32)?;
+            // Handshakes without a min version advertise a single version.
+            let min_version = if r.has_remaining() {
+                r.field("min_version", read_version)?
+            } else {
+                max_version
+            };
+
+            Ok(MessagePayload::Handshake(AgentIdentity {
+                instance_id,
+                os_type,
+                agent_version,
+                supported_versions: VersionRange {
+                    min: min_version,
+                    max: max_version,
+                },
+                capabilities,
+            }))
+        })?,
+        MessageType::HandshakeAck => r.field("handshake_ack", |r| {
+            // An empty ack (sent before version negotiation existed) accepts the envelope
+            // version without additional capabilities.
+            let ack = if r.has_remaining() {
+                HandshakeAckPayload {
+                    negotiated_version: r.field("negotiated_version", read_version)?,
+                    negotiated_capabilities: r
+                        .field("negotiated_capabilities", FieldReader::u32)?,
+                }
+            } else {
+                HandshakeAckPayload {
+                    negotiated_version: version,
+                    negotiated_capabilities: 0,
+                }
+            };
+            Ok(MessagePayload::HandshakeAck(ack))
+        })?,
+        MessageType::Heartbeat => MessagePayload::Heartbeat,
+        MessageType::Snapshot => {
+            MessagePayload::Snapshot(r.field("snapshot", SnapshotRef::parse)?.to_owned())
+        }
+        MessageType::SnapshotPart => r.field("snapshot_part", |r| {
+            Ok(MessagePayload::SnapshotPart(SnapshotPartPayload {
+                snapshot_id: *r.field("snapshot_id", FieldReader::array)?,
+                part_index: r.field("part_index", FieldReader::u32)?,
+                part_count: r.field("part_count", FieldReader::u32)?,
+                snapshot: r.field("snapshot", SnapshotRef::parse)?.to_owned(),
+            }))
+        })?,
+        MessageType::SnapshotDelta => r.field("snapshot_delta", |r| {
+            let base_message_id = *r.field("base_message_id", FieldReader::array)?;
+            let window_start_secs = r.field("window_start_secs", FieldReader::i64)?;
+            let window_end_secs = r.field("window_end_secs", FieldReader::i64)?;
+            let total_cpu_percent = r.field("total_cpu_percent", FieldReader::f32)?;
+            let memory_used_bytes = r.field("memory_used_bytes", FieldReader::u64)?;
+            let memory_total_bytes = r.field("memory_total_bytes", FieldReader::u64)?;
+
+            let added_count = r.field("added_count", FieldReader::count)?;
+            let mut added = Vec::with_capacity(r.capacity(added_count, MIN_PROCESS_LEN));
+            for index in 0..added_count {
+                let process = r.field(|| format!("added[{index}]"), ProcessSampleRef::parse)?;
+                added.push(process.to_owned());
+            }
+            let changed_count = r.field("changed_count", FieldReader::count)?;
+            let mut changed = Vec::with_capacity(r.capacity(changed_count, CHANGE_LEN));
+            for index in 0..changed_count {
+                changed.push(r.field(
+                    || format!("changed[{index}]"),
+                    |r| {
+                        Ok(ProcessChange {
+                            pid: r.field("pid", FieldReader::u32)?,
+                            cpu_percent: r.field("cpu_percent", FieldReader::f32)?,
+                            memory_percent: r.field("memory_percent", FieldReader::f32)?,
+                            memory_bytes: r.field("memory_bytes", FieldReader::u64)?,
+                        })
+                    },
+                )?);
+            }
+            let removed_count = r.field("removed_count", FieldReader::count)?;
+            let mut removed = Vec::with_capacity(r.capacity(removed_count, 4));
+            for index in 0..removed_count {
+                removed.push(r.field(|| format!("removed[{index}]"), FieldReader::u32)?);
+            }
+
+            Ok(MessagePayload::SnapshotDelta(Box::new(
+                SnapshotDeltaPayload {
+                    base_message_id,
+                    window_start_secs,
+                    window_end_secs,
+                    total_cpu_percent,
+                    memory_used_bytes,
+                    memory_total_bytes,
+                    added,
+                    changed,
+                    removed,
+                    truncated: r.field("truncated", FieldReader::bool)?,
+                },
+            )))
+        })?,
+        MessageType::Ack => r.field("ack", |r| {
+            Ok(MessagePayload::Ack(MessageAck {
+                message_id: *r.field("message_id", FieldReader::array)?,
+                success: r.field("success", FieldReader::bool)?,
+                error_code: r.field("error_code", FieldReader::optional_u32)?,
+            }))
+        })?,
+        MessageType::Backpressure => r.field("backpressure", |r| {
+            Ok(MessagePayload::Backpressure(BackpressureSignal {
+                throttle_delay_ms: r.field("throttle_delay_ms", FieldReader::u32)?,
+                reason: r
+                    .field("reason", FieldReader::optional_str)?
+                    .map(str::to_string),
+            }))
+        })?,
+        MessageType::Error => r.field("error", |r| {
+            Ok(MessagePayload::Error {
+                code: r.field("code", FieldReader::u32)?,
+                message: r.field("message", FieldReader::str)?.to_string(),
+            })
+        })?,
+    };
+    Ok(payload)
+}
+
+fn read_version(reader: &mut FieldReader<'_>) -> Result<ProtocolVersion, ProtocolError> {
+    Ok(ProtocolVersion {
+        major: reader.u8()?,
+        minor: reader.u8()?,
+    })
+}
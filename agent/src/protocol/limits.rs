@@ -0,0 +1,121 @@
+//! Resource limits applied while decoding untrusted frames.
+//!
+//! A frame body is capped at `MAX_FRAME_SIZE`, but lengths and counts inside it are not: a
+//! string length or process count read from the wire could otherwise request an arbitrarily
+//! large allocation, and a small zstd payload can expand to gigabytes. Every decode path checks
+//! these limits before allocating.
+
+use super::{CompressionDictionary, ProtocolError};
+use std::io::Read;
+
+/// Limits applied while decoding a frame.
+///
+/// `Default` suits agent snapshots; servers may tighten or relax them per connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodeLimits {
+    /// Maximum length of any string field in bytes (agent id, process name, command line, ...)
+    pub max_string_len: usize,
+    /// Maximum number of process samples in one snapshot
+    pub max_process_count: usize,
+    /// Maximum size of a decompressed payload in bytes
+    pub max_decompressed_size: usize,
+    /// Maximum ratio of decompressed to compressed payload size
+    pub max_compression_ratio: usize,
+}
+
+impl DecodeLimits {
+    /// Default maximum string length (64 KiB).
+    pub const DEFAULT_MAX_STRING_LEN: usize = 64 * 1024;
+    /// Default maximum process count.
+    pub const DEFAULT_MAX_PROCESS_COUNT: usize = 65_536;
+    /// Default maximum decompressed payload size (16 MiB).
+    pub const DEFAULT_MAX_DECOMPRESSED_SIZE: usize = 16 * 1024 * 1024;
+    /// Default maximum compression ratio.
+    pub const DEFAULT_MAX_COMPRESSION_RATIO: usize = 100;
+
+    /// Check a string length read from the wire.
+    pub(super) fn check_string_len(&self, len: u64) -> Result<usize, ProtocolError> {
+        match usize::try_from(len) {
+            Ok(len) if len <= self.max_string_len => Ok(len),
+            _ => Err(ProtocolError::StringTooLong {
+                len,
+                max: self.max_string_len,
+            }),
+        }
+    }
+
+    /// Check a process count read from the wire.
+    pub(super) fn check_process_count(&self, count: u64) -> Result<usize, ProtocolError> {
+        match usize::try_from(count) {
+            Ok(count) if count <= self.max_process_count => Ok(count),
+            _ => Err(ProtocolError::TooManyProcesses {
+                count,
+                max: self.max_process_count,
+            }),
+        }
+    }
+
+    /// Decompress a zstd payload, stopping as soon as the output exceeds either limit.
+    ///
+    /// Never buffers more than the effective limit plus one byte. `dictionary` must be the one
+    /// the payload was compressed with, if any.
+    pub(super) fn decompress(
+        &self,
+        compressed: &[u8],
+        dictionary: Option<&CompressionDictionary>,
+    ) -> Result<Vec<u8>, ProtocolError> {
+        let ratio_limit = compressed.len().saturating_mul(self.max_compression_ratio);
+        let limit = self.max_decompressed_size.min(ratio_limit);
+
+        let decoder = match dictionary {
+            Some(dictionary) => zstd::stream::read::Decoder::with_prepared_dictionary(
+                compressed,
+                dictionary.decoder(),
+            ),
+            None => zstd::stream::read::Decoder::with_buffer(compressed),
+        }
+        .map_err(|e| ProtocolError::Compression(e.to_string()))?;
+        let mut output = Vec::new();
+        decoder
+            .take(limit as u64 + 1)
+            .read_to_end(&mut output)
+            .map_err(|e| ProtocolError::Compression(e.to_string()))?;
+
+        if output.len() <= limit {
+            Ok(output)
+        } else if ratio_limit < self.max_decompressed_size {
+            Err(ProtocolError::CompressionRatioExceeded {
+                compressed: compressed.len(),
+                max_ratio: self.max_compression_ratio,
+            })
+        } else {
+            Err(ProtocolError::DecompressedSizeExceeded {
+                max: self.max_decompressed_size,
+            })
+        }
+    }
+}
+
+/// How strictly a frame body is validated.
+///
+/// `Lenient` accepts everything earlier decoders accepted. `Strict` additionally rejects input
+/// that a conforming encoder never produces, which catches cross-language encoder bugs early.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DecodeMode {
+    /// Ignore trailing payload bytes and unknown flag bits; treat any nonzero byte as `true`
+    #[default]
+    Lenient,
+    /// Reject trailing payload bytes, unknown flag bits and booleans other than 0 and 1
+    Strict,
+}
+
+impl Default for DecodeLimits {
+    fn default() -> Self {
+        Self {
+            max_string_len: Self::DEFAULT_MAX_STRING_LEN,
+            max_process_count: Self::DEFAULT_MAX_PROCESS_COUNT,
+            max_decompressed_size: Self::DEFAULT_MAX_DECOMPRESSED_SIZE,
+            max_compression_ratio: Self::DEFAULT_MAX_COMPRESSION_RATIO,
+        }
+    }
+}
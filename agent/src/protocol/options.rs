@@ -0,0 +1,92 @@
+//! Settings for a single decode call.
+//!
+//! Limits, validation mode, frame layout and the session state a frame may need (dictionary,
+//! authentication keys, payload cipher) are independent of each other, so they are combined in
+//! one [`DecodeOptions`] value instead of one decode function per combination.
+
+use super::{
+    CompressionDictionary, DecodeLimits, DecodeMode, FrameLayout, KeyStore, PayloadCipher,
+};
+
+/// Options for `FrameCodec::decode_with_options` and the `MessageRef` constructors.
+///
+/// `Default` decodes legacy-layout frames leniently with default limits, no dictionary, no
+/// authentication and no encryption, which is what `FrameCodec::decode` does.
+#[derive(Debug, Default)]
+pub struct DecodeOptions<'a> {
+    pub(super) limits: DecodeLimits,
+    pub(super) mode: DecodeMode,
+    pub(super) layout: FrameLayout,
+    pub(super) dictionary: Option<&'a CompressionDictionary>,
+    pub(super) keys: Option<&'a dyn KeyStore>,
+    pub(super) cipher: Option<&'a mut PayloadCipher>,
+}
+
+impl<'a> DecodeOptions<'a> {
+    /// Create the default options.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Enforce `limits` on lengths, counts and decompression.
+    pub fn with_limits(self, limits: DecodeLimits) -> Self {
+        Self { limits, ..self }
+    }
+
+    /// Apply the validation rules of `mode`.
+    pub fn with_mode(self, mode: DecodeMode) -> Self {
+        Self { mode, ..self }
+    }
+
+    /// Read frames in `layout`.
+    pub fn with_layout(self, layout: FrameLayout) -> Self {
+        Self { layout, ..self }
+    }
+
+    /// Decompress payloads that reference `dictionary` (the one negotiated for the session).
+    ///
+    /// A payload compressed with any other dictionary fails with
+    /// `ProtocolError::UnknownDictionary`.
+    pub fn with_dictionary(self, dictionary: &'a CompressionDictionary) -> Self {
+        Self {
+            dictionary: Some(dictionary),
+            ..self
+        }
+    }
+
+    /// Require every frame to be authenticated with a key from `keys`.
+    ///
+    /// The tag is checked before the payload is decrypted or decompressed.
+    pub fn with_key_store(self, keys: &'a dyn KeyStore) -> Self {
+        Self {
+            keys: Some(keys),
+            ..self
+        }
+    }
+
+    /// Decrypt payloads with `cipher` (the session opener for this direction).
+    ///
+    /// A configured cipher makes encryption mandatory: a frame without `FLAG_ENCRYPTED` fails
+    /// with `ProtocolError::UnencryptedFrame`.
+    pub fn with_cipher(self, cipher: &'a mut PayloadCipher) -> Self {
+        Self {
+            cipher: Some(cipher),
+            ..self
+        }
+    }
+
+    /// Limits enforced while decoding.
+    pub fn limits(&self) -> &DecodeLimits {
+        &self.limits
+    }
+
+    /// Validation mode applied while decoding.
+    pub fn mode(&self) -> DecodeMode {
+        self.mode
+    }
+
+    /// Frame layout expected by the decoder.
+    pub fn layout(&self) -> FrameLayout {
+        self.layout
+    }
+}
@@ -0,0 +1,198 @@
+//! Bounds-checked field reader shared by the owned and borrowed decoders.
+//!
+//! Fields are read through [`FieldReader::field`], which locates any error at the field's
+//! byte offset. Nested `field` calls join their names, so an error in a process name surfaces
+//! as `snapshot.processes[3].name` at the offset of the name itself. Offsets are relative to
+//! the frame body; fields inside a compressed payload report their offset within the
+//! decompressed payload instead.
+
+use super::{DecodeLimits, DecodeMode, ProtocolError};
+use std::io;
+
+/// Name of a field path segment; closures defer formatting until an error occurs.
+pub(super) trait FieldName {
+    fn into_name(self) -> String;
+}
+
+impl FieldName for &'static str {
+    fn into_name(self) -> String {
+        self.to_string()
+    }
+}
+
+impl<F: FnOnce() -> String> FieldName for F {
+    fn into_name(self) -> String {
+        self()
+    }
+}
+
+/// Little-endian reader over a byte slice that hands out borrowed slices.
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct FieldReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    /// Offset of `data[0]` in the reported coordinate space
+    base: usize,
+    limits: DecodeLimits,
+    mode: DecodeMode,
+}
+
+impl<'a> FieldReader<'a> {
+    pub(super) fn new(data: &'a [u8], base: usize, limits: DecodeLimits, mode: DecodeMode) -> Self {
+        Self {
+            data,
+            pos: 0,
+            base,
+            limits,
+            mode,
+        }
+    }
+
+    pub(super) fn limits(&self) -> &DecodeLimits {
+        &self.limits
+    }
+
+    pub(super) fn mode(&self) -> DecodeMode {
+        self.mode
+    }
+
+    /// Reported offset of the next unread byte.
+    pub(super) fn offset(&self) -> usize {
+        self.base + self.pos
+    }
+
+    pub(super) fn has_remaining(&self) -> bool {
+        self.pos < self.data.len()
+    }
+
+    pub(super) fn remaining(&self) -> usize {
+        self.data.len() - self.pos
+    }
+
+    /// Take all remaining bytes.
+    pub(super) fn rest(&mut self) -> &'a [u8] {
+        let rest = &self.data[self.pos..];
+        self.pos = self.data.len();
+        rest
+    }
+
+    /// Read one field, locating any error at `name` and the field's starting offset.
+    pub(super) fn field<T>(
+        &mut self,
+        name: impl FieldName,
+        read: impl FnOnce(&mut Self) -> Result<T, ProtocolError>,
+    ) -> Result<T, ProtocolError> {
+        let offset = self.offset();
+        read(self).map_err(|e| e.at(name.into_name(), offset))
+    }
+
+    pub(super) fn bytes(&mut self, len: usize) -> Result<&'a [u8], ProtocolError> {
+        let end = self
+            .pos
+            .checked_add(len)
+            .filter(|&end| end <= self.data.len())
+            .ok_or_else(|| ProtocolError::Io(io::ErrorKind::UnexpectedEof.into()))?;
+        let bytes = &self.data[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    pub(super) fn array<const N: usize>(&mut self) -> Result<&'a [u8; N], ProtocolError> {
+        self.bytes(N)?
+            .try_into()
+            .map_err(|_| ProtocolError::Io(io::ErrorKind::UnexpectedEof.into()))
+    }
+
+    pub(super) fn u8(&mut self) -> Result<u8, ProtocolError> {
+        Ok(self.array::<1>()?[0])
+    }
+
+    pub(super) fn u16(&mut self) -> Result<u16, ProtocolError> {
+        Ok(u16::from_le_bytes(*self.array()?))
+    }
+
+    pub(super) fn u32(&mut self) -> Result<u32, ProtocolError> {
+        Ok(u32::from_le_bytes(*self.array()?))
+    }
+
+    pub(super) fn u64(&mut self) -> Result<u64, ProtocolError> {
+        Ok(u64::from_le_bytes(*self.array()?))
+    }
+
+    pub(super) fn i64(&mut self) -> Result<i64, ProtocolError> {
+        Ok(i64::from_le_bytes(*self.array()?))
+    }
+
+    pub(super) fn f32(&mut self) -> Result<f32, ProtocolError> {
+        Ok(f32::from_le_bytes(*self.array()?))
+    }
+
+    /// Boolean or presence flag; strict mode accepts only 0 and 1.
+    pub(super) fn bool(&mut self) -> Result<bool, ProtocolError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            other if self.mode == DecodeMode::Strict => Err(ProtocolError::InvalidBool(other)),
+            _ => Ok(true),
+        }
+    }
+
+    /// u64 element count of a process list, bounded by `DecodeLimits::max_process_count`.
+    pub(super) fn count(&mut self) -> Result<usize, ProtocolError> {
+        let count = self.u64()?;
+        self.limits.check_process_count(count)
+    }
+
+    /// Capacity to reserve for `count` elements (already checked against the limits) of at least
+    /// `min_size` bytes each: no more than the unread bytes can hold, so a count the peer
+    /// inflated cannot force a large allocation before the elements are read.
+    pub(super) fn capacity(&self, count: usize, min_size: usize) -> usize {
+        count.min(self.remaining() / min_size.max(1))
+    }
+
+    /// u64 length-prefixed UTF-8 string, bounded by `DecodeLimits::max_string_len`.
+    pub(super) fn str(&mut self) -> Result<&'a str, ProtocolError> {
+        let len = self.u64()?;
+        let len = self.limits.check_string_len(len)?;
+        std::str::from_utf8(self.bytes(len)?)
+            .map_err(|e| ProtocolError::Serialization(e.to_string()))
+    }
+
+    pub(super) fn optional_str(&mut self) -> Result<Option<&'a str>, ProtocolError> {
+        if self.bool()? {
+            Ok(Some(self.str()?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    pub(super) fn optional_u32(&mut self) -> Result<Option<u32>, ProtocolError> {
+        if self.bool()? {
+            Ok(Some(self.u32()?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Validate an extension area and return its raw bytes; see `extensions.rs` for the layout.
+    ///
+    /// Rejects duplicate tags without allocating.
+    pub(super) fn extension_area(&mut self) -> Result<&'a [u8], ProtocolError> {
+        let start = self.pos;
+        let mut seen = [0u64; 1024];
+        for _ in 0..self.u16()? {
+            let tag = self.u16()?;
+            let len = self.u32()? as usize;
+            self.bytes(len)?;
+            let (word, bit) = (usize::from(tag) / 64, 1u64 << (tag % 64));
+            if seen[word] & bit != 0 {
+                return Err(ProtocolError::InvalidExtension {
+                    tag,
+                    reason: "duplicate tag".to_string(),
+                });
+            }
+            seen[word] |= bit;
+        }
+        Ok(&self.data[start..self.pos])
+    }
+}
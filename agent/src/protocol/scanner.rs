@@ -0,0 +1,304 @@
+//! Recovery-mode frame scanning for damaged captures.
+//!
+//! `FrameCodec::decode` stops at the first invalid frame, so a single corrupt byte or a
+//! half-written tail makes every following frame unreachable. `FrameScanner` instead walks a
+//! buffer of concatenated frames and, whenever the frame at the current offset is invalid,
+//! advances byte by byte until the next offset that holds a length-, CRC- and decode-valid frame.
+//! Every recovered message is reported with its byte offset and every skipped region with the
+//! reason the first offset in that region was rejected.
+//!
+//! A capture may be in either [`FrameLayout`]; unless one is given, the scanner detects it.
+//! Frames compressed with a dictionary the scanner was not given are intact, so they are skipped
+//! as a whole and reported with the dictionary id rather than as corruption.
+
+use super::{
+    decode_body, verify_crc32, CompressionDictionary, DecodeOptions, FrameLayout, Message,
+    MessageType, ProtocolError,
+};
+
+/// Offset of the message type byte within the frame body (after major/minor).
+const MESSAGE_TYPE_OFFSET: usize = 2;
+/// Smallest possible body: fixed envelope fields with an empty agent id and no payload.
+const MIN_BODY_SIZE: usize = 1 + 1 + 1 + 16 + 8 + 8 + 1 + 1;
+
+/// Why a byte range was skipped during a recovery scan.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SkipReason {
+    /// Declared body length cannot hold a valid frame (too small or above `MAX_FRAME_SIZE`)
+    InvalidLength(usize),
+    /// Declared frame extends beyond the end of the data
+    Truncated { declared: usize, available: usize },
+    /// Message type byte does not name a known message type
+    InvalidMessageType(u8),
+    /// Length was plausible but the CRC32 trailer did not match the body
+    Crc32Mismatch { expected: u32, actual: u32 },
+    /// CRC32 matched but the body could not be decoded
+    Malformed(String),
+    /// CRC32 matched but the payload needs compression dictionary `dictionary_id`, which the
+    /// scanner was not given; the skipped range is exactly the frame's `len` bytes
+    UnknownDictionary { dictionary_id: u32, len: usize },
+    /// CRC32 matched but the payload is encrypted, which the scanner cannot open; the skipped
+    /// range is exactly the frame's `len` bytes
+    Encrypted { len: usize },
+}
+
+/// A message recovered by a [`FrameScanner`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecoveredFrame {
+    /// Byte offset of the frame's length prefix
+    pub offset: usize,
+    /// Total frame length in bytes (length prefix + CRC32 + body)
+    pub len: usize,
+    /// Decoded message
+    pub message: Message,
+}
+
+/// A contiguous byte range that did not contain a valid frame.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SkippedRange {
+    /// First skipped byte offset (inclusive)
+    pub start: usize,
+    /// End of the skipped region (exclusive); the next valid frame or the end of data
+    pub end: usize,
+    /// Reason the frame at `start` was rejected
+    pub reason: SkipReason,
+}
+
+impl SkippedRange {
+    /// Number of skipped bytes.
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    /// True if the range is empty (never produced by the scanner).
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+}
+
+/// Item produced by a [`FrameScanner`], in file order.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ScanItem {
+    Frame(RecoveredFrame),
+    Skipped(SkippedRange),
+}
+
+/// Collected result of [`scan_frames`].
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ScanReport {
+    /// Frame layout of the data
+    pub layout: FrameLayout,
+    /// Recovered frames in file order
+    pub frames: Vec<RecoveredFrame>,
+    /// Skipped regions in file order
+    pub skipped: Vec<SkippedRange>,
+}
+
+impl ScanReport {
+    /// True if the data was a clean sequence of valid frames.
+    pub fn is_clean(&self) -> bool {
+        self.skipped.is_empty()
+    }
+
+    /// Total number of skipped bytes.
+    pub fn skipped_bytes(&self) -> usize {
+        self.skipped.iter().map(SkippedRange::len).sum()
+    }
+}
+
+/// Iterator that resynchronizes on the next valid frame after corrupt or truncated regions.
+#[derive(Debug)]
+pub struct FrameScanner<'a> {
+    data: &'a [u8],
+    layout: FrameLayout,
+    dictionary: Option<&'a CompressionDictionary>,
+    offset: usize,
+    /// Frame found while searching for the end of a skipped range, returned next
+    pending: Option<RecoveredFrame>,
+}
+
+impl<'a> FrameScanner<'a> {
+    /// Create a scanner over a buffer of concatenated frames, detecting their layout from the
+    /// first frame.
+    ///
+    /// Falls back to `FrameLayout::Legacy` if the first frame is valid in neither layout.
+    pub fn new(data: &'a [u8]) -> Self {
+        Self::with_layout(data, FrameLayout::detect(data).unwrap_or_default())
+    }
+
+    /// Create a scanner over a buffer of concatenated frames in `layout`.
+    pub fn with_layout(data: &'a [u8], layout: FrameLayout) -> Self {
+        Self {
+            data,
+            layout,
+            dictionary: None,
+            offset: 0,
+            pending: None,
+        }
+    }
+
+    /// Decompress payloads that reference `dictionary` instead of skipping their frames.
+    pub fn with_dictionary(self, dictionary: &'a CompressionDictionary) -> Self {
+        Self {
+            dictionary: Some(dictionary),
+            ..self
+        }
+    }
+
+    /// Frame layout the scanner reads.
+    pub fn layout(&self) -> FrameLayout {
+        self.layout
+    }
+
+    /// Current scan position (start of the next item).
+    pub fn offset(&self) -> usize {
+        self.pending
+            .as_ref()
+            .map_or(self.offset, |frame| frame.offset)
+    }
+}
+
+impl Iterator for FrameScanner<'_> {
+    type Item = ScanItem;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(frame) = self.pending.take() {
+            return Some(ScanItem::Frame(frame));
+        }
+        if self.offset >= self.data.len() {
+            return None;
+        }
+
+        let start = self.offset;
+        match try_frame_at(self.data, start, self.layout, self.dictionary) {
+            Ok(frame) => {
+                self.offset = start + frame.len;
+                Some(ScanItem::Frame(frame))
+            }
+            // An intact frame: resume right after it
+            Err(
+                reason
+                @ (SkipReason::UnknownDictionary { len, .. } | SkipReason::Encrypted { len }),
+            ) => {
+                self.offset = start + len;
+                Some(ScanItem::Skipped(SkippedRange {
+                    start,
+                    end: self.offset,
+                    reason,
+                }))
+            }
+            Err(reason) => {
+                // Slide forward one byte at a time until a valid frame starts.
+                let mut pos = start + 1;
+                while pos < self.data.len() {
+                    if let Ok(frame) = try_frame_at(self.data, pos, self.layout, self.dictionary) {
+                        self.offset = pos + frame.len;
+                        self.pending = Some(frame);
+                        break;
+                    }
+                    pos += 1;
+                }
+                if self.pending.is_none() {
+                    self.offset = self.data.len();
+                }
+                Some(ScanItem::Skipped(SkippedRange {
+                    start,
+                    end: pos,
+                    reason,
+                }))
+            }
+        }
+    }
+}
+
+/// Scan a buffer of concatenated frames, collecting recovered frames and skipped regions.
+///
+/// The frame layout is detected as in [`FrameScanner::new`].
+pub fn scan_frames(data: &[u8]) -> ScanReport {
+    let scanner = FrameScanner::new(data);
+    let mut report = ScanReport {
+        layout: scanner.layout(),
+        ..ScanReport::default()
+    };
+    for item in scanner {
+        match item {
+            ScanItem::Frame(frame) => report.frames.push(frame),
+            ScanItem::Skipped(range) => report.skipped.push(range),
+        }
+    }
+    report
+}
+
+/// Attempt to decode a complete, valid frame in `layout` starting at `offset`, decompressing
+/// with `dictionary` if the payload references it.
+///
+/// Cheap structural checks run before the CRC so that sliding over garbage stays fast.
+pub(super) fn try_frame_at(
+    data: &[u8],
+    offset: usize,
+    layout: FrameLayout,
+    dictionary: Option<&CompressionDictionary>,
+) -> Result<RecoveredFrame, SkipReason> {
+    let available = data.len() - offset;
+    let header_len = layout.header_len();
+    if available < header_len {
+        return Err(SkipReason::Truncated {
+            declared: header_len,
+            available,
+        });
+    }
+
+    let header = &data[offset..offset + header_len];
+    let body_len = match layout.body_len(header) {
+        Ok(body_len) if body_len >= MIN_BODY_SIZE => body_len,
+        Ok(body_len) | Err(ProtocolError::FrameTooLarge(body_len, _)) => {
+            return Err(SkipReason::InvalidLength(body_len))
+        }
+        Err(_) => return Err(SkipReason::InvalidLength(0)),
+    };
+
+    let frame_len = layout.frame_len(body_len);
+    if available < frame_len {
+        return Err(SkipReason::Truncated {
+            declared: frame_len,
+            available,
+        });
+    }
+
+    let body_start = offset + header_len;
+    let body = &data[body_start..body_start + body_len];
+    let message_type = body[MESSAGE_TYPE_OFFSET];
+    if MessageType::from_u8(message_type).is_err() {
+        return Err(SkipReason::InvalidMessageType(message_type));
+    }
+
+    let trailer = &data[body_start + body_len..offset + frame_len];
+    match layout
+        .stored_crc(header, trailer)
+        .and_then(|expected| verify_crc32(body, expected))
+    {
+        Ok(()) => {}
+        Err(ProtocolError::Crc32Mismatch { expected, actual }) => {
+            return Err(SkipReason::Crc32Mismatch { expected, actual })
+        }
+        Err(other) => return Err(SkipReason::Malformed(other.to_string())),
+    }
+
+    let mut options = DecodeOptions {
+        dictionary,
+        ..DecodeOptions::default()
+    };
+    let message = decode_body(body, &mut options).map_err(|e| match e.root_cause() {
+        ProtocolError::UnknownDictionary(dictionary_id) => SkipReason::UnknownDictionary {
+            dictionary_id: *dictionary_id,
+            len: frame_len,
+        },
+        ProtocolError::Encryption(_) => SkipReason::Encrypted { len: frame_len },
+        _ => SkipReason::Malformed(e.to_string()),
+    })?;
+    Ok(RecoveredFrame {
+        offset,
+        len: frame_len,
+        message,
+    })
+}
@@ -0,0 +1,377 @@
+//! Snapshot segmentation and reassembly (FR-009).
+//!
+//! An all-process snapshot can exceed `TARGET_FRAME_SIZE` even after compression. The splitter
+//! cuts the process list into contiguous slices so that every `SnapshotPart` frame fits the
+//! target, and the reassembler rebuilds the original snapshot on the receiving side. Parts share
+//! a 16-byte snapshot id and may arrive duplicated (at-least-once delivery) or out of order.
+
+use super::{
+    Envelope, Extensions, FrameEncoder, Message, MessagePayload, MessageType, ProtocolError,
+    SnapshotPartPayload, SnapshotPayload,
+};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::ops::Range;
+
+/// Upper bound on `part_count` (1024 parts of 64 KiB is a 64 MiB snapshot).
+///
+/// Bounds the slot table a reassembler allocates for an untrusted part count.
+pub const MAX_SNAPSHOT_PARTS: u32 = 1024;
+
+/// Split a snapshot into parts whose encoded frames fit within `target_frame_size` bytes.
+///
+/// Frames are sized with `encoder`, the one the parts will be sent with, so its compression
+/// policy and dictionary, authentication and encryption count towards the target. `envelope`
+/// supplies the fields the parts will be sent with (agent id, compression flag); its message
+/// type is replaced with `SnapshotPart` for sizing. Each part needs its own `message_id` when
+/// wrapped into a message. A snapshot that already fits yields a single part.
+///
+/// Each part is found by growing a slice of the remaining processes until it no longer fits,
+/// then bisecting, so sizing accounts for compression exactly rather than estimating it.
+///
+/// Returns `ProtocolError::FrameTooLarge` if a single process cannot fit in a part, and
+/// `ProtocolError::InvalidSnapshotPart` if more than `MAX_SNAPSHOT_PARTS` parts are needed.
+pub fn split_snapshot(
+    encoder: &mut FrameEncoder,
+    envelope: &Envelope,
+    snapshot: &SnapshotPayload,
+    snapshot_id: [u8; 16],
+    target_frame_size: usize,
+) -> Result<Vec<SnapshotPartPayload>, ProtocolError> {
+    let envelope = Envelope {
+        message_type: MessageType::SnapshotPart,
+        ..envelope.clone()
+    };
+    let total = snapshot.processes.len();
+    let mut fits = |part: SnapshotPartPayload| -> Result<(bool, usize), ProtocolError> {
+        let len = part_frame_len(encoder, &envelope, part)?;
+        Ok((len <= target_frame_size, len))
+    };
+
+    let (whole_fits, whole_len) = fits(make_part(snapshot, snapshot_id, 0, 1, 0..total))?;
+    if whole_fits {
+        return Ok(vec![make_part(snapshot, snapshot_id, 0, 1, 0..total)]);
+    }
+    if total == 0 {
+        return Err(ProtocolError::FrameTooLarge(whole_len, target_frame_size));
+    }
+
+    // The part index and count are compressed along with the processes, so parts are sized
+    // with their final values. Split again with the count found until it stops growing.
+    let mut part_count = 2;
+    let ranges = loop {
+        let mut ranges: Vec<Range<usize>> = Vec::new();
+        let mut start = 0;
+        while start < total && ranges.len() <= MAX_SNAPSHOT_PARTS as usize {
+            let index = ranges.len() as u32;
+            let end = largest_fitting_end(
+                |end| {
+                    fits(make_part(
+                        snapshot,
+                        snapshot_id,
+                        index,
+                        part_count,
+                        start..end,
+                    ))
+                },
+                start,
+                total,
+                target_frame_size,
+            )?;
+            ranges.push(start..end);
+            start = end;
+        }
+        if ranges.len() > MAX_SNAPSHOT_PARTS as usize {
+            return Err(ProtocolError::InvalidSnapshotPart(format!(
+                "snapshot needs more than {MAX_SNAPSHOT_PARTS} parts"
+            )));
+        }
+        if ranges.len() <= part_count as usize {
+            break ranges;
+        }
+        part_count = ranges.len() as u32;
+    };
+
+    // Fewer parts than sized for: empty parts make up the count every part was sized with
+    let mut parts = Vec::with_capacity(part_count as usize);
+    for index in 0..part_count {
+        let range = ranges.get(index as usize).cloned().unwrap_or(total..total);
+        let part = make_part(snapshot, snapshot_id, index, part_count, range);
+        let (part_fits, len) = fits(part.clone())?;
+        if !part_fits {
+            return Err(ProtocolError::FrameTooLarge(len, target_frame_size));
+        }
+        parts.push(part);
+    }
+    Ok(parts)
+}
+
+/// Find the largest `end` such that processes `start..end` fit in one part frame.
+///
+/// The slice doubles until it no longer fits, so no probe encodes more than twice the
+/// processes of the part it finds, however many remain.
+fn largest_fitting_end(
+    mut fits: impl FnMut(usize) -> Result<(bool, usize), ProtocolError>,
+    start: usize,
+    total: usize,
+    target_frame_size: usize,
+) -> Result<usize, ProtocolError> {
+    let (first_fits, first_len) = fits(start + 1)?;
+    if !first_fits {
+        return Err(ProtocolError::FrameTooLarge(first_len, target_frame_size));
+    }
+
+    // Invariant: start..lo fits, start..hi does not (`total + 1` until a probe fails).
+    let (mut lo, mut hi) = (start + 1, total + 1);
+    while lo < total && hi > total {
+        let probe = (start + 2 * (lo - start)).min(total);
+        if fits(probe)?.0 {
+            lo = probe;
+        } else {
+            hi = probe;
+        }
+    }
+    while hi - lo > 1 {
+        let mid = lo + (hi - lo) / 2;
+        if fits(mid)?.0 {
+            lo = mid;
+        } else {
+            hi = mid;
+        }
+    }
+    Ok(lo)
+}
+
+fn make_part(
+    snapshot: &SnapshotPayload,
+    snapshot_id: [u8; 16],
+    part_index: u32,
+    part_count: u32,
+    range: Range<usize>,
+) -> SnapshotPartPayload {
+    SnapshotPartPayload {
+        snapshot_id,
+        part_index,
+        part_count,
+        snapshot: SnapshotPayload {
+            window_start_secs: snapshot.window_start_secs,
+            window_end_secs: snapshot.window_end_secs,
+            total_cpu_percent: snapshot.total_cpu_percent,
+            memory_used_bytes: snapshot.memory_used_bytes,
+            memory_total_bytes: snapshot.memory_total_bytes,
+            processes: snapshot.processes[range].to_vec(),
+            truncated: snapshot.truncated,
+        },
+    }
+}
+
+/// Encoded frame length of a part; frames above `MAX_FRAME_SIZE` report their size too.
+fn part_frame_len(
+    encoder: &mut FrameEncoder,
+    envelope: &Envelope,
+    part: SnapshotPartPayload,
+) -> Result<usize, ProtocolError> {
+    let message = Message {
+        envelope: envelope.clone(),
+        payload: MessagePayload::SnapshotPart(part),
+        payload_extensions: Extensions::new(),
+    };
+    encoder.frame_len(&message)
+}
+
+/// Bounds on the state a [`SnapshotReassembler`] keeps for untrusted senders.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReassemblyLimits {
+    /// Maximum number of incomplete snapshots; the oldest is dropped to make room for a new one
+    pub max_pending_snapshots: usize,
+    /// Maximum number of part slots (sum of `part_count`) over all incomplete snapshots
+    pub max_pending_parts: usize,
+    /// Number of completed snapshot ids remembered to ignore late duplicates; oldest forgotten first
+    pub max_completed_snapshots: usize,
+}
+
+impl ReassemblyLimits {
+    /// Default maximum number of incomplete snapshots.
+    pub const DEFAULT_MAX_PENDING_SNAPSHOTS: usize = 16;
+    /// Default maximum number of part slots over all incomplete snapshots.
+    pub const DEFAULT_MAX_PENDING_PARTS: usize = 4 * MAX_SNAPSHOT_PARTS as usize;
+    /// Default number of completed snapshot ids remembered.
+    pub const DEFAULT_MAX_COMPLETED_SNAPSHOTS: usize = 1024;
+}
+
+impl Default for ReassemblyLimits {
+    fn default() -> Self {
+        Self {
+            max_pending_snapshots: Self::DEFAULT_MAX_PENDING_SNAPSHOTS,
+            max_pending_parts: Self::DEFAULT_MAX_PENDING_PARTS,
+            max_completed_snapshots: Self::DEFAULT_MAX_COMPLETED_SNAPSHOTS,
+        }
+    }
+}
+
+/// Parts received so far for one snapshot id.
+#[derive(Debug)]
+struct PendingSnapshot {
+    parts: Vec<Option<SnapshotPayload>>,
+    received: usize,
+}
+
+/// Reassembles segmented snapshots from their parts.
+///
+/// Parts may arrive in any order and more than once. Each snapshot is returned exactly once,
+/// when its last missing part arrives; later duplicates of a completed snapshot are ignored
+/// while its id is still remembered. Memory is bounded by [`ReassemblyLimits`]: when a new
+/// snapshot would exceed them, the oldest incomplete snapshots are dropped.
+#[derive(Debug, Default)]
+pub struct SnapshotReassembler {
+    limits: ReassemblyLimits,
+    pending: HashMap<[u8; 16], PendingSnapshot>,
+    /// Pending snapshot ids, oldest first
+    pending_order: VecDeque<[u8; 16]>,
+    pending_parts: usize,
+    completed: HashSet<[u8; 16]>,
+    /// Completed snapshot ids, oldest first
+    completed_order: VecDeque<[u8; 16]>,
+}
+
+impl SnapshotReassembler {
+    /// Create an empty reassembler with default limits.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create an empty reassembler with custom limits.
+    pub fn with_limits(limits: ReassemblyLimits) -> Self {
+        Self {
+            limits,
+            ..Self::default()
+        }
+    }
+
+    /// Add a part; returns the complete snapshot when this part finishes it.
+    ///
+    /// Returns `ProtocolError::InvalidSnapshotPart` if the part's index or count is
+    /// inconsistent with itself or with earlier parts of the same snapshot.
+    pub fn push(
+        &mut self,
+        part: SnapshotPartPayload,
+    ) -> Result<Option<SnapshotPayload>, ProtocolError> {
+        if part.part_count == 0 || part.part_count > MAX_SNAPSHOT_PARTS {
+            return Err(ProtocolError::InvalidSnapshotPart(format!(
+                "part_count {} outside 1..={MAX_SNAPSHOT_PARTS}",
+                part.part_count
+            )));
+        }
+        if part.part_index >= part.part_count {
+            return Err(ProtocolError::InvalidSnapshotPart(format!(
+                "part_index {} out of range for part_count {}",
+                part.part_index, part.part_count
+            )));
+        }
+        if self.completed.contains(&part.snapshot_id) {
+            return Ok(None);
+        }
+        if !self.pending.contains_key(&part.snapshot_id) {
+            self.make_room(part.part_count as usize)?;
+            self.pending.insert(
+                part.snapshot_id,
+                PendingSnapshot {
+                    parts: vec![None; part.part_count as usize],
+                    received: 0,
+                },
+            );
+            self.pending_order.push_back(part.snapshot_id);
+            self.pending_parts += part.part_count as usize;
+        }
+
+        let Some(pending) = self.pending.get_mut(&part.snapshot_id) else {
+            return Ok(None);
+        };
+        if pending.parts.len() != part.part_count as usize {
+            return Err(ProtocolError::InvalidSnapshotPart(format!(
+                "part_count {} does not match earlier parts ({})",
+                part.part_count,
+                pending.parts.len()
+            )));
+        }
+
+        let slot = &mut pending.parts[part.part_index as usize];
+        if slot.is_some() {
+            return Ok(None);
+        }
+        *slot = Some(part.snapshot);
+        pending.received += 1;
+        if pending.received < pending.parts.len() {
+            return Ok(None);
+        }
+
+        let parts = self
+            .remove_pending(&part.snapshot_id)
+            .map(|pending| pending.parts)
+            .unwrap_or_default();
+        self.remember_completed(part.snapshot_id);
+        Ok(assemble(parts.into_iter().flatten()))
+    }
+
+    /// Drop the oldest incomplete snapshots until one with `part_count` parts fits the limits.
+    fn make_room(&mut self, part_count: usize) -> Result<(), ProtocolError> {
+        if part_count > self.limits.max_pending_parts || self.limits.max_pending_snapshots == 0 {
+            return Err(ProtocolError::InvalidSnapshotPart(format!(
+                "part_count {part_count} exceeds the reassembly limit of {} parts",
+                self.limits.max_pending_parts
+            )));
+        }
+        while self.pending.len() >= self.limits.max_pending_snapshots
+            || self.pending_parts + part_count > self.limits.max_pending_parts
+        {
+            let Some(oldest) = self.pending_order.front().copied() else {
+                break;
+            };
+            self.remove_pending(&oldest);
+        }
+        Ok(())
+    }
+
+    fn remove_pending(&mut self, snapshot_id: &[u8; 16]) -> Option<PendingSnapshot> {
+        let pending = self.pending.remove(snapshot_id)?;
+        self.pending_order.retain(|id| id != snapshot_id);
+        self.pending_parts -= pending.parts.len();
+        Some(pending)
+    }
+
+    fn remember_completed(&mut self, snapshot_id: [u8; 16]) {
+        if self.limits.max_completed_snapshots == 0 || !self.completed.insert(snapshot_id) {
+            return;
+        }
+        self.completed_order.push_back(snapshot_id);
+        while self.completed_order.len() > self.limits.max_completed_snapshots {
+            if let Some(oldest) = self.completed_order.pop_front() {
+                self.completed.remove(&oldest);
+            }
+        }
+    }
+
+    /// Number of snapshots with at least one part received but not yet complete.
+    pub fn pending_count(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Check if a snapshot id has already been reassembled.
+    pub fn is_completed(&self, snapshot_id: &[u8; 16]) -> bool {
+        self.completed.contains(snapshot_id)
+    }
+
+    /// Drop the parts received so far for an incomplete snapshot.
+    pub fn discard(&mut self, snapshot_id: &[u8; 16]) {
+        self.remove_pending(snapshot_id);
+    }
+}
+
+/// Concatenate parts (in index order) into one snapshot; aggregates come from the first part.
+fn assemble(mut parts: impl Iterator<Item = SnapshotPayload>) -> Option<SnapshotPayload> {
+    let mut snapshot = parts.next()?;
+    for part in parts {
+        snapshot.truncated |= part.truncated;
+        snapshot.processes.extend(part.processes);
+    }
+    Some(snapshot)
+}
@@ -0,0 +1,204 @@
+//! Async framing for tokio (enabled with the `tokio` feature).
+//!
+//! `MessageCodec` plugs into `tokio_util::codec::{Framed, FramedRead, FramedWrite}`;
+//! [`read_message`] and [`write_message`] are the `AsyncRead`/`AsyncWrite` counterparts of
+//! `FrameCodec::decode` and `FrameCodec::write`. Both apply the same length, CRC32 and size
+//! checks as the synchronous path. `MessageCodec` and [`read_message_with_options`] read the
+//! legacy frame layout unless another [`FrameLayout`] is set; [`write_message`] writes it.
+
+use super::{
+    decode_body, verify_crc32, CompressionDictionary, DecodeLimits, DecodeMode, DecodeOptions,
+    FrameCodec, FrameEncoder, FrameLayout, KeyStore, Message, ProtocolError, CRC_SIZE,
+    LENGTH_PREFIX_SIZE,
+};
+use bytes::{Bytes, BytesMut};
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio_util::codec::{Decoder, Encoder};
+
+/// `tokio_util` codec for protocol frames.
+///
+/// A frame error (oversized length, CRC mismatch, malformed body) is returned from `decode`;
+/// the stream should be closed afterwards, since the next frame boundary is unknown.
+///
+/// Frames are written with one [`FrameEncoder`] for the codec's lifetime, so its buffers and
+/// zstd context are reused across messages. A clone gets an encoder of its own.
+#[derive(Debug, Default)]
+pub struct MessageCodec {
+    limits: DecodeLimits,
+    mode: DecodeMode,
+    dictionary: Option<CompressionDictionary>,
+    keys: Option<Arc<dyn KeyStore>>,
+    layout: FrameLayout,
+    encoder: FrameEncoder,
+}
+
+impl Clone for MessageCodec {
+    fn clone(&self) -> Self {
+        Self {
+            limits: self.limits,
+            mode: self.mode,
+            dictionary: self.dictionary.clone(),
+            keys: self.keys.clone(),
+            layout: self.layout,
+            encoder: FrameEncoder::new(),
+        }
+        .rebuild_encoder()
+    }
+}
+
+impl MessageCodec {
+    /// Create a codec with default decode limits.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a codec that enforces `limits` on every decoded frame.
+    pub fn with_limits(limits: DecodeLimits) -> Self {
+        Self::with_options(limits, DecodeMode::Lenient)
+    }
+
+    /// Create a codec that enforces `limits` and the rules of `mode` on every decoded frame.
+    pub fn with_options(limits: DecodeLimits, mode: DecodeMode) -> Self {
+        Self {
+            limits,
+            mode,
+            dictionary: None,
+            keys: None,
+            layout: FrameLayout::Legacy,
+            encoder: FrameEncoder::new(),
+        }
+    }
+
+    /// Compress and decompress payloads with `dictionary` (the one negotiated for the session).
+    pub fn with_dictionary(self, dictionary: CompressionDictionary) -> Self {
+        Self {
+            dictionary: Some(dictionary),
+            ..self
+        }
+        .rebuild_encoder()
+    }
+
+    /// Sign written frames with the signing key `keys` holds for their agent and timestamp, and
+    /// require every read frame to be authenticated with a key from `keys`.
+    ///
+    /// Frames that fail verification are reported as `ProtocolError::AuthenticationFailed`.
+    pub fn with_key_store(self, keys: Arc<dyn KeyStore>) -> Self {
+        Self {
+            keys: Some(keys),
+            ..self
+        }
+        .rebuild_encoder()
+    }
+
+    /// Read and write frames in `layout`.
+    pub fn with_layout(self, layout: FrameLayout) -> Self {
+        Self { layout, ..self }.rebuild_encoder()
+    }
+
+    /// Replace the encoder with one configured for the codec's dictionary, keys and layout.
+    fn rebuild_encoder(self) -> Self {
+        let mut encoder = FrameEncoder::new().with_layout(self.layout);
+        if let Some(dictionary) = &self.dictionary {
+            encoder = encoder.with_dictionary(dictionary.clone());
+        }
+        if let Some(keys) = &self.keys {
+            encoder = encoder.with_key_store(Arc::clone(keys));
+        }
+        Self { encoder, ..self }
+    }
+}
+
+impl Decoder for MessageCodec {
+    type Item = Message;
+    type Error = ProtocolError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Message>, ProtocolError> {
+        let header_len = self.layout.header_len();
+        if src.len() < header_len {
+            return Ok(None);
+        }
+        let body_len = self.layout.body_len(&src[..header_len])?;
+
+        let frame_len = self.layout.frame_len(body_len);
+        if src.len() < frame_len {
+            src.reserve(frame_len - src.len());
+            return Ok(None);
+        }
+
+        let frame = src.split_to(frame_len);
+        let body = self.layout.verified_body(&frame, body_len)?;
+        let mut options = DecodeOptions {
+            limits: self.limits,
+            mode: self.mode,
+            layout: self.layout,
+            dictionary: self.dictionary.as_ref(),
+            keys: self.keys.as_deref(),
+            cipher: None,
+        };
+        decode_body(body, &mut options).map(Some)
+    }
+}
+
+impl Encoder<Message> for MessageCodec {
+    type Error = ProtocolError;
+
+    fn encode(&mut self, item: Message, dst: &mut BytesMut) -> Result<(), ProtocolError> {
+        self.encode(&item, dst)
+    }
+}
+
+impl Encoder<&Message> for MessageCodec {
+    type Error = ProtocolError;
+
+    fn encode(&mut self, item: &Message, dst: &mut BytesMut) -> Result<(), ProtocolError> {
+        // `encode_into` appends to a `Vec`; converting `dst` to one and back takes over its
+        // allocation rather than copying while `dst` is its buffer's only handle.
+        let mut out = Vec::from(std::mem::take(dst));
+        let result = self.encoder.encode_into(item, &mut out);
+        *dst = BytesMut::from(Bytes::from(out));
+        result
+    }
+}
+
+/// Read one framed message from an async reader.
+///
+/// Async counterpart of `FrameCodec::decode`.
+pub async fn read_message<R: AsyncRead + Unpin>(reader: &mut R) -> Result<Message, ProtocolError> {
+    read_message_with_options(reader, &mut DecodeOptions::default()).await
+}
+
+/// Read one framed message from an async reader, decoding it with `options`.
+///
+/// Async counterpart of `FrameCodec::decode_with_options`.
+pub async fn read_message_with_options<R: AsyncRead + Unpin>(
+    reader: &mut R,
+    options: &mut DecodeOptions<'_>,
+) -> Result<Message, ProtocolError> {
+    let layout = options.layout;
+    let mut header_buf = [0u8; LENGTH_PREFIX_SIZE + CRC_SIZE];
+    let header = &mut header_buf[..layout.header_len()];
+    reader.read_exact(header).await?;
+    let body_len = layout.body_len(header)?;
+
+    let mut body = vec![0u8; body_len + layout.trailer_len()];
+    reader.read_exact(&mut body).await?;
+    let expected_crc = layout.stored_crc(header, &body[body_len..])?;
+    body.truncate(body_len);
+
+    verify_crc32(&body, expected_crc)?;
+    decode_body(&body, options)
+}
+
+/// Encode and write one framed message to an async writer, then flush.
+///
+/// Async counterpart of `FrameCodec::write`.
+pub async fn write_message<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    message: &Message,
+) -> Result<(), ProtocolError> {
+    let frame = FrameCodec::encode(message)?;
+    writer.write_all(&frame).await?;
+    writer.flush().await?;
+    Ok(())
+}
@@ -0,0 +1,105 @@
+//! Deterministic top-N process selection and size-aware snapshot truncation (FR-005).
+//!
+//! Producers collect every process, then keep the top `top_n` by CPU usage. If the encoded
+//! frame still exceeds the size target, the lowest-ranked processes are dropped until it fits.
+//! Ordering is `cpu_percent` descending (NaN last) with `pid` ascending as the tie-break, so two
+//! agents with the same input always send the same snapshot.
+
+use super::{
+    Envelope, Extensions, FrameEncoder, Message, MessagePayload, MessageType, ProcessSample,
+    ProtocolError, SnapshotPayload,
+};
+use std::cmp::Ordering;
+
+/// Default number of processes kept per snapshot (FR-001).
+pub const DEFAULT_TOP_N: usize = 100;
+
+/// Sort processes by `cpu_percent` descending, then `pid` ascending.
+///
+/// NaN readings rank after every number, so a broken sample never displaces a real one from
+/// the top N; among themselves they use IEEE 754 total ordering to stay deterministic.
+pub fn sort_processes(processes: &mut [ProcessSample]) {
+    processes.sort_by(compare_processes);
+}
+
+fn compare_processes(a: &ProcessSample, b: &ProcessSample) -> Ordering {
+    a.cpu_percent
+        .is_nan()
+        .cmp(&b.cpu_percent.is_nan())
+        .then_with(|| b.cpu_percent.total_cmp(&a.cpu_percent))
+        .then_with(|| a.pid.cmp(&b.pid))
+}
+
+/// Reduce a snapshot to its top `top_n` processes, then to as many as fit `target_frame_size`.
+///
+/// Frames are sized with `encoder`, the one the snapshot will be sent with, so its compression
+/// policy and dictionary, authentication and encryption count towards `target_frame_size`.
+/// `envelope` supplies the fields the snapshot will be sent with (agent id, compression flag,
+/// envelope extensions); its message type is replaced with `Snapshot` for sizing. Payload
+/// extensions are not accounted for.
+///
+/// The returned processes are sorted (see [`sort_processes`]) and `truncated` is set if any
+/// process was dropped; it stays set if the input was already marked truncated.
+///
+/// Returns `ProtocolError::FrameTooLarge` if the snapshot does not fit even without processes.
+pub fn truncate_snapshot(
+    encoder: &mut FrameEncoder,
+    envelope: &Envelope,
+    mut snapshot: SnapshotPayload,
+    top_n: usize,
+    target_frame_size: usize,
+) -> Result<SnapshotPayload, ProtocolError> {
+    let envelope = Envelope {
+        message_type: MessageType::Snapshot,
+        ..envelope.clone()
+    };
+    let original_count = snapshot.processes.len();
+    sort_processes(&mut snapshot.processes);
+    snapshot.processes.truncate(top_n);
+
+    let mut processes = std::mem::take(&mut snapshot.processes);
+    let mut fits = |count: usize| -> Result<(bool, usize), ProtocolError> {
+        snapshot.processes = processes[..count].to_vec();
+        let len = snapshot_frame_len(encoder, &envelope, &snapshot)?;
+        Ok((len <= target_frame_size, len))
+    };
+
+    let keep = if fits(processes.len())?.0 {
+        processes.len()
+    } else {
+        let (empty_fits, empty_len) = fits(0)?;
+        if !empty_fits {
+            return Err(ProtocolError::FrameTooLarge(empty_len, target_frame_size));
+        }
+        // Invariant: the first `lo` processes fit, the first `hi` do not.
+        let (mut lo, mut hi) = (0, processes.len());
+        while hi - lo > 1 {
+            let mid = lo + (hi - lo) / 2;
+            if fits(mid)?.0 {
+                lo = mid;
+            } else {
+                hi = mid;
+            }
+        }
+        lo
+    };
+
+    processes.truncate(keep);
+    snapshot.processes = processes;
+    snapshot.truncated |= keep < original_count;
+    Ok(snapshot)
+}
+
+/// Encoded frame length of a snapshot; frames above `MAX_FRAME_SIZE` report their size too.
+fn snapshot_frame_len(
+    encoder: &mut FrameEncoder,
+    envelope: &Envelope,
+    snapshot: &SnapshotPayload,
+) -> Result<usize, ProtocolError> {
+    let message = Message {
+        envelope: envelope.clone(),
+        payload: MessagePayload::Snapshot(snapshot.clone()),
+        payload_extensions: Extensions::new(),
+    };
+    encoder.frame_len(&message)
+}
@@ -0,0 +1,301 @@
+//! Semantic validation of messages.
+//!
+//! Decoding only checks that a frame is well-formed; a well-formed snapshot can still report
+//! 250% CPU, more memory in use than installed, or an agent id other than the one that opened
+//! the session. `Message::validate` checks those invariants and reports every violation found,
+//! each located by the same field paths decode errors use.
+
+use super::{
+    AgentIdentity, Message, MessagePayload, MessageType, OsType, ProtocolVersion,
+    SnapshotDeltaPayload, SnapshotPayload,
+};
+use std::fmt;
+
+/// Session context a message is validated against.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ValidationContext<'a> {
+    /// Handshake that opened the session; envelopes must carry its instance id and platform
+    pub handshake: Option<&'a AgentIdentity>,
+    /// Upper bound for per-process CPU percentages (100 unless usage is reported per core)
+    pub max_process_cpu_percent: f32,
+}
+
+impl<'a> ValidationContext<'a> {
+    /// Check envelopes against the session's handshake.
+    pub fn with_handshake(self, handshake: &'a AgentIdentity) -> Self {
+        Self {
+            handshake: Some(handshake),
+            ..self
+        }
+    }
+
+    /// Allow per-process CPU usage of up to 100% per logical CPU.
+    pub fn with_logical_cpus(self, logical_cpus: u32) -> Self {
+        Self {
+            max_process_cpu_percent: 100.0 * logical_cpus.max(1) as f32,
+            ..self
+        }
+    }
+}
+
+impl Default for ValidationContext<'_> {
+    fn default() -> Self {
+        Self {
+            handshake: None,
+            max_process_cpu_percent: 100.0,
+        }
+    }
+}
+
+/// One semantic invariant a message violates.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Violation {
+    /// Field path, e.g. `snapshot.processes[3].cpu_percent`
+    pub field: String,
+    /// What is wrong with the field
+    pub kind: ViolationKind,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.field, self.kind)
+    }
+}
+
+/// Kinds of semantic violations.
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+pub enum ViolationKind {
+    #[error("envelope type {envelope:?} does not match payload type {payload:?}")]
+    MessageTypeMismatch {
+        envelope: MessageType,
+        payload: MessageType,
+    },
+    #[error("value {0} is not finite")]
+    NotFinite(f32),
+    #[error("percentage {value} is outside 0..={max}")]
+    PercentOutOfRange { value: f32, max: f32 },
+    #[error("memory used ({used} bytes) exceeds memory total ({total} bytes)")]
+    MemoryUsedExceedsTotal { used: u64, total: u64 },
+    #[error("window end {end} is before window start {start}")]
+    WindowEndsBeforeStart { start: i64, end: i64 },
+    #[error("part index {index} is not below part count {count}")]
+    PartIndexOutOfRange { index: u32, count: u32 },
+    #[error(
+        "minimum version {}.{} is above maximum version {}.{}",
+        .min.major, .min.minor, .max.major, .max.minor
+    )]
+    InvertedVersionRange {
+        min: ProtocolVersion,
+        max: ProtocolVersion,
+    },
+    #[error("agent id {actual:?} does not match handshake instance id {expected:?}")]
+    AgentIdMismatch { expected: String, actual: String },
+    #[error("platform {actual:?} does not match handshake platform {expected:?}")]
+    PlatformMismatch { expected: OsType, actual: OsType },
+}
+
+impl Message {
+    /// Check semantic invariants with the default context (no session, percentages 0..=100).
+    pub fn validate(&self) -> Result<(), Vec<Violation>> {
+        self.validate_with(&ValidationContext::default())
+    }
+
+    /// Check semantic invariants against a session context.
+    ///
+    /// Returns every violation found, not just the first. A `Handshake` message is checked
+    /// against its own identity rather than `context.handshake`.
+    pub fn validate_with(&self, context: &ValidationContext<'_>) -> Result<(), Vec<Violation>> {
+        let mut violations = Violations::default();
+
+        let payload_type = self.payload.message_type();
+        if self.envelope.message_type != payload_type {
+            violations.push(
+                "envelope.message_type",
+                ViolationKind::MessageTypeMismatch {
+                    envelope: self.envelope.message_type,
+                    payload: payload_type,
+                },
+            );
+        }
+
+        let identity = match &self.payload {
+            MessagePayload::Handshake(identity) => Some(identity),
+            _ => context.handshake,
+        };
+        if let Some(identity) = identity {
+            if self.envelope.agent_id != identity.instance_id {
+                violations.push(
+                    "envelope.agent_id",
+                    ViolationKind::AgentIdMismatch {
+                        expected: identity.instance_id.clone(),
+                        actual: self.envelope.agent_id.clone(),
+                    },
+                );
+            }
+            if self.envelope.platform != identity.os_type {
+                violations.push(
+                    "envelope.platform",
+                    ViolationKind::PlatformMismatch {
+                        expected: identity.os_type,
+                        actual: self.envelope.platform,
+                    },
+                );
+            }
+        }
+
+        match &self.payload {
+            MessagePayload::Handshake(identity) => {
+                let range = identity.supported_versions;
+                if range.min > range.max {
+                    violations.push(
+                        "handshake.supported_versions",
+                        ViolationKind::InvertedVersionRange {
+                            min: range.min,
+                            max: range.max,
+                        },
+                    );
+                }
+            }
+            MessagePayload::Snapshot(snapshot) => {
+                validate_snapshot(snapshot, "snapshot", context, &mut violations)
+            }
+            MessagePayload::SnapshotPart(part) => {
+                if part.part_index >= part.part_count {
+                    violations.push(
+                        "snapshot_part.part_index",
+                        ViolationKind::PartIndexOutOfRange {
+                            index: part.part_index,
+                            count: part.part_count,
+                        },
+                    );
+                }
+                validate_snapshot(
+                    &part.snapshot,
+                    "snapshot_part.snapshot",
+                    context,
+                    &mut violations,
+                );
+            }
+            MessagePayload::SnapshotDelta(delta) => {
+                validate_snapshot_delta(delta, context, &mut violations)
+            }
+            _ => {}
+        }
+
+        violations.into_result()
+    }
+}
+
+fn validate_snapshot(
+    snapshot: &SnapshotPayload,
+    path: &str,
+    context: &ValidationContext<'_>,
+    violations: &mut Violations,
+) {
+    violations.aggregates(
+        path,
+        (snapshot.window_start_secs, snapshot.window_end_secs),
+        (snapshot.memory_used_bytes, snapshot.memory_total_bytes),
+        snapshot.total_cpu_percent,
+    );
+    for (index, process) in snapshot.processes.iter().enumerate() {
+        violations.process(
+            || format!("{path}.processes[{index}]"),
+            (process.cpu_percent, process.memory_percent),
+            context,
+        );
+    }
+}
+
+fn validate_snapshot_delta(
+    delta: &SnapshotDeltaPayload,
+    context: &ValidationContext<'_>,
+    violations: &mut Violations,
+) {
+    violations.aggregates(
+        "snapshot_delta",
+        (delta.window_start_secs, delta.window_end_secs),
+        (delta.memory_used_bytes, delta.memory_total_bytes),
+        delta.total_cpu_percent,
+    );
+    for (index, process) in delta.added.iter().enumerate() {
+        violations.process(
+            || format!("snapshot_delta.added[{index}]"),
+            (process.cpu_percent, process.memory_percent),
+            context,
+        );
+    }
+    for (index, change) in delta.changed.iter().enumerate() {
+        violations.process(
+            || format!("snapshot_delta.changed[{index}]"),
+            (change.cpu_percent, change.memory_percent),
+            context,
+        );
+    }
+}
+
+#[derive(Default)]
+struct Violations(Vec<Violation>);
+
+impl Violations {
+    fn push(&mut self, field: impl Into<String>, kind: ViolationKind) {
+        self.0.push(Violation {
+            field: field.into(),
+            kind,
+        });
+    }
+
+    /// Check the window, memory and CPU aggregates of a snapshot at `path`.
+    fn aggregates(&mut self, path: &str, window: (i64, i64), memory: (u64, u64), cpu: f32) {
+        let (start, end) = window;
+        if end < start {
+            self.push(
+                format!("{path}.window_end_secs"),
+                ViolationKind::WindowEndsBeforeStart { start, end },
+            );
+        }
+        let (used, total) = memory;
+        if used > total {
+            self.push(
+                format!("{path}.memory_used_bytes"),
+                ViolationKind::MemoryUsedExceedsTotal { used, total },
+            );
+        }
+        self.percent(|| format!("{path}.total_cpu_percent"), cpu, 100.0);
+    }
+
+    /// Check the CPU and memory percentages of one process at `path`.
+    fn process(
+        &mut self,
+        path: impl Fn() -> String,
+        (cpu_percent, memory_percent): (f32, f32),
+        context: &ValidationContext<'_>,
+    ) {
+        self.percent(
+            || format!("{}.cpu_percent", path()),
+            cpu_percent,
+            context.max_process_cpu_percent,
+        );
+        self.percent(
+            || format!("{}.memory_percent", path()),
+            memory_percent,
+            100.0,
+        );
+    }
+
+    fn percent(&mut self, field: impl FnOnce() -> String, value: f32, max: f32) {
+        if !value.is_finite() {
+            self.push(field(), ViolationKind::NotFinite(value));
+        } else if !(0.0..=max).contains(&value) {
+            self.push(field(), ViolationKind::PercentOutOfRange { value, max });
+        }
+    }
+
+    fn into_result(self) -> Result<(), Vec<Violation>> {
+        if self.0.is_empty() {
+            Ok(())
+        } else {
+            Err(self.0)
+        }
+    }
+}
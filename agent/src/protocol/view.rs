@@ -0,0 +1,524 @@
+//! Zero-copy borrowed message views.
+//!
+//! `MessageRef` parses a frame body without allocating: the agent id, process names and command
+//! lines are `&str` slices of the input buffer. An uncompressed payload is borrowed as well; a
+//! compressed payload is decompressed once into a buffer owned by the view, and the payload
+//! views borrow from that buffer. Filtering archived frames by envelope fields never touches
+//! the payload at all. Call `to_owned()` to turn a view into the owned types.
+
+use super::{
+    auth, check_decodable, decode_payload, dictionary_for, read_extensions, DecodeLimits,
+    DecodeMode, DecodeOptions, Envelope, Extensions, FieldReader, KeyStore, Message,
+    MessagePayload, MessageType, OsType, ProcessSample, ProtocolError, ProtocolVersion,
+    SnapshotPayload, AUTH_TAG_SIZE, FLAG_AUTHENTICATED, FLAG_COMPRESSED, FLAG_ENCRYPTED,
+    FLAG_EXTENSIONS, KNOWN_FLAGS,
+};
+use std::borrow::Cow;
+
+/// Borrowed view of an envelope.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EnvelopeRef<'a> {
+    /// Protocol version
+    pub version: ProtocolVersion,
+    /// Message type
+    pub message_type: MessageType,
+    /// Unique message ID
+    pub message_id: &'a [u8; 16],
+    /// Timestamp when message was created (UTC Unix epoch milliseconds)
+    pub timestamp_utc_ms: i64,
+    /// Agent instance identifier
+    pub agent_id: &'a str,
+    /// Platform (OS type)
+    pub platform: OsType,
+    /// True if payload is zstd-compressed
+    pub compressed: bool,
+    /// True if the frame body ends with an authentication tag
+    authenticated: bool,
+    /// True if the payload is AEAD-encrypted
+    encrypted: bool,
+    /// Raw envelope extension area (empty if absent)
+    extensions: &'a [u8],
+    /// Limits and mode the envelope was parsed with, reused for the extension area
+    limits: DecodeLimits,
+    mode: DecodeMode,
+}
+
+impl<'a> EnvelopeRef<'a> {
+    /// Parse only the envelope at the start of a frame body, leniently with default limits.
+    ///
+    /// Reads the envelope of a frame whose payload cannot be decoded, e.g. because it is
+    /// encrypted; the CRC32 and the payload are not checked.
+    pub fn from_body(body: &'a [u8]) -> Result<Self, ProtocolError> {
+        let mut reader = FieldReader::new(body, 0, DecodeLimits::default(), DecodeMode::Lenient);
+        reader.field("envelope", Self::parse)
+    }
+
+    fn parse(reader: &mut FieldReader<'a>) -> Result<Self, ProtocolError> {
+        let version = reader.field("version", |r| {
+            let version = ProtocolVersion {
+                major: r.u8()?,
+                minor: r.u8()?,
+            };
+            check_decodable(version)?;
+            Ok(version)
+        })?;
+        let message_type = reader.field("message_type", |r| MessageType::from_u8(r.u8()?))?;
+        let message_id = reader.field("message_id", FieldReader::array)?;
+        let timestamp_utc_ms = reader.field("timestamp_utc_ms", FieldReader::i64)?;
+        let agent_id = reader.field("agent_id", FieldReader::str)?;
+        let platform = reader.field("platform", |r| OsType::from_u8(r.u8()?))?;
+        let flags = reader.field("flags", |r| {
+            let flags = r.u8()?;
+            if r.mode() == DecodeMode::Strict && flags & !KNOWN_FLAGS != 0 {
+                return Err(ProtocolError::InvalidFlags(flags));
+            }
+            Ok(flags)
+        })?;
+        let extensions = if flags & FLAG_EXTENSIONS != 0 {
+            reader.field("extensions", FieldReader::extension_area)?
+        } else {
+            &[]
+        };
+
+        Ok(Self {
+            version,
+            message_type,
+            message_id,
+            timestamp_utc_ms,
+            agent_id,
+            platform,
+            compressed: flags & FLAG_COMPRESSED != 0,
+            authenticated: flags & FLAG_AUTHENTICATED != 0,
+            encrypted: flags & FLAG_ENCRYPTED != 0,
+            extensions,
+            limits: *reader.limits(),
+            mode: reader.mode(),
+        })
+    }
+
+    /// True if the frame carries an authentication tag (not whether the tag was verified).
+    pub fn is_authenticated(&self) -> bool {
+        self.authenticated
+    }
+
+    /// True if the payload is encrypted.
+    pub fn is_encrypted(&self) -> bool {
+        self.encrypted
+    }
+
+    /// Decode the envelope extension area with the limits and mode the frame was parsed with.
+    pub fn extensions(&self) -> Result<Extensions, ProtocolError> {
+        if self.extensions.is_empty() {
+            return Ok(Extensions::new());
+        }
+        let mut reader = FieldReader::new(self.extensions, 0, self.limits, self.mode);
+        read_extensions(&mut reader)
+    }
+
+    /// Convert to an owned `Envelope`.
+    pub fn to_owned(&self) -> Result<Envelope, ProtocolError> {
+        Ok(Envelope {
+            version: self.version,
+            message_type: self.message_type,
+            message_id: *self.message_id,
+            timestamp_utc_ms: self.timestamp_utc_ms,
+            agent_id: self.agent_id.to_string(),
+            platform: self.platform,
+            compressed: self.compressed,
+            extensions: self.extensions()?,
+        })
+    }
+}
+
+/// Borrowed view of a message.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MessageRef<'a> {
+    /// Envelope fields borrowed from the frame
+    pub envelope: EnvelopeRef<'a>,
+    /// Payload bytes: borrowed when uncompressed, decompressed into an owned buffer otherwise
+    payload_bytes: Cow<'a, [u8]>,
+    /// Frame body covered by the authentication tag (the whole body if there is no tag)
+    signed: &'a [u8],
+    /// Authentication tag at the end of the frame body
+    auth_tag: Option<&'a [u8]>,
+    /// Offset of the payload in the frame body (0 for a decompressed payload)
+    payload_offset: usize,
+    /// Size of the payload as carried in the frame body (compressed and/or encrypted)
+    encoded_payload_len: usize,
+    /// Limits applied when the payload is decoded
+    limits: DecodeLimits,
+    /// Validation rules applied when the payload is decoded
+    mode: DecodeMode,
+}
+
+/// Borrowed view of a payload.
+///
+/// Snapshot payloads are borrowed; the remaining (small) payload types are decoded into their
+/// owned form.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PayloadRef<'p> {
+    Snapshot(SnapshotRef<'p>),
+    SnapshotPart {
+        snapshot_id: &'p [u8; 16],
+        part_index: u32,
+        part_count: u32,
+        snapshot: SnapshotRef<'p>,
+    },
+    Other(MessagePayload),
+}
+
+impl<'a> MessageRef<'a> {
+    /// Parse one frame at the start of `data`, verifying its length and CRC32.
+    ///
+    /// Returns the view and the total frame length, so a caller can step through a buffer of
+    /// concatenated frames.
+    pub fn from_frame(data: &'a [u8]) -> Result<(Self, usize), ProtocolError> {
+        Self::from_frame_with_options(data, &mut DecodeOptions::default())
+    }
+
+    /// Parse one frame in the layout of `options` at the start of `data`, decoding it with
+    /// `options`.
+    pub fn from_frame_with_options(
+        data: &'a [u8],
+        options: &mut DecodeOptions<'_>,
+    ) -> Result<(Self, usize), ProtocolError> {
+        let (body, frame_len) = options.layout.split_frame(data)?;
+        Ok((Self::from_body_with_options(body, options)?, frame_len))
+    }
+
+    /// Parse a CRC-validated frame body (envelope + payload).
+    ///
+    /// A compressed payload is decompressed here; an uncompressed one is borrowed.
+    pub fn from_body(body: &'a [u8]) -> Result<Self, ProtocolError> {
+        Self::from_body_with_options(body, &mut DecodeOptions::default())
+    }
+
+    /// Parse a CRC-validated frame body with `options`.
+    ///
+    /// The limits and mode are kept for the later `payload()` and `to_owned()` calls.
+    pub fn from_body_with_options(
+        body: &'a [u8],
+        options: &mut DecodeOptions<'_>,
+    ) -> Result<Self, ProtocolError> {
+        Self::parse_body(body, options)
+    }
+
+    /// Parse a frame body, verifying its authentication tag against the key store of `options`
+    /// (if set) before the payload is decrypted with its cipher and decompressed.
+    ///
+    /// With a cipher, a frame without `FLAG_ENCRYPTED` fails with
+    /// `ProtocolError::UnencryptedFrame`.
+    pub(super) fn parse_body(
+        body: &'a [u8],
+        options: &mut DecodeOptions<'_>,
+    ) -> Result<Self, ProtocolError> {
+        let (message, opened) = Self::parse_body_uncommitted(body, options)?;
+        if let (Some(cipher), Some(counter)) = (options.cipher.as_deref_mut(), opened) {
+            cipher.commit_opened(counter);
+        }
+        Ok(message)
+    }
+
+    /// Like [`MessageRef::parse_body`], but leaves the cipher's replay counter alone and
+    /// returns the counter of a decrypted frame, to be committed once the frame is accepted.
+    pub(super) fn parse_body_uncommitted(
+        body: &'a [u8],
+        options: &DecodeOptions<'_>,
+    ) -> Result<(Self, Option<u64>), ProtocolError> {
+        let (limits, mode) = (options.limits, options.mode);
+        let mut reader = FieldReader::new(body, 0, limits, mode);
+        let envelope = reader.field("envelope", EnvelopeRef::parse)?;
+        let payload_offset = reader.offset();
+
+        // An authenticated body ends with the tag; everything before it is signed
+        let (signed, auth_tag) = if envelope.authenticated {
+            let tag_offset = body
+                .len()
+                .checked_sub(AUTH_TAG_SIZE)
+                .filter(|&offset| offset >= payload_offset)
+                .ok_or_else(|| {
+                    ProtocolError::AuthenticationFailed(
+                        "frame too short for an authentication tag".to_string(),
+                    )
+                    .at("auth_tag".to_string(), payload_offset)
+                })?;
+            let (signed, tag) = body.split_at(tag_offset);
+            (signed, Some(tag))
+        } else {
+            (body, None)
+        };
+        if let Some(keys) = options.keys {
+            auth::verify(&envelope, signed, auth_tag, keys)?;
+        }
+
+        // Remaining bytes are payload (possibly compressed, then possibly encrypted)
+        let mut payload_bytes = Cow::Borrowed(&signed[payload_offset..]);
+        let encoded_payload_len = payload_bytes.len();
+        let mut opened = None;
+        match (options.cipher.as_deref(), envelope.encrypted) {
+            (Some(cipher), true) => {
+                let (payload, counter) = cipher
+                    .open(signed, payload_offset)
+                    .map_err(|e| e.at("payload".to_string(), payload_offset))?;
+                payload_bytes = Cow::Owned(payload);
+                opened = Some(counter);
+            }
+            // A configured cipher makes encryption mandatory, so a peer cannot downgrade
+            (Some(_), false) => {
+                return Err(ProtocolError::UnencryptedFrame.at("envelope.flags".to_string(), 0))
+            }
+            (None, true) => {
+                return Err(ProtocolError::Encryption(
+                    "payload is encrypted but no cipher is set".to_string(),
+                )
+                .at("payload".to_string(), payload_offset))
+            }
+            (None, false) => {}
+        }
+        if envelope.compressed {
+            let payload = dictionary_for(&payload_bytes, options.dictionary)
+                .and_then(|dictionary| limits.decompress(&payload_bytes, dictionary))
+                .map_err(|e| e.at("payload".to_string(), payload_offset))?;
+            payload_bytes = Cow::Owned(payload);
+        }
+        let payload_offset = match payload_bytes {
+            Cow::Borrowed(_) => payload_offset,
+            Cow::Owned(_) => 0,
+        };
+
+        let message = Self {
+            envelope,
+            payload_bytes,
+            signed,
+            auth_tag,
+            payload_offset,
+            encoded_payload_len,
+            limits,
+            mode,
+        };
+        Ok((message, opened))
+    }
+
+    /// Verify the frame's authentication tag against `keys`.
+    ///
+    /// Fails with `ProtocolError::AuthenticationFailed` if the frame is not authenticated or
+    /// the tag does not match a valid key of its agent.
+    pub fn verify(&self, keys: &dyn KeyStore) -> Result<(), ProtocolError> {
+        auth::verify(&self.envelope, self.signed, self.auth_tag, keys)
+    }
+
+    /// True if the payload is borrowed from the input (uncompressed frame).
+    pub fn is_payload_borrowed(&self) -> bool {
+        matches!(self.payload_bytes, Cow::Borrowed(_))
+    }
+
+    /// Payload bytes after decompression.
+    pub fn payload_bytes(&self) -> &[u8] {
+        &self.payload_bytes
+    }
+
+    /// Size of the payload as carried in the frame body: compressed and/or encrypted, without
+    /// the authentication tag.
+    pub fn encoded_payload_len(&self) -> usize {
+        self.encoded_payload_len
+    }
+
+    /// Parse the payload into a borrowed view.
+    pub fn payload(&self) -> Result<PayloadRef<'_>, ProtocolError> {
+        let mut reader = self.payload_reader();
+        // Only 1.x envelopes pass `EnvelopeRef::parse`; snapshots are borrowed in that layout.
+        match self.envelope.message_type {
+            MessageType::Snapshot => Ok(PayloadRef::Snapshot(
+                reader.field("snapshot", SnapshotRef::parse)?,
+            )),
+            MessageType::SnapshotPart => reader.field("snapshot_part", |r| {
+                Ok(PayloadRef::SnapshotPart {
+                    snapshot_id: r.field("snapshot_id", FieldReader::array)?,
+                    part_index: r.field("part_index", FieldReader::u32)?,
+                    part_count: r.field("part_count", FieldReader::u32)?,
+                    snapshot: r.field("snapshot", SnapshotRef::parse)?,
+                })
+            }),
+            message_type => decode_payload(message_type, self.envelope.version, reader)
+                .map(|(payload, _)| PayloadRef::Other(payload)),
+        }
+    }
+
+    /// Snapshot view if this is a `Snapshot` or `SnapshotPart` message.
+    pub fn snapshot(&self) -> Result<Option<SnapshotRef<'_>>, ProtocolError> {
+        match self.payload()? {
+            PayloadRef::Snapshot(snapshot) | PayloadRef::SnapshotPart { snapshot, .. } => {
+                Ok(Some(snapshot))
+            }
+            PayloadRef::Other(_) => Ok(None),
+        }
+    }
+
+    /// Convert to an owned `Message` (including payload extensions).
+    pub fn to_owned(&self) -> Result<Message, ProtocolError> {
+        let (payload, payload_extensions) = decode_payload(
+            self.envelope.message_type,
+            self.envelope.version,
+            self.payload_reader(),
+        )?;
+        Ok(Message {
+            envelope: self.envelope.to_owned()?,
+            payload,
+            payload_extensions,
+        })
+    }
+
+    fn payload_reader(&self) -> FieldReader<'_> {
+        FieldReader::new(
+            &self.payload_bytes,
+            self.payload_offset,
+            self.limits,
+            self.mode,
+        )
+    }
+}
+
+/// Borrowed view of a snapshot payload.
+///
+/// The process list is validated when the view is created (without allocating), so iterating
+/// over it cannot fail.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SnapshotRef<'a> {
+    /// Sampling window start timestamp (Unix epoch seconds)
+    pub window_start_secs: i64,
+    /// Sampling window end timestamp (Unix epoch seconds)
+    pub window_end_secs: i64,
+    /// Aggregate CPU usage percentage (0.0 - 100.0)
+    pub total_cpu_percent: f32,
+    /// Memory currently in use (bytes)
+    pub memory_used_bytes: u64,
+    /// Total system memory (bytes)
+    pub memory_total_bytes: u64,
+    /// True if process list was truncated to fit size cap
+    pub truncated: bool,
+    process_count: usize,
+    /// Reader positioned at the first process sample
+    process_reader: FieldReader<'a>,
+}
+
+impl<'a> SnapshotRef<'a> {
+    pub(super) fn parse(reader: &mut FieldReader<'a>) -> Result<Self, ProtocolError> {
+        let window_start_secs = reader.field("window_start_secs", FieldReader::i64)?;
+        let window_end_secs = reader.field("window_end_secs", FieldReader::i64)?;
+        let total_cpu_percent = reader.field("total_cpu_percent", FieldReader::f32)?;
+        let memory_used_bytes = reader.field("memory_used_bytes", FieldReader::u64)?;
+        let memory_total_bytes = reader.field("memory_total_bytes", FieldReader::u64)?;
+        let process_count = reader.field("process_count", FieldReader::count)?;
+
+        let process_reader = reader.clone();
+        for index in 0..process_count {
+            reader.field(|| format!("processes[{index}]"), ProcessSampleRef::parse)?;
+        }
+        let truncated = reader.field("truncated", FieldReader::bool)?;
+
+        Ok(Self {
+            window_start_secs,
+            window_end_secs,
+            total_cpu_percent,
+            memory_used_bytes,
+            memory_total_bytes,
+            truncated,
+            process_count,
+            process_reader,
+        })
+    }
+
+    /// Number of process samples.
+    pub fn process_count(&self) -> usize {
+        self.process_count
+    }
+
+    /// Iterate over the process samples without allocating.
+    pub fn processes(&self) -> ProcessIter<'a> {
+        ProcessIter {
+            reader: self.process_reader.clone(),
+            remaining: self.process_count,
+        }
+    }
+
+    /// Convert to an owned `SnapshotPayload`.
+    pub fn to_owned(&self) -> SnapshotPayload {
+        SnapshotPayload {
+            window_start_secs: self.window_start_secs,
+            window_end_secs: self.window_end_secs,
+            total_cpu_percent: self.total_cpu_percent,
+            memory_used_bytes: self.memory_used_bytes,
+            memory_total_bytes: self.memory_total_bytes,
+            processes: self.processes().map(|p| p.to_owned()).collect(),
+            truncated: self.truncated,
+        }
+    }
+}
+
+/// Borrowed view of a process sample.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ProcessSampleRef<'a> {
+    /// Process ID
+    pub pid: u32,
+    /// Process name
+    pub name: &'a str,
+    /// CPU usage percentage (0.0 - 100.0)
+    pub cpu_percent: f32,
+    /// Memory usage percentage of total system memory (0.0 - 100.0)
+    pub memory_percent: f32,
+    /// Memory usage in bytes (RSS)
+    pub memory_bytes: u64,
+    /// Optional command line
+    pub cmdline: Option<&'a str>,
+}
+
+impl<'a> ProcessSampleRef<'a> {
+    pub(super) fn parse(reader: &mut FieldReader<'a>) -> Result<Self, ProtocolError> {
+        Ok(Self {
+            pid: reader.field("pid", FieldReader::u32)?,
+            name: reader.field("name", FieldReader::str)?,
+            cpu_percent: reader.field("cpu_percent", FieldReader::f32)?,
+            memory_percent: reader.field("memory_percent", FieldReader::f32)?,
+            memory_bytes: reader.field("memory_bytes", FieldReader::u64)?,
+            cmdline: reader.field("cmdline", FieldReader::optional_str)?,
+        })
+    }
+
+    /// Convert to an owned `ProcessSample`.
+    pub fn to_owned(&self) -> ProcessSample {
+        ProcessSample {
+            pid: self.pid,
+            name: self.name.to_string(),
+            cpu_percent: self.cpu_percent,
+            memory_percent: self.memory_percent,
+            memory_bytes: self.memory_bytes,
+            cmdline: self.cmdline.map(str::to_string),
+        }
+    }
+}
+
+/// Iterator over the process samples of a [`SnapshotRef`].
+#[derive(Debug, Clone)]
+pub struct ProcessIter<'a> {
+    reader: FieldReader<'a>,
+    remaining: usize,
+}
+
+impl<'a> Iterator for ProcessIter<'a> {
+    type Item = ProcessSampleRef<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        // The process list was validated when the snapshot view was created.
+        ProcessSampleRef::parse(&mut self.reader).ok()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl ExactSizeIterator for ProcessIter<'_> {}
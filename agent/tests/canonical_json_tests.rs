@@ -0,0 +1,291 @@
+//! Integration tests for the canonical JSON form of messages and the JSON Lines converter.
+
+#![cfg(feature = "json")]
+
+#[path = "../src/bin/golden_corpus/golden.rs"]
+#[allow(dead_code)]
+mod golden;
+
+use agent::protocol::*;
+use std::io::Cursor;
+
+// Test helper: Create a 16-byte message ID from a u64 for simplicity in tests
+fn test_message_id(n: u64) -> [u8; 16] {
+    let mut id = [0u8; 16];
+    id[0..8].copy_from_slice(&n.to_le_bytes());
+    id
+}
+
+fn message(payload: MessagePayload) -> Message {
+    Message {
+        envelope: Envelope {
+            version: ProtocolVersion::CURRENT,
+            message_type: payload.message_type(),
+            message_id: test_message_id(42),
+            timestamp_utc_ms: 1703174405000,
+            agent_id: "test-agent-001".to_string(),
+            platform: OsType::Linux,
+            compressed: false,
+            extensions: Extensions::new(),
+        },
+        payload,
+        payload_extensions: Extensions::new(),
+    }
+}
+
+fn snapshot() -> Message {
+    message(MessagePayload::Snapshot(SnapshotPayload {
+        window_start_secs: 1703174400,
+        window_end_secs: 1703174410,
+        total_cpu_percent: 75.5,
+        memory_used_bytes: 15_000_000_000,
+        memory_total_bytes: 16_000_000_000,
+        processes: vec![ProcessSample {
+            pid: 1003,
+            name: "code".to_string(),
+            cpu_percent: 10.5,
+            memory_percent: 5.0,
+            memory_bytes: 800_000_000,
+            cmdline: None,
+        }],
+        truncated: false,
+    }))
+}
+
+fn json_error(json: &str) -> String {
+    match Message::from_json(json) {
+        Err(ProtocolError::InvalidJson(reason)) => reason,
+        other => panic!("Expected InvalidJson, got {other:?}"),
+    }
+}
+
+#[test]
+fn every_golden_case_round_trips() {
+    let rfc3339 = JsonOptions::default().with_rfc3339_timestamps();
+    for case in golden::cases().expect("Failed to build cases") {
+        for options in [
+            JsonOptions::default(),
+            rfc3339,
+            JsonOptions::default().with_pretty(),
+        ] {
+            let json = case
+                .message
+                .to_json_with(&options)
+                .expect("Failed to write JSON");
+            let parsed = Message::from_json(&json)
+                .unwrap_or_else(|err| panic!("{} ({options:?}): {err}", case.name));
+            assert_eq!(parsed, case.message, "{} ({options:?})", case.name);
+        }
+    }
+}
+
+#[test]
+fn canonical_form_is_stable() {
+    let mut ack = message(MessagePayload::Ack(MessageAck {
+        message_id: test_message_id(1),
+        success: true,
+        error_code: None,
+    }));
+    ack.envelope.set_sequence_number(7);
+    assert_eq!(
+        ack.to_json().expect("Failed to write JSON"),
+        concat!(
+            r#"{"version":"1.0","type":"Ack","message_id":"2a000000000000000000000000000000","#,
+            r#""timestamp":1703174405000,"agent_id":"test-agent-001","platform":"Linux","#,
+            r#""compressed":false,"extensions":[{"tag":1,"value":"0700000000000000"}],"#,
+            r#""payload":{"message_id":"01000000000000000000000000000000","success":true,"#,
+            r#""error_code":null}}"#
+        )
+    );
+
+    let json = snapshot()
+        .to_json_with(&JsonOptions::default().with_rfc3339_timestamps())
+        .expect("Failed to write JSON");
+    assert_eq!(
+        json,
+        concat!(
+            r#"{"version":"1.0","type":"Snapshot","message_id":"2a000000000000000000000000000000","#,
+            r#""timestamp":"2023-12-21T16:00:05.000Z","agent_id":"test-agent-001","#,
+            r#""platform":"Linux","compressed":false,"payload":{"#,
+            r#""window_start":"2023-12-21T16:00:00Z","window_end":"2023-12-21T16:00:10Z","#,
+            r#""total_cpu_percent":75.5,"memory_used_bytes":15000000000,"#,
+            r#""memory_total_bytes":16000000000,"processes":[{"pid":1003,"name":"code","#,
+            r#""cpu_percent":10.5,"memory_percent":5.0,"memory_bytes":800000000,"#,
+            r#""cmdline":null}],"truncated":false}}"#
+        )
+    );
+    assert_eq!(MessageType::SnapshotDelta.name(), "SnapshotDelta");
+    assert_eq!(
+        MessageType::from_name("Backpressure"),
+        Some(MessageType::Backpressure)
+    );
+    assert_eq!(MessageType::from_name("backpressure"), None);
+}
+
+#[test]
+fn hand_written_messages_are_accepted() {
+    // Uppercase hex, offset timestamps, omitted optional fields
+    let json = r#"{
+        "version": "1.0",
+        "type": "Backpressure",
+        "message_id": "2A000000000000000000000000000000",
+        "timestamp": "2023-12-21T17:00:05.000+01:00",
+        "agent_id": "test-agent-001",
+        "platform": "Linux",
+        "compressed": false,
+        "payload": { "throttle_delay_ms": 5000 }
+    }"#;
+    assert_eq!(
+        Message::from_json(json).expect("Failed to parse"),
+        message(MessagePayload::Backpressure(BackpressureSignal {
+            throttle_delay_ms: 5000,
+            reason: None,
+        }))
+    );
+
+    let json = r#"{"version":"1.0","type":"Heartbeat","message_id":"2a000000000000000000000000000000",
+        "timestamp":1703174405000,"agent_id":"test-agent-001","platform":"Linux","compressed":true,
+        "payload":{},"payload_extensions":[{"tag":32769,"value":"CAFE"}]}"#;
+    let heartbeat = Message::from_json(json).expect("Failed to parse");
+    assert!(heartbeat.envelope.compressed);
+    assert_eq!(
+        heartbeat.payload_extensions.get(0x8001),
+        Some(&[0xCA, 0xFE][..])
+    );
+
+    // A hand-written message encodes and decodes like any other
+    let frame = FrameCodec::encode(&heartbeat).expect("Failed to encode");
+    assert_eq!(
+        FrameCodec::decode(&mut Cursor::new(frame)).expect("Failed to decode"),
+        heartbeat
+    );
+}
+
+#[test]
+fn malformed_json_is_rejected_with_a_reason() {
+    let valid = snapshot().to_json().expect("Failed to write JSON");
+    let cases = [
+        (
+            valid.replace(r#""agent_id""#, r#""agent":"x","agent_id""#),
+            "unknown field `agent`",
+        ),
+        (
+            valid.replace(r#""total_cpu_percent""#, r#""cpu":1,"total_cpu_percent""#),
+            "payload: unknown field `cpu`",
+        ),
+        (
+            valid.replace("2a000000000000000000000000000000", "2a00"),
+            "expected a 16-byte id",
+        ),
+        (
+            valid.replace("2a000000000000000000000000000000", "zz000000000000000000000000000000"),
+            "invalid hex string",
+        ),
+        (
+            valid.replace("2a000000000000000000000000000000", "+a00000000000000000000000000000é"),
+            "invalid hex string",
+        ),
+        (
+            valid.replace("2a000000000000000000000000000000", "2a0"),
+            "invalid hex string",
+        ),
+        (
+            valid.replace(r#""type":"Snapshot""#, r#""type":"Snapshots""#),
+            "unknown message type \"Snapshots\"",
+        ),
+        (
+            valid.replace(r#""type":"Snapshot""#, r#""type":"Ack""#),
+            "payload: unknown field",
+        ),
+        (
+            valid.replace(r#""version":"1.0""#, r#""version":"1""#),
+            "invalid version \"1\"",
+        ),
+        (
+            valid.replace("1703174405000", r#""2023-12-21T16:00:05.0001Z""#),
+            "timestamp: \"2023-12-21T16:00:05.0001Z\" is more precise than the field",
+        ),
+        (
+            valid.replace("1703174400", r#""2023-12-21T16:00:00.5Z""#),
+            "payload.window_start: \"2023-12-21T16:00:00.5Z\" is more precise",
+        ),
+        (
+            valid.replace("1703174405000", r#""yesterday""#),
+            "timestamp: invalid RFC 3339 time \"yesterday\"",
+        ),
+        (
+            valid.replace("75.5", "null"),
+            "payload: invalid type: null, expected f32",
+        ),
+        (
+            valid.replace(
+                r#""compressed":false"#,
+                r#""compressed":false,"extensions":[{"tag":1,"value":"00"},{"tag":1,"value":"01"}]"#,
+            ),
+            "extension tag 0x0001 appears more than once",
+        ),
+    ];
+    for (json, expected) in cases {
+        let reason = json_error(&json);
+        assert!(reason.contains(expected), "{reason:?} lacks {expected:?}");
+    }
+    assert_eq!(
+        ProtocolError::InvalidJson(String::new()).error_code(),
+        ErrorCode::MalformedPayload
+    );
+}
+
+#[test]
+fn frame_files_convert_to_json_lines_and_back() {
+    let mut compressed = snapshot();
+    compressed.envelope.compressed = true;
+    let messages = [snapshot(), message(MessagePayload::Heartbeat), compressed];
+
+    for layout in FrameLayout::ALL {
+        let mut capture = Vec::new();
+        for message in &messages {
+            capture
+                .extend(FrameCodec::encode_with_layout(message, layout).expect("Failed to encode"));
+        }
+
+        let jsonl = frames_to_json_lines(
+            &capture,
+            layout,
+            &JsonOptions::default()
+                .with_rfc3339_timestamps()
+                .with_pretty(),
+        )
+        .expect("Failed to convert");
+        // Always one message per line
+        assert_eq!(jsonl.lines().count(), messages.len());
+        for (line, message) in jsonl.lines().zip(&messages) {
+            assert_eq!(&Message::from_json(line).expect("Failed to parse"), message);
+        }
+
+        // Blank lines are skipped and the frames come back byte for byte
+        let spaced = jsonl.replace('\n', "\n\n");
+        assert_eq!(
+            json_lines_to_frames(&spaced, layout).expect("Failed to convert"),
+            capture
+        );
+
+        // A truncated capture fails instead of dropping the last frame
+        assert!(frames_to_json_lines(
+            &capture[..capture.len() - 1],
+            layout,
+            &JsonOptions::default()
+        )
+        .is_err());
+    }
+
+    let jsonl = format!(
+        "{}\n\n{{\"version\":\"1.0\"}}\n",
+        snapshot().to_json().expect("Failed to write JSON")
+    );
+    match json_lines_to_frames(&jsonl, FrameLayout::Legacy) {
+        Err(ProtocolError::InvalidJson(reason)) => {
+            assert!(reason.starts_with("line 3: "), "{reason}")
+        }
+        other => panic!("Expected InvalidJson, got {other:?}"),
+    }
+}
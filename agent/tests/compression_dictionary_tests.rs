@@ -0,0 +1,229 @@
+//! Integration tests for trained zstd dictionaries negotiated at handshake.
+
+use agent::protocol::*;
+use std::io::Cursor;
+
+// Test helper: Create a 16-byte message ID from a u64 for simplicity in tests
+fn test_message_id(n: u64) -> [u8; 16] {
+    let mut id = [0u8; 16];
+    id[0..8].copy_from_slice(&n.to_le_bytes());
+    id
+}
+
+fn snapshot_message(n: u64, compressed: bool) -> Message {
+    let processes = (0..12)
+        .map(|i| {
+            let pid = 1000 + (n as u32 * 7 + i * 13) % 400;
+            ProcessSample {
+                pid,
+                name: [
+                    "postgres",
+                    "nginx",
+                    "systemd-journald",
+                    "containerd",
+                    "java",
+                ][(pid % 5) as usize]
+                    .to_string(),
+                cpu_percent: (pid % 17) as f32 * 1.25,
+                memory_percent: (pid % 9) as f32 * 0.5,
+                memory_bytes: pid as u64 * 4096,
+                cmdline: Some(format!(
+                    "/usr/bin/service-{} --config /etc/app.conf",
+                    pid % 5
+                )),
+            }
+        })
+        .collect();
+    Message {
+        envelope: Envelope {
+            version: ProtocolVersion::CURRENT,
+            message_type: MessageType::Snapshot,
+            message_id: test_message_id(n),
+            timestamp_utc_ms: 1703174400000 + n as i64 * 10_000,
+            agent_id: "test-agent-001".to_string(),
+            platform: OsType::Linux,
+            compressed,
+            extensions: Extensions::new(),
+        },
+        payload: MessagePayload::Snapshot(SnapshotPayload {
+            window_start_secs: 1703174400 + n as i64 * 10,
+            window_end_secs: 1703174410 + n as i64 * 10,
+            total_cpu_percent: (n % 100) as f32,
+            memory_used_bytes: 4_000_000_000 + n * 1_000,
+            memory_total_bytes: 16_000_000_000,
+            processes,
+            truncated: false,
+        }),
+        payload_extensions: Extensions::new(),
+    }
+}
+
+/// Capture of 200 uncompressed snapshot frames, split over two files.
+fn corpus() -> Vec<Vec<u8>> {
+    let mut files = vec![Vec::new(), Vec::new()];
+    for n in 0..200 {
+        let frame = FrameCodec::encode(&snapshot_message(n, false)).expect("Failed to encode");
+        files[n as usize % 2].extend_from_slice(&frame);
+    }
+    files
+}
+
+fn dictionary(id: u16) -> CompressionDictionary {
+    CompressionDictionary::train_from_frames(id, &corpus(), 4096).expect("Failed to train")
+}
+
+#[test]
+fn trained_dictionary_round_trips_and_compresses_better() {
+    let dictionary = dictionary(7);
+    assert_eq!(dictionary.id(), 7);
+    let reloaded = CompressionDictionary::from_bytes(dictionary.as_bytes().to_vec())
+        .expect("Failed to load dictionary");
+    assert_eq!(reloaded.id(), 7);
+
+    let message = snapshot_message(1_000, true);
+    let plain = FrameCodec::encode(&message).expect("Failed to encode");
+    let frame =
+        FrameCodec::encode_with_dictionary(&message, &dictionary).expect("Failed to encode");
+    assert!(
+        frame.len() < plain.len(),
+        "dictionary frame {} bytes, plain frame {} bytes",
+        frame.len(),
+        plain.len()
+    );
+
+    let decoded = FrameCodec::decode_with_options(
+        &mut Cursor::new(&frame),
+        &mut DecodeOptions::new()
+            .with_mode(DecodeMode::Strict)
+            .with_dictionary(&reloaded),
+    )
+    .expect("Failed to decode");
+    assert_eq!(decoded, message);
+
+    let mut decoder = FrameDecoder::new().with_dictionary(reloaded.clone());
+    let frames = decoder.decode(&frame).expect("Failed to decode");
+    assert_eq!(frames.messages, vec![message.clone()]);
+
+    // Frames without a dictionary still decode with a dictionary configured
+    let frames = decoder.decode(&plain).expect("Failed to decode");
+    assert_eq!(frames.messages, vec![message]);
+}
+
+#[test]
+fn frame_encoder_output_matches_codec_with_dictionary() {
+    let dictionary = dictionary(3);
+    let mut encoder = FrameEncoder::new().with_dictionary(dictionary.clone());
+    for n in 0..3 {
+        let message = snapshot_message(500 + n, true);
+        let mut frame = Vec::new();
+        encoder
+            .encode_into(&message, &mut frame)
+            .expect("Failed to encode");
+        assert_eq!(
+            frame,
+            FrameCodec::encode_with_dictionary(&message, &dictionary).expect("Failed to encode")
+        );
+    }
+}
+
+#[test]
+fn unknown_dictionary_is_reported() {
+    let frame = FrameCodec::encode_with_dictionary(&snapshot_message(1, true), &dictionary(9))
+        .expect("Failed to encode");
+
+    let err = FrameCodec::decode(&mut Cursor::new(&frame)).unwrap_err();
+    assert!(matches!(
+        err.root_cause(),
+        ProtocolError::UnknownDictionary(9)
+    ));
+    assert_eq!(err.field_path(), Some("payload"));
+
+    let other = dictionary(10);
+    let err = FrameCodec::decode_with_options(
+        &mut Cursor::new(&frame),
+        &mut DecodeOptions::new()
+            .with_mode(DecodeMode::Lenient)
+            .with_dictionary(&other),
+    )
+    .unwrap_err();
+    assert!(matches!(
+        err.root_cause(),
+        ProtocolError::UnknownDictionary(9)
+    ));
+    assert!(err.to_string().contains("unknown zstd dictionary 9"));
+}
+
+#[test]
+fn scanner_reports_unknown_dictionary_frames_as_intact() {
+    let dictionary = dictionary(9);
+    let mut capture = FrameCodec::encode(&snapshot_message(1, true)).expect("Failed to encode");
+    let first_len = capture.len();
+    capture.extend(
+        FrameCodec::encode_with_dictionary(&snapshot_message(2, true), &dictionary)
+            .expect("Failed to encode"),
+    );
+    capture.extend(FrameCodec::encode(&snapshot_message(3, false)).expect("Failed to encode"));
+
+    let report = scan_frames(&capture);
+    assert_eq!(report.frames.len(), 2);
+    assert_eq!(report.skipped.len(), 1);
+    let skipped = &report.skipped[0];
+    assert_eq!(skipped.start, first_len);
+    assert_eq!(skipped.end, report.frames[1].offset);
+    assert_eq!(
+        skipped.reason,
+        SkipReason::UnknownDictionary {
+            dictionary_id: 9,
+            len: skipped.len(),
+        }
+    );
+
+    let items: Vec<ScanItem> = FrameScanner::new(&capture)
+        .with_dictionary(&dictionary)
+        .collect();
+    assert_eq!(items.len(), 3);
+    match &items[1] {
+        ScanItem::Frame(frame) => assert_eq!(frame.message, snapshot_message(2, true)),
+        other => panic!("expected a frame, got {other:?}"),
+    }
+}
+
+#[test]
+fn dictionary_is_negotiated_only_when_both_sides_hold_it() {
+    let agent = AgentIdentity {
+        instance_id: "test-agent-001".to_string(),
+        os_type: OsType::Linux,
+        agent_version: "1.0.0".to_string(),
+        supported_versions: VersionRange::CURRENT,
+        capabilities: AgentIdentity::CAP_COMPRESSION | AgentIdentity::dictionary_capabilities(7),
+    };
+    assert_eq!(agent.dictionary_id(), Some(7));
+
+    let server = AgentIdentity::CAP_COMPRESSION | AgentIdentity::dictionary_capabilities(7);
+    let ack = negotiate(&agent, VersionRange::CURRENT, server).expect("Failed to negotiate");
+    assert!(ack.compression_enabled());
+    assert_eq!(ack.dictionary_id(), Some(7));
+
+    let other = AgentIdentity::CAP_COMPRESSION | AgentIdentity::dictionary_capabilities(8);
+    let ack = negotiate(&agent, VersionRange::CURRENT, other).expect("Failed to negotiate");
+    assert!(ack.compression_enabled());
+    assert_eq!(ack.dictionary_id(), None);
+    assert_eq!(ack.negotiated_capabilities, AgentIdentity::CAP_COMPRESSION);
+
+    let no_compression = AgentIdentity::dictionary_capabilities(7);
+    let ack =
+        negotiate(&agent, VersionRange::CURRENT, no_compression).expect("Failed to negotiate");
+    assert_eq!(ack.dictionary_id(), None);
+}
+
+#[test]
+fn invalid_dictionaries_are_rejected() {
+    assert!(matches!(
+        CompressionDictionary::from_bytes(b"not a dictionary".to_vec()),
+        Err(ProtocolError::InvalidDictionary(_))
+    ));
+    assert!(matches!(
+        CompressionDictionary::train_from_frames(0, &corpus(), 4096),
+        Err(ProtocolError::InvalidDictionary(_))
+    ));
+}
@@ -0,0 +1,194 @@
+//! Integration tests for `CompressionPolicy` on `FrameEncoder` and its handshake negotiation.
+
+use agent::protocol::*;
+use std::io::Cursor;
+
+// Test helper: Create a 16-byte message ID from a u64 for simplicity in tests
+fn test_message_id(n: u64) -> [u8; 16] {
+    let mut id = [0u8; 16];
+    id[0..8].copy_from_slice(&n.to_le_bytes());
+    id
+}
+
+fn message(payload: MessagePayload, compressed: bool) -> Message {
+    Message {
+        envelope: Envelope {
+            version: ProtocolVersion::CURRENT,
+            message_type: payload.message_type(),
+            message_id: test_message_id(1),
+            timestamp_utc_ms: 1703174400000,
+            agent_id: "test-agent-001".to_string(),
+            platform: OsType::Linux,
+            compressed,
+            extensions: Extensions::new(),
+        },
+        payload,
+        payload_extensions: Extensions::new(),
+    }
+}
+
+fn snapshot(process_count: u32, name: impl Fn(u32) -> String) -> MessagePayload {
+    let processes = (0..process_count)
+        .map(|pid| ProcessSample {
+            pid,
+            name: name(pid),
+            cpu_percent: 1.0,
+            memory_percent: 1.0,
+            memory_bytes: 1_000,
+            cmdline: None,
+        })
+        .collect();
+    MessagePayload::Snapshot(SnapshotPayload {
+        window_start_secs: 1703174400,
+        window_end_secs: 1703174410,
+        total_cpu_percent: 10.0,
+        memory_used_bytes: 1_000_000_000,
+        memory_total_bytes: 8_000_000_000,
+        processes,
+        truncated: false,
+    })
+}
+
+fn encode(encoder: &mut FrameEncoder, message: &Message) -> Vec<u8> {
+    let mut frame = Vec::new();
+    encoder
+        .encode_into(message, &mut frame)
+        .expect("Failed to encode");
+    frame
+}
+
+fn decode(frame: &[u8]) -> Message {
+    FrameCodec::decode_with_options(
+        &mut Cursor::new(frame),
+        &mut DecodeOptions::new().with_mode(DecodeMode::Strict),
+    )
+    .expect("Failed to decode")
+}
+
+/// Heartbeat carrying pseudo-random bytes that zstd cannot shrink.
+fn incompressible() -> Message {
+    let mut state = 2_654_435_761u32;
+    let noise = (0..2048)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as u8
+        })
+        .collect();
+    let mut message = message(MessagePayload::Heartbeat, false);
+    message.payload_extensions.insert(0x8000, noise);
+    message
+}
+
+#[test]
+fn small_payloads_are_sent_uncompressed() {
+    let mut encoder = FrameEncoder::new().with_policy(CompressionPolicy::default());
+    // The policy decides; the envelope flag of the input is ignored
+    let heartbeat = message(MessagePayload::Heartbeat, true);
+    let frame = encode(&mut encoder, &heartbeat);
+
+    assert_eq!(
+        frame,
+        FrameCodec::encode(&message(MessagePayload::Heartbeat, false)).expect("Failed to encode")
+    );
+    assert!(!decode(&frame).envelope.compressed);
+}
+
+#[test]
+fn large_payloads_are_compressed() {
+    let mut encoder = FrameEncoder::new().with_policy(CompressionPolicy::default());
+    let snapshot = message(snapshot(50, |pid| format!("proc-{pid}")), false);
+    let frame = encode(&mut encoder, &snapshot);
+
+    let decoded = decode(&frame);
+    assert!(decoded.envelope.compressed);
+    assert_eq!(decoded.payload, snapshot.payload);
+    assert!(
+        frame.len()
+            < FrameCodec::encode(&snapshot)
+                .expect("Failed to encode")
+                .len()
+    );
+}
+
+#[test]
+fn compression_is_dropped_when_it_does_not_shrink_the_payload() {
+    let heartbeat = incompressible();
+
+    let mut encoder = FrameEncoder::new().with_policy(CompressionPolicy::default());
+    let frame = encode(&mut encoder, &heartbeat);
+    assert!(!decode(&frame).envelope.compressed);
+    assert_eq!(
+        frame,
+        FrameCodec::encode(&heartbeat).expect("Failed to encode")
+    );
+
+    let always = CompressionPolicy {
+        only_if_smaller: false,
+        ..CompressionPolicy::default()
+    };
+    let mut encoder = FrameEncoder::new().with_policy(always);
+    let frame = encode(&mut encoder, &heartbeat);
+    assert!(decode(&frame).envelope.compressed);
+}
+
+#[test]
+fn level_is_configurable() {
+    let snapshot = message(snapshot(200, |pid| format!("process-{}", pid % 7)), false);
+    let frames: Vec<Vec<u8>> = [1, 19]
+        .into_iter()
+        .map(|level| {
+            let policy = CompressionPolicy {
+                level,
+                ..CompressionPolicy::default()
+            };
+            encode(&mut FrameEncoder::new().with_policy(policy), &snapshot)
+        })
+        .collect();
+
+    assert_ne!(frames[0], frames[1]);
+    for frame in &frames {
+        assert_eq!(decode(frame).payload, snapshot.payload);
+    }
+}
+
+#[test]
+fn policy_follows_handshake() {
+    let agent = AgentIdentity {
+        instance_id: "test-agent-001".to_string(),
+        os_type: OsType::Linux,
+        agent_version: "1.0.0".to_string(),
+        supported_versions: VersionRange::CURRENT,
+        capabilities: AgentIdentity::CAP_COMPRESSION
+            | AgentIdentity::compression_level_capabilities(9),
+    };
+    assert_eq!(agent.compression_level(), Some(9));
+
+    let server = AgentIdentity::CAP_COMPRESSION | AgentIdentity::compression_level_capabilities(5);
+    let ack = negotiate(&agent, VersionRange::CURRENT, server).expect("Failed to negotiate");
+    assert_eq!(ack.compression_level(), Some(5));
+    let policy = CompressionPolicy::negotiated(&ack);
+    assert!(policy.enabled);
+    assert_eq!(policy.level, 5);
+
+    let ack = negotiate(
+        &agent,
+        VersionRange::CURRENT,
+        AgentIdentity::CAP_COMPRESSION,
+    )
+    .expect("Failed to negotiate");
+    assert_eq!(CompressionPolicy::negotiated(&ack).level, 9);
+
+    let ack = negotiate(&agent, VersionRange::CURRENT, 0).expect("Failed to negotiate");
+    assert_eq!(ack.negotiated_capabilities, 0);
+    let policy = CompressionPolicy::negotiated(&ack);
+    assert_eq!(policy, CompressionPolicy::disabled());
+
+    let mut encoder = FrameEncoder::new().with_policy(policy);
+    let frame = encode(
+        &mut encoder,
+        &message(snapshot(50, |pid| format!("p{pid}")), true),
+    );
+    assert!(!decode(&frame).envelope.compressed);
+}
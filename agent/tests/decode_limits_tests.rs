@@ -0,0 +1,246 @@
+//! Integration tests for `DecodeLimits`.
+//!
+//! Crafts frames whose declared lengths, counts or compressed sizes exceed the limits and
+//! checks that every decode path rejects them with the specific error before allocating.
+
+use agent::protocol::*;
+use std::io::Cursor;
+
+// Test helper: Create a 16-byte message ID from a u64 for simplicity in tests
+fn test_message_id(n: u64) -> [u8; 16] {
+    let mut id = [0u8; 16];
+    id[0..8].copy_from_slice(&n.to_le_bytes());
+    id
+}
+
+fn message(payload: MessagePayload, message_type: MessageType, compressed: bool) -> Message {
+    Message {
+        envelope: Envelope {
+            version: ProtocolVersion::CURRENT,
+            message_type,
+            message_id: test_message_id(1),
+            timestamp_utc_ms: 1703174400000,
+            agent_id: "test-agent-001".to_string(),
+            platform: OsType::Linux,
+            compressed,
+            extensions: Extensions::new(),
+        },
+        payload,
+        payload_extensions: Extensions::new(),
+    }
+}
+
+fn snapshot(processes: Vec<ProcessSample>) -> MessagePayload {
+    MessagePayload::Snapshot(SnapshotPayload {
+        window_start_secs: 1703174400,
+        window_end_secs: 1703174410,
+        total_cpu_percent: 10.0,
+        memory_used_bytes: 1_000_000_000,
+        memory_total_bytes: 8_000_000_000,
+        processes,
+        truncated: false,
+    })
+}
+
+fn process(pid: u32, cmdline: Option<String>) -> ProcessSample {
+    ProcessSample {
+        pid,
+        name: format!("proc-{pid}"),
+        cpu_percent: 1.0,
+        memory_percent: 1.0,
+        memory_bytes: 1_000,
+        cmdline,
+    }
+}
+
+/// Length of an uncompressed envelope with agent id "test-agent-001" and no extensions.
+const ENVELOPE_LEN: usize = 1 + 1 + 1 + 16 + 8 + 8 + 14 + 1 + 1;
+
+/// Rebuild a frame around a modified body with a valid CRC32.
+fn reframe(body: &[u8]) -> Vec<u8> {
+    let mut frame = (body.len() as u32).to_be_bytes().to_vec();
+    frame.extend_from_slice(body);
+    frame.extend_from_slice(&crc32fast::hash(body).to_le_bytes());
+    frame
+}
+
+fn body_of(message: &Message) -> Vec<u8> {
+    let frame = FrameCodec::encode(message).expect("Failed to encode");
+    frame[4..frame.len() - 4].to_vec()
+}
+
+#[test]
+fn huge_string_length_is_rejected_before_allocation() {
+    let error = MessagePayload::Error {
+        code: 7,
+        message: "hello".to_string(),
+    };
+    let mut body = body_of(&message(error, MessageType::Error, false));
+    // Error payload: [code u32][len u64][bytes]
+    let len_offset = ENVELOPE_LEN + 4;
+    body[len_offset..len_offset + 8].copy_from_slice(&u64::MAX.to_le_bytes());
+    let frame = reframe(&body);
+
+    assert!(matches!(
+        FrameCodec::decode(&mut Cursor::new(&frame))
+            .unwrap_err()
+            .root_cause(),
+        ProtocolError::StringTooLong { len: u64::MAX, .. }
+    ));
+    let (view, _) = MessageRef::from_frame(&frame).expect("Envelope is valid");
+    assert!(matches!(
+        view.to_owned().unwrap_err().root_cause(),
+        ProtocolError::StringTooLong { .. }
+    ));
+}
+
+#[test]
+fn huge_process_count_is_rejected_before_allocation() {
+    let mut body = body_of(&message(snapshot(vec![]), MessageType::Snapshot, false));
+    // Snapshot payload: window start/end, cpu, memory used/total, then the u64 process count
+    let count_offset = ENVELOPE_LEN + 8 + 8 + 4 + 8 + 8;
+    body[count_offset..count_offset + 8].copy_from_slice(&(1u64 << 40).to_le_bytes());
+    let frame = reframe(&body);
+
+    assert!(matches!(
+        FrameCodec::decode(&mut Cursor::new(&frame)).unwrap_err().root_cause(),
+        ProtocolError::TooManyProcesses { count, .. } if *count == 1 << 40
+    ));
+}
+
+#[test]
+fn delta_count_within_limit_fails_on_missing_elements() {
+    let delta = MessagePayload::SnapshotDelta(Box::new(SnapshotDeltaPayload {
+        base_message_id: test_message_id(9),
+        window_start_secs: 1703174400,
+        window_end_secs: 1703174410,
+        total_cpu_percent: 10.0,
+        memory_used_bytes: 1,
+        memory_total_bytes: 2,
+        added: vec![],
+        changed: vec![],
+        removed: vec![],
+        truncated: false,
+    }));
+    let mut body = body_of(&message(delta, MessageType::SnapshotDelta, false));
+    // Delta payload: base id, window start/end, cpu, memory used/total, then the added count
+    let count_offset = ENVELOPE_LEN + 16 + 8 + 8 + 4 + 8 + 8;
+    let max = DecodeLimits::DEFAULT_MAX_PROCESS_COUNT as u64;
+    body[count_offset..count_offset + 8].copy_from_slice(&max.to_le_bytes());
+    let frame = reframe(&body);
+
+    // The count passes the limit, but only the elements actually present are read
+    let err = FrameCodec::decode(&mut Cursor::new(&frame)).unwrap_err();
+    assert!(err
+        .field_path()
+        .is_some_and(|path| path.starts_with("snapshot_delta.added[0].")));
+    assert!(matches!(err.root_cause(), ProtocolError::Io(_)));
+}
+
+#[test]
+fn custom_limits_apply_to_strings_and_process_counts() {
+    let processes: Vec<ProcessSample> = (0..11).map(|pid| process(pid, None)).collect();
+    let frame = FrameCodec::encode(&message(snapshot(processes), MessageType::Snapshot, false))
+        .expect("Failed to encode");
+
+    let tight = DecodeLimits {
+        max_process_count: 10,
+        ..DecodeLimits::default()
+    };
+    assert!(matches!(
+        FrameCodec::decode_with_options(
+            &mut Cursor::new(&frame),
+            &mut DecodeOptions::new().with_limits(tight)
+        )
+        .unwrap_err()
+        .root_cause(),
+        ProtocolError::TooManyProcesses { count: 11, max: 10 }
+    ));
+    let relaxed = DecodeLimits {
+        max_process_count: 11,
+        ..tight
+    };
+    assert!(FrameCodec::decode_with_options(
+        &mut Cursor::new(&frame),
+        &mut DecodeOptions::new().with_limits(relaxed)
+    )
+    .is_ok());
+
+    // The agent id is 14 bytes long
+    let short_strings = DecodeLimits {
+        max_string_len: 13,
+        ..DecodeLimits::default()
+    };
+    assert!(matches!(
+        FrameCodec::decode_with_options(
+            &mut Cursor::new(&frame),
+            &mut DecodeOptions::new().with_limits(short_strings)
+        )
+        .unwrap_err()
+        .root_cause(),
+        ProtocolError::StringTooLong { len: 14, max: 13 }
+    ));
+    let mut decoder = FrameDecoder::with_limits(short_strings);
+    assert!(matches!(
+        decoder.decode(&frame).unwrap_err().root_cause(),
+        ProtocolError::StringTooLong { .. }
+    ));
+}
+
+#[test]
+fn decompression_bomb_is_rejected_by_ratio() {
+    let bomb = snapshot(vec![process(1, Some("a".repeat(200_000)))]);
+    let frame =
+        FrameCodec::encode(&message(bomb, MessageType::Snapshot, true)).expect("Failed to encode");
+    assert!(frame.len() < 1024, "Payload should compress extremely well");
+
+    assert!(matches!(
+        FrameCodec::decode(&mut Cursor::new(&frame))
+            .unwrap_err()
+            .root_cause(),
+        ProtocolError::CompressionRatioExceeded {
+            max_ratio: DecodeLimits::DEFAULT_MAX_COMPRESSION_RATIO,
+            ..
+        }
+    ));
+    assert!(matches!(
+        MessageRef::from_frame(&frame).unwrap_err().root_cause(),
+        ProtocolError::CompressionRatioExceeded { .. }
+    ));
+
+    let permissive = DecodeLimits {
+        max_compression_ratio: usize::MAX,
+        max_string_len: 256 * 1024,
+        ..DecodeLimits::default()
+    };
+    assert!(FrameCodec::decode_with_options(
+        &mut Cursor::new(&frame),
+        &mut DecodeOptions::new().with_limits(permissive)
+    )
+    .is_ok());
+}
+
+#[test]
+fn decompressed_size_limit_is_enforced() {
+    let processes: Vec<ProcessSample> = (0..200)
+        .map(|pid| process(pid, Some(format!("/usr/bin/worker --id {pid}"))))
+        .collect();
+    let frame = FrameCodec::encode(&message(snapshot(processes), MessageType::Snapshot, true))
+        .expect("Failed to encode");
+
+    let limits = DecodeLimits {
+        max_decompressed_size: 1024,
+        max_compression_ratio: usize::MAX,
+        ..DecodeLimits::default()
+    };
+    assert!(matches!(
+        FrameCodec::decode_with_options(
+            &mut Cursor::new(&frame),
+            &mut DecodeOptions::new().with_limits(limits)
+        )
+        .unwrap_err()
+        .root_cause(),
+        ProtocolError::DecompressedSizeExceeded { max: 1024 }
+    ));
+    assert!(FrameCodec::decode(&mut Cursor::new(&frame)).is_ok());
+}
@@ -1,7 +1,31 @@
-use agent::demo_protocol::{build_demo_message, encode_demo_frame_bytes};
-use agent::protocol::{FrameCodec, MessagePayload, OsType};
+#![cfg(feature = "json")]
+#![allow(clippy::bool_assert_comparison)]
+
+use agent::demo_protocol::{
+    build_demo_message, build_demo_messages, build_random_snapshot, corrupt_legacy_frame,
+    encode_demo_frame_bytes, format_message_for_console, Corruption, ProducerArgs, DEFAULT_SEED,
+};
+use agent::protocol::{FrameCodec, Message, MessagePayload, MessageType, OsType, ProtocolError};
 use std::io::Cursor;
 
+fn parse(args: &[&str]) -> Result<ProducerArgs, String> {
+    ProducerArgs::parse_from(args.iter().map(|arg| arg.to_string()))
+}
+
+fn decode_all(data: &[u8]) -> Vec<Result<Message, ProtocolError>> {
+    let mut cursor = Cursor::new(data);
+    let mut results = Vec::new();
+    while (cursor.position() as usize) < data.len() {
+        let result = FrameCodec::decode(&mut cursor);
+        let failed = result.is_err();
+        results.push(result);
+        if failed {
+            break;
+        }
+    }
+    results
+}
+
 #[test]
 fn demo_message_is_deterministic() {
     let message_a = build_demo_message(OsType::Linux);
@@ -48,3 +72,180 @@ fn demo_frame_round_trips_through_framecodec() {
     assert_eq!(snapshot.processes[1].cmdline, None);
     assert_eq!(snapshot.truncated, false);
 }
+
+#[test]
+fn default_args_write_the_fixed_demo_snapshot() {
+    let args = parse(&[]).expect("parse");
+    assert_eq!(args.count, 1);
+    assert_eq!(args.message_type, MessageType::Snapshot);
+    assert!(!args.compressed && args.processes.is_none() && args.corruption.is_none());
+    assert_eq!(
+        build_demo_messages(&args, OsType::Linux),
+        vec![build_demo_message(OsType::Linux)]
+    );
+}
+
+#[test]
+fn args_parse_every_option_and_reject_bad_values() {
+    let args = parse(&[
+        "--out",
+        "x.bin",
+        "--count",
+        "3",
+        "--type",
+        "SnapshotPart",
+        "--compress",
+        "--processes",
+        "20",
+        "--seed",
+        "99",
+        "--corrupt",
+        "crc",
+    ])
+    .expect("parse");
+    assert_eq!(args.out_path.to_str(), Some("x.bin"));
+    assert_eq!(args.count, 3);
+    assert_eq!(args.message_type, MessageType::SnapshotPart);
+    assert!(args.compressed);
+    assert_eq!(args.processes, Some(20));
+    assert_eq!(args.seed, 99);
+    assert_eq!(args.corruption, Some(Corruption::Crc));
+
+    for (bad, expected) in [
+        (&["--type", "snapshot"][..], "Invalid --type value"),
+        (&["--corrupt", "bits"], "Invalid --corrupt value"),
+        (&["--processes", "-1"], "Invalid --processes value"),
+        (&["--seed"], "--seed requires"),
+        (
+            &["--type", "Heartbeat", "--processes", "5"],
+            "--processes needs",
+        ),
+        (&["--count", "0"], "--count must be"),
+        (&["--processes", "10001"], "--processes must be"),
+    ] {
+        let err = parse(bad).expect_err("should fail");
+        assert!(err.contains(expected), "{bad:?}: {err}");
+    }
+    for kind in Corruption::ALL {
+        assert_eq!(Corruption::from_name(kind.name()), Some(kind));
+        assert!(ProducerArgs::usage().contains(kind.name()));
+    }
+}
+
+#[test]
+fn every_message_type_round_trips_plain_and_compressed() {
+    for message_type in (1..=9).map(|n| MessageType::from_u8(n).expect("type")) {
+        for compressed in [false, true] {
+            let args = ProducerArgs {
+                message_type,
+                compressed,
+                count: 2,
+                ..ProducerArgs::default()
+            };
+            let messages = build_demo_messages(&args, OsType::Windows);
+            assert_eq!(messages.len(), 2);
+            for message in &messages {
+                assert_eq!(message.envelope.message_type, message_type);
+                assert_eq!(message.envelope.compressed, compressed);
+                let frame = encode_demo_frame_bytes(message).expect("encode");
+                let decoded = FrameCodec::decode(&mut Cursor::new(frame)).expect("decode");
+                assert_eq!(
+                    &decoded, message,
+                    "{message_type:?} compressed={compressed}"
+                );
+            }
+
+            let console = format_message_for_console(&messages[0], 1);
+            assert!(console.contains(&format!("message_type={}", message_type.name())));
+            if message_type != MessageType::Snapshot {
+                let json = console
+                    .lines()
+                    .find_map(|line| line.strip_prefix("json="))
+                    .expect("json line");
+                assert_eq!(&Message::from_json(json).expect("parse"), &messages[0]);
+            }
+        }
+    }
+}
+
+#[test]
+fn random_snapshots_are_seeded() {
+    let snapshot = build_random_snapshot(40, 7);
+    assert_eq!(snapshot.processes.len(), 40);
+    assert_eq!(snapshot, build_random_snapshot(40, 7));
+    assert_ne!(snapshot, build_random_snapshot(40, 8));
+    assert!(snapshot.total_cpu_percent <= 100.0);
+    assert!(snapshot.memory_used_bytes <= snapshot.memory_total_bytes);
+    assert!(build_random_snapshot(0, 7).processes.is_empty());
+
+    // Frames of one file differ from each other, but the file is reproducible
+    let args = parse(&["--count", "3", "--processes", "40", "--seed", "7"]).expect("parse");
+    let messages = build_demo_messages(&args, OsType::Linux);
+    assert_eq!(messages, build_demo_messages(&args, OsType::Linux));
+    let MessagePayload::Snapshot(first) = &messages[0].payload else {
+        panic!("expected Snapshot payload");
+    };
+    assert_eq!(first, &snapshot);
+    assert_ne!(messages[0], messages[1]);
+
+    let delta = parse(&["--type", "SnapshotDelta", "--processes", "10"]).expect("parse");
+    let MessagePayload::SnapshotDelta(delta) =
+        &build_demo_messages(&delta, OsType::Linux)[0].payload
+    else {
+        panic!("expected SnapshotDelta payload");
+    };
+    assert_eq!(delta.added.len() + delta.changed.len(), 10);
+    assert_eq!(
+        build_random_snapshot(10, DEFAULT_SEED).processes[0],
+        delta.added[0]
+    );
+}
+
+#[test]
+fn corrupted_last_frame_fails_to_decode() {
+    for kind in Corruption::ALL {
+        let args = ProducerArgs {
+            count: 3,
+            ..ProducerArgs::default()
+        };
+        let mut frames: Vec<Vec<u8>> = build_demo_messages(&args, OsType::Linux)
+            .iter()
+            .map(|message| encode_demo_frame_bytes(message).expect("encode"))
+            .collect();
+        corrupt_legacy_frame(frames.last_mut().expect("frame"), kind).expect("corrupt");
+
+        // Frames before the damaged one are untouched; trailing bytes follow an intact frame
+        let intact = if kind == Corruption::Trailing { 3 } else { 2 };
+        let results = decode_all(&frames.concat());
+        assert_eq!(results.len(), intact + 1, "{kind:?}");
+        assert!(results[..intact].iter().all(Result::is_ok), "{kind:?}");
+        let err = results[intact]
+            .as_ref()
+            .expect_err("damaged frame decoded")
+            .root_cause();
+        let expected = match kind {
+            Corruption::Crc => matches!(err, ProtocolError::Crc32Mismatch { .. }),
+            Corruption::Length => matches!(err, ProtocolError::FrameTooLarge(..)),
+            Corruption::Truncate | Corruption::Trailing => matches!(err, ProtocolError::Io(_)),
+            Corruption::Version => matches!(err, ProtocolError::IncompatibleVersion),
+            Corruption::Type => matches!(err, ProtocolError::InvalidMessageType(0xFF)),
+        };
+        assert!(expected, "{kind:?}: {err:?}");
+    }
+
+    // Only whole legacy-layout frames can be damaged
+    let frame = encode_demo_frame_bytes(&build_demo_message(OsType::Linux)).expect("encode");
+    for mut bad in [
+        frame[..6].to_vec(),
+        frame[..frame.len() - 1].to_vec(),
+        Vec::new(),
+    ] {
+        let before = bad.clone();
+        let err = corrupt_legacy_frame(&mut bad, Corruption::Type).expect_err("not a frame");
+        assert!(
+            matches!(err, ProtocolError::InvalidFrameLength(_)),
+            "{err:?}"
+        );
+        assert_eq!(bad, before);
+    }
+}
@@ -0,0 +1,246 @@
+//! Integration tests for the wire error code registry.
+
+use agent::protocol::*;
+use std::io::Cursor;
+
+// Test helper: Create a 16-byte message ID from a u64 for simplicity in tests
+fn test_message_id(n: u64) -> [u8; 16] {
+    let mut id = [0u8; 16];
+    id[0..8].copy_from_slice(&n.to_le_bytes());
+    id
+}
+
+fn message(payload: MessagePayload) -> Message {
+    Message {
+        envelope: Envelope {
+            version: ProtocolVersion::CURRENT,
+            message_type: payload.message_type(),
+            message_id: test_message_id(1),
+            timestamp_utc_ms: 1703174400000,
+            agent_id: "test-agent-001".to_string(),
+            platform: OsType::Linux,
+            compressed: false,
+            extensions: Extensions::new(),
+        },
+        payload,
+        payload_extensions: Extensions::new(),
+    }
+}
+
+fn round_trip(message: &Message) -> Message {
+    FrameCodec::decode(&mut Cursor::new(
+        FrameCodec::encode(message).expect("Failed to encode"),
+    ))
+    .expect("Failed to decode")
+}
+
+#[test]
+fn known_codes_have_stable_values_and_semantics() {
+    let expected = [
+        (ErrorCode::UnsupportedVersion, 1001, ErrorAction::GiveUp),
+        (ErrorCode::FrameTooLarge, 1002, ErrorAction::Drop),
+        (ErrorCode::CrcMismatch, 1003, ErrorAction::Reconnect),
+        (ErrorCode::MalformedPayload, 1004, ErrorAction::Drop),
+        (
+            ErrorCode::UnknownDeltaBase,
+            1005,
+            ErrorAction::SendFullSnapshot,
+        ),
+        (ErrorCode::Unauthenticated, 2001, ErrorAction::GiveUp),
+        (ErrorCode::Throttled, 3001, ErrorAction::Retry),
+        (ErrorCode::Internal, 5001, ErrorAction::Retry),
+    ];
+    assert_eq!(
+        ErrorCode::KNOWN.to_vec(),
+        expected.iter().map(|(code, ..)| *code).collect::<Vec<_>>()
+    );
+    for (code, value, action) in expected {
+        assert_eq!(code.to_u32(), value);
+        assert_eq!(ErrorCode::from_u32(value), code);
+        assert_eq!(code.action(), action, "{code:?}");
+    }
+
+    assert_eq!(ErrorCode::CrcMismatch.category(), ErrorCategory::Protocol);
+    assert_eq!(
+        ErrorCode::Unauthenticated.category(),
+        ErrorCategory::Security
+    );
+    assert_eq!(ErrorCode::Throttled.category(), ErrorCategory::FlowControl);
+    assert_eq!(ErrorCode::Internal.category(), ErrorCategory::Server);
+    assert!(ErrorCode::Throttled.is_retryable());
+    assert!(!ErrorCode::Throttled.is_fatal());
+    assert!(ErrorCode::Unauthenticated.is_fatal());
+    assert!(!ErrorCode::UnknownDeltaBase.is_retryable());
+}
+
+#[test]
+fn unknown_codes_round_trip_and_follow_their_category() {
+    for (value, category, action) in [
+        (1999, ErrorCategory::Protocol, ErrorAction::Drop),
+        (2042, ErrorCategory::Security, ErrorAction::GiveUp),
+        (3007, ErrorCategory::FlowControl, ErrorAction::Retry),
+        (5100, ErrorCategory::Server, ErrorAction::Retry),
+        (7, ErrorCategory::Unassigned, ErrorAction::Drop),
+    ] {
+        let code = ErrorCode::from(value);
+        assert_eq!(code, ErrorCode::Unknown(value));
+        assert_eq!(u32::from(code), value);
+        assert_eq!(code.category(), category);
+        assert_eq!(code.action(), action, "{value}");
+
+        let error = message(MessagePayload::Error {
+            code: value,
+            message: "from a newer server".to_string(),
+        });
+        assert_eq!(round_trip(&error).payload.error_code(), Some(code));
+
+        let ack = message(MessagePayload::Ack(MessageAck::failed(
+            test_message_id(2),
+            code,
+        )));
+        match round_trip(&ack).payload {
+            MessagePayload::Ack(ack) => {
+                assert_eq!(ack.error_code, Some(value));
+                assert_eq!(ack.code(), Some(code));
+            }
+            other => panic!("Expected ack, got {other:?}"),
+        }
+    }
+}
+
+#[test]
+fn protocol_errors_map_to_wire_errors() {
+    let mut frame =
+        FrameCodec::encode(&message(MessagePayload::Heartbeat)).expect("Failed to encode");
+    let last = frame.len() - 1;
+    frame[last] ^= 0xFF;
+    let err = FrameCodec::decode(&mut Cursor::new(&frame)).unwrap_err();
+    assert_eq!(err.error_code(), ErrorCode::CrcMismatch);
+    assert_eq!(err.error_code().action(), ErrorAction::Reconnect);
+
+    // Located errors map by their root cause
+    let located = ProtocolError::Field {
+        path: "snapshot.processes[0].name".to_string(),
+        offset: 42,
+        source: Box::new(ProtocolError::StringTooLong {
+            len: 9_999,
+            max: 256,
+        }),
+    };
+    assert_eq!(located.error_code(), ErrorCode::MalformedPayload);
+    match located.to_wire_error() {
+        MessagePayload::Error { code, message } => {
+            assert_eq!(code, 1004);
+            assert!(message.contains("snapshot.processes[0].name"));
+        }
+        other => panic!("Expected error payload, got {other:?}"),
+    }
+
+    for (err, code) in [
+        (
+            ProtocolError::IncompatibleVersion,
+            ErrorCode::UnsupportedVersion,
+        ),
+        (
+            ProtocolError::FrameTooLarge(20_000_000, 16_777_216),
+            ErrorCode::FrameTooLarge,
+        ),
+        (
+            ProtocolError::DecompressedSizeExceeded { max: 1024 },
+            ErrorCode::FrameTooLarge,
+        ),
+        (
+            ProtocolError::AuthenticationFailed("tag mismatch".to_string()),
+            ErrorCode::Unauthenticated,
+        ),
+        (
+            ProtocolError::Encryption("payload authentication failed".to_string()),
+            ErrorCode::Unauthenticated,
+        ),
+        (
+            ProtocolError::UnknownDeltaBase(test_message_id(3)),
+            ErrorCode::UnknownDeltaBase,
+        ),
+        (
+            ProtocolError::InvalidMessageType(99),
+            ErrorCode::MalformedPayload,
+        ),
+    ] {
+        assert_eq!(ErrorCode::from(&err), code, "{err}");
+        assert_eq!(err.to_wire_error().error_code(), Some(code));
+    }
+
+    assert_eq!(MessagePayload::Heartbeat.error_code(), None);
+}
+
+#[test]
+fn truncated_payloads_are_malformed_not_internal() {
+    // A valid CRC over a snapshot payload cut short: the last field hits end of input
+    let snapshot = message(MessagePayload::Snapshot(SnapshotPayload {
+        window_start_secs: 1703174400,
+        window_end_secs: 1703174410,
+        total_cpu_percent: 1.0,
+        memory_used_bytes: 1,
+        memory_total_bytes: 2,
+        processes: Vec::new(),
+        truncated: false,
+    }));
+    let frame = FrameCodec::encode(&snapshot).expect("Failed to encode");
+    let mut body = frame[4..frame.len() - 4].to_vec();
+    body.truncate(body.len() - 3);
+    let mut truncated = (body.len() as u32).to_be_bytes().to_vec();
+    truncated.extend_from_slice(&body);
+    truncated.extend_from_slice(&crc32fast::hash(&body).to_le_bytes());
+
+    let err = FrameCodec::decode(&mut Cursor::new(&truncated)).unwrap_err();
+    assert!(matches!(err.root_cause(), ProtocolError::Io(_)), "{err:?}");
+    assert_eq!(err.error_code(), ErrorCode::MalformedPayload);
+    assert_eq!(err.error_code().action(), ErrorAction::Drop);
+
+    for (kind, code) in [
+        (
+            std::io::ErrorKind::UnexpectedEof,
+            ErrorCode::MalformedPayload,
+        ),
+        (std::io::ErrorKind::InvalidData, ErrorCode::MalformedPayload),
+        (std::io::ErrorKind::BrokenPipe, ErrorCode::Internal),
+    ] {
+        let located = ProtocolError::Field {
+            path: "snapshot.truncated".to_string(),
+            offset: 51,
+            source: Box::new(ProtocolError::Io(std::io::Error::from(kind))),
+        };
+        assert_eq!(located.error_code(), code, "{kind:?}");
+    }
+}
+
+#[test]
+fn unknown_delta_base_asks_for_a_full_snapshot() {
+    let err = ProtocolError::UnknownDeltaBase(test_message_id(3));
+    assert_eq!(err.error_code(), ErrorCode::UnknownDeltaBase);
+    assert!(!err.error_code().is_retryable());
+    assert_eq!(err.error_code().action(), ErrorAction::SendFullSnapshot);
+
+    // The server answers a delta it cannot resolve with an ack asking for a full snapshot
+    let mut store = SnapshotBaseStore::new();
+    let delta = message(MessagePayload::SnapshotDelta(Box::new(
+        SnapshotDeltaPayload {
+            base_message_id: test_message_id(3),
+            window_start_secs: 1703174400,
+            window_end_secs: 1703174410,
+            total_cpu_percent: 0.0,
+            memory_used_bytes: 0,
+            memory_total_bytes: 0,
+            added: Vec::new(),
+            changed: Vec::new(),
+            removed: Vec::new(),
+            truncated: false,
+        },
+    )));
+    let err = store.resolve(&delta).unwrap_err();
+    let ack = MessageAck::failed(delta.envelope.message_id, err.error_code());
+    assert_eq!(
+        ack.code().map(ErrorCode::action),
+        Some(ErrorAction::SendFullSnapshot)
+    );
+}
@@ -0,0 +1,206 @@
+//! Integration tests for envelope and payload extension areas.
+//!
+//! Validates that tagged optional fields round-trip, that unknown tags survive decode and
+//! re-encode, and that messages without extensions keep the original v1.0 byte layout.
+
+use agent::protocol::*;
+use std::io::Cursor;
+
+// Test helper: Create a 16-byte message ID from a u64 for simplicity in tests
+fn test_message_id(n: u64) -> [u8; 16] {
+    let mut id = [0u8; 16];
+    id[0..8].copy_from_slice(&n.to_le_bytes());
+    id
+}
+
+fn snapshot_message(compressed: bool) -> Message {
+    Message {
+        envelope: Envelope {
+            version: ProtocolVersion::CURRENT,
+            message_type: MessageType::Snapshot,
+            message_id: test_message_id(1),
+            timestamp_utc_ms: 1703174410000,
+            agent_id: "test-agent-001".to_string(),
+            platform: OsType::Linux,
+            compressed,
+            extensions: Extensions::new(),
+        },
+        payload: MessagePayload::Snapshot(SnapshotPayload {
+            window_start_secs: 1703174400,
+            window_end_secs: 1703174410,
+            total_cpu_percent: 12.5,
+            memory_used_bytes: 1_500_000_000,
+            memory_total_bytes: 8_000_000_000,
+            processes: vec![ProcessSample {
+                pid: 1234,
+                name: "test-process".to_string(),
+                cpu_percent: 1.5,
+                memory_percent: 0.5,
+                memory_bytes: 50_000_000,
+                cmdline: Some("/usr/bin/test".to_string()),
+            }],
+            truncated: true,
+        }),
+        payload_extensions: Extensions::new(),
+    }
+}
+
+/// Offset of the envelope flags byte in a frame whose agent id is "test-agent-001".
+const FLAGS_OFFSET: usize = 4 + 1 + 1 + 1 + 16 + 8 + 8 + 14 + 1;
+
+#[test]
+fn known_extensions_round_trip_with_typed_accessors() {
+    for compressed in [false, true] {
+        let mut message = snapshot_message(compressed);
+        message.envelope.set_sequence_number(42);
+        message.set_total_process_count(1_500);
+
+        let frame = FrameCodec::encode(&message).expect("Failed to encode");
+        let decoded = FrameCodec::decode(&mut Cursor::new(frame)).expect("Failed to decode");
+
+        assert_eq!(decoded, message);
+        assert_eq!(decoded.envelope.sequence_number().expect("Valid"), Some(42));
+        assert_eq!(decoded.total_process_count().expect("Valid"), Some(1_500));
+    }
+}
+
+#[test]
+fn unknown_tags_are_kept_and_re_encoded() {
+    let mut message = snapshot_message(true);
+    message.envelope.extensions.insert(0x7F00, vec![1, 2, 3]);
+    message
+        .payload_extensions
+        .insert(0x8001, b"future".to_vec());
+    message.payload_extensions.insert(0x0002, Vec::new());
+
+    let frame = FrameCodec::encode(&message).expect("Failed to encode");
+    let decoded = FrameCodec::decode(&mut Cursor::new(frame.clone())).expect("Failed to decode");
+
+    assert_eq!(
+        decoded.envelope.extensions.get(0x7F00),
+        Some(&[1u8, 2, 3][..])
+    );
+    let tags: Vec<u16> = decoded
+        .payload_extensions
+        .iter()
+        .map(|(tag, _)| tag)
+        .collect();
+    assert_eq!(tags, vec![0x8001, 0x0002], "Decode order must be preserved");
+    assert_eq!(decoded.envelope.sequence_number().expect("Valid"), None);
+
+    // Re-encoding a relayed message reproduces the original bytes
+    let re_encoded = FrameCodec::encode(&decoded).expect("Failed to re-encode");
+    assert_eq!(re_encoded, frame);
+}
+
+#[test]
+fn messages_without_extensions_keep_v1_layout() {
+    let plain = FrameCodec::encode(&snapshot_message(false)).expect("Failed to encode");
+    assert_eq!(plain[FLAGS_OFFSET], 0x00);
+
+    let compressed = FrameCodec::encode(&snapshot_message(true)).expect("Failed to encode");
+    assert_eq!(compressed[FLAGS_OFFSET], 0x01);
+
+    let mut with_envelope_ext = snapshot_message(false);
+    with_envelope_ext.envelope.set_sequence_number(1);
+    let frame = FrameCodec::encode(&with_envelope_ext).expect("Failed to encode");
+    assert_eq!(frame[FLAGS_OFFSET], 0x02);
+
+    // A payload trailer leaves the fixed fields (and the frame prefix) untouched
+    let mut with_payload_ext = snapshot_message(false);
+    with_payload_ext.set_total_process_count(7);
+    let frame = FrameCodec::encode(&with_payload_ext).expect("Failed to encode");
+    let plain_body_end = plain.len() - 4;
+    assert_eq!(frame[4..plain_body_end], plain[4..plain_body_end]);
+    assert_eq!(frame[FLAGS_OFFSET], 0x00);
+}
+
+#[test]
+fn lenient_decode_ignores_trailing_bytes_that_are_not_an_extension_area() {
+    let message = snapshot_message(false);
+    let frame = FrameCodec::encode(&message).expect("Failed to encode");
+    for trailer in [
+        &[0xFF][..],
+        &[0x05, 0x00, 0x01],
+        b"arbitrary trailing bytes",
+    ] {
+        let mut body = frame[4..frame.len() - 4].to_vec();
+        body.extend_from_slice(trailer);
+        let mut padded = (body.len() as u32).to_be_bytes().to_vec();
+        padded.extend_from_slice(&body);
+        padded.extend_from_slice(&crc32fast::hash(&body).to_le_bytes());
+
+        let decoded = FrameCodec::decode(&mut Cursor::new(&padded)).expect("Lenient decode");
+        assert_eq!(decoded, message, "{trailer:?}");
+        let err = FrameCodec::decode_with_options(
+            &mut Cursor::new(&padded),
+            &mut DecodeOptions::new().with_mode(DecodeMode::Strict),
+        )
+        .unwrap_err();
+        assert_eq!(err.field_path(), Some("payload_extensions"), "{trailer:?}");
+    }
+}
+
+#[test]
+fn known_tag_with_wrong_size_is_rejected_by_accessor() {
+    let mut message = snapshot_message(false);
+    message
+        .envelope
+        .extensions
+        .insert(tags::SEQUENCE_NUMBER, vec![1, 2, 3]);
+
+    let frame = FrameCodec::encode(&message).expect("Failed to encode");
+    let decoded =
+        FrameCodec::decode(&mut Cursor::new(frame)).expect("Unknown layout still decodes");
+
+    assert!(matches!(
+        decoded.envelope.sequence_number(),
+        Err(ProtocolError::InvalidExtension {
+            tag: tags::SEQUENCE_NUMBER,
+            ..
+        })
+    ));
+}
+
+#[test]
+fn extensions_insert_replaces_and_remove_returns_value() {
+    let mut extensions = Extensions::new();
+    assert!(extensions.is_empty());
+
+    extensions.insert_u32(0x10, 5);
+    extensions.insert_str(0x11, "eu-west");
+    extensions.insert_u32(0x10, 6);
+
+    assert_eq!(extensions.len(), 2);
+    assert_eq!(extensions.get_u32(0x10).expect("Valid"), Some(6));
+    assert_eq!(extensions.get_str(0x11).expect("Valid"), Some("eu-west"));
+    assert_eq!(extensions.remove(0x11), Some(b"eu-west".to_vec()));
+    assert!(!extensions.contains(0x11));
+}
+
+#[test]
+fn extension_area_over_count_limit_is_an_encode_error() {
+    // Every u16 tag once: one entry more than the u16 count can describe
+    let entries: Vec<serde_json::Value> = (0..=u16::MAX)
+        .map(|tag| serde_json::json!([tag, []]))
+        .collect();
+    let extensions: Extensions =
+        serde_json::from_value(serde_json::json!({ "entries": entries })).expect("Valid JSON");
+    assert_eq!(extensions.len(), usize::from(u16::MAX) + 1);
+
+    let mut message = snapshot_message(false);
+    message.payload_extensions = extensions.clone();
+    assert!(matches!(
+        FrameCodec::encode(&message),
+        Err(ProtocolError::InvalidExtension { .. })
+    ));
+
+    let mut message = snapshot_message(false);
+    message.envelope.extensions = extensions;
+    let mut out = vec![0xAA];
+    assert!(matches!(
+        FrameEncoder::new().encode_into(&message, &mut out),
+        Err(ProtocolError::InvalidExtension { .. })
+    ));
+    assert_eq!(out, [0xAA], "Failed encode must leave the buffer unchanged");
+}
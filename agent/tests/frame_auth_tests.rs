@@ -0,0 +1,194 @@
+//! Integration tests for HMAC-SHA256 frame authentication and key rotation.
+
+use agent::protocol::*;
+use std::io::Cursor;
+use std::sync::Arc;
+
+// Test helper: Create a 16-byte message ID from a u64 for simplicity in tests
+fn test_message_id(n: u64) -> [u8; 16] {
+    let mut id = [0u8; 16];
+    id[0..8].copy_from_slice(&n.to_le_bytes());
+    id
+}
+
+fn message(agent_id: &str, timestamp_utc_ms: i64, compressed: bool) -> Message {
+    let processes = (0..40)
+        .map(|pid| ProcessSample {
+            pid,
+            name: format!("proc-{pid}"),
+            cpu_percent: 1.0,
+            memory_percent: 1.0,
+            memory_bytes: 1_000,
+            cmdline: None,
+        })
+        .collect();
+    let payload = MessagePayload::Snapshot(SnapshotPayload {
+        window_start_secs: 1703174400,
+        window_end_secs: 1703174410,
+        total_cpu_percent: 10.0,
+        memory_used_bytes: 1_000_000_000,
+        memory_total_bytes: 8_000_000_000,
+        processes,
+        truncated: false,
+    });
+    Message {
+        envelope: Envelope {
+            version: ProtocolVersion::CURRENT,
+            message_type: payload.message_type(),
+            message_id: test_message_id(1),
+            timestamp_utc_ms,
+            agent_id: agent_id.to_string(),
+            platform: OsType::Linux,
+            compressed,
+            extensions: Extensions::new(),
+        },
+        payload,
+        payload_extensions: Extensions::new(),
+    }
+}
+
+fn key_ring(agent_id: &str, keys: impl IntoIterator<Item = AuthKey>) -> Arc<KeyRing> {
+    let mut ring = KeyRing::new();
+    for key in keys {
+        ring.insert(agent_id, key);
+    }
+    Arc::new(ring)
+}
+
+fn sign(keys: Arc<KeyRing>, message: &Message) -> Result<Vec<u8>, ProtocolError> {
+    let mut frame = Vec::new();
+    FrameEncoder::new()
+        .with_key_store(keys)
+        .encode_into(message, &mut frame)?;
+    Ok(frame)
+}
+
+fn verify(frame: &[u8], keys: &KeyRing) -> Result<Message, ProtocolError> {
+    FrameCodec::decode_with_options(
+        &mut Cursor::new(frame),
+        &mut DecodeOptions::new()
+            .with_mode(DecodeMode::Strict)
+            .with_key_store(keys),
+    )
+}
+
+/// Flip one body byte and fix up the CRC32, as a forger would.
+fn tamper(frame: &mut [u8], body_offset: usize) {
+    let body_end = frame.len() - 4;
+    frame[4 + body_offset] ^= 0x01;
+    let checksum = crc32fast::hash(&frame[4..body_end]);
+    frame[body_end..].copy_from_slice(&checksum.to_le_bytes());
+}
+
+#[test]
+fn authenticated_frames_round_trip() {
+    let keys = key_ring("test-agent-001", [AuthKey::new(1, *b"shared secret")]);
+    for compressed in [false, true] {
+        let message = message("test-agent-001", 1703174400000, compressed);
+        let frame = sign(keys.clone(), &message).expect("Failed to encode");
+
+        let decoded = verify(&frame, &keys).expect("Failed to decode");
+        assert_eq!(decoded.envelope.key_id().expect("Invalid key id"), Some(1));
+        assert_eq!(decoded.payload, message.payload);
+
+        // Decoders without a key store skip the tag
+        let unverified = FrameCodec::decode_with_options(
+            &mut Cursor::new(&frame),
+            &mut DecodeOptions::new().with_mode(DecodeMode::Strict),
+        )
+        .expect("Failed to decode");
+        assert_eq!(unverified, decoded);
+
+        let mut decoder = FrameDecoder::new().with_key_store(keys.clone());
+        let frames = decoder.decode(&frame).expect("Failed to decode");
+        assert_eq!(frames.messages, vec![decoded]);
+
+        let (view, _) = MessageRef::from_frame(&frame).expect("Failed to parse");
+        assert!(view.envelope.is_authenticated());
+        view.verify(keys.as_ref()).expect("Failed to verify");
+    }
+}
+
+#[test]
+fn forged_frames_are_rejected() {
+    let keys = key_ring("test-agent-001", [AuthKey::new(1, *b"shared secret")]);
+    let snapshot = message("test-agent-001", 1703174400000, false);
+
+    let unsigned = FrameCodec::encode(&snapshot).expect("Failed to encode");
+    let err = verify(&unsigned, &keys).unwrap_err();
+    assert!(matches!(err, ProtocolError::AuthenticationFailed(_)));
+    assert!(err.to_string().contains("not authenticated"));
+
+    let frame = sign(keys.clone(), &snapshot).expect("Failed to encode");
+    // Tamper with the payload and with the tag itself
+    for offset in [120, frame.len() - 9] {
+        let mut forged = frame.clone();
+        tamper(&mut forged, offset);
+        let err = verify(&forged, &keys).unwrap_err();
+        assert!(err.to_string().contains("tag mismatch"), "{err}");
+    }
+
+    // A key for one agent cannot sign frames for another
+    let impostor = key_ring("test-agent-002", [AuthKey::new(1, *b"other secret")]);
+    let forged =
+        sign(impostor, &message("test-agent-002", 1703174400000, false)).expect("Failed to encode");
+    let err = verify(&forged, &keys).unwrap_err();
+    assert!(err
+        .to_string()
+        .contains("unknown key 1 for agent test-agent-002"));
+}
+
+#[test]
+fn keys_rotate_with_overlapping_validity() {
+    let old = AuthKey::new(1, *b"old secret").valid_until(2_000);
+    let new = AuthKey::new(2, *b"new secret").valid_from(1_000);
+    let agent_keys = key_ring("test-agent-001", [old.clone(), new.clone()]);
+    let server_keys = key_ring("test-agent-001", [old.clone(), new]);
+
+    // The agent switches to the new key as soon as it becomes valid
+    for (timestamp, key_id) in [(500, 1), (1_500, 2), (2_500, 2)] {
+        let frame = sign(
+            agent_keys.clone(),
+            &message("test-agent-001", timestamp, false),
+        )
+        .expect("Failed to encode");
+        let decoded = verify(&frame, &server_keys).expect("Failed to decode");
+        assert_eq!(
+            decoded.envelope.key_id().expect("Invalid key id"),
+            Some(key_id)
+        );
+    }
+
+    // An agent that has not picked up the new key yet is accepted during the overlap only
+    let lagging = key_ring("test-agent-001", [AuthKey::new(1, *b"old secret")]);
+    let frame =
+        sign(lagging.clone(), &message("test-agent-001", 1_500, false)).expect("Failed to encode");
+    verify(&frame, &server_keys).expect("Failed to decode");
+    let frame = sign(lagging, &message("test-agent-001", 2_500, false)).expect("Failed to encode");
+    let err = verify(&frame, &server_keys).unwrap_err();
+    assert!(err.to_string().contains("key 1 is not valid at 2500"));
+
+    // Once the old key expires the agent has nothing to sign with
+    let expired = key_ring("test-agent-001", [old]);
+    let err = sign(expired, &message("test-agent-001", 2_500, false)).unwrap_err();
+    assert!(matches!(err, ProtocolError::AuthenticationFailed(_)));
+}
+
+#[test]
+fn removed_keys_are_revoked() {
+    let mut keys = KeyRing::new();
+    keys.insert("test-agent-001", AuthKey::new(1, *b"shared secret"));
+    let frame = sign(
+        Arc::new(keys.clone()),
+        &message("test-agent-001", 1703174400000, true),
+    )
+    .expect("Failed to encode");
+    verify(&frame, &keys).expect("Failed to decode");
+
+    assert_eq!(
+        keys.remove("test-agent-001", 1).map(|k| k.key_id()),
+        Some(1)
+    );
+    let err = verify(&frame, &keys).unwrap_err();
+    assert!(matches!(err, ProtocolError::AuthenticationFailed(_)));
+}
@@ -0,0 +1,174 @@
+//! Integration tests for the push-based `FrameDecoder`.
+//!
+//! Validates that frames split across arbitrary chunk boundaries decode identically to
+//! `FrameCodec::decode`, and that invalid frames are reported without losing earlier messages.
+
+use agent::protocol::*;
+
+// Test helper: Create a 16-byte message ID from a u64 for simplicity in tests
+fn test_message_id(n: u64) -> [u8; 16] {
+    let mut id = [0u8; 16];
+    id[0..8].copy_from_slice(&n.to_le_bytes());
+    id
+}
+
+fn heartbeat(n: u64) -> Message {
+    Message {
+        envelope: Envelope {
+            version: ProtocolVersion::CURRENT,
+            message_type: MessageType::Heartbeat,
+            message_id: test_message_id(n),
+            timestamp_utc_ms: 1703174400000 + n as i64,
+            agent_id: "test-agent-001".to_string(),
+            platform: OsType::Linux,
+            compressed: false,
+            extensions: Extensions::new(),
+        },
+        payload: MessagePayload::Heartbeat,
+        payload_extensions: Extensions::new(),
+    }
+}
+
+fn snapshot(n: u64, compressed: bool) -> Message {
+    Message {
+        envelope: Envelope {
+            version: ProtocolVersion::CURRENT,
+            message_type: MessageType::Snapshot,
+            message_id: test_message_id(n),
+            timestamp_utc_ms: 1703174410000,
+            agent_id: "test-agent-001".to_string(),
+            platform: OsType::Linux,
+            compressed,
+            extensions: Extensions::new(),
+        },
+        payload: MessagePayload::Snapshot(SnapshotPayload {
+            window_start_secs: 1703174400,
+            window_end_secs: 1703174410,
+            total_cpu_percent: 42.0,
+            memory_used_bytes: 4_000_000_000,
+            memory_total_bytes: 8_000_000_000,
+            processes: (0..20)
+                .map(|i| ProcessSample {
+                    pid: 100 + i,
+                    name: format!("process-{i}"),
+                    cpu_percent: 1.5,
+                    memory_percent: 0.5,
+                    memory_bytes: 1_000_000,
+                    cmdline: Some(format!("/usr/bin/process-{i} --flag")),
+                })
+                .collect(),
+            truncated: false,
+        }),
+        payload_extensions: Extensions::new(),
+    }
+}
+
+fn encode_all(messages: &[Message]) -> Vec<u8> {
+    messages
+        .iter()
+        .flat_map(|m| FrameCodec::encode(m).expect("encode"))
+        .collect()
+}
+
+#[test]
+fn frame_decoder_single_chunk_with_multiple_frames() {
+    let messages = vec![heartbeat(1), snapshot(2, false), snapshot(3, true)];
+    let stream = encode_all(&messages);
+
+    let mut decoder = FrameDecoder::new();
+    let decoded = decoder.decode(&stream).expect("decode");
+
+    assert_eq!(decoded.consumed, stream.len());
+    assert_eq!(decoded.messages, messages);
+    assert!(decoder.is_at_frame_boundary());
+}
+
+#[test]
+fn frame_decoder_byte_at_a_time() {
+    let messages = vec![snapshot(10, true), heartbeat(11)];
+    let stream = encode_all(&messages);
+
+    let mut decoder = FrameDecoder::new();
+    let mut collected = Vec::new();
+    for byte in &stream {
+        let decoded = decoder.decode(std::slice::from_ref(byte)).expect("decode");
+        assert_eq!(decoded.consumed, 1, "Every byte should be consumed");
+        collected.extend(decoded.messages);
+    }
+
+    assert_eq!(collected, messages);
+    assert_eq!(decoder.buffered_len(), 0);
+}
+
+#[test]
+fn frame_decoder_keeps_partial_frame_between_calls() {
+    let frame = FrameCodec::encode(&snapshot(20, false)).expect("encode");
+    let split = frame.len() / 2;
+
+    let mut decoder = FrameDecoder::new();
+    let first = decoder.decode(&frame[..split]).expect("first half");
+    assert!(first.messages.is_empty(), "Half a frame yields no message");
+    assert_eq!(first.consumed, split);
+    assert_eq!(decoder.buffered_len(), split);
+    assert!(!decoder.is_at_frame_boundary());
+
+    let second = decoder.decode(&frame[split..]).expect("second half");
+    assert_eq!(second.messages.len(), 1);
+    assert_eq!(second.messages[0].envelope.message_id, test_message_id(20));
+    assert!(decoder.is_at_frame_boundary());
+}
+
+#[test]
+fn frame_decoder_empty_chunk_is_a_no_op() {
+    let mut decoder = FrameDecoder::new();
+    let decoded = decoder.decode(&[]).expect("decode");
+    assert!(decoded.messages.is_empty());
+    assert_eq!(decoded.consumed, 0);
+}
+
+#[test]
+fn frame_decoder_reports_crc_error_after_returning_good_messages() {
+    let good = FrameCodec::encode(&heartbeat(30)).expect("encode");
+    let mut bad = FrameCodec::encode(&heartbeat(31)).expect("encode");
+    let last = bad.len() - 1;
+    bad[last] ^= 0xFF; // Corrupt the CRC trailer
+
+    let mut stream = good.clone();
+    stream.extend_from_slice(&bad);
+
+    let mut decoder = FrameDecoder::new();
+    let decoded = decoder.decode(&stream).expect("good frame first");
+    assert_eq!(decoded.messages.len(), 1);
+    assert_eq!(
+        decoded.consumed,
+        good.len(),
+        "Consumption should stop at the start of the corrupt frame"
+    );
+
+    let result = decoder.decode(&stream[decoded.consumed..]);
+    assert!(matches!(result, Err(ProtocolError::Crc32Mismatch { .. })));
+    assert_eq!(
+        decoder.error_consumed(),
+        bad.len(),
+        "The failing call took the whole corrupt frame"
+    );
+    assert!(
+        decoder.is_at_frame_boundary(),
+        "Buffer is discarded on error"
+    );
+}
+
+#[test]
+fn frame_decoder_rejects_oversized_length_prefix() {
+    let mut decoder = FrameDecoder::new();
+    let result = decoder.decode(&u32::MAX.to_be_bytes());
+    match result {
+        Err(ProtocolError::FrameTooLarge(size, max)) => assert!(size > max),
+        other => panic!("Expected FrameTooLarge error, got {other:?}"),
+    }
+    assert_eq!(
+        decoder.error_consumed(),
+        4,
+        "Only the length prefix was taken"
+    );
+}
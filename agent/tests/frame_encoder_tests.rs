@@ -0,0 +1,180 @@
+//! Integration tests for the allocation-reusing `FrameEncoder`.
+//!
+//! The encoder must produce exactly the bytes `FrameCodec::encode` produces, for every message
+//! type, with and without compression, across repeated use of the same encoder.
+
+use agent::protocol::*;
+
+// Test helper: Create a 16-byte message ID from a u64 for simplicity in tests
+fn test_message_id(n: u64) -> [u8; 16] {
+    let mut id = [0u8; 16];
+    id[0..8].copy_from_slice(&n.to_le_bytes());
+    id
+}
+
+fn message(n: u64, payload: MessagePayload, compressed: bool) -> Message {
+    let message_type = match &payload {
+        MessagePayload::Handshake(_) => MessageType::Handshake,
+        MessagePayload::HandshakeAck(_) => MessageType::HandshakeAck,
+        MessagePayload::Heartbeat => MessageType::Heartbeat,
+        MessagePayload::Snapshot(_) => MessageType::Snapshot,
+        MessagePayload::Ack(_) => MessageType::Ack,
+        MessagePayload::Backpressure(_) => MessageType::Backpressure,
+        MessagePayload::Error { .. } => MessageType::Error,
+        MessagePayload::SnapshotPart(_) => MessageType::SnapshotPart,
+        MessagePayload::SnapshotDelta(_) => MessageType::SnapshotDelta,
+    };
+    Message {
+        envelope: Envelope {
+            version: ProtocolVersion::CURRENT,
+            message_type,
+            message_id: test_message_id(n),
+            timestamp_utc_ms: 1703174400000 + n as i64,
+            agent_id: "test-agent-001".to_string(),
+            platform: OsType::Windows,
+            compressed,
+            extensions: Extensions::new(),
+        },
+        payload,
+        payload_extensions: Extensions::new(),
+    }
+}
+
+fn snapshot(process_count: u32) -> SnapshotPayload {
+    SnapshotPayload {
+        window_start_secs: 1703174400,
+        window_end_secs: 1703174410,
+        total_cpu_percent: 40.0,
+        memory_used_bytes: 6_000_000_000,
+        memory_total_bytes: 16_000_000_000,
+        processes: (0..process_count)
+            .map(|i| ProcessSample {
+                pid: i,
+                name: format!("svc-{}", i * 7919 % 10007),
+                cpu_percent: (i % 13) as f32 * 0.7,
+                memory_percent: (i % 5) as f32,
+                memory_bytes: 1_000_000 + (i as u64 * 4099),
+                cmdline: (i % 3 != 0).then(|| format!("C:\\svc\\{i}.exe /run {}", i * 31)),
+            })
+            .collect(),
+        truncated: false,
+    }
+}
+
+fn sample_payloads() -> Vec<MessagePayload> {
+    vec![
+        MessagePayload::Handshake(AgentIdentity {
+            instance_id: "instance-1".to_string(),
+            os_type: OsType::Windows,
+            agent_version: "1.2.3".to_string(),
+            supported_versions: VersionRange::CURRENT,
+            capabilities: AgentIdentity::CAP_COMPRESSION,
+        }),
+        MessagePayload::HandshakeAck(HandshakeAckPayload {
+            negotiated_version: ProtocolVersion::CURRENT,
+            negotiated_capabilities: 0,
+        }),
+        MessagePayload::Heartbeat,
+        MessagePayload::Snapshot(snapshot(0)),
+        MessagePayload::Snapshot(snapshot(40)),
+        // Payload above zstd's 128 KiB block size
+        MessagePayload::Snapshot(snapshot(4_000)),
+        MessagePayload::SnapshotPart(SnapshotPartPayload {
+            snapshot_id: test_message_id(77),
+            part_index: 1,
+            part_count: 3,
+            snapshot: snapshot(10),
+        }),
+        MessagePayload::Ack(MessageAck {
+            message_id: test_message_id(5),
+            success: false,
+            error_code: Some(7),
+        }),
+        MessagePayload::Backpressure(BackpressureSignal {
+            throttle_delay_ms: 250,
+            reason: Some("queue full".to_string()),
+        }),
+        MessagePayload::Error {
+            code: 500,
+            message: "internal".to_string(),
+        },
+    ]
+}
+
+#[test]
+fn encoder_output_is_byte_identical_to_framecodec() {
+    let mut encoder = FrameEncoder::new();
+    // Alternate compressed and uncompressed messages through one encoder
+    for (n, payload) in sample_payloads().into_iter().enumerate() {
+        for compressed in [true, false] {
+            let mut message = message(n as u64, payload.clone(), compressed);
+            if n % 2 == 0 {
+                message.envelope.set_sequence_number(n as u64);
+                message.payload_extensions.insert(0x8000, vec![n as u8; 3]);
+            }
+
+            let expected = FrameCodec::encode(&message).expect("FrameCodec failed");
+            let mut out = Vec::new();
+            encoder
+                .encode_into(&message, &mut out)
+                .expect("FrameEncoder failed");
+            assert_eq!(
+                out, expected,
+                "Mismatch for message {n} (compressed={compressed})"
+            );
+        }
+    }
+}
+
+#[test]
+fn encode_into_appends_to_existing_buffer() {
+    let mut encoder = FrameEncoder::new();
+    let first = message(1, MessagePayload::Snapshot(snapshot(20)), true);
+    let second = message(2, MessagePayload::Heartbeat, false);
+
+    let mut out = vec![0xAA, 0xBB];
+    encoder
+        .encode_into(&first, &mut out)
+        .expect("Encode failed");
+    encoder
+        .encode_into(&second, &mut out)
+        .expect("Encode failed");
+
+    let mut expected = vec![0xAA, 0xBB];
+    expected.extend(FrameCodec::encode(&first).unwrap());
+    expected.extend(FrameCodec::encode(&second).unwrap());
+    assert_eq!(out, expected);
+}
+
+#[test]
+fn oversized_message_leaves_buffer_unchanged() {
+    let mut encoder = FrameEncoder::new();
+    let oversized = message(1, MessagePayload::Snapshot(snapshot(8_000)), false);
+
+    let mut out = vec![1, 2, 3];
+    let result = encoder.encode_into(&oversized, &mut out);
+    assert!(matches!(result, Err(ProtocolError::FrameTooLarge(_, _))));
+    assert_eq!(out, vec![1, 2, 3]);
+
+    // The encoder is still usable afterwards
+    let small = message(2, MessagePayload::Snapshot(snapshot(5)), true);
+    encoder
+        .encode_into(&small, &mut out)
+        .expect("Encode failed");
+    assert_eq!(out[3..], FrameCodec::encode(&small).unwrap()[..]);
+}
+
+#[test]
+fn write_to_matches_framecodec_write() {
+    let mut encoder = FrameEncoder::new();
+    let mut written = Vec::new();
+    let mut expected = Vec::new();
+    for (n, payload) in sample_payloads().into_iter().enumerate() {
+        let message = message(n as u64, payload, n % 2 == 1);
+        encoder
+            .write_to(&mut written, &message)
+            .expect("write_to failed");
+        FrameCodec::write(&mut expected, &message).expect("FrameCodec::write failed");
+    }
+    assert_eq!(written, expected);
+}
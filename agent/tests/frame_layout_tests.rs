@@ -0,0 +1,276 @@
+//! Integration tests for the legacy and spec (FR-002) frame layouts.
+//!
+//! The frames in `tests/data/framing/` pin both layouts byte for byte; never regenerate them
+//! with the current encoder.
+
+use agent::protocol::*;
+use std::io::Cursor;
+
+// Test helper: Create a 16-byte message ID from a u64 for simplicity in tests
+fn test_message_id(n: u64) -> [u8; 16] {
+    let mut id = [0u8; 16];
+    id[0..8].copy_from_slice(&n.to_le_bytes());
+    id
+}
+
+const LEGACY_SNAPSHOT: &[u8] = include_bytes!("data/framing/snapshot_legacy.bin");
+const SPEC_SNAPSHOT: &[u8] = include_bytes!("data/framing/snapshot_spec.bin");
+
+fn message(id: u64, payload: MessagePayload) -> Message {
+    Message {
+        envelope: Envelope {
+            version: ProtocolVersion::CURRENT,
+            message_type: payload.message_type(),
+            message_id: test_message_id(id),
+            timestamp_utc_ms: 1703174400000,
+            agent_id: "framing-agent".to_string(),
+            platform: OsType::Linux,
+            compressed: false,
+            extensions: Extensions::new(),
+        },
+        payload,
+        payload_extensions: Extensions::new(),
+    }
+}
+
+fn snapshot() -> Message {
+    message(
+        1,
+        MessagePayload::Snapshot(SnapshotPayload {
+            window_start_secs: 1703174400,
+            window_end_secs: 1703174410,
+            total_cpu_percent: 12.5,
+            memory_used_bytes: 4_000_000_000,
+            memory_total_bytes: 8_000_000_000,
+            processes: vec![ProcessSample {
+                pid: 42,
+                name: "framing".to_string(),
+                cpu_percent: 12.5,
+                memory_percent: 1.5,
+                memory_bytes: 120_000_000,
+                cmdline: Some("framing --golden".to_string()),
+            }],
+            truncated: false,
+        }),
+    )
+}
+
+fn decode(frame: &[u8], layout: FrameLayout) -> Result<Message, ProtocolError> {
+    FrameCodec::decode_with_options(
+        &mut Cursor::new(frame),
+        &mut DecodeOptions::new()
+            .with_mode(DecodeMode::Strict)
+            .with_layout(layout),
+    )
+}
+
+#[test]
+fn golden_frames_pin_both_layouts() {
+    let message = snapshot();
+    assert_eq!(
+        FrameCodec::encode(&message).expect("Failed to encode"),
+        LEGACY_SNAPSHOT
+    );
+    assert_eq!(
+        FrameCodec::encode_with_layout(&message, FrameLayout::Legacy).expect("Failed to encode"),
+        LEGACY_SNAPSHOT
+    );
+    assert_eq!(
+        FrameCodec::encode_with_layout(&message, FrameLayout::Spec).expect("Failed to encode"),
+        SPEC_SNAPSHOT
+    );
+    for (layout, golden) in [
+        (FrameLayout::Legacy, LEGACY_SNAPSHOT),
+        (FrameLayout::Spec, SPEC_SNAPSHOT),
+    ] {
+        let mut frame = Vec::new();
+        FrameEncoder::new()
+            .with_layout(layout)
+            .encode_into(&message, &mut frame)
+            .expect("Failed to encode");
+        assert_eq!(frame, golden, "{layout:?}");
+        assert_eq!(decode(golden, layout).expect("Failed to decode"), message);
+    }
+}
+
+#[test]
+fn spec_layout_matches_fr_002() {
+    // Legacy: [length: u32 BE][body][crc32: u32 LE]
+    let body_len = LEGACY_SNAPSHOT.len() - 8;
+    let body = &LEGACY_SNAPSHOT[4..4 + body_len];
+    assert_eq!(&LEGACY_SNAPSHOT[..4], &(body_len as u32).to_be_bytes());
+    assert_eq!(
+        &LEGACY_SNAPSHOT[4 + body_len..],
+        &crc32fast::hash(body).to_le_bytes()
+    );
+
+    // Spec: [length of everything after the prefix: u32 LE][crc32: u32 LE][body]
+    assert_eq!(SPEC_SNAPSHOT.len(), LEGACY_SNAPSHOT.len());
+    assert_eq!(&SPEC_SNAPSHOT[..4], &((body_len + 4) as u32).to_le_bytes());
+    assert_eq!(&SPEC_SNAPSHOT[4..8], &crc32fast::hash(body).to_le_bytes());
+    assert_eq!(&SPEC_SNAPSHOT[8..], body);
+
+    assert_eq!(FrameLayout::Legacy.header_len(), 4);
+    assert_eq!(FrameLayout::Spec.header_len(), 8);
+    for layout in FrameLayout::ALL {
+        assert_eq!(layout.frame_len(body_len), SPEC_SNAPSHOT.len());
+    }
+}
+
+#[test]
+fn frames_are_rejected_in_the_wrong_layout() {
+    assert!(decode(LEGACY_SNAPSHOT, FrameLayout::Spec).is_err());
+    assert!(decode(SPEC_SNAPSHOT, FrameLayout::Legacy).is_err());
+
+    // A spec length prefix shorter than the CRC32
+    let mut short = SPEC_SNAPSHOT.to_vec();
+    short[..4].copy_from_slice(&3u32.to_le_bytes());
+    assert!(matches!(
+        decode(&short, FrameLayout::Spec),
+        Err(ProtocolError::InvalidFrameLength(3))
+    ));
+
+    let mut corrupt = SPEC_SNAPSHOT.to_vec();
+    corrupt[20] ^= 0xFF;
+    assert!(matches!(
+        decode(&corrupt, FrameLayout::Spec),
+        Err(ProtocolError::Crc32Mismatch { .. })
+    ));
+
+    let mut oversized = SPEC_SNAPSHOT.to_vec();
+    oversized[..4].copy_from_slice(&(1u32 << 20).to_le_bytes());
+    assert!(matches!(
+        decode(&oversized, FrameLayout::Spec),
+        Err(ProtocolError::FrameTooLarge(..))
+    ));
+}
+
+#[test]
+fn push_decoding_and_views_follow_the_layout() {
+    let messages = [snapshot(), message(2, MessagePayload::Heartbeat)];
+    let mut encoder = FrameEncoder::new()
+        .with_layout(FrameLayout::Spec)
+        .with_policy(CompressionPolicy::default());
+    let mut stream = Vec::new();
+    for message in &messages {
+        encoder
+            .encode_into(message, &mut stream)
+            .expect("Failed to encode");
+    }
+
+    // One byte at a time
+    let mut decoder = FrameDecoder::new().with_layout(FrameLayout::Spec);
+    let mut decoded = Vec::new();
+    for byte in &stream {
+        decoded.extend(
+            decoder
+                .decode(std::slice::from_ref(byte))
+                .expect("Failed to decode")
+                .messages,
+        );
+    }
+    assert_eq!(decoded, messages);
+    assert!(decoder.is_at_frame_boundary());
+
+    let (view, len) = MessageRef::from_frame_with_options(
+        &stream,
+        &mut DecodeOptions::new()
+            .with_mode(DecodeMode::Strict)
+            .with_layout(FrameLayout::Spec),
+    )
+    .expect("Failed to parse view");
+    assert_eq!(view.to_owned().expect("Failed to decode"), messages[0]);
+    let (view, _) = MessageRef::from_frame_with_options(
+        &stream[len..],
+        &mut DecodeOptions::new()
+            .with_mode(DecodeMode::Strict)
+            .with_layout(FrameLayout::Spec),
+    )
+    .expect("Failed to parse view");
+    assert_eq!(view.envelope.message_type, MessageType::Heartbeat);
+}
+
+#[test]
+fn captures_are_detected_per_file() {
+    let frames = [
+        snapshot(),
+        message(2, MessagePayload::Heartbeat),
+        snapshot(),
+    ];
+    for layout in FrameLayout::ALL {
+        let mut capture = Vec::new();
+        for message in &frames {
+            capture
+                .extend(FrameCodec::encode_with_layout(message, layout).expect("Failed to encode"));
+        }
+        assert_eq!(FrameLayout::detect(&capture), Some(layout));
+
+        let report = scan_frames(&capture);
+        assert_eq!(report.layout, layout);
+        assert!(report.is_clean());
+        assert_eq!(report.frames.len(), 3);
+
+        // Only the first frame is looked at: leading garbage and a damaged first frame need the
+        // layout from the caller
+        let mut damaged = b"garbage".to_vec();
+        damaged.extend_from_slice(&capture);
+        damaged[7 + 30] ^= 0xFF;
+        assert_eq!(FrameLayout::detect(&damaged), None);
+        assert_eq!(
+            FrameLayout::detect(&capture[..capture.len() - 1]),
+            Some(layout)
+        );
+        let scanner = FrameScanner::with_layout(&damaged, layout);
+        assert_eq!(scanner.layout(), layout);
+        let recovered = scanner
+            .filter(|item| matches!(item, ScanItem::Frame(_)))
+            .count();
+        assert_eq!(recovered, 2);
+    }
+
+    assert_eq!(FrameLayout::detect(b""), None);
+    assert_eq!(FrameLayout::detect(&[0u8; 64]), None);
+    assert_eq!(scan_frames(&[0u8; 64]).layout, FrameLayout::Legacy);
+}
+
+#[test]
+fn spec_layout_is_negotiated_after_the_handshake() {
+    let identity = AgentIdentity {
+        instance_id: "framing-agent".to_string(),
+        os_type: OsType::Linux,
+        agent_version: "1.0.0".to_string(),
+        supported_versions: VersionRange::CURRENT,
+        capabilities: AgentIdentity::CAP_SPEC_FRAMING,
+    };
+    assert!(identity.supports_spec_framing());
+    let ack = negotiate(&identity, VersionRange::CURRENT, 0).expect("Failed to negotiate");
+    assert_eq!(ack.frame_layout(), FrameLayout::Legacy);
+    let ack = negotiate(
+        &identity,
+        VersionRange::CURRENT,
+        AgentIdentity::CAP_SPEC_FRAMING,
+    )
+    .expect("Failed to negotiate");
+    assert_eq!(ack.frame_layout(), FrameLayout::Spec);
+
+    // Handshake and ack in the legacy layout, every later frame in the negotiated one
+    let mut stream = FrameCodec::encode(&message(1, MessagePayload::Handshake(identity)))
+        .expect("Failed to encode");
+    let mut encoder = FrameEncoder::new().with_layout(ack.frame_layout());
+    encoder
+        .encode_into(&snapshot(), &mut stream)
+        .expect("Failed to encode");
+
+    let mut decoder = FrameDecoder::new();
+    let first = decoder.decode(&stream).expect("Failed to decode");
+    assert_eq!(first.messages.len(), 1);
+    assert_eq!(
+        first.messages[0].envelope.message_type,
+        MessageType::Handshake
+    );
+    let mut decoder = decoder.with_layout(ack.frame_layout());
+    let rest = decoder
+        .decode(&stream[first.consumed..])
+        .expect("Failed to decode");
+    assert_eq!(rest.messages, [snapshot()]);
+}
@@ -0,0 +1,132 @@
+//! Integration tests for the resynchronizing `FrameScanner`.
+//!
+//! Builds concatenated frame files with corrupt and truncated regions and verifies that every
+//! intact frame is recovered at the right offset and every damaged region is reported.
+
+use agent::protocol::*;
+
+// Test helper: Create a 16-byte message ID from a u64 for simplicity in tests
+fn test_message_id(n: u64) -> [u8; 16] {
+    let mut id = [0u8; 16];
+    id[0..8].copy_from_slice(&n.to_le_bytes());
+    id
+}
+
+fn heartbeat_frame(n: u64) -> Vec<u8> {
+    let message = Message {
+        envelope: Envelope {
+            version: ProtocolVersion::CURRENT,
+            message_type: MessageType::Heartbeat,
+            message_id: test_message_id(n),
+            timestamp_utc_ms: 1703174400000,
+            agent_id: "test-agent-001".to_string(),
+            platform: OsType::Linux,
+            compressed: false,
+            extensions: Extensions::new(),
+        },
+        payload: MessagePayload::Heartbeat,
+        payload_extensions: Extensions::new(),
+    };
+    FrameCodec::encode(&message).expect("encode")
+}
+
+fn recovered_ids(report: &ScanReport) -> Vec<[u8; 16]> {
+    report
+        .frames
+        .iter()
+        .map(|f| f.message.envelope.message_id)
+        .collect()
+}
+
+#[test]
+fn scanner_clean_file_has_no_skipped_ranges() {
+    let frames = [heartbeat_frame(1), heartbeat_frame(2), heartbeat_frame(3)];
+    let data = frames.concat();
+
+    let report = scan_frames(&data);
+
+    assert!(report.is_clean());
+    assert_eq!(report.frames.len(), 3);
+    assert_eq!(report.frames[1].offset, frames[0].len());
+    assert_eq!(report.frames[2].len, frames[2].len());
+}
+
+#[test]
+fn scanner_recovers_frames_after_crc_mismatch() {
+    let first = heartbeat_frame(1);
+    let mut corrupt = heartbeat_frame(2);
+    corrupt[20] ^= 0x55; // Flip bits inside the message id
+    let last = heartbeat_frame(3);
+    let data = [first.clone(), corrupt.clone(), last].concat();
+
+    let report = scan_frames(&data);
+
+    assert_eq!(
+        recovered_ids(&report),
+        vec![test_message_id(1), test_message_id(3)]
+    );
+    assert_eq!(report.skipped.len(), 1);
+    let skipped = &report.skipped[0];
+    assert_eq!(skipped.start, first.len());
+    assert_eq!(skipped.end, first.len() + corrupt.len());
+    assert!(matches!(skipped.reason, SkipReason::Crc32Mismatch { .. }));
+    assert_eq!(report.frames[1].offset, first.len() + corrupt.len());
+}
+
+#[test]
+fn scanner_skips_leading_garbage() {
+    let garbage = vec![0xAB; 17];
+    let frame = heartbeat_frame(7);
+    let data = [garbage.clone(), frame].concat();
+
+    let report = scan_frames(&data);
+
+    assert_eq!(recovered_ids(&report), vec![test_message_id(7)]);
+    assert_eq!(report.skipped.len(), 1);
+    assert_eq!(report.skipped[0].start, 0);
+    assert_eq!(report.skipped[0].end, garbage.len());
+    assert_eq!(report.skipped_bytes(), garbage.len());
+}
+
+#[test]
+fn scanner_reports_truncated_tail() {
+    let first = heartbeat_frame(1);
+    let partial = heartbeat_frame(2);
+    let cut = partial.len() - 10;
+    let data = [first.clone(), partial[..cut].to_vec()].concat();
+
+    let report = scan_frames(&data);
+
+    assert_eq!(recovered_ids(&report), vec![test_message_id(1)]);
+    assert_eq!(report.skipped.len(), 1);
+    assert_eq!(report.skipped[0].start, first.len());
+    assert_eq!(report.skipped[0].end, data.len());
+    assert!(matches!(
+        report.skipped[0].reason,
+        SkipReason::Truncated { .. }
+    ));
+}
+
+#[test]
+fn scanner_rejects_bad_length_prefix() {
+    let mut data = u32::MAX.to_be_bytes().to_vec();
+    data.extend_from_slice(&heartbeat_frame(9));
+
+    let items: Vec<ScanItem> = FrameScanner::new(&data).collect();
+
+    assert_eq!(items.len(), 2);
+    match &items[0] {
+        ScanItem::Skipped(range) => {
+            assert_eq!(range.len(), 4);
+            assert_eq!(range.reason, SkipReason::InvalidLength(u32::MAX as usize));
+        }
+        other => panic!("Expected skipped range, got {other:?}"),
+    }
+    match &items[1] {
+        ScanItem::Frame(frame) => {
+            assert_eq!(frame.offset, 4);
+            assert_eq!(frame.message.envelope.message_id, test_message_id(9));
+        }
+        other => panic!("Expected recovered frame, got {other:?}"),
+    }
+}
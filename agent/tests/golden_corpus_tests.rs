@@ -0,0 +1,201 @@
+//! Integration tests for the golden test-vector corpus.
+//!
+//! The corpus in `tests/data/golden/v1/` is released: other implementations decode it, so it
+//! must keep matching the current encoder and decoder. Never regenerate it in place; add cases
+//! in a new corpus version instead.
+
+#![cfg(feature = "json")]
+
+#[path = "../src/bin/golden_corpus/golden.rs"]
+mod golden;
+
+use agent::protocol::*;
+use golden::{GoldenError, Manifest, Mismatch, CORPUS_VERSION};
+use serde_json::json;
+use std::path::{Path, PathBuf};
+
+fn corpus_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/data/golden/v1")
+}
+
+fn load() -> Manifest {
+    Manifest::load(&corpus_dir()).expect("Failed to load manifest")
+}
+
+#[test]
+fn checked_in_corpus_decodes_to_its_manifest() {
+    let manifest = load();
+    assert_eq!(manifest.corpus_version, CORPUS_VERSION);
+    assert_eq!(manifest.protocol_version, ProtocolVersion::CURRENT);
+
+    let decoded = golden::decode_corpus(&corpus_dir(), &manifest).expect("Failed to decode");
+    assert_eq!(decoded.len(), manifest.entries.len());
+    assert!(decoded.iter().all(|frame| frame.error.is_none()));
+    assert_eq!(golden::verify(&manifest, &decoded), Vec::<Mismatch>::new());
+
+    // The decode output survives its JSON Lines form
+    let jsonl: String = decoded
+        .iter()
+        .map(|frame| serde_json::to_string(frame).expect("Failed to serialize") + "\n")
+        .collect();
+    let parsed = golden::parse_decoded(&jsonl).expect("Failed to parse");
+    assert_eq!(parsed, decoded);
+}
+
+#[test]
+fn corpus_matches_the_current_encoder() {
+    let manifest = load();
+    let encoded = golden::encode_cases().expect("Failed to encode");
+    assert_eq!(encoded.len(), manifest.entries.len());
+
+    for ((mut entry, frame), released) in encoded.into_iter().zip(&manifest.entries) {
+        if entry.compressed {
+            // zstd output may change between library versions; the decoded values may not
+            entry.frame_len = released.frame_len;
+            entry.file_crc32 = released.file_crc32;
+        } else {
+            assert_eq!(
+                frame,
+                released.read_frame(&corpus_dir()).expect("Failed to read"),
+                "{}",
+                entry.name
+            );
+        }
+        assert_eq!(&entry, released);
+    }
+}
+
+#[test]
+fn corpus_covers_every_message_type_and_edge_case() {
+    let manifest = load();
+    for message_type in (1..=9).map(|n| MessageType::from_u8(n).expect("Invalid type")) {
+        for compressed in [false, true] {
+            assert!(
+                manifest.entries.iter().any(|entry| {
+                    entry.message_type == format!("{message_type:?}")
+                        && entry.compressed == compressed
+                }),
+                "{message_type:?} compressed={compressed}"
+            );
+        }
+    }
+
+    let entry = |name: &str| {
+        manifest
+            .entries
+            .iter()
+            .find(|entry| entry.name == name)
+            .unwrap_or_else(|| panic!("Missing case {name}"))
+    };
+    let max = entry("snapshot_max_size");
+    assert_eq!(max.frame_len, FrameLayout::Legacy.frame_len(MAX_FRAME_SIZE));
+    assert!(entry("snapshot_max_size_compressed").frame_len < max.frame_len);
+
+    let snapshot = &entry("snapshot_zero_processes").expected["payload"];
+    assert_eq!(snapshot["processes"], json!([]));
+    let snapshot = &entry("snapshot_empty_strings").expected;
+    assert_eq!(snapshot["agent_id"], "");
+    assert_eq!(snapshot["payload"]["processes"][0]["name"], "");
+    let snapshot = &entry("snapshot_non_ascii").expected;
+    assert!(!snapshot["agent_id"]
+        .as_str()
+        .expect("Expected a string")
+        .is_ascii());
+    assert_eq!(
+        entry("backpressure_no_reason").expected["payload"]["reason"],
+        json!(null)
+    );
+    assert_eq!(entry("ack").expected["payload"]["error_code"], json!(null));
+}
+
+#[test]
+fn verify_reports_every_kind_of_mismatch() {
+    let manifest = load();
+    let reference = golden::decode_corpus(&corpus_dir(), &manifest).expect("Failed to decode");
+    let index = |name: &str| {
+        reference
+            .iter()
+            .position(|frame| frame.name == name)
+            .expect("Missing case")
+    };
+
+    // f32 fields printed as f64 still match
+    let mut widened = reference.clone();
+    let snapshot = &mut widened[index("snapshot")];
+    let cpu = &mut snapshot.message.as_mut().expect("Expected a message")["payload"]["processes"]
+        [2]["cpu_percent"];
+    *cpu = json!(10.5f32 as f64 + 1e-9);
+    assert!(golden::verify(&manifest, &widened).is_empty());
+
+    let mut decoded = reference.clone();
+    let snapshot = decoded[index("snapshot")]
+        .message
+        .as_mut()
+        .expect("Expected a message");
+    snapshot["payload"]["processes"][2]["pid"] = json!(1004);
+    decoded[index("error")].message = None;
+    decoded[index("error")].error = Some("invalid message type 7".to_string());
+    decoded.remove(index("heartbeat"));
+    decoded.push(reference[index("ack")].clone());
+    let mut extra = reference[index("ack")].clone();
+    extra.name = "not_a_case".to_string();
+    decoded.push(extra);
+
+    let details: Vec<String> = golden::verify(&manifest, &decoded)
+        .iter()
+        .map(Mismatch::to_string)
+        .collect();
+    assert_eq!(
+        details,
+        [
+            "heartbeat: no decode output",
+            "snapshot: message.payload.processes[2].pid: expected 1003, got 1004",
+            "ack: decoded more than once",
+            "error: failed to decode: invalid message type 7",
+            "not_a_case: not in the manifest",
+        ]
+    );
+
+    // Fields the manifest does not expect
+    let mut decoded = reference.clone();
+    decoded[index("heartbeat")]
+        .message
+        .as_mut()
+        .expect("Expected a message")["extra"] = json!(true);
+    assert_eq!(
+        golden::verify(&manifest, &decoded)[0].detail,
+        "message.extra: unexpected field"
+    );
+}
+
+#[test]
+fn generated_corpus_round_trips_and_guards_itself() {
+    let dir = std::env::temp_dir().join(format!("golden-corpus-{}", std::process::id()));
+    let manifest = golden::generate(&dir).expect("Failed to generate");
+    assert_eq!(Manifest::load(&dir).expect("Failed to load"), manifest);
+
+    let entry = &manifest.entries[0];
+    let path = dir.join(&entry.file);
+    let mut frame = std::fs::read(&path).expect("Failed to read");
+    frame[10] ^= 0xFF;
+    std::fs::write(&path, &frame).expect("Failed to write");
+    assert!(matches!(
+        entry.read_frame(&dir),
+        Err(GoldenError::CorruptCorpus { .. })
+    ));
+
+    let newer = Manifest {
+        corpus_version: CORPUS_VERSION + 1,
+        ..manifest
+    };
+    std::fs::write(
+        dir.join(golden::MANIFEST_FILE),
+        newer.to_json().expect("Failed to serialize"),
+    )
+    .expect("Failed to write");
+    assert!(matches!(
+        Manifest::load(&dir),
+        Err(GoldenError::UnsupportedCorpusVersion { .. })
+    ));
+    std::fs::remove_dir_all(&dir).expect("Failed to clean up");
+}
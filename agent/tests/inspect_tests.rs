@@ -0,0 +1,357 @@
+//! Integration tests for frame file inspection (`agent-proto inspect`).
+
+#![cfg(feature = "json")]
+
+use agent::inspect::{
+    inspect, FrameFilter, FrameInfo, FrameStatus, InspectItem, PayloadSizes, DEFAULT_TOP_FRAMES,
+};
+use agent::protocol::*;
+
+// Test helper: Create a 16-byte message ID from a u64 for simplicity in tests
+fn test_message_id(n: u64) -> [u8; 16] {
+    let mut id = [0u8; 16];
+    id[0..8].copy_from_slice(&n.to_le_bytes());
+    id
+}
+
+fn message(n: u64, agent_id: &str, payload: MessagePayload, compressed: bool) -> Message {
+    Message {
+        envelope: Envelope {
+            version: ProtocolVersion::CURRENT,
+            message_type: payload.message_type(),
+            message_id: test_message_id(n),
+            timestamp_utc_ms: 1703174400000 + n as i64 * 1000,
+            agent_id: agent_id.to_string(),
+            platform: OsType::Linux,
+            compressed,
+            extensions: Extensions::new(),
+        },
+        payload,
+        payload_extensions: Extensions::new(),
+    }
+}
+
+fn snapshot(process_count: u32) -> MessagePayload {
+    MessagePayload::Snapshot(SnapshotPayload {
+        window_start_secs: 1703174400,
+        window_end_secs: 1703174410,
+        total_cpu_percent: 50.0,
+        memory_used_bytes: 8_000_000_000,
+        memory_total_bytes: 16_000_000_000,
+        processes: (0..process_count)
+            .map(|i| ProcessSample {
+                pid: 1000 + i,
+                name: "worker".to_string(),
+                cpu_percent: 1.0,
+                memory_percent: 0.5,
+                memory_bytes: 10_000_000,
+                cmdline: Some("/usr/bin/worker --serve".to_string()),
+            })
+            .collect(),
+        truncated: false,
+    })
+}
+
+/// Five frames from two agents, one second apart.
+fn messages() -> Vec<Message> {
+    vec![
+        message(1, "agent-a", snapshot(3), false),
+        message(2, "agent-b", MessagePayload::Heartbeat, false),
+        message(3, "agent-a", snapshot(50), true),
+        message(4, "agent-b", snapshot(10), false),
+        message(5, "agent-a", MessagePayload::Heartbeat, false),
+    ]
+}
+
+fn capture(messages: &[Message], layout: FrameLayout) -> Vec<u8> {
+    messages
+        .iter()
+        .flat_map(|message| {
+            FrameCodec::encode_with_layout(message, layout).expect("Failed to encode")
+        })
+        .collect()
+}
+
+fn frames(items: &[InspectItem]) -> Vec<&FrameInfo> {
+    items
+        .iter()
+        .filter_map(|item| match item {
+            InspectItem::Frame(frame) => Some(frame),
+            InspectItem::Corrupt(_) => None,
+        })
+        .collect()
+}
+
+fn sizes(frame: &FrameInfo) -> PayloadSizes {
+    match &frame.status {
+        FrameStatus::Decoded {
+            sizes: Ok(sizes), ..
+        } => *sizes,
+        other => panic!("Expected payload sizes, got {other:?}"),
+    }
+}
+
+#[test]
+fn lists_every_frame_with_sizes_and_summary() {
+    let messages = messages();
+    for layout in FrameLayout::ALL {
+        let data = capture(&messages, layout);
+        let inspection = inspect(&data, None, &FrameFilter::default(), DEFAULT_TOP_FRAMES);
+        assert_eq!(inspection.layout, layout);
+
+        let listed = frames(&inspection.items);
+        assert_eq!(listed.len(), messages.len());
+        let mut offset = 0;
+        for (frame, message) in listed.iter().zip(&messages) {
+            assert_eq!(frame.offset, offset);
+            assert_eq!(frame.frame_len, layout.frame_len(frame.declared_len));
+            assert_eq!(frame.message(), Some(message));
+            offset += frame.frame_len;
+        }
+        assert_eq!(offset, data.len());
+
+        // Uncompressed payloads are the same size on the wire; compression shrinks the big one
+        let sizes: Vec<PayloadSizes> = listed.iter().map(|frame| sizes(frame)).collect();
+        assert_eq!(sizes[0].encoded, sizes[0].decoded);
+        assert_eq!(sizes[1].decoded, 0);
+        assert_eq!(sizes[1].compression_ratio(), 1.0);
+        assert!(sizes[2].encoded < sizes[2].decoded);
+        assert!(sizes[2].compression_ratio() > 1.0);
+
+        let summary = &inspection.summary;
+        assert_eq!((summary.total_frames, summary.matched_frames), (5, 5));
+        assert_eq!(summary.undecoded_frames, 0);
+        assert_eq!(summary.corrupt_regions, 0);
+        assert_eq!(
+            summary.per_type.iter().collect::<Vec<_>>(),
+            [(&MessageType::Heartbeat, &2), (&MessageType::Snapshot, &3)]
+        );
+        assert_eq!(summary.per_agent["agent-a"], 3);
+        assert_eq!(summary.per_agent["agent-b"], 2);
+        assert_eq!(summary.time_range, Some((1703174401000, 1703174405000)));
+        assert_eq!(summary.largest.len(), DEFAULT_TOP_FRAMES);
+        assert!(summary
+            .largest
+            .windows(2)
+            .all(|pair| pair[0].1 >= pair[1].1));
+    }
+}
+
+#[test]
+fn filters_by_agent_type_and_time_window() {
+    let data = capture(&messages(), FrameLayout::Legacy);
+    let ids = |filter: FrameFilter| -> Vec<u64> {
+        let inspection = inspect(&data, None, &filter, 1);
+        assert_eq!(inspection.summary.total_frames, 5);
+        assert!(inspection.summary.largest.len() <= 1);
+        frames(&inspection.items)
+            .iter()
+            .map(|frame| frame.envelope().message_id[0] as u64)
+            .collect()
+    };
+
+    let by_agent = FrameFilter {
+        agents: vec!["agent-b".to_string()],
+        ..FrameFilter::default()
+    };
+    assert_eq!(ids(by_agent), [2, 4]);
+
+    let by_type = FrameFilter {
+        types: vec![MessageType::Snapshot],
+        ..FrameFilter::default()
+    };
+    assert_eq!(ids(by_type), [1, 3, 4]);
+
+    // Both ends of the window are inclusive
+    let window = FrameFilter {
+        since_ms: Some(1703174402000),
+        until_ms: Some(1703174404000),
+        ..FrameFilter::default()
+    };
+    assert_eq!(ids(window), [2, 3, 4]);
+
+    let combined = FrameFilter {
+        agents: vec!["agent-a".to_string(), "agent-c".to_string()],
+        types: vec![MessageType::Snapshot, MessageType::Ack],
+        since_ms: Some(1703174402000),
+        until_ms: None,
+    };
+    assert_eq!(ids(combined), [3]);
+
+    let none = FrameFilter {
+        agents: vec!["agent-c".to_string()],
+        ..FrameFilter::default()
+    };
+    let inspection = inspect(&data, None, &none, DEFAULT_TOP_FRAMES);
+    assert!(inspection.items.is_empty());
+    assert_eq!(inspection.summary.time_range, None);
+    assert!(inspection.to_string().contains("frames: 0 of 5 matched"));
+}
+
+#[test]
+fn keeps_going_past_corrupt_regions() {
+    let messages = messages();
+    for layout in FrameLayout::ALL {
+        let clean = capture(&messages, layout);
+        let first_len = layout.frame_len(
+            layout
+                .body_len(&clean[..layout.header_len()])
+                .expect("Invalid header"),
+        );
+
+        // Flip a payload byte in the first frame, insert garbage after it, truncate the tail
+        let mut data = clean.clone();
+        data[first_len - 10] ^= 0xFF;
+        data.splice(first_len..first_len, *b"not a frame");
+        data.truncate(data.len() - 5);
+
+        let inspection = inspect(&data, Some(layout), &FrameFilter::default(), 2);
+        let listed = frames(&inspection.items);
+        let ids: Vec<u8> = listed
+            .iter()
+            .map(|frame| frame.envelope().message_id[0])
+            .collect();
+        assert_eq!(ids, [1, 2, 3, 4]);
+
+        // The damaged frame keeps its envelope and says why it did not decode
+        assert_eq!((listed[0].offset, listed[0].frame_len), (0, first_len));
+        assert!(matches!(listed[0].status, FrameStatus::CrcMismatch { .. }));
+        assert_eq!(listed[0].envelope(), &messages[0].envelope);
+        assert_eq!(listed[0].message(), None);
+
+        let corrupt: Vec<_> = inspection
+            .items
+            .iter()
+            .filter_map(|item| match item {
+                InspectItem::Corrupt(region) => Some(region),
+                InspectItem::Frame(_) => None,
+            })
+            .collect();
+        assert_eq!(corrupt.len(), 2);
+        assert_eq!(corrupt[0].range.start, first_len);
+        assert_eq!(corrupt[0].range.end, first_len + b"not a frame".len());
+        assert!(matches!(
+            corrupt[1].range.reason,
+            SkipReason::Truncated { .. }
+        ));
+        assert_eq!(corrupt[1].range.end, data.len());
+
+        let summary = &inspection.summary;
+        assert_eq!(summary.total_frames, 4);
+        assert_eq!(summary.undecoded_frames, 1);
+        assert_eq!(summary.corrupt_regions, 2);
+        assert_eq!(
+            summary.corrupt_bytes,
+            corrupt[0].range.len() + corrupt[1].range.len()
+        );
+        assert_eq!(summary.largest.len(), 2);
+
+        let report = inspection.to_string();
+        assert!(report.contains("@0 Snapshot frame "), "{report}");
+        assert!(report.contains("CRC bad: stored 0x"), "{report}");
+        assert!(report.contains(r#"agent "agent-a""#), "{report}");
+        assert!(
+            report.contains(&format!("@{first_len} corrupt region 11 bytes")),
+            "{report}"
+        );
+        assert!(report.contains("truncated"), "{report}");
+    }
+}
+
+#[test]
+fn lists_encrypted_and_unknown_dictionary_frames_with_their_envelope() {
+    let samples: Vec<Vec<u8>> = (0..200)
+        .map(|n| {
+            FrameCodec::encode(&message(n, "agent-a", snapshot(10), false))
+                .expect("Failed to encode")
+        })
+        .collect();
+    let dictionary =
+        CompressionDictionary::train_from_frames(3, &samples, 4096).expect("Failed to train");
+    let messages = messages();
+
+    for layout in FrameLayout::ALL {
+        let mut data = Vec::new();
+        FrameEncoder::new()
+            .with_layout(layout)
+            .encode_into(&messages[0], &mut data)
+            .expect("Failed to encode");
+        let mut encrypting = FrameEncoder::new()
+            .with_layout(layout)
+            .with_cipher(PayloadCipher::new(&[7; 32]));
+        for message in &messages[1..3] {
+            encrypting
+                .encode_into(message, &mut data)
+                .expect("Failed to encode");
+        }
+        FrameEncoder::new()
+            .with_layout(layout)
+            .with_dictionary(dictionary.clone())
+            .encode_into(&messages[2], &mut data)
+            .expect("Failed to encode");
+
+        let inspection = inspect(&data, None, &FrameFilter::default(), DEFAULT_TOP_FRAMES);
+        assert_eq!(inspection.layout, layout);
+        let listed = frames(&inspection.items);
+        assert_eq!(listed.len(), 4);
+        assert_eq!(inspection.summary.corrupt_regions, 0);
+        assert_eq!(inspection.summary.undecoded_frames, 3);
+        assert_eq!(listed[0].message(), Some(&messages[0]));
+        for (frame, message) in listed[1..3].iter().zip(&messages[1..3]) {
+            assert!(matches!(frame.status, FrameStatus::Encrypted { .. }));
+            assert_eq!(frame.envelope().message_id, message.envelope.message_id);
+        }
+        assert!(matches!(
+            listed[3].status,
+            FrameStatus::UnknownDictionary {
+                dictionary_id: 3,
+                ..
+            }
+        ));
+        assert_eq!(
+            listed[3].offset + listed[3].frame_len,
+            data.len(),
+            "{layout:?}"
+        );
+
+        // Filters apply to frames that did not decode as well
+        let by_agent = FrameFilter {
+            agents: vec!["agent-b".to_string()],
+            ..FrameFilter::default()
+        };
+        let filtered = inspect(&data, None, &by_agent, DEFAULT_TOP_FRAMES);
+        assert_eq!(frames(&filtered.items).len(), 1);
+        assert_eq!(filtered.summary.total_frames, 4);
+
+        let report = inspection.to_string();
+        assert!(report.contains(", CRC ok, payload encrypted\n"), "{report}");
+        assert!(report.contains("unknown dictionary id 3"), "{report}");
+        assert!(report.contains("(3 not decoded)"), "{report}");
+    }
+}
+
+#[test]
+fn report_shows_frame_fields_and_summary() {
+    let data = capture(&messages()[..3], FrameLayout::Legacy);
+    let report = inspect(&data, None, &FrameFilter::default(), DEFAULT_TOP_FRAMES).to_string();
+    let lines: Vec<&str> = report.lines().collect();
+
+    assert_eq!(lines[0], "layout: Legacy");
+    assert!(lines[1].starts_with("@0 Snapshot frame "), "{}", lines[1]);
+    assert!(lines[1].contains(", CRC ok, payload uncompressed "));
+    // Decoded fields are the canonical JSON form with RFC 3339 timestamps
+    let json = lines[2].trim_start();
+    assert_eq!(
+        Message::from_json(json).expect("Failed to parse"),
+        messages()[0]
+    );
+    assert!(json.contains(r#""timestamp":"2023-12-21T16:00:01.000Z""#));
+    assert!(lines[5].contains("payload compressed ") && lines[5].contains("(ratio "));
+
+    assert!(
+        report.contains("  frames: 3 of 3 matched (0 not decoded); 0 corrupt regions (0 bytes)\n")
+    );
+    assert!(report.contains("  types: Heartbeat 1, Snapshot 2\n"));
+    assert!(report.contains("  agents: \"agent-a\" 2, \"agent-b\" 1\n"));
+    assert!(report.contains("  time range: 2023-12-21T16:00:01.000Z .. 2023-12-21T16:00:03.000Z\n"));
+}
@@ -0,0 +1,152 @@
+//! Integration tests for `MessageBuilder`: generated ids, injected clocks and determinism.
+
+use agent::protocol::*;
+use std::collections::HashSet;
+use std::io::Cursor;
+
+fn identity() -> AgentIdentity {
+    AgentIdentity {
+        instance_id: "test-agent-001".to_string(),
+        os_type: OsType::Linux,
+        agent_version: "1.0.0".to_string(),
+        supported_versions: VersionRange::CURRENT,
+        capabilities: AgentIdentity::CAP_COMPRESSION,
+    }
+}
+
+fn snapshot(process_count: u32) -> MessagePayload {
+    let processes = (0..process_count)
+        .map(|pid| ProcessSample {
+            pid,
+            name: format!("proc-{pid}"),
+            cpu_percent: 1.0,
+            memory_percent: 1.0,
+            memory_bytes: 1_000,
+            cmdline: None,
+        })
+        .collect();
+    MessagePayload::Snapshot(SnapshotPayload {
+        window_start_secs: 1703174400,
+        window_end_secs: 1703174410,
+        total_cpu_percent: 10.0,
+        memory_used_bytes: 1_000_000_000,
+        memory_total_bytes: 8_000_000_000,
+        processes,
+        truncated: false,
+    })
+}
+
+#[test]
+fn builder_fills_the_envelope() {
+    let mut builder = MessageBuilder::new(identity()).with_clock(FixedClock::new(1703174400000));
+    let message = builder.build(MessagePayload::Heartbeat);
+
+    assert_eq!(message.envelope.version, ProtocolVersion::CURRENT);
+    assert_eq!(message.envelope.message_type, MessageType::Heartbeat);
+    assert_eq!(message.envelope.timestamp_utc_ms, 1703174400000);
+    assert_eq!(message.envelope.agent_id, "test-agent-001");
+    assert_eq!(message.envelope.platform, OsType::current());
+    assert!(!message.envelope.compressed);
+    assert_eq!(
+        message_id_timestamp_ms(&message.envelope.message_id),
+        Some(1703174400000)
+    );
+
+    let handshake = builder.handshake();
+    assert_eq!(handshake.payload, MessagePayload::Handshake(identity()));
+    assert!(handshake.validate().is_ok());
+    let decoded = FrameCodec::decode_with_options(
+        &mut Cursor::new(FrameCodec::encode(&handshake).expect("Failed to encode")),
+        &mut DecodeOptions::new().with_mode(DecodeMode::Strict),
+    )
+    .expect("Failed to decode");
+    assert_eq!(decoded, handshake);
+}
+
+#[test]
+fn message_ids_are_unique_and_time_ordered() {
+    // Many ids per millisecond, then a clock that goes backwards
+    let mut builder = MessageBuilder::new(identity()).with_clock(FixedClock::new(1_000));
+    let mut ids: Vec<[u8; 16]> = (0..5_000).map(|_| builder.next_message_id()).collect();
+    let mut builder = builder.with_clock(FixedClock::new(500));
+    ids.extend((0..10).map(|_| builder.next_message_id()));
+
+    assert!(ids.windows(2).all(|pair| pair[0] < pair[1]));
+    assert_eq!(ids.iter().collect::<HashSet<_>>().len(), ids.len());
+    for id in &ids {
+        assert_eq!(id[6] >> 4, 7, "version");
+        assert_eq!(id[8] >> 6, 0b10, "variant");
+        assert!(message_id_timestamp_ms(id).expect("Not a UUIDv7 id") >= 1_000);
+    }
+
+    // Independent builders do not collide
+    let a = MessageBuilder::new(identity())
+        .with_clock(FixedClock::new(1_000))
+        .next_message_id();
+    let b = MessageBuilder::new(identity())
+        .with_clock(FixedClock::new(1_000))
+        .next_message_id();
+    assert_ne!(a, b);
+}
+
+#[test]
+fn deterministic_builders_are_reproducible() {
+    let build = || {
+        let mut builder = MessageBuilder::deterministic(
+            identity(),
+            42,
+            FixedClock::stepping(1703174400000, 10_000),
+        )
+        .with_platform(OsType::Linux);
+        (0..3)
+            .map(|_| FrameCodec::encode(&builder.build(snapshot(3))).expect("Failed to encode"))
+            .collect::<Vec<_>>()
+    };
+    let frames = build();
+    assert_eq!(frames, build());
+
+    let timestamps: Vec<i64> = frames
+        .iter()
+        .map(|frame| {
+            FrameCodec::decode(&mut Cursor::new(frame))
+                .expect("Failed to decode")
+                .envelope
+                .timestamp_utc_ms
+        })
+        .collect();
+    assert_eq!(timestamps, [1703174400000, 1703174410000, 1703174420000]);
+
+    let other = MessageBuilder::deterministic(identity(), 43, FixedClock::new(1703174400000))
+        .with_platform(OsType::Linux)
+        .build(snapshot(3));
+    assert_ne!(
+        FrameCodec::encode(&other).expect("Failed to encode"),
+        frames[0]
+    );
+}
+
+#[test]
+fn compression_policy_sets_the_compressed_flag() {
+    let ack = negotiate(
+        &identity(),
+        VersionRange::CURRENT,
+        AgentIdentity::CAP_COMPRESSION,
+    )
+    .expect("Failed to negotiate");
+    let mut builder = MessageBuilder::new(identity())
+        .with_version(ack.negotiated_version)
+        .with_policy(CompressionPolicy::negotiated(&ack));
+
+    assert!(!builder.build(MessagePayload::Heartbeat).envelope.compressed);
+    let large = builder.build(snapshot(100));
+    assert!(large.envelope.compressed);
+    let decoded = FrameCodec::decode(&mut Cursor::new(
+        FrameCodec::encode(&large).expect("Failed to encode"),
+    ))
+    .expect("Failed to decode");
+    assert_eq!(decoded, large);
+
+    // Nothing is compressed before compression is negotiated
+    let mut builder = MessageBuilder::new(identity());
+    assert!(!builder.build(snapshot(100)).envelope.compressed);
+}
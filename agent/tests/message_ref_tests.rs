@@ -0,0 +1,175 @@
+//! Integration tests for the zero-copy `MessageRef` view.
+//!
+//! Validates that borrowed views match `FrameCodec::decode`, that uncompressed payloads are
+//! borrowed from the input buffer, and that `to_owned()` reproduces the owned message.
+
+use agent::protocol::*;
+use std::io::Cursor;
+
+// Test helper: Create a 16-byte message ID from a u64 for simplicity in tests
+fn test_message_id(n: u64) -> [u8; 16] {
+    let mut id = [0u8; 16];
+    id[0..8].copy_from_slice(&n.to_le_bytes());
+    id
+}
+
+fn envelope(n: u64, message_type: MessageType, agent_id: &str, compressed: bool) -> Envelope {
+    Envelope {
+        version: ProtocolVersion::CURRENT,
+        message_type,
+        message_id: test_message_id(n),
+        timestamp_utc_ms: 1703174410000 + n as i64,
+        agent_id: agent_id.to_string(),
+        platform: OsType::Linux,
+        compressed,
+        extensions: Extensions::new(),
+    }
+}
+
+fn snapshot_message(n: u64, agent_id: &str, compressed: bool) -> Message {
+    Message {
+        envelope: envelope(n, MessageType::Snapshot, agent_id, compressed),
+        payload: MessagePayload::Snapshot(SnapshotPayload {
+            window_start_secs: 1703174400,
+            window_end_secs: 1703174410,
+            total_cpu_percent: 42.0,
+            memory_used_bytes: 3_000_000_000,
+            memory_total_bytes: 8_000_000_000,
+            processes: vec![
+                ProcessSample {
+                    pid: 1,
+                    name: "systemd".to_string(),
+                    cpu_percent: 0.1,
+                    memory_percent: 0.2,
+                    memory_bytes: 10_000_000,
+                    cmdline: Some("/sbin/init splash".to_string()),
+                },
+                ProcessSample {
+                    pid: 4242,
+                    name: "postgres-π".to_string(),
+                    cpu_percent: 35.5,
+                    memory_percent: 12.0,
+                    memory_bytes: 900_000_000,
+                    cmdline: None,
+                },
+            ],
+            truncated: true,
+        }),
+        payload_extensions: Extensions::new(),
+    }
+}
+
+#[test]
+fn uncompressed_view_borrows_from_frame_buffer() {
+    let message = snapshot_message(1, "agent-α", false);
+    let frame = FrameCodec::encode(&message).expect("Failed to encode");
+
+    let (view, consumed) = MessageRef::from_frame(&frame).expect("Failed to parse view");
+    assert_eq!(consumed, frame.len());
+    assert!(view.is_payload_borrowed());
+
+    let frame_range = frame.as_ptr_range();
+    assert!(frame_range.contains(&view.envelope.agent_id.as_ptr()));
+    assert_eq!(view.envelope.agent_id, "agent-α");
+    assert_eq!(view.envelope.message_type, MessageType::Snapshot);
+
+    let snapshot = view
+        .snapshot()
+        .expect("Valid payload")
+        .expect("Snapshot payload");
+    assert_eq!(snapshot.process_count(), 2);
+    assert!(snapshot.truncated);
+    let names: Vec<&str> = snapshot.processes().map(|p| p.name).collect();
+    assert_eq!(names, vec!["systemd", "postgres-π"]);
+    assert!(snapshot
+        .processes()
+        .all(|p| frame_range.contains(&p.name.as_ptr())));
+    assert_eq!(
+        snapshot.processes().next().and_then(|p| p.cmdline),
+        Some("/sbin/init splash")
+    );
+}
+
+#[test]
+fn view_to_owned_matches_framecodec_decode() {
+    for compressed in [false, true] {
+        let mut message = snapshot_message(2, "agent-001", compressed);
+        message.envelope.set_sequence_number(9);
+        message.set_total_process_count(3);
+        let frame = FrameCodec::encode(&message).expect("Failed to encode");
+
+        let (view, _) = MessageRef::from_frame(&frame).expect("Failed to parse view");
+        assert_eq!(view.is_payload_borrowed(), !compressed);
+
+        let decoded = FrameCodec::decode(&mut Cursor::new(&frame)).expect("Failed to decode");
+        assert_eq!(view.to_owned().expect("Valid message"), decoded);
+        assert_eq!(decoded, message);
+
+        let snapshot = view.snapshot().expect("Valid").expect("Snapshot payload");
+        assert_eq!(
+            MessagePayload::Snapshot(snapshot.to_owned()),
+            message.payload
+        );
+    }
+}
+
+#[test]
+fn filter_concatenated_frames_by_agent_and_process_name() {
+    let mut buffer = Vec::new();
+    for n in 0..6 {
+        let agent_id = if n % 2 == 0 {
+            "agent-even"
+        } else {
+            "agent-odd"
+        };
+        buffer.extend(FrameCodec::encode(&snapshot_message(n, agent_id, n % 3 == 0)).unwrap());
+    }
+    let heartbeat = Message {
+        envelope: envelope(99, MessageType::Heartbeat, "agent-even", false),
+        payload: MessagePayload::Heartbeat,
+        payload_extensions: Extensions::new(),
+    };
+    buffer.extend(FrameCodec::encode(&heartbeat).unwrap());
+
+    let mut offset = 0;
+    let mut matches = Vec::new();
+    while offset < buffer.len() {
+        let (view, len) = MessageRef::from_frame(&buffer[offset..]).expect("Valid frame");
+        offset += len;
+        if view.envelope.agent_id != "agent-even" {
+            continue;
+        }
+        if let Some(snapshot) = view.snapshot().expect("Valid payload") {
+            if snapshot.processes().any(|p| p.name.starts_with("postgres")) {
+                matches.push(*view.envelope.message_id);
+            }
+        } else {
+            assert_eq!(
+                view.payload().expect("Valid payload"),
+                PayloadRef::Other(MessagePayload::Heartbeat)
+            );
+        }
+    }
+
+    assert_eq!(
+        matches,
+        vec![test_message_id(0), test_message_id(2), test_message_id(4)]
+    );
+}
+
+#[test]
+fn view_rejects_corrupt_and_truncated_frames() {
+    let frame = FrameCodec::encode(&snapshot_message(3, "agent-001", false)).unwrap();
+
+    let mut corrupt = frame.clone();
+    corrupt[30] ^= 0x01;
+    assert!(matches!(
+        MessageRef::from_frame(&corrupt),
+        Err(ProtocolError::Crc32Mismatch { .. })
+    ));
+
+    assert!(matches!(
+        MessageRef::from_frame(&frame[..frame.len() - 1]),
+        Err(ProtocolError::Io(_))
+    ));
+}
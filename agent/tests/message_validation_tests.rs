@@ -0,0 +1,229 @@
+//! Integration tests for envelope/payload type consistency and `Message::validate`.
+
+use agent::protocol::*;
+
+// Test helper: Create a 16-byte message ID from a u64 for simplicity in tests
+fn test_message_id(n: u64) -> [u8; 16] {
+    let mut id = [0u8; 16];
+    id[0..8].copy_from_slice(&n.to_le_bytes());
+    id
+}
+
+fn message(payload: MessagePayload) -> Message {
+    Message {
+        envelope: Envelope {
+            version: ProtocolVersion::CURRENT,
+            message_type: payload.message_type(),
+            message_id: test_message_id(1),
+            timestamp_utc_ms: 1703174400000,
+            agent_id: "test-agent-001".to_string(),
+            platform: OsType::Linux,
+            compressed: false,
+            extensions: Extensions::new(),
+        },
+        payload,
+        payload_extensions: Extensions::new(),
+    }
+}
+
+fn snapshot(processes: Vec<ProcessSample>) -> SnapshotPayload {
+    SnapshotPayload {
+        window_start_secs: 1703174400,
+        window_end_secs: 1703174410,
+        total_cpu_percent: 25.0,
+        memory_used_bytes: 4_000_000_000,
+        memory_total_bytes: 8_000_000_000,
+        processes,
+        truncated: false,
+    }
+}
+
+fn process(pid: u32, cpu_percent: f32, memory_percent: f32) -> ProcessSample {
+    ProcessSample {
+        pid,
+        name: format!("proc-{pid}"),
+        cpu_percent,
+        memory_percent,
+        memory_bytes: 1_000,
+        cmdline: None,
+    }
+}
+
+fn identity(instance_id: &str, os_type: OsType) -> AgentIdentity {
+    AgentIdentity {
+        instance_id: instance_id.to_string(),
+        os_type,
+        agent_version: "1.0.0".to_string(),
+        supported_versions: VersionRange::CURRENT,
+        capabilities: 0,
+    }
+}
+
+fn fields(violations: &[Violation]) -> Vec<&str> {
+    violations.iter().map(|v| v.field.as_str()).collect()
+}
+
+#[test]
+fn encode_rejects_envelope_type_that_does_not_match_payload() {
+    let mut mismatched = message(MessagePayload::Snapshot(snapshot(vec![])));
+    mismatched.envelope.message_type = MessageType::Heartbeat;
+
+    assert!(matches!(
+        FrameCodec::encode(&mismatched),
+        Err(ProtocolError::MessageTypeMismatch {
+            envelope: MessageType::Heartbeat,
+            payload: MessageType::Snapshot,
+        })
+    ));
+    assert!(matches!(
+        FrameEncoder::new().encode_into(&mismatched, &mut Vec::new()),
+        Err(ProtocolError::MessageTypeMismatch { .. })
+    ));
+
+    let violations = mismatched.validate().unwrap_err();
+    assert_eq!(fields(&violations), ["envelope.message_type"]);
+}
+
+#[test]
+fn payload_reports_its_message_type() {
+    let payloads = [
+        (
+            MessagePayload::Handshake(identity("a", OsType::Linux)),
+            MessageType::Handshake,
+        ),
+        (MessagePayload::Heartbeat, MessageType::Heartbeat),
+        (
+            MessagePayload::Snapshot(snapshot(vec![])),
+            MessageType::Snapshot,
+        ),
+        (
+            MessagePayload::Error {
+                code: 1,
+                message: String::new(),
+            },
+            MessageType::Error,
+        ),
+    ];
+    for (payload, message_type) in payloads {
+        assert_eq!(payload.message_type(), message_type);
+    }
+}
+
+#[test]
+fn valid_snapshot_passes() {
+    let message = message(MessagePayload::Snapshot(snapshot(vec![
+        process(1, 0.0, 0.0),
+        process(2, 100.0, 100.0),
+    ])));
+    assert_eq!(message.validate(), Ok(()));
+}
+
+#[test]
+fn every_snapshot_violation_is_reported() {
+    let mut payload = snapshot(vec![
+        process(1, 10.0, 5.0),
+        process(2, f32::NAN, 5.0),
+        process(3, 10.0, 150.0),
+    ]);
+    payload.window_end_secs = payload.window_start_secs - 1;
+    payload.memory_used_bytes = payload.memory_total_bytes + 1;
+    payload.total_cpu_percent = -1.0;
+
+    let violations = message(MessagePayload::Snapshot(payload))
+        .validate()
+        .unwrap_err();
+    assert_eq!(
+        fields(&violations),
+        [
+            "snapshot.window_end_secs",
+            "snapshot.memory_used_bytes",
+            "snapshot.total_cpu_percent",
+            "snapshot.processes[1].cpu_percent",
+            "snapshot.processes[2].memory_percent",
+        ]
+    );
+    assert!(matches!(violations[3].kind, ViolationKind::NotFinite(_)));
+    assert_eq!(
+        violations[4].kind,
+        ViolationKind::PercentOutOfRange {
+            value: 150.0,
+            max: 100.0
+        }
+    );
+    assert_eq!(
+        violations[4].to_string(),
+        "snapshot.processes[2].memory_percent: percentage 150 is outside 0..=100"
+    );
+}
+
+#[test]
+fn per_core_cpu_bounds_are_configurable() {
+    let message = message(MessagePayload::Snapshot(snapshot(vec![process(
+        1, 350.0, 5.0,
+    )])));
+    assert!(message.validate().is_err());
+
+    let context = ValidationContext::default().with_logical_cpus(4);
+    assert_eq!(message.validate_with(&context), Ok(()));
+    let context = ValidationContext::default().with_logical_cpus(2);
+    assert!(message.validate_with(&context).is_err());
+}
+
+#[test]
+fn envelope_must_match_session_handshake() {
+    let session = identity("test-agent-001", OsType::Linux);
+    let heartbeat = message(MessagePayload::Heartbeat);
+    let context = ValidationContext::default().with_handshake(&session);
+    assert_eq!(heartbeat.validate_with(&context), Ok(()));
+
+    let other = identity("other-agent", OsType::Windows);
+    let context = ValidationContext::default().with_handshake(&other);
+    let violations = heartbeat.validate_with(&context).unwrap_err();
+    assert_eq!(
+        fields(&violations),
+        ["envelope.agent_id", "envelope.platform"]
+    );
+    assert_eq!(
+        violations[1].kind,
+        ViolationKind::PlatformMismatch {
+            expected: OsType::Windows,
+            actual: OsType::Linux
+        }
+    );
+}
+
+#[test]
+fn handshake_is_checked_against_its_own_identity() {
+    let mut claimed = identity("someone-else", OsType::Linux);
+    claimed.supported_versions = VersionRange {
+        min: ProtocolVersion { major: 1, minor: 3 },
+        max: ProtocolVersion { major: 1, minor: 0 },
+    };
+    let violations = message(MessagePayload::Handshake(claimed))
+        .validate()
+        .unwrap_err();
+    assert_eq!(
+        fields(&violations),
+        ["envelope.agent_id", "handshake.supported_versions"]
+    );
+}
+
+#[test]
+fn snapshot_part_index_must_be_below_count() {
+    let part = SnapshotPartPayload {
+        snapshot_id: test_message_id(9),
+        part_index: 2,
+        part_count: 2,
+        snapshot: snapshot(vec![process(1, 101.0, 1.0)]),
+    };
+    let violations = message(MessagePayload::SnapshotPart(part))
+        .validate()
+        .unwrap_err();
+    assert_eq!(
+        fields(&violations),
+        [
+            "snapshot_part.part_index",
+            "snapshot_part.snapshot.processes[0].cpu_percent"
+        ]
+    );
+}
@@ -0,0 +1,374 @@
+//! Integration tests for AEAD payload encryption with handshake-derived session keys.
+
+use agent::protocol::*;
+use std::io::Cursor;
+use std::sync::Arc;
+
+// Test helper: Create a 16-byte message ID from a u64 for simplicity in tests
+fn test_message_id(n: u64) -> [u8; 16] {
+    let mut id = [0u8; 16];
+    id[0..8].copy_from_slice(&n.to_le_bytes());
+    id
+}
+
+const PRE_SHARED_KEY: &[u8] = b"pre-shared key of test-agent-001";
+
+fn message(payload: MessagePayload, compressed: bool) -> Message {
+    Message {
+        envelope: Envelope {
+            version: ProtocolVersion::CURRENT,
+            message_type: payload.message_type(),
+            message_id: test_message_id(1),
+            timestamp_utc_ms: 1703174400000,
+            agent_id: "test-agent-001".to_string(),
+            platform: OsType::Linux,
+            compressed,
+            extensions: Extensions::new(),
+        },
+        payload,
+        payload_extensions: Extensions::new(),
+    }
+}
+
+fn snapshot(compressed: bool) -> Message {
+    let processes = (0..40)
+        .map(|pid| ProcessSample {
+            pid,
+            name: format!("secret-process-{pid}"),
+            cpu_percent: 1.0,
+            memory_percent: 1.0,
+            memory_bytes: 1_000,
+            cmdline: None,
+        })
+        .collect();
+    message(
+        MessagePayload::Snapshot(SnapshotPayload {
+            window_start_secs: 1703174400,
+            window_end_secs: 1703174410,
+            total_cpu_percent: 10.0,
+            memory_used_bytes: 1_000_000_000,
+            memory_total_bytes: 8_000_000_000,
+            processes,
+            truncated: false,
+        }),
+        compressed,
+    )
+}
+
+/// Run the handshake and return the keys derived by the agent and by the server.
+fn handshake() -> (SessionKeys, SessionKeys) {
+    let identity = AgentIdentity {
+        instance_id: "test-agent-001".to_string(),
+        os_type: OsType::Linux,
+        agent_version: "1.0.0".to_string(),
+        supported_versions: VersionRange::CURRENT,
+        capabilities: AgentIdentity::CAP_COMPRESSION | AgentIdentity::CAP_ENCRYPTION,
+    };
+    let mut hello = message(MessagePayload::Handshake(identity.clone()), false);
+    hello.set_session_nonce(&SessionKeys::random_nonce());
+    let hello = FrameCodec::decode(&mut Cursor::new(
+        FrameCodec::encode(&hello).expect("Failed to encode"),
+    ))
+    .expect("Failed to decode");
+
+    // Server side
+    let ack = negotiate(
+        &identity,
+        VersionRange::CURRENT,
+        AgentIdentity::CAP_COMPRESSION | AgentIdentity::CAP_ENCRYPTION,
+    )
+    .expect("Failed to negotiate");
+    assert!(ack.encryption_enabled());
+    let agent_nonce = hello
+        .session_nonce()
+        .expect("Invalid nonce")
+        .expect("Missing nonce");
+    let mut reply = message(MessagePayload::HandshakeAck(ack), false);
+    reply.set_session_nonce(&SessionKeys::random_nonce());
+    let server_nonce = reply
+        .session_nonce()
+        .expect("Invalid nonce")
+        .expect("Missing nonce");
+    let server_keys = SessionKeys::derive(PRE_SHARED_KEY, &agent_nonce, &server_nonce)
+        .expect("Failed to derive keys");
+
+    // Agent side
+    let reply = FrameCodec::decode(&mut Cursor::new(
+        FrameCodec::encode(&reply).expect("Failed to encode"),
+    ))
+    .expect("Failed to decode");
+    let server_nonce = reply
+        .session_nonce()
+        .expect("Invalid nonce")
+        .expect("Missing nonce");
+    let agent_keys = SessionKeys::derive(PRE_SHARED_KEY, &agent_nonce, &server_nonce)
+        .expect("Failed to derive keys");
+
+    (agent_keys, server_keys)
+}
+
+fn decrypt(frame: &[u8], cipher: &mut PayloadCipher) -> Result<Message, ProtocolError> {
+    FrameCodec::decode_with_options(
+        &mut Cursor::new(frame),
+        &mut DecodeOptions::new()
+            .with_mode(DecodeMode::Strict)
+            .with_cipher(cipher),
+    )
+}
+
+#[test]
+fn encrypted_payloads_round_trip_after_handshake() {
+    let (agent_keys, server_keys) = handshake();
+    let mut sealer = agent_keys.sealer(SessionRole::Agent);
+    let mut opener = server_keys.opener(SessionRole::Server);
+
+    for compressed in [false, true] {
+        let message = snapshot(compressed);
+        let frame = FrameCodec::encode_encrypted(&message, &mut sealer).expect("Failed to encode");
+        assert!(!frame
+            .windows(b"secret-process".len())
+            .any(|window| window == b"secret-process"));
+
+        // Without the cipher only the envelope is readable
+        let err = MessageRef::from_frame(&frame).unwrap_err();
+        assert!(matches!(err.root_cause(), ProtocolError::Encryption(_)));
+        assert_eq!(err.field_path(), Some("payload"));
+        assert_eq!(
+            decrypt(&frame, &mut opener).expect("Failed to decode"),
+            message
+        );
+    }
+
+    // The other direction uses the other key
+    let mut reply_sealer = server_keys.sealer(SessionRole::Server);
+    let frame =
+        FrameCodec::encode_encrypted(&snapshot(true), &mut reply_sealer).expect("Failed to encode");
+    let mut agent_opener = agent_keys.opener(SessionRole::Agent);
+    decrypt(&frame, &mut agent_opener).expect("Failed to decode");
+    assert!(matches!(
+        decrypt(&frame, &mut opener).unwrap_err().root_cause(),
+        ProtocolError::Encryption(_)
+    ));
+}
+
+#[test]
+fn nonces_are_never_reused() {
+    let (agent_keys, server_keys) = handshake();
+    let mut codec_sealer = agent_keys.sealer(SessionRole::Agent);
+    let mut encoder = FrameEncoder::new().with_cipher(agent_keys.sealer(SessionRole::Agent));
+    let mut opener = server_keys.opener(SessionRole::Server);
+
+    let message = snapshot(true);
+    let mut previous = Vec::new();
+    for _ in 0..3 {
+        let mut frame = Vec::new();
+        encoder
+            .encode_into(&message, &mut frame)
+            .expect("Failed to encode");
+        assert_eq!(
+            frame,
+            FrameCodec::encode_encrypted(&message, &mut codec_sealer).expect("Failed to encode")
+        );
+        // Same message, fresh counter: different ciphertext
+        assert_ne!(frame, previous);
+        assert_eq!(
+            decrypt(&frame, &mut opener).expect("Failed to decode"),
+            message
+        );
+        previous = frame;
+    }
+
+    // Fresh handshake nonces give fresh keys, so counters restarting at 0 are safe
+    let (other_keys, _) = handshake();
+    let frame = FrameCodec::encode_encrypted(&message, &mut other_keys.sealer(SessionRole::Agent))
+        .expect("Failed to encode");
+    assert!(decrypt(&frame, &mut opener).is_err());
+}
+
+#[test]
+fn replayed_frames_are_rejected() {
+    let (agent_keys, server_keys) = handshake();
+    let mut sealer = agent_keys.sealer(SessionRole::Agent);
+    let mut opener = server_keys.opener(SessionRole::Server);
+    let first =
+        FrameCodec::encode_encrypted(&snapshot(false), &mut sealer).expect("Failed to encode");
+    let second =
+        FrameCodec::encode_encrypted(&snapshot(true), &mut sealer).expect("Failed to encode");
+
+    decrypt(&first, &mut opener).expect("Failed to decode");
+    decrypt(&second, &mut opener).expect("Failed to decode");
+    // The same frame again, or an older one delivered late
+    for frame in [&second, &first] {
+        let err = decrypt(frame, &mut opener).unwrap_err();
+        assert!(
+            matches!(err.root_cause(), ProtocolError::Encryption(reason) if reason.contains("replayed")),
+            "{err}"
+        );
+    }
+
+    // A forged frame does not advance the counter: the next genuine frame still opens
+    let third =
+        FrameCodec::encode_encrypted(&snapshot(false), &mut sealer).expect("Failed to encode");
+    let mut forged = third.clone();
+    let body_end = forged.len() - 4;
+    forged[body_end - 2] ^= 0x01;
+    let checksum = crc32fast::hash(&forged[4..body_end]);
+    forged[body_end..].copy_from_slice(&checksum.to_le_bytes());
+    assert!(decrypt(&forged, &mut opener).is_err());
+    decrypt(&third, &mut opener).expect("Failed to decode");
+}
+
+#[test]
+fn frames_rejected_after_decryption_do_not_advance_the_counter() {
+    let (agent_keys, server_keys) = handshake();
+    let mut sealer = agent_keys.sealer(SessionRole::Agent);
+    let mut opener = server_keys.opener(SessionRole::Server);
+    let frame =
+        FrameCodec::encode_encrypted(&snapshot(false), &mut sealer).expect("Failed to encode");
+
+    // The payload decrypts but exceeds the process limit
+    let limits = DecodeLimits {
+        max_process_count: 0,
+        ..DecodeLimits::default()
+    };
+    let err = FrameCodec::decode_with_options(
+        &mut Cursor::new(&frame),
+        &mut DecodeOptions::new()
+            .with_limits(limits)
+            .with_cipher(&mut opener),
+    )
+    .unwrap_err();
+    assert!(matches!(
+        err.root_cause(),
+        ProtocolError::TooManyProcesses { .. }
+    ));
+
+    // Its counter was not used up, so the frame still opens once it can be decoded
+    assert_eq!(
+        decrypt(&frame, &mut opener).expect("Failed to decode"),
+        snapshot(false)
+    );
+    let err = decrypt(&frame, &mut opener).unwrap_err();
+    assert!(
+        matches!(err.root_cause(), ProtocolError::Encryption(reason) if reason.contains("replayed"))
+    );
+}
+
+#[test]
+fn plaintext_frames_are_rejected_when_a_cipher_is_set() {
+    let (_, server_keys) = handshake();
+    let frame = FrameCodec::encode(&snapshot(true)).expect("Failed to encode");
+
+    let mut opener = server_keys.opener(SessionRole::Server);
+    let err = decrypt(&frame, &mut opener).unwrap_err();
+    assert!(matches!(err.root_cause(), ProtocolError::UnencryptedFrame));
+    assert_eq!(err.error_code(), ErrorCode::Unauthenticated);
+
+    let mut decoder = FrameDecoder::new().with_cipher(server_keys.opener(SessionRole::Server));
+    let err = decoder.decode(&frame).unwrap_err();
+    assert!(matches!(err.root_cause(), ProtocolError::UnencryptedFrame));
+}
+
+#[test]
+fn tampered_envelopes_and_payloads_are_rejected() {
+    let (agent_keys, server_keys) = handshake();
+    let mut opener = server_keys.opener(SessionRole::Server);
+    let frame =
+        FrameCodec::encode_encrypted(&snapshot(false), &mut agent_keys.sealer(SessionRole::Agent))
+            .expect("Failed to encode");
+
+    // Byte 19 is in the envelope timestamp, the second to last body byte is in the AEAD tag
+    let body_end = frame.len() - 4;
+    for offset in [4 + 19, 4 + 80, body_end - 2] {
+        let mut forged = frame.clone();
+        forged[offset] ^= 0x01;
+        let checksum = crc32fast::hash(&forged[4..body_end]);
+        forged[body_end..].copy_from_slice(&checksum.to_le_bytes());
+
+        let err = decrypt(&forged, &mut opener).unwrap_err();
+        assert!(
+            matches!(err.root_cause(), ProtocolError::Encryption(_)),
+            "{err}"
+        );
+    }
+}
+
+#[test]
+fn encryption_combines_with_authentication_and_push_decoding() {
+    let (agent_keys, server_keys) = handshake();
+    let mut keys = KeyRing::new();
+    keys.insert("test-agent-001", AuthKey::new(1, PRE_SHARED_KEY));
+    let keys = Arc::new(keys);
+
+    let mut encoder = FrameEncoder::new()
+        .with_policy(CompressionPolicy::default())
+        .with_cipher(agent_keys.sealer(SessionRole::Agent))
+        .with_key_store(keys.clone());
+    let messages = [snapshot(true), message(MessagePayload::Heartbeat, false)];
+    let mut stream = Vec::new();
+    for message in &messages {
+        encoder
+            .encode_into(message, &mut stream)
+            .expect("Failed to encode");
+    }
+
+    let mut decoder = FrameDecoder::with_options(DecodeLimits::default(), DecodeMode::Strict)
+        .with_cipher(server_keys.opener(SessionRole::Server))
+        .with_key_store(keys);
+    let frames = decoder.decode(&stream).expect("Failed to decode");
+    assert_eq!(frames.messages.len(), 2);
+    assert!(frames.messages[0].envelope.compressed);
+    assert_eq!(frames.messages[0].payload, messages[0].payload);
+    assert_eq!(frames.messages[1].payload, MessagePayload::Heartbeat);
+    assert_eq!(
+        frames.messages[1]
+            .envelope
+            .key_id()
+            .expect("Invalid key id"),
+        Some(1)
+    );
+}
+
+#[test]
+fn scanner_reports_encrypted_frames_as_intact() {
+    let (agent_keys, _) = handshake();
+    let mut sealer = agent_keys.sealer(SessionRole::Agent);
+    let mut capture = FrameCodec::encode(&snapshot(false)).expect("Failed to encode");
+    let first_len = capture.len();
+    capture.extend(
+        FrameCodec::encode_encrypted(&snapshot(true), &mut sealer).expect("Failed to encode"),
+    );
+    capture.extend(FrameCodec::encode(&snapshot(true)).expect("Failed to encode"));
+
+    let report = scan_frames(&capture);
+    assert_eq!(report.frames.len(), 2);
+    assert_eq!(report.skipped.len(), 1);
+    let skipped = &report.skipped[0];
+    assert_eq!(skipped.start, first_len);
+    assert_eq!(skipped.end, report.frames[1].offset);
+    assert_eq!(skipped.reason, SkipReason::Encrypted { len: skipped.len() });
+
+    // An encrypted capture still has a detectable layout
+    assert_eq!(
+        FrameLayout::detect(&capture[first_len..]),
+        Some(FrameLayout::Legacy)
+    );
+}
+
+#[test]
+fn encryption_is_negotiated_only_when_both_sides_support_it() {
+    let agent = AgentIdentity {
+        instance_id: "test-agent-001".to_string(),
+        os_type: OsType::Linux,
+        agent_version: "1.0.0".to_string(),
+        supported_versions: VersionRange::CURRENT,
+        capabilities: AgentIdentity::CAP_ENCRYPTION,
+    };
+    assert!(agent.supports_encryption());
+
+    let ack = negotiate(&agent, VersionRange::CURRENT, 0).expect("Failed to negotiate");
+    assert!(!ack.encryption_enabled());
+    let ack = negotiate(&agent, VersionRange::CURRENT, AgentIdentity::CAP_ENCRYPTION)
+        .expect("Failed to negotiate");
+    assert!(ack.encryption_enabled());
+}
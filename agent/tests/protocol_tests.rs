@@ -10,6 +10,7 @@
 //!
 //! The tests are organized into logical modules for maintainability:
 //! - Protocol version compatibility
+//! - Version range negotiation
 //! - Message types and conversions
 //! - Encoding and decoding round-trips
 //! - Compression and frame handling
@@ -61,6 +62,181 @@ fn protocol_version_different_major_is_incompatible() {
     );
 }
 
+// ============================================================================
+// Module: Version Range Negotiation
+// ============================================================================
+
+/// Tests for handshake version range negotiation (FR-003).
+/// The server picks the highest version inside both ranges and intersects capabilities.
+fn identity_with_range(min: (u8, u8), max: (u8, u8), capabilities: u32) -> AgentIdentity {
+    AgentIdentity {
+        instance_id: "agent-negotiate".to_string(),
+        os_type: OsType::Linux,
+        agent_version: "0.2.0".to_string(),
+        supported_versions: VersionRange::new(
+            ProtocolVersion {
+                major: min.0,
+                minor: min.1,
+            },
+            ProtocolVersion {
+                major: max.0,
+                minor: max.1,
+            },
+        )
+        .expect("valid range"),
+        capabilities,
+    }
+}
+
+// Test helper: Re-frame a body with a fresh length prefix and CRC32 trailer
+fn reframe(body: &[u8]) -> Vec<u8> {
+    let mut frame = (body.len() as u32).to_be_bytes().to_vec();
+    frame.extend_from_slice(body);
+    frame.extend_from_slice(&crc32fast::hash(body).to_le_bytes());
+    frame
+}
+
+#[test]
+fn negotiate_picks_highest_common_version() {
+    let agent = identity_with_range((1, 0), (1, 4), AgentIdentity::CAP_COMPRESSION);
+    let server = VersionRange::new(
+        ProtocolVersion { major: 1, minor: 2 },
+        ProtocolVersion { major: 2, minor: 0 },
+    )
+    .unwrap();
+
+    let ack = negotiate(&agent, server, AgentIdentity::CAP_COMPRESSION).expect("overlap");
+    assert_eq!(
+        ack.negotiated_version,
+        ProtocolVersion { major: 1, minor: 4 },
+        "Highest version inside both ranges should be chosen"
+    );
+}
+
+#[test]
+fn negotiate_intersects_capabilities() {
+    let agent = identity_with_range(
+        (1, 0),
+        (1, 0),
+        AgentIdentity::CAP_ALL_PROCESS | AgentIdentity::CAP_COMPRESSION,
+    );
+
+    let ack = negotiate(
+        &agent,
+        VersionRange::CURRENT,
+        AgentIdentity::CAP_COMPRESSION,
+    )
+    .expect("overlap");
+    assert_eq!(ack.negotiated_capabilities, AgentIdentity::CAP_COMPRESSION);
+    assert!(ack.compression_enabled());
+    assert!(!ack.all_process_enabled());
+}
+
+#[test]
+fn negotiate_without_overlap_is_incompatible() {
+    let agent = identity_with_range((2, 0), (2, 3), 0);
+
+    let result = negotiate(&agent, VersionRange::CURRENT, 0);
+    assert!(
+        matches!(result, Err(ProtocolError::IncompatibleVersion)),
+        "Disjoint ranges must fail the handshake"
+    );
+}
+
+#[test]
+fn version_range_rejects_inverted_bounds() {
+    let v1_0 = ProtocolVersion { major: 1, minor: 0 };
+    let v1_1 = ProtocolVersion { major: 1, minor: 1 };
+    assert!(VersionRange::new(v1_1, v1_0).is_none());
+    assert!(VersionRange::new(v1_0, v1_1).unwrap().contains(v1_1));
+    assert!(!VersionRange::single(v1_0).contains(v1_1));
+}
+
+#[test]
+fn handshake_version_range_round_trips() {
+    let identity = identity_with_range((1, 0), (1, 3), AgentIdentity::CAP_ALL_PROCESS);
+    let message = Message {
+        envelope: Envelope {
+            version: ProtocolVersion::CURRENT,
+            message_type: MessageType::Handshake,
+            message_id: test_message_id(2),
+            timestamp_utc_ms: 1703174400000,
+            agent_id: "agent-negotiate".to_string(),
+            platform: OsType::Linux,
+            compressed: false,
+            extensions: Extensions::new(),
+        },
+        payload: MessagePayload::Handshake(identity.clone()),
+        payload_extensions: Extensions::new(),
+    };
+
+    let encoded = FrameCodec::encode(&message).expect("Failed to encode handshake");
+    let decoded = FrameCodec::decode(&mut Cursor::new(&encoded)).expect("Failed to decode");
+
+    assert_eq!(decoded.payload, MessagePayload::Handshake(identity));
+}
+
+#[test]
+fn handshake_without_min_version_decodes_as_single_version() {
+    let identity = identity_with_range((1, 0), (1, 2), 0);
+    let message = Message {
+        envelope: Envelope {
+            version: ProtocolVersion::CURRENT,
+            message_type: MessageType::Handshake,
+            message_id: test_message_id(3),
+            timestamp_utc_ms: 1703174400000,
+            agent_id: "agent-negotiate".to_string(),
+            platform: OsType::Linux,
+            compressed: false,
+            extensions: Extensions::new(),
+        },
+        payload: MessagePayload::Handshake(identity),
+        payload_extensions: Extensions::new(),
+    };
+
+    // Strip the trailing min version to mimic a handshake from an older agent
+    let encoded = FrameCodec::encode(&message).expect("Failed to encode handshake");
+    let body = &encoded[4..encoded.len() - 4 - 2];
+    let decoded = FrameCodec::decode(&mut Cursor::new(reframe(body))).expect("Failed to decode");
+
+    match decoded.payload {
+        MessagePayload::Handshake(decoded_identity) => {
+            assert_eq!(
+                decoded_identity.supported_versions,
+                VersionRange::single(ProtocolVersion { major: 1, minor: 2 })
+            );
+        }
+        _ => panic!("Expected Handshake payload"),
+    }
+}
+
+#[test]
+fn encode_decode_handshake_ack_message() {
+    let ack = HandshakeAckPayload {
+        negotiated_version: ProtocolVersion::CURRENT,
+        negotiated_capabilities: AgentIdentity::CAP_COMPRESSION,
+    };
+    let message = Message {
+        envelope: Envelope {
+            version: ProtocolVersion::CURRENT,
+            message_type: MessageType::HandshakeAck,
+            message_id: test_message_id(4),
+            timestamp_utc_ms: 1703174400500,
+            agent_id: "agent-negotiate".to_string(),
+            platform: OsType::Linux,
+            compressed: false,
+            extensions: Extensions::new(),
+        },
+        payload: MessagePayload::HandshakeAck(ack),
+        payload_extensions: Extensions::new(),
+    };
+
+    let encoded = FrameCodec::encode(&message).expect("Failed to encode handshake ack");
+    let decoded = FrameCodec::decode(&mut Cursor::new(&encoded)).expect("Failed to decode");
+
+    assert_eq!(decoded.payload, MessagePayload::HandshakeAck(ack));
+}
+
 // ============================================================================
 // Module: Message Type Conversions
 // ============================================================================
@@ -77,13 +253,18 @@ fn message_type_from_u8_all_types() {
     assert_eq!(MessageType::from_u8(5).unwrap(), MessageType::Ack);
     assert_eq!(MessageType::from_u8(6).unwrap(), MessageType::Backpressure);
     assert_eq!(MessageType::from_u8(7).unwrap(), MessageType::Error);
+    assert_eq!(MessageType::from_u8(8).unwrap(), MessageType::SnapshotPart);
+    assert_eq!(MessageType::from_u8(9).unwrap(), MessageType::SnapshotDelta);
 }
 
 #[test]
 fn message_type_from_u8_invalid_type() {
     // Test that invalid discriminants produce errors
     assert!(MessageType::from_u8(0).is_err(), "Type 0 should be invalid");
-    assert!(MessageType::from_u8(8).is_err(), "Type 8 should be invalid");
+    assert!(
+        MessageType::from_u8(10).is_err(),
+        "Type 10 should be invalid"
+    );
     assert!(
         MessageType::from_u8(255).is_err(),
         "Type 255 should be invalid"
@@ -102,7 +283,7 @@ fn agent_identity_capabilities_all_process() {
         instance_id: "agent-001".to_string(),
         os_type: OsType::Linux,
         agent_version: "0.1.0".to_string(),
-        protocol_version: ProtocolVersion::CURRENT,
+        supported_versions: VersionRange::CURRENT,
         capabilities: AgentIdentity::CAP_ALL_PROCESS,
     };
     assert!(identity.supports_all_process());
@@ -115,7 +296,7 @@ fn agent_identity_capabilities_compression() {
         instance_id: "agent-002".to_string(),
         os_type: OsType::Windows,
         agent_version: "0.1.0".to_string(),
-        protocol_version: ProtocolVersion::CURRENT,
+        supported_versions: VersionRange::CURRENT,
         capabilities: AgentIdentity::CAP_COMPRESSION,
     };
     assert!(!identity.supports_all_process());
@@ -129,7 +310,7 @@ fn agent_identity_capabilities_both() {
         instance_id: "agent-003".to_string(),
         os_type: OsType::Linux,
         agent_version: "0.1.0".to_string(),
-        protocol_version: ProtocolVersion::CURRENT,
+        supported_versions: VersionRange::CURRENT,
         capabilities,
     };
     assert!(identity.supports_all_process());
@@ -154,14 +335,16 @@ fn encode_decode_handshake_message() {
             agent_id: "test-agent-001".to_string(),
             platform: OsType::Linux,
             compressed: false,
+            extensions: Extensions::new(),
         },
         payload: MessagePayload::Handshake(AgentIdentity {
             instance_id: "test-agent-001".to_string(),
             os_type: OsType::Linux,
             agent_version: "0.1.0".to_string(),
-            protocol_version: ProtocolVersion::CURRENT,
+            supported_versions: VersionRange::CURRENT,
             capabilities: AgentIdentity::CAP_ALL_PROCESS | AgentIdentity::CAP_COMPRESSION,
         }),
+        payload_extensions: Extensions::new(),
     };
 
     // Encode to bytes
@@ -188,6 +371,7 @@ fn encode_decode_snapshot_message() {
             agent_id: "test-agent-001".to_string(),
             platform: OsType::Linux,
             compressed: false,
+            extensions: Extensions::new(),
         },
         payload: MessagePayload::Snapshot(SnapshotPayload {
             window_start_secs: 1703174400,
@@ -225,6 +409,7 @@ fn encode_decode_snapshot_message() {
             ],
             truncated: false,
         }),
+        payload_extensions: Extensions::new(),
     };
 
     let encoded = FrameCodec::encode(&original).expect("Failed to encode snapshot");
@@ -246,12 +431,14 @@ fn encode_decode_ack_message() {
             agent_id: "test-agent-001".to_string(),
             platform: OsType::Linux,
             compressed: false,
+            extensions: Extensions::new(),
         },
         payload: MessagePayload::Ack(MessageAck {
             message_id: test_message_id(99),
             success: true,
             error_code: None,
         }),
+        payload_extensions: Extensions::new(),
     };
 
     let encoded = FrameCodec::encode(&original).expect("Failed to encode ack");
@@ -272,11 +459,13 @@ fn encode_decode_backpressure_message() {
             agent_id: "test-agent-001".to_string(),
             platform: OsType::Linux,
             compressed: false,
+            extensions: Extensions::new(),
         },
         payload: MessagePayload::Backpressure(BackpressureSignal {
             throttle_delay_ms: 5000,
             reason: Some("Server buffer threshold exceeded".to_string()),
         }),
+        payload_extensions: Extensions::new(),
     };
 
     let encoded = FrameCodec::encode(&original).expect("Failed to encode backpressure");
@@ -365,6 +554,7 @@ fn encode_decode_with_compression() {
             agent_id: "test-agent-001".to_string(),
             platform: OsType::Linux,
             compressed: true, // Enable compression
+            extensions: Extensions::new(),
         },
         payload: MessagePayload::Snapshot(SnapshotPayload {
             window_start_secs: 1703174410,
@@ -375,6 +565,7 @@ fn encode_decode_with_compression() {
             processes,
             truncated: false,
         }),
+        payload_extensions: Extensions::new(),
     };
 
     let encoded = FrameCodec::encode(&message).expect("Failed to encode with compression");
@@ -389,8 +580,10 @@ fn encode_decode_with_compression() {
             agent_id: message.envelope.agent_id.clone(),
             platform: message.envelope.platform,
             compressed: false,
+            extensions: Extensions::new(),
         },
         payload: message.payload.clone(),
+        payload_extensions: Extensions::new(),
     };
     let encoded_uncompressed =
         FrameCodec::encode(&message_uncompressed).expect("Failed to encode without compression");
@@ -446,6 +639,7 @@ fn frame_size_validation_oversized_payload() {
             agent_id: "test-agent-001".to_string(),
             platform: OsType::Linux,
             compressed: false,
+            extensions: Extensions::new(),
         },
         payload: MessagePayload::Snapshot(SnapshotPayload {
             window_start_secs: 1703174400,
@@ -456,6 +650,7 @@ fn frame_size_validation_oversized_payload() {
             processes,
             truncated: false,
         }),
+        payload_extensions: Extensions::new(),
     };
 
     // Should fail to encode due to size
@@ -528,8 +723,10 @@ fn cross_language_serialization_snapshot() {
             agent_id: "test-agent-001".to_string(),
             platform: OsType::Linux,
             compressed: false,
+            extensions: Extensions::new(),
         },
         payload: MessagePayload::Snapshot(snapshot),
+        payload_extensions: Extensions::new(),
     };
 
     // Encode the message
@@ -587,8 +784,10 @@ fn heartbeat_message_minimal_payload() {
             agent_id: "test-agent-001".to_string(),
             platform: OsType::Linux,
             compressed: false,
+            extensions: Extensions::new(),
         },
         payload: MessagePayload::Heartbeat,
+        payload_extensions: Extensions::new(),
     };
 
     let encoded = FrameCodec::encode(&message).expect("Failed to encode heartbeat");
@@ -612,11 +811,13 @@ fn error_message_with_details() {
             agent_id: "test-agent-001".to_string(),
             platform: OsType::Linux,
             compressed: false,
+            extensions: Extensions::new(),
         },
         payload: MessagePayload::Error {
             code: 1001,
             message: "Agent encountered an internal error while sampling processes".to_string(),
         },
+        payload_extensions: Extensions::new(),
     };
 
     let encoded = FrameCodec::encode(&message).expect("Failed to encode error");
@@ -638,6 +839,7 @@ fn snapshot_with_no_processes() {
             agent_id: "test-agent-001".to_string(),
             platform: OsType::Linux,
             compressed: false,
+            extensions: Extensions::new(),
         },
         payload: MessagePayload::Snapshot(SnapshotPayload {
             window_start_secs: 1703174435,
@@ -648,6 +850,7 @@ fn snapshot_with_no_processes() {
             processes: vec![],
             truncated: false,
         }),
+        payload_extensions: Extensions::new(),
     };
 
     let encoded = FrameCodec::encode(&message).expect("Failed to encode empty snapshot");
@@ -670,6 +873,7 @@ fn snapshot_with_truncation_flag() {
             agent_id: "test-agent-001".to_string(),
             platform: OsType::Linux,
             compressed: false,
+            extensions: Extensions::new(),
         },
         payload: MessagePayload::Snapshot(SnapshotPayload {
             window_start_secs: 1703174440,
@@ -687,6 +891,7 @@ fn snapshot_with_truncation_flag() {
             }],
             truncated: true, // Flag indicates more processes were filtered out
         }),
+        payload_extensions: Extensions::new(),
     };
 
     let encoded = FrameCodec::encode(&message).expect("Failed to encode truncated snapshot");
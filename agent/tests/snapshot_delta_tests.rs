@@ -0,0 +1,238 @@
+//! Integration tests for `SnapshotDelta` messages.
+//!
+//! Covers the wire round trip, the differ/applier pair, the agent-side choice between delta
+//! and full snapshots, and the server-side base store.
+
+use agent::protocol::*;
+use std::io::Cursor;
+
+// Test helper: Create a 16-byte message ID from a u64 for simplicity in tests
+fn test_message_id(n: u64) -> [u8; 16] {
+    let mut id = [0u8; 16];
+    id[0..8].copy_from_slice(&n.to_le_bytes());
+    id
+}
+
+fn message(n: u64, payload: MessagePayload) -> Message {
+    Message {
+        envelope: Envelope {
+            version: ProtocolVersion::CURRENT,
+            message_type: payload.message_type(),
+            message_id: test_message_id(n),
+            timestamp_utc_ms: 1703174400000,
+            agent_id: "test-agent-001".to_string(),
+            platform: OsType::Linux,
+            compressed: false,
+            extensions: Extensions::new(),
+        },
+        payload,
+        payload_extensions: Extensions::new(),
+    }
+}
+
+fn process(pid: u32, cpu_percent: f32) -> ProcessSample {
+    ProcessSample {
+        pid,
+        name: format!("process-with-a-long-name-{pid}"),
+        cpu_percent,
+        memory_percent: 1.5,
+        memory_bytes: 100_000 * pid as u64,
+        cmdline: Some(format!("/usr/bin/process-{pid} --flag")),
+    }
+}
+
+fn snapshot(window_start_secs: i64, mut processes: Vec<ProcessSample>) -> SnapshotPayload {
+    sort_processes(&mut processes);
+    SnapshotPayload {
+        window_start_secs,
+        window_end_secs: window_start_secs + 10,
+        total_cpu_percent: 30.0,
+        memory_used_bytes: 4_000_000_000,
+        memory_total_bytes: 8_000_000_000,
+        processes,
+        truncated: false,
+    }
+}
+
+fn base() -> SnapshotPayload {
+    snapshot(0, (1..=20).map(|pid| process(pid, pid as f32)).collect())
+}
+
+/// Base with one process gone, one added, one renamed and one with new measurements.
+fn next() -> SnapshotPayload {
+    let mut processes: Vec<ProcessSample> = (2..=20).map(|pid| process(pid, pid as f32)).collect();
+    processes.push(process(21, 4.5));
+    processes[0].cpu_percent = 50.0;
+    processes[1].name = "renamed".to_string();
+    snapshot(10, processes)
+}
+
+fn ack(n: u64, success: bool) -> MessageAck {
+    MessageAck {
+        message_id: test_message_id(n),
+        success,
+        error_code: None,
+    }
+}
+
+#[test]
+fn delta_round_trips_through_codec() {
+    let delta = SnapshotDeltaPayload::diff(test_message_id(1), &base(), &next());
+    let message = message(2, MessagePayload::SnapshotDelta(Box::new(delta)));
+    for compressed in [false, true] {
+        let mut message = message.clone();
+        message.envelope.compressed = compressed;
+        let frame = FrameCodec::encode(&message).expect("Failed to encode");
+        let decoded = FrameCodec::decode_with_options(
+            &mut Cursor::new(&frame),
+            &mut DecodeOptions::new().with_mode(DecodeMode::Strict),
+        )
+        .expect("Failed to decode");
+        assert_eq!(decoded, message);
+    }
+}
+
+#[test]
+fn diff_then_apply_rebuilds_target() {
+    let delta = SnapshotDeltaPayload::diff(test_message_id(1), &base(), &next());
+    assert_eq!(delta.removed, vec![1]);
+    assert_eq!(
+        delta.added.iter().map(|p| p.pid).collect::<Vec<_>>(),
+        vec![21, 3],
+        "new and renamed processes are sent in full, in target order"
+    );
+    assert_eq!(delta.changed.len(), 1);
+    assert_eq!(delta.changed[0].pid, 2);
+
+    assert_eq!(delta.apply(&base()).expect("Failed to apply"), next());
+
+    let unchanged = SnapshotDeltaPayload::diff(test_message_id(1), &base(), &base());
+    assert!(unchanged.added.is_empty() && unchanged.changed.is_empty());
+    assert!(unchanged.removed.is_empty());
+    assert_eq!(unchanged.apply(&base()).expect("Failed to apply"), base());
+}
+
+#[test]
+fn apply_rejects_pids_missing_from_base() {
+    let delta = SnapshotDeltaPayload::diff(test_message_id(1), &base(), &next());
+    let other_base = snapshot(0, vec![process(99, 1.0)]);
+    assert!(matches!(
+        delta.apply(&other_base),
+        Err(ProtocolError::InvalidSnapshotDelta(_))
+    ));
+}
+
+#[test]
+fn encoder_sends_full_snapshots_until_acked() {
+    let mut encoder = SnapshotDeltaEncoder::new();
+    let first = encoder.payload(test_message_id(1), base());
+    assert_eq!(first, MessagePayload::Snapshot(base()));
+
+    let second = encoder.payload(test_message_id(2), next());
+    assert!(matches!(second, MessagePayload::Snapshot(_)));
+
+    encoder.handle_ack(&ack(1, true));
+    assert_eq!(encoder.base_message_id(), Some(&test_message_id(1)));
+    match encoder.payload(test_message_id(3), next()) {
+        MessagePayload::SnapshotDelta(delta) => {
+            assert_eq!(delta.base_message_id, test_message_id(1))
+        }
+        other => panic!("Expected a delta, got {other:?}"),
+    }
+
+    // Acking message 3 makes it the base; the older unacked message 2 is forgotten
+    encoder.handle_ack(&ack(3, true));
+    assert_eq!(encoder.base_message_id(), Some(&test_message_id(3)));
+    encoder.handle_ack(&ack(2, true));
+    assert_eq!(encoder.base_message_id(), Some(&test_message_id(3)));
+}
+
+#[test]
+fn encoder_falls_back_after_rejection_or_when_delta_is_larger() {
+    let mut encoder = SnapshotDeltaEncoder::new();
+    encoder.payload(test_message_id(1), base());
+    encoder.handle_ack(&ack(1, true));
+    encoder.payload(test_message_id(2), next());
+    encoder.handle_ack(&ack(2, false));
+    assert_eq!(encoder.base_message_id(), None);
+    assert!(matches!(
+        encoder.payload(test_message_id(3), next()),
+        MessagePayload::Snapshot(_)
+    ));
+
+    // Every process replaced: the delta would carry everything plus the removals
+    encoder.handle_ack(&ack(3, true));
+    let replaced = snapshot(20, (100..120).map(|pid| process(pid, 1.0)).collect());
+    assert!(matches!(
+        encoder.payload(test_message_id(4), replaced),
+        MessagePayload::Snapshot(_)
+    ));
+}
+
+#[test]
+fn base_store_resolves_deltas_and_rejects_unknown_bases() {
+    let mut agent = SnapshotDeltaEncoder::new();
+    let mut server = SnapshotBaseStore::new();
+
+    let first = message(1, agent.payload(test_message_id(1), base()));
+    assert_eq!(
+        server.resolve(&first).expect("Failed to resolve"),
+        Some(base())
+    );
+    agent.handle_ack(&ack(1, true));
+
+    let second = message(2, agent.payload(test_message_id(2), next()));
+    assert_eq!(second.envelope.message_type, MessageType::SnapshotDelta);
+    assert_eq!(
+        server.resolve(&second).expect("Failed to resolve"),
+        Some(next())
+    );
+    assert_eq!(server.get(&test_message_id(2)), Some(&next()));
+
+    // A fresh server (e.g. after a restart) does not know the base
+    let mut restarted = SnapshotBaseStore::with_capacity(1);
+    let err = restarted.resolve(&second).unwrap_err();
+    assert!(matches!(err, ProtocolError::UnknownDeltaBase(id) if id == test_message_id(1)));
+    agent.handle_ack(&ack(2, false));
+    let third = message(3, agent.payload(test_message_id(3), next()));
+    assert_eq!(
+        restarted.resolve(&third).expect("Failed to resolve"),
+        Some(next())
+    );
+
+    let heartbeat = message(4, MessagePayload::Heartbeat);
+    assert_eq!(server.resolve(&heartbeat).expect("Failed to resolve"), None);
+}
+
+#[test]
+fn encoder_and_store_agree_on_unsorted_snapshots() {
+    // Agent order: ascending pid, unrelated to cpu_percent
+    let unsorted = |window_start_secs, cpu: fn(u32) -> f32| {
+        let mut snapshot = snapshot(window_start_secs, Vec::new());
+        snapshot.processes = (1..=20).map(|pid| process(pid, cpu(pid))).collect();
+        snapshot
+    };
+    let first = unsorted(0, |pid| (pid % 7) as f32);
+    let second = unsorted(10, |pid| if pid == 3 { 90.0 } else { (pid % 7) as f32 });
+    let mut agent = SnapshotDeltaEncoder::new();
+    let mut server = SnapshotBaseStore::new();
+
+    let full = message(1, agent.payload(test_message_id(1), first.clone()));
+    let rebuilt = server.resolve(&full).expect("Failed to resolve");
+    assert_eq!(rebuilt, Some(snapshot(0, first.processes)));
+    agent.handle_ack(&ack(1, true));
+
+    let delta = message(2, agent.payload(test_message_id(2), second.clone()));
+    assert_eq!(delta.envelope.message_type, MessageType::SnapshotDelta);
+    let rebuilt = server.resolve(&delta).expect("Failed to resolve");
+    assert_eq!(rebuilt, Some(snapshot(10, second.processes)));
+}
+
+#[test]
+fn delta_capability_is_negotiated() {
+    let ack = HandshakeAckPayload {
+        negotiated_version: ProtocolVersion::CURRENT,
+        negotiated_capabilities: AgentIdentity::CAP_SNAPSHOT_DELTA,
+    };
+    assert!(ack.snapshot_delta_enabled());
+}
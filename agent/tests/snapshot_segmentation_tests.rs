@@ -0,0 +1,298 @@
+//! Integration tests for snapshot segmentation and reassembly (FR-009).
+//!
+//! Splits all-process snapshots that exceed the target frame size, checks every part frame fits,
+//! and reassembles parts delivered out of order and more than once.
+
+use agent::protocol::*;
+use std::io::Cursor;
+use std::sync::Arc;
+
+// Test helper: Create a 16-byte message ID from a u64 for simplicity in tests
+fn test_message_id(n: u64) -> [u8; 16] {
+    let mut id = [0u8; 16];
+    id[0..8].copy_from_slice(&n.to_le_bytes());
+    id
+}
+
+fn envelope(compressed: bool) -> Envelope {
+    Envelope {
+        version: ProtocolVersion::CURRENT,
+        message_type: MessageType::Snapshot,
+        message_id: test_message_id(1),
+        timestamp_utc_ms: 1703174410000,
+        agent_id: "test-agent-001".to_string(),
+        platform: OsType::Linux,
+        compressed,
+        extensions: Extensions::new(),
+    }
+}
+
+fn large_snapshot(process_count: u32) -> SnapshotPayload {
+    SnapshotPayload {
+        window_start_secs: 1703174400,
+        window_end_secs: 1703174410,
+        total_cpu_percent: 88.0,
+        memory_used_bytes: 30_000_000_000,
+        memory_total_bytes: 32_000_000_000,
+        processes: (0..process_count)
+            .map(|i| ProcessSample {
+                pid: i,
+                name: format!("very-long-process-name-with-lots-of-characters-{i:05}"),
+                cpu_percent: (i % 100) as f32 * 0.01,
+                memory_percent: 0.5,
+                memory_bytes: 1_000_000 + i as u64,
+                cmdline: Some(format!(
+                    "/very/long/command/line/path/with/many/arguments/{i:05} --id={}",
+                    i * 7919
+                )),
+            })
+            .collect(),
+        truncated: false,
+    }
+}
+
+fn part_message(n: u64, compressed: bool, part: SnapshotPartPayload) -> Message {
+    Message {
+        envelope: Envelope {
+            message_type: MessageType::SnapshotPart,
+            message_id: test_message_id(n),
+            ..envelope(compressed)
+        },
+        payload: MessagePayload::SnapshotPart(part),
+        payload_extensions: Extensions::new(),
+    }
+}
+
+#[test]
+fn split_snapshot_parts_fit_target_frame_size() {
+    let snapshot = large_snapshot(10_000);
+    let snapshot_id = test_message_id(0xABCD);
+
+    let parts = split_snapshot(
+        &mut FrameEncoder::new(),
+        &envelope(false),
+        &snapshot,
+        snapshot_id,
+        TARGET_FRAME_SIZE,
+    )
+    .expect("Failed to split snapshot");
+
+    assert!(parts.len() > 1, "Oversized snapshot should be segmented");
+    let process_total: usize = parts.iter().map(|p| p.snapshot.processes.len()).sum();
+    assert_eq!(process_total, snapshot.processes.len());
+
+    for (index, part) in parts.iter().enumerate() {
+        assert_eq!(part.snapshot_id, snapshot_id);
+        assert_eq!(part.part_index, index as u32);
+        assert_eq!(part.part_count, parts.len() as u32);
+
+        let frame = FrameCodec::encode(&part_message(index as u64, false, part.clone()))
+            .expect("Part should encode");
+        assert!(
+            frame.len() <= TARGET_FRAME_SIZE,
+            "Part {index} frame ({} bytes) exceeds target",
+            frame.len()
+        );
+    }
+}
+
+#[test]
+fn split_snapshot_sizes_parts_with_the_sending_encoder() {
+    let snapshot = large_snapshot(2_000);
+    let target = 8 * 1024;
+    let mut keys = KeyRing::new();
+    keys.insert("test-agent-001", AuthKey::new(1, *b"shared secret"));
+    let mut encoder = FrameEncoder::new()
+        .with_policy(CompressionPolicy::default())
+        .with_key_store(Arc::new(keys))
+        .with_cipher(PayloadCipher::new(&[7; 32]));
+
+    let parts = split_snapshot(
+        &mut encoder,
+        &envelope(false),
+        &snapshot,
+        test_message_id(0xABCD),
+        target,
+    )
+    .expect("Failed to split snapshot");
+    assert!(parts.len() > 1, "Oversized snapshot should be segmented");
+    let process_total: usize = parts.iter().map(|p| p.snapshot.processes.len()).sum();
+    assert_eq!(process_total, snapshot.processes.len());
+
+    for (index, part) in parts.iter().enumerate() {
+        let mut frame = Vec::new();
+        encoder
+            .encode_into(&part_message(index as u64, false, part.clone()), &mut frame)
+            .expect("Part should encode");
+        assert!(
+            frame.len() <= target,
+            "Part {index} frame ({} bytes) exceeds target",
+            frame.len()
+        );
+    }
+}
+
+#[test]
+fn split_small_snapshot_yields_single_part() {
+    let snapshot = large_snapshot(3);
+
+    let parts = split_snapshot(
+        &mut FrameEncoder::new(),
+        &envelope(true),
+        &snapshot,
+        test_message_id(7),
+        TARGET_FRAME_SIZE,
+    )
+    .expect("Failed to split snapshot");
+
+    assert_eq!(parts.len(), 1);
+    assert_eq!(parts[0].part_count, 1);
+    assert_eq!(parts[0].snapshot, snapshot);
+}
+
+#[test]
+fn split_snapshot_rejects_process_larger_than_target() {
+    let mut snapshot = large_snapshot(1);
+    snapshot.processes[0].cmdline = Some("x".repeat(2048));
+
+    let result = split_snapshot(
+        &mut FrameEncoder::new(),
+        &envelope(false),
+        &snapshot,
+        test_message_id(8),
+        1024,
+    );
+    assert!(matches!(result, Err(ProtocolError::FrameTooLarge(_, 1024))));
+}
+
+#[test]
+fn snapshot_part_round_trips_through_framecodec() {
+    let snapshot = large_snapshot(500);
+    let parts = split_snapshot(
+        &mut FrameEncoder::new(),
+        &envelope(true),
+        &snapshot,
+        test_message_id(9),
+        2048,
+    )
+    .expect("Failed to split snapshot");
+    assert!(parts.len() > 1);
+
+    let message = part_message(1, true, parts[1].clone());
+    let frame = FrameCodec::encode(&message).expect("Failed to encode part");
+    let decoded = FrameCodec::decode(&mut Cursor::new(frame)).expect("Failed to decode part");
+
+    assert_eq!(decoded.envelope.message_type, MessageType::SnapshotPart);
+    assert_eq!(decoded.payload, message.payload);
+}
+
+#[test]
+fn reassembler_handles_out_of_order_and_duplicate_parts() {
+    let snapshot = large_snapshot(2_000);
+    let snapshot_id = test_message_id(0x5151);
+    let parts = split_snapshot(
+        &mut FrameEncoder::new(),
+        &envelope(true),
+        &snapshot,
+        snapshot_id,
+        8 * 1024,
+    )
+    .expect("Failed to split snapshot");
+    assert!(parts.len() >= 3, "Need several parts for ordering test");
+
+    // Deliver in reverse order, resending every part twice
+    let mut reassembler = SnapshotReassembler::new();
+    let mut completed = Vec::new();
+    for part in parts.iter().rev().flat_map(|p| [p.clone(), p.clone()]) {
+        if let Some(full) = reassembler.push(part).expect("Valid part") {
+            completed.push(full);
+        }
+    }
+    // Late duplicate after completion is ignored
+    assert_eq!(
+        reassembler.push(parts[0].clone()).expect("Valid part"),
+        None
+    );
+
+    assert_eq!(completed.len(), 1, "Snapshot must be emitted exactly once");
+    assert_eq!(completed[0], snapshot);
+    assert!(reassembler.is_completed(&snapshot_id));
+    assert_eq!(reassembler.pending_count(), 0);
+}
+
+#[test]
+fn reassembler_rejects_inconsistent_parts() {
+    let snapshot = large_snapshot(2);
+    let part = SnapshotPartPayload {
+        snapshot_id: test_message_id(1),
+        part_index: 0,
+        part_count: 2,
+        snapshot: snapshot.clone(),
+    };
+
+    let mut reassembler = SnapshotReassembler::new();
+    assert_eq!(reassembler.push(part.clone()).expect("Valid part"), None);
+
+    let mismatched_count = SnapshotPartPayload {
+        part_index: 1,
+        part_count: 3,
+        ..part.clone()
+    };
+    assert!(matches!(
+        reassembler.push(mismatched_count),
+        Err(ProtocolError::InvalidSnapshotPart(_))
+    ));
+
+    let index_out_of_range = SnapshotPartPayload {
+        part_index: 2,
+        ..part
+    };
+    assert!(matches!(
+        reassembler.push(index_out_of_range),
+        Err(ProtocolError::InvalidSnapshotPart(_))
+    ));
+}
+
+#[test]
+fn reassembler_bounds_pending_and_completed_state() {
+    let part = |id: u64, part_index: u32, part_count: u32| SnapshotPartPayload {
+        snapshot_id: test_message_id(id),
+        part_index,
+        part_count,
+        snapshot: large_snapshot(1),
+    };
+    let mut reassembler = SnapshotReassembler::with_limits(ReassemblyLimits {
+        max_pending_snapshots: 2,
+        max_pending_parts: 5,
+        max_completed_snapshots: 1,
+    });
+
+    // A third incomplete snapshot evicts the oldest one
+    for id in 1..=3 {
+        assert_eq!(reassembler.push(part(id, 0, 2)).expect("Valid part"), None);
+    }
+    assert_eq!(reassembler.pending_count(), 2);
+    // Snapshot 1 starts over, evicting snapshot 2
+    assert_eq!(reassembler.push(part(1, 1, 2)).expect("Valid part"), None);
+    assert!(reassembler
+        .push(part(3, 1, 2))
+        .expect("Valid part")
+        .is_some());
+
+    // Too many part slots in total: the remaining pending snapshot is dropped
+    assert_eq!(reassembler.push(part(4, 0, 4)).expect("Valid part"), None);
+    assert_eq!(reassembler.pending_count(), 1);
+    assert!(matches!(
+        reassembler.push(part(5, 0, 6)),
+        Err(ProtocolError::InvalidSnapshotPart(_))
+    ));
+
+    // Only the most recent completed id is remembered
+    assert!(reassembler.is_completed(&test_message_id(3)));
+    assert!(reassembler
+        .push(part(6, 0, 1))
+        .expect("Valid part")
+        .is_some());
+    assert!(reassembler.is_completed(&test_message_id(6)));
+    assert!(!reassembler.is_completed(&test_message_id(3)));
+}
@@ -0,0 +1,270 @@
+//! Integration tests for deterministic top-N selection and size-aware truncation (FR-005).
+//!
+//! Checks the cpu%-descending / pid-ascending ordering, the top-N cut, the frame-size cut and
+//! the `truncated` flag.
+
+use agent::protocol::*;
+use std::sync::Arc;
+
+// Test helper: Create a 16-byte message ID from a u64 for simplicity in tests
+fn test_message_id(n: u64) -> [u8; 16] {
+    let mut id = [0u8; 16];
+    id[0..8].copy_from_slice(&n.to_le_bytes());
+    id
+}
+
+fn envelope(compressed: bool) -> Envelope {
+    Envelope {
+        version: ProtocolVersion::CURRENT,
+        message_type: MessageType::Snapshot,
+        message_id: test_message_id(1),
+        timestamp_utc_ms: 1703174410000,
+        agent_id: "test-agent-001".to_string(),
+        platform: OsType::Windows,
+        compressed,
+        extensions: Extensions::new(),
+    }
+}
+
+fn process(pid: u32, cpu_percent: f32) -> ProcessSample {
+    ProcessSample {
+        pid,
+        name: format!("process-with-a-fairly-long-name-{pid:06}"),
+        cpu_percent,
+        memory_percent: 0.1,
+        memory_bytes: 4_096 * pid as u64,
+        cmdline: Some(format!(
+            "C:\\Program Files\\Vendor\\bin\\{pid}.exe --service --id={pid}"
+        )),
+    }
+}
+
+fn snapshot(processes: Vec<ProcessSample>) -> SnapshotPayload {
+    SnapshotPayload {
+        window_start_secs: 1703174400,
+        window_end_secs: 1703174410,
+        total_cpu_percent: 55.0,
+        memory_used_bytes: 4_000_000_000,
+        memory_total_bytes: 16_000_000_000,
+        processes,
+        truncated: false,
+    }
+}
+
+#[test]
+fn sort_orders_by_cpu_descending_then_pid_ascending() {
+    let mut processes = vec![
+        process(30, 5.0),
+        process(10, 20.0),
+        process(20, 5.0),
+        process(5, 5.0),
+        process(40, 0.0),
+    ];
+
+    sort_processes(&mut processes);
+
+    let pids: Vec<u32> = processes.iter().map(|p| p.pid).collect();
+    assert_eq!(pids, vec![10, 5, 20, 30, 40]);
+}
+
+#[test]
+fn sort_ranks_nan_readings_last() {
+    let mut processes = vec![
+        process(1, f32::NAN),
+        process(2, 0.0),
+        process(3, -f32::NAN),
+        process(4, 50.0),
+    ];
+
+    sort_processes(&mut processes);
+
+    let pids: Vec<u32> = processes.iter().map(|p| p.pid).collect();
+    assert_eq!(&pids[..2], [4, 2]);
+
+    // A NaN reading never takes a top-N slot from a real one
+    let result = truncate_snapshot(
+        &mut FrameEncoder::new(),
+        &envelope(false),
+        snapshot(vec![process(1, f32::NAN), process(2, 0.5), process(3, 1.0)]),
+        2,
+        TARGET_FRAME_SIZE,
+    )
+    .expect("Failed to truncate snapshot");
+    let pids: Vec<u32> = result.processes.iter().map(|p| p.pid).collect();
+    assert_eq!(pids, [3, 2]);
+}
+
+#[test]
+fn truncate_keeps_top_n_and_sets_flag() {
+    let processes: Vec<ProcessSample> = (0..250).map(|i| process(i, (i % 17) as f32)).collect();
+
+    let result = truncate_snapshot(
+        &mut FrameEncoder::new(),
+        &envelope(true),
+        snapshot(processes),
+        100,
+        TARGET_FRAME_SIZE,
+    )
+    .expect("Failed to truncate snapshot");
+
+    assert_eq!(result.processes.len(), 100);
+    assert!(result.truncated);
+    assert!(result.processes.iter().all(|p| p.cpu_percent >= 10.0));
+    assert_eq!(
+        result.processes[0].pid, 16,
+        "Lowest pid with top cpu comes first"
+    );
+}
+
+#[test]
+fn truncate_without_drops_leaves_flag_clear() {
+    let processes: Vec<ProcessSample> = (0..10).map(|i| process(i, i as f32)).collect();
+
+    let result = truncate_snapshot(
+        &mut FrameEncoder::new(),
+        &envelope(false),
+        snapshot(processes),
+        DEFAULT_TOP_N,
+        TARGET_FRAME_SIZE,
+    )
+    .expect("Failed to truncate snapshot");
+
+    assert_eq!(result.processes.len(), 10);
+    assert!(!result.truncated);
+    assert_eq!(result.processes[0].pid, 9);
+}
+
+#[test]
+fn truncate_drops_processes_until_frame_fits() {
+    let processes: Vec<ProcessSample> = (0..2_000).map(|i| process(i, (i % 100) as f32)).collect();
+    let target = 8 * 1024;
+
+    for compressed in [false, true] {
+        let result = truncate_snapshot(
+            &mut FrameEncoder::new(),
+            &envelope(compressed),
+            snapshot(processes.clone()),
+            2_000,
+            target,
+        )
+        .expect("Failed to truncate snapshot");
+        assert!(result.truncated);
+        assert!(!result.processes.is_empty());
+
+        let message = Message {
+            envelope: envelope(compressed),
+            payload: MessagePayload::Snapshot(result.clone()),
+            payload_extensions: Extensions::new(),
+        };
+        let frame = FrameCodec::encode(&message).expect("Failed to encode");
+        assert!(
+            frame.len() <= target,
+            "Frame ({} bytes) exceeds target",
+            frame.len()
+        );
+
+        // One more process would not have fit
+        let mut sorted = processes.clone();
+        sort_processes(&mut sorted);
+        let mut larger = result.clone();
+        larger.processes = sorted[..result.processes.len() + 1].to_vec();
+        let larger_frame = FrameCodec::encode(&Message {
+            payload: MessagePayload::Snapshot(larger),
+            ..message
+        })
+        .expect("Failed to encode");
+        assert!(larger_frame.len() > target);
+    }
+}
+
+#[test]
+fn truncate_sizes_frames_with_the_sending_encoder() {
+    let processes: Vec<ProcessSample> = (0..2_000).map(|i| process(i, (i % 100) as f32)).collect();
+    let target = 8 * 1024;
+    let mut keys = KeyRing::new();
+    keys.insert("test-agent-001", AuthKey::new(1, *b"shared secret"));
+    let mut encoder = FrameEncoder::new()
+        .with_policy(CompressionPolicy::default())
+        .with_key_store(Arc::new(keys))
+        .with_cipher(PayloadCipher::new(&[7; 32]));
+
+    let result = truncate_snapshot(
+        &mut encoder,
+        &envelope(false),
+        snapshot(processes.clone()),
+        2_000,
+        target,
+    )
+    .expect("Failed to truncate snapshot");
+    assert!(result.truncated);
+
+    // The authenticated, encrypted frame fits; one more process would not have
+    let message = Message {
+        envelope: envelope(false),
+        payload: MessagePayload::Snapshot(result.clone()),
+        payload_extensions: Extensions::new(),
+    };
+    let mut frame = Vec::new();
+    encoder
+        .encode_into(&message, &mut frame)
+        .expect("Failed to encode");
+    assert!(
+        frame.len() <= target,
+        "Frame ({} bytes) exceeds target",
+        frame.len()
+    );
+
+    let mut sorted = processes;
+    sort_processes(&mut sorted);
+    let mut larger = result.clone();
+    larger.processes = sorted[..result.processes.len() + 1].to_vec();
+    let mut larger_frame = Vec::new();
+    encoder
+        .encode_into(
+            &Message {
+                payload: MessagePayload::Snapshot(larger),
+                ..message
+            },
+            &mut larger_frame,
+        )
+        .expect("Failed to encode");
+    assert!(larger_frame.len() > target);
+}
+
+#[test]
+fn truncate_is_deterministic_regardless_of_input_order() {
+    let processes: Vec<ProcessSample> = (0..300).map(|i| process(i, (i % 7) as f32)).collect();
+    let mut reversed = processes.clone();
+    reversed.reverse();
+
+    let a = truncate_snapshot(
+        &mut FrameEncoder::new(),
+        &envelope(true),
+        snapshot(processes),
+        50,
+        TARGET_FRAME_SIZE,
+    )
+    .expect("Failed to truncate snapshot");
+    let b = truncate_snapshot(
+        &mut FrameEncoder::new(),
+        &envelope(true),
+        snapshot(reversed),
+        50,
+        TARGET_FRAME_SIZE,
+    )
+    .expect("Failed to truncate snapshot");
+
+    assert_eq!(a, b);
+}
+
+#[test]
+fn truncate_rejects_target_smaller_than_empty_snapshot() {
+    let result = truncate_snapshot(
+        &mut FrameEncoder::new(),
+        &envelope(false),
+        snapshot(vec![process(1, 1.0)]),
+        10,
+        16,
+    );
+    assert!(matches!(result, Err(ProtocolError::FrameTooLarge(_, 16))));
+}
@@ -0,0 +1,250 @@
+//! Integration tests for `DecodeMode::Strict` and field-located decode errors.
+//!
+//! Crafts bodies that only a non-conforming encoder would produce and checks that strict mode
+//! rejects them while lenient mode keeps accepting them, and that every decode error names the
+//! failing field and its byte offset.
+
+use agent::protocol::*;
+use std::io::Cursor;
+
+// Test helper: Create a 16-byte message ID from a u64 for simplicity in tests
+fn test_message_id(n: u64) -> [u8; 16] {
+    let mut id = [0u8; 16];
+    id[0..8].copy_from_slice(&n.to_le_bytes());
+    id
+}
+
+fn message(payload: MessagePayload, message_type: MessageType) -> Message {
+    Message {
+        envelope: Envelope {
+            version: ProtocolVersion::CURRENT,
+            message_type,
+            message_id: test_message_id(1),
+            timestamp_utc_ms: 1703174400000,
+            agent_id: "test-agent-001".to_string(),
+            platform: OsType::Linux,
+            compressed: false,
+            extensions: Extensions::new(),
+        },
+        payload,
+        payload_extensions: Extensions::new(),
+    }
+}
+
+fn ack() -> Message {
+    message(
+        MessagePayload::Ack(MessageAck {
+            message_id: test_message_id(2),
+            success: true,
+            error_code: None,
+        }),
+        MessageType::Ack,
+    )
+}
+
+fn snapshot(process_count: u32) -> Message {
+    let processes = (0..process_count)
+        .map(|pid| ProcessSample {
+            pid,
+            name: format!("proc-{pid}"),
+            cpu_percent: 1.0,
+            memory_percent: 1.0,
+            memory_bytes: 1_000,
+            cmdline: None,
+        })
+        .collect();
+    message(
+        MessagePayload::Snapshot(SnapshotPayload {
+            window_start_secs: 1703174400,
+            window_end_secs: 1703174410,
+            total_cpu_percent: 10.0,
+            memory_used_bytes: 1_000_000_000,
+            memory_total_bytes: 8_000_000_000,
+            processes,
+            truncated: false,
+        }),
+        MessageType::Snapshot,
+    )
+}
+
+/// Length of an uncompressed envelope with agent id "test-agent-001" and no extensions.
+const ENVELOPE_LEN: usize = 1 + 1 + 1 + 16 + 8 + 8 + 14 + 1 + 1;
+
+/// Snapshot fields before the first process sample.
+const SNAPSHOT_HEADER_LEN: usize = 8 + 8 + 4 + 8 + 8 + 8;
+
+/// Encoded size of a sample named "proc-N" (single digit) without a command line.
+const PROCESS_LEN: usize = 4 + 8 + 6 + 4 + 4 + 8 + 1;
+
+/// Rebuild a frame around a modified body with a valid CRC32.
+fn reframe(body: &[u8]) -> Vec<u8> {
+    let mut frame = (body.len() as u32).to_be_bytes().to_vec();
+    frame.extend_from_slice(body);
+    frame.extend_from_slice(&crc32fast::hash(body).to_le_bytes());
+    frame
+}
+
+fn body_of(message: &Message) -> Vec<u8> {
+    let frame = FrameCodec::encode(message).expect("Failed to encode");
+    frame[4..frame.len() - 4].to_vec()
+}
+
+fn decode(frame: &[u8], mode: DecodeMode) -> Result<Message, ProtocolError> {
+    FrameCodec::decode_with_options(
+        &mut Cursor::new(frame),
+        &mut DecodeOptions::new().with_mode(mode),
+    )
+}
+
+#[test]
+fn encoder_output_is_accepted_in_strict_mode() {
+    let mut message = snapshot(3);
+    message.envelope.compressed = true;
+    message.envelope.set_sequence_number(42);
+    message.set_total_process_count(10);
+    let frame = FrameCodec::encode(&message).expect("Failed to encode");
+
+    let decoded = FrameCodec::decode_with_options(
+        &mut Cursor::new(&frame),
+        &mut DecodeOptions::new().with_mode(DecodeMode::Strict),
+    )
+    .expect("Failed to decode");
+    assert_eq!(decoded, message);
+
+    let mut decoder = FrameDecoder::with_options(DecodeLimits::default(), DecodeMode::Strict);
+    let frames = decoder.decode(&frame).expect("Failed to decode");
+    assert_eq!(frames.messages, vec![message]);
+}
+
+#[test]
+fn borrowed_view_decodes_envelope_extensions_in_strict_mode() {
+    let mut message = snapshot(3);
+    message.envelope.set_sequence_number(42);
+    message.set_total_process_count(10);
+    let frame = FrameCodec::encode(&message).expect("Failed to encode");
+
+    let (view, _) = MessageRef::from_frame_with_options(
+        &frame,
+        &mut DecodeOptions::new().with_mode(DecodeMode::Strict),
+    )
+    .expect("Failed to parse");
+    assert_eq!(
+        view.envelope.extensions().expect("Failed to decode"),
+        message.envelope.extensions
+    );
+    assert_eq!(view.to_owned().expect("Failed to decode"), message);
+
+    // Unknown flag bits next to the extension area: lenient views accept, strict views reject
+    let mut body = body_of(&message);
+    body[ENVELOPE_LEN - 1] |= 0x80;
+    let frame = reframe(&body);
+    let (view, _) = MessageRef::from_frame_with_options(
+        &frame,
+        &mut DecodeOptions::new().with_mode(DecodeMode::Lenient),
+    )
+    .expect("Lenient mode ignores unknown flags");
+    assert_eq!(
+        view.envelope.extensions().expect("Failed to decode"),
+        message.envelope.extensions
+    );
+    let err = MessageRef::from_frame_with_options(
+        &frame,
+        &mut DecodeOptions::new().with_mode(DecodeMode::Strict),
+    )
+    .unwrap_err();
+    assert!(matches!(err.root_cause(), ProtocolError::InvalidFlags(_)));
+}
+
+#[test]
+fn trailing_payload_bytes_are_rejected_in_strict_mode() {
+    let mut body = body_of(&ack());
+    // Empty payload extension area followed by one stray byte
+    body.extend_from_slice(&[0, 0, 0xFF]);
+    let frame = reframe(&body);
+
+    assert_eq!(
+        decode(&frame, DecodeMode::Lenient).expect("Lenient mode ignores trailing bytes"),
+        ack()
+    );
+    let err = decode(&frame, DecodeMode::Strict).unwrap_err();
+    assert!(matches!(err.root_cause(), ProtocolError::TrailingBytes(1)));
+    assert_eq!(err.field_path(), Some("payload"));
+    assert_eq!(err.byte_offset(), Some(body.len() - 1));
+}
+
+#[test]
+fn non_canonical_bool_is_rejected_in_strict_mode() {
+    let mut body = body_of(&ack());
+    let success_offset = ENVELOPE_LEN + 16;
+    body[success_offset] = 2;
+    let frame = reframe(&body);
+
+    let lenient = decode(&frame, DecodeMode::Lenient).expect("Lenient mode accepts nonzero");
+    assert_eq!(lenient, ack());
+
+    let err = decode(&frame, DecodeMode::Strict).unwrap_err();
+    assert!(matches!(err.root_cause(), ProtocolError::InvalidBool(2)));
+    assert_eq!(err.field_path(), Some("ack.success"));
+    assert_eq!(err.byte_offset(), Some(success_offset));
+}
+
+#[test]
+fn unknown_enum_values_have_dedicated_errors() {
+    let platform_offset = ENVELOPE_LEN - 2;
+    let mut body = body_of(&ack());
+    body[platform_offset] = 7;
+    let frame = reframe(&body);
+    for mode in [DecodeMode::Lenient, DecodeMode::Strict] {
+        let err = decode(&frame, mode).unwrap_err();
+        assert!(matches!(
+            err.root_cause(),
+            ProtocolError::InvalidPlatform(7)
+        ));
+        assert_eq!(err.field_path(), Some("envelope.platform"));
+        assert_eq!(err.byte_offset(), Some(platform_offset));
+    }
+
+    let flags_offset = ENVELOPE_LEN - 1;
+    let mut body = body_of(&ack());
+    body[flags_offset] |= 0x80;
+    let frame = reframe(&body);
+    assert!(decode(&frame, DecodeMode::Lenient).is_ok());
+    let err = decode(&frame, DecodeMode::Strict).unwrap_err();
+    assert!(matches!(
+        err.root_cause(),
+        ProtocolError::InvalidFlags(0x80)
+    ));
+    assert_eq!(err.field_path(), Some("envelope.flags"));
+}
+
+#[test]
+fn errors_name_the_nested_field_and_offset() {
+    let mut payload = body_of(&snapshot(3))[ENVELOPE_LEN..].to_vec();
+    let name_offset = SNAPSHOT_HEADER_LEN + PROCESS_LEN + 4;
+    payload[name_offset..name_offset + 8].copy_from_slice(&u64::MAX.to_le_bytes());
+
+    let mut body = body_of(&snapshot(0))[..ENVELOPE_LEN].to_vec();
+    body.extend_from_slice(&payload);
+    let frame = reframe(&body);
+
+    let err = decode(&frame, DecodeMode::Lenient).unwrap_err();
+    assert!(matches!(
+        err.root_cause(),
+        ProtocolError::StringTooLong { len: u64::MAX, .. }
+    ));
+    assert_eq!(err.field_path(), Some("snapshot.processes[1].name"));
+    assert_eq!(err.byte_offset(), Some(ENVELOPE_LEN + name_offset));
+    assert!(err.to_string().contains("snapshot.processes[1].name"));
+
+    let (view, _) = MessageRef::from_frame(&frame).expect("Envelope is valid");
+    let err = view.snapshot().unwrap_err();
+    assert_eq!(err.field_path(), Some("snapshot.processes[1].name"));
+
+    // Inside a compressed payload, offsets are relative to the decompressed payload
+    let mut body = body_of(&snapshot(0))[..ENVELOPE_LEN].to_vec();
+    body[ENVELOPE_LEN - 1] = 0x01;
+    body.extend_from_slice(&zstd::encode_all(payload.as_slice(), 3).expect("Failed to compress"));
+    let err = decode(&reframe(&body), DecodeMode::Lenient).unwrap_err();
+    assert_eq!(err.field_path(), Some("snapshot.processes[1].name"));
+    assert_eq!(err.byte_offset(), Some(name_offset));
+}
@@ -0,0 +1,279 @@
+//! Integration tests for the async tokio codec (`tokio` feature).
+//!
+//! Sends messages end to end over in-memory duplex streams with `Framed` and the
+//! `read_message`/`write_message` helpers, and checks that corrupt and oversized frames are
+//! rejected like on the synchronous path.
+
+#![cfg(feature = "tokio")]
+
+use agent::protocol::*;
+use futures_util::{SinkExt, StreamExt};
+use std::sync::Arc;
+use tokio::io::{duplex, AsyncWriteExt};
+use tokio_util::codec::{Encoder, Framed, FramedRead};
+
+// Test helper: Create a 16-byte message ID from a u64 for simplicity in tests
+fn test_message_id(n: u64) -> [u8; 16] {
+    let mut id = [0u8; 16];
+    id[0..8].copy_from_slice(&n.to_le_bytes());
+    id
+}
+
+fn snapshot_message(n: u64, compressed: bool) -> Message {
+    Message {
+        envelope: Envelope {
+            version: ProtocolVersion::CURRENT,
+            message_type: MessageType::Snapshot,
+            message_id: test_message_id(n),
+            timestamp_utc_ms: 1703174410000 + n as i64,
+            agent_id: "test-agent-001".to_string(),
+            platform: OsType::Linux,
+            compressed,
+            extensions: Extensions::new(),
+        },
+        payload: MessagePayload::Snapshot(SnapshotPayload {
+            window_start_secs: 1703174400,
+            window_end_secs: 1703174410,
+            total_cpu_percent: 25.0,
+            memory_used_bytes: 2_000_000_000,
+            memory_total_bytes: 8_000_000_000,
+            processes: (0..50)
+                .map(|i| ProcessSample {
+                    pid: i,
+                    name: format!("process-{i}"),
+                    cpu_percent: i as f32 * 0.1,
+                    memory_percent: 0.2,
+                    memory_bytes: 1_000_000,
+                    cmdline: None,
+                })
+                .collect(),
+            truncated: false,
+        }),
+        payload_extensions: Extensions::new(),
+    }
+}
+
+#[tokio::test]
+async fn framed_duplex_round_trip() {
+    // Small buffer forces frames to be split across many reads
+    let (client, server) = duplex(64);
+    let messages: Vec<Message> = (0..5).map(|n| snapshot_message(n, n % 2 == 0)).collect();
+
+    let expected = messages.clone();
+    let writer = tokio::spawn(async move {
+        let mut framed = Framed::new(client, MessageCodec::new());
+        for message in messages {
+            framed.send(message).await.expect("Failed to send");
+        }
+    });
+
+    let mut framed = FramedRead::new(server, MessageCodec::new());
+    let mut received = Vec::new();
+    while let Some(message) = framed.next().await {
+        received.push(message.expect("Failed to decode"));
+    }
+    writer.await.expect("Writer task panicked");
+
+    assert_eq!(received, expected);
+}
+
+#[tokio::test]
+async fn framed_duplex_round_trip_in_spec_layout() {
+    let (client, server) = duplex(64);
+    let messages: Vec<Message> = (0..3).map(|n| snapshot_message(n, n % 2 == 0)).collect();
+
+    let expected = messages.clone();
+    let writer = tokio::spawn(async move {
+        let mut framed = Framed::new(client, MessageCodec::new().with_layout(FrameLayout::Spec));
+        for message in messages {
+            framed.send(message).await.expect("Failed to send");
+        }
+    });
+
+    let mut framed = FramedRead::new(server, MessageCodec::new().with_layout(FrameLayout::Spec));
+    let mut received = Vec::new();
+    while let Some(message) = framed.next().await {
+        received.push(message.expect("Failed to decode"));
+    }
+    writer.await.expect("Writer task panicked");
+    assert_eq!(received, expected);
+
+    let mut frame = bytes::BytesMut::new();
+    Encoder::encode(
+        &mut MessageCodec::new().with_layout(FrameLayout::Spec),
+        &expected[0],
+        &mut frame,
+    )
+    .expect("Failed to encode");
+    assert_eq!(
+        &frame[..],
+        FrameCodec::encode_with_layout(&expected[0], FrameLayout::Spec).expect("Failed to encode")
+    );
+}
+
+#[test]
+fn codec_reuses_its_encoder_and_appends_to_dst() {
+    let messages: Vec<Message> = (0..4).map(|n| snapshot_message(n, n % 2 == 0)).collect();
+    let mut codec = MessageCodec::new().with_layout(FrameLayout::Spec);
+    let mut expected = Vec::new();
+    let mut dst = bytes::BytesMut::new();
+    for message in &messages {
+        Encoder::encode(&mut codec, message, &mut dst).expect("Failed to encode");
+        expected.extend(
+            FrameCodec::encode_with_layout(message, FrameLayout::Spec).expect("Failed to encode"),
+        );
+    }
+    assert_eq!(&dst[..], &expected[..]);
+
+    // A clone writes with the same layout through an encoder of its own
+    let mut clone = codec.clone();
+    let mut frame = bytes::BytesMut::new();
+    Encoder::encode(&mut clone, &messages[0], &mut frame).expect("Failed to encode");
+    assert_eq!(
+        &frame[..],
+        FrameCodec::encode_with_layout(&messages[0], FrameLayout::Spec).expect("Failed to encode")
+    );
+}
+
+#[tokio::test]
+async fn async_helpers_round_trip() {
+    let (mut client, mut server) = duplex(128);
+    let message = snapshot_message(7, true);
+
+    let sent = message.clone();
+    let writer = tokio::spawn(async move {
+        write_message(&mut client, &sent)
+            .await
+            .expect("Failed to write");
+    });
+
+    let decoded = read_message(&mut server).await.expect("Failed to read");
+    writer.await.expect("Writer task panicked");
+    assert_eq!(decoded, message);
+}
+
+#[tokio::test]
+async fn key_store_signs_and_verifies_frames() {
+    let mut ring = KeyRing::new();
+    ring.insert(
+        "test-agent-001",
+        AuthKey::new(1, b"pre-shared secret".to_vec()),
+    );
+    let keys: Arc<dyn KeyStore> = Arc::new(ring);
+
+    let (client, server) = duplex(64);
+    let messages: Vec<Message> = (0..3).map(|n| snapshot_message(n, n % 2 == 0)).collect();
+    let expected = messages.clone();
+    let writer_keys = Arc::clone(&keys);
+    let writer = tokio::spawn(async move {
+        let mut framed = Framed::new(client, MessageCodec::new().with_key_store(writer_keys));
+        for message in messages {
+            framed.send(message).await.expect("Failed to send");
+        }
+    });
+
+    let mut framed = FramedRead::new(
+        server,
+        MessageCodec::new().with_key_store(Arc::clone(&keys)),
+    );
+    let mut received = Vec::new();
+    while let Some(message) = framed.next().await {
+        received.push(message.expect("Failed to verify"));
+    }
+    writer.await.expect("Writer task panicked");
+    assert_eq!(received.len(), expected.len());
+    for (decoded, message) in received.iter().zip(&expected) {
+        assert_eq!(decoded.envelope.key_id().expect("Invalid key id"), Some(1));
+        assert_eq!(decoded.payload, message.payload);
+    }
+
+    // Unsigned frames are rejected by the codec and by the async helper
+    let frame = FrameCodec::encode(&expected[0]).expect("Failed to encode");
+    let (mut client, server) = duplex(frame.len() * 2);
+    client.write_all(&frame).await.expect("Failed to write");
+    drop(client);
+    let mut framed = FramedRead::new(
+        server,
+        MessageCodec::new().with_key_store(Arc::clone(&keys)),
+    );
+    let err = framed.next().await.expect("Expected an item").unwrap_err();
+    assert!(matches!(
+        err.root_cause(),
+        ProtocolError::AuthenticationFailed(_)
+    ));
+
+    let (mut client, mut server) = duplex(frame.len() * 2);
+    client.write_all(&frame).await.expect("Failed to write");
+    let err = read_message_with_options(
+        &mut server,
+        &mut DecodeOptions::new().with_key_store(keys.as_ref()),
+    )
+    .await
+    .unwrap_err();
+    assert!(matches!(
+        err.root_cause(),
+        ProtocolError::AuthenticationFailed(_)
+    ));
+}
+
+#[tokio::test]
+async fn corrupt_frame_is_rejected_with_crc_mismatch() {
+    let mut frame = FrameCodec::encode(&snapshot_message(1, false)).expect("Failed to encode");
+    frame[20] ^= 0xFF;
+
+    let (mut client, server) = duplex(frame.len() * 2);
+    client.write_all(&frame).await.expect("Failed to write");
+    drop(client);
+
+    let mut framed = FramedRead::new(server, MessageCodec::new());
+    let result = framed.next().await.expect("Expected an item");
+    assert!(matches!(result, Err(ProtocolError::Crc32Mismatch { .. })));
+
+    let (mut client, mut server) = duplex(frame.len() * 2);
+    client.write_all(&frame).await.expect("Failed to write");
+    assert!(matches!(
+        read_message(&mut server).await,
+        Err(ProtocolError::Crc32Mismatch { .. })
+    ));
+}
+
+#[tokio::test]
+async fn oversized_length_is_rejected_before_buffering() {
+    let (mut client, mut server) = duplex(64);
+    client
+        .write_all(&(300u32 * 1024).to_be_bytes())
+        .await
+        .expect("Failed to write");
+
+    assert!(matches!(
+        read_message(&mut server).await,
+        Err(ProtocolError::FrameTooLarge(_, _))
+    ));
+
+    let (mut client, server) = duplex(64);
+    client
+        .write_all(&(300u32 * 1024).to_be_bytes())
+        .await
+        .expect("Failed to write");
+    let mut framed = FramedRead::new(server, MessageCodec::new());
+    assert!(matches!(
+        framed.next().await,
+        Some(Err(ProtocolError::FrameTooLarge(_, _)))
+    ));
+}
+
+#[tokio::test]
+async fn truncated_stream_reports_io_error() {
+    let frame = FrameCodec::encode(&snapshot_message(2, false)).expect("Failed to encode");
+    let (mut client, mut server) = duplex(frame.len());
+    client
+        .write_all(&frame[..frame.len() - 3])
+        .await
+        .expect("Failed to write");
+    drop(client);
+
+    assert!(matches!(
+        read_message(&mut server).await,
+        Err(ProtocolError::Io(_))
+    ));
+}
@@ -0,0 +1,253 @@
+//! Compatibility matrix of archived frames across protocol versions.
+//!
+//! The frames in `tests/data/compat/` are frozen captures; never regenerate them with the
+//! current encoder. Each one is decoded through every decode path and compared with the
+//! expected outcome:
+//! - 1.0 frames decode exactly, including legacy 1.0 payloads that omit fields added later
+//!   (which take their defaults);
+//! - 1.1 frames carry an unknown field after the 1.0 fields, which is skipped;
+//! - frames of any other major version fail with `IncompatibleVersion`.
+
+use agent::protocol::*;
+use std::io::Cursor;
+
+// Test helper: Create a 16-byte message ID from a u64 for simplicity in tests
+fn test_message_id(n: u64) -> [u8; 16] {
+    let mut id = [0u8; 16];
+    id[0..8].copy_from_slice(&n.to_le_bytes());
+    id
+}
+
+fn message(
+    version: (u8, u8),
+    message_type: MessageType,
+    compressed: bool,
+    payload: MessagePayload,
+) -> Message {
+    Message {
+        envelope: Envelope {
+            version: ProtocolVersion {
+                major: version.0,
+                minor: version.1,
+            },
+            message_type,
+            message_id: test_message_id(7),
+            timestamp_utc_ms: 1703174400000,
+            agent_id: "compat-agent".to_string(),
+            platform: OsType::Windows,
+            compressed,
+            extensions: Extensions::new(),
+        },
+        payload,
+        payload_extensions: Extensions::new(),
+    }
+}
+
+fn snapshot() -> MessagePayload {
+    MessagePayload::Snapshot(SnapshotPayload {
+        window_start_secs: 1703174400,
+        window_end_secs: 1703174410,
+        total_cpu_percent: 42.5,
+        memory_used_bytes: 4_000_000_000,
+        memory_total_bytes: 16_000_000_000,
+        processes: vec![
+            ProcessSample {
+                pid: 4,
+                name: "System".to_string(),
+                cpu_percent: 12.5,
+                memory_percent: 0.5,
+                memory_bytes: 80_000_000,
+                cmdline: None,
+            },
+            ProcessSample {
+                pid: 1234,
+                name: "chrome.exe".to_string(),
+                cpu_percent: 30.0,
+                memory_percent: 4.0,
+                memory_bytes: 640_000_000,
+                cmdline: Some("chrome.exe --type=renderer".to_string()),
+            },
+        ],
+        truncated: false,
+    })
+}
+
+fn handshake(min_minor: u8) -> MessagePayload {
+    MessagePayload::Handshake(AgentIdentity {
+        instance_id: "compat-agent".to_string(),
+        os_type: OsType::Windows,
+        agent_version: "0.9.0".to_string(),
+        supported_versions: VersionRange {
+            min: ProtocolVersion {
+                major: 1,
+                minor: min_minor,
+            },
+            max: ProtocolVersion { major: 1, minor: 2 },
+        },
+        capabilities: AgentIdentity::CAP_COMPRESSION,
+    })
+}
+
+/// Expected outcome of decoding one archived frame.
+enum Expected {
+    Decodes(Message),
+    Incompatible,
+}
+
+fn matrix() -> Vec<(&'static str, &'static [u8], Expected)> {
+    use Expected::*;
+    use MessageType::*;
+    vec![
+        (
+            "v1_0_snapshot",
+            include_bytes!("data/compat/v1_0_snapshot.bin"),
+            Decodes(message((1, 0), Snapshot, false, snapshot())),
+        ),
+        (
+            "v1_0_snapshot_compressed",
+            include_bytes!("data/compat/v1_0_snapshot_compressed.bin"),
+            Decodes(message((1, 0), Snapshot, true, snapshot())),
+        ),
+        (
+            "v1_0_handshake",
+            include_bytes!("data/compat/v1_0_handshake.bin"),
+            Decodes(message((1, 0), Handshake, false, handshake(0))),
+        ),
+        (
+            // No min version: the range defaults to the single max version
+            "v1_0_handshake_legacy",
+            include_bytes!("data/compat/v1_0_handshake_legacy.bin"),
+            Decodes(message((1, 0), Handshake, false, handshake(2))),
+        ),
+        (
+            // Empty ack: negotiated version defaults to the envelope version
+            "v1_0_handshake_ack_legacy",
+            include_bytes!("data/compat/v1_0_handshake_ack_legacy.bin"),
+            Decodes(message(
+                (1, 0),
+                HandshakeAck,
+                false,
+                MessagePayload::HandshakeAck(HandshakeAckPayload {
+                    negotiated_version: ProtocolVersion::CURRENT,
+                    negotiated_capabilities: 0,
+                }),
+            )),
+        ),
+        (
+            "v1_1_snapshot",
+            include_bytes!("data/compat/v1_1_snapshot.bin"),
+            Decodes(message((1, 1), Snapshot, false, snapshot())),
+        ),
+        (
+            "v1_1_handshake",
+            include_bytes!("data/compat/v1_1_handshake.bin"),
+            Decodes(message((1, 1), Handshake, false, handshake(0))),
+        ),
+        (
+            "v2_0_snapshot",
+            include_bytes!("data/compat/v2_0_snapshot.bin"),
+            Incompatible,
+        ),
+        (
+            "v0_9_heartbeat",
+            include_bytes!("data/compat/v0_9_heartbeat.bin"),
+            Incompatible,
+        ),
+    ]
+}
+
+fn check(name: &str, mode: &str, result: Result<Message, ProtocolError>, expected: &Expected) {
+    match (result, expected) {
+        (Ok(decoded), Expected::Decodes(message)) => {
+            assert_eq!(&decoded, message, "{name} ({mode})")
+        }
+        (Err(err), Expected::Incompatible) => {
+            assert!(
+                matches!(err.root_cause(), ProtocolError::IncompatibleVersion),
+                "{name} ({mode}): unexpected error {err}"
+            );
+            assert_eq!(
+                err.field_path(),
+                Some("envelope.version"),
+                "{name} ({mode})"
+            );
+        }
+        (Ok(decoded), Expected::Incompatible) => {
+            panic!("{name} ({mode}): expected IncompatibleVersion, decoded {decoded:?}")
+        }
+        (Err(err), Expected::Decodes(_)) => panic!("{name} ({mode}): failed to decode: {err}"),
+    }
+}
+
+#[test]
+fn archived_frames_decode_per_version() {
+    for (name, frame, expected) in matrix() {
+        check(
+            name,
+            "lenient",
+            FrameCodec::decode(&mut Cursor::new(frame)),
+            &expected,
+        );
+        check(
+            name,
+            "strict",
+            FrameCodec::decode_with_options(
+                &mut Cursor::new(frame),
+                &mut DecodeOptions::new().with_mode(DecodeMode::Strict),
+            ),
+            &expected,
+        );
+        check(
+            name,
+            "view",
+            MessageRef::from_frame(frame).and_then(|(view, _)| view.to_owned()),
+            &expected,
+        );
+        let decoded = FrameDecoder::new()
+            .decode(frame)
+            .map(|mut frames| frames.messages.remove(0));
+        check(name, "push decoder", decoded, &expected);
+    }
+}
+
+#[test]
+fn newer_minor_snapshot_view_skips_unknown_fields() {
+    let frame = include_bytes!("data/compat/v1_1_snapshot.bin");
+    let (view, _) = MessageRef::from_frame(frame).expect("Failed to parse frame");
+    let snapshot = view
+        .snapshot()
+        .expect("Failed to parse snapshot")
+        .expect("Expected a snapshot");
+    assert_eq!(snapshot.process_count(), 2);
+    assert_eq!(
+        MessagePayload::Snapshot(snapshot.to_owned()),
+        self::snapshot()
+    );
+}
+
+#[test]
+fn current_frames_match_archived_frames() {
+    let current = FrameCodec::encode(&message((1, 0), MessageType::Snapshot, false, snapshot()))
+        .expect("Failed to encode");
+    assert_eq!(current, include_bytes!("data/compat/v1_0_snapshot.bin"));
+}
+
+#[test]
+fn encoding_an_unknown_version_is_rejected() {
+    for version in [(2, 0), (0, 9), (1, ProtocolVersion::CURRENT.minor + 1)] {
+        let message = message(
+            version,
+            MessageType::Heartbeat,
+            false,
+            MessagePayload::Heartbeat,
+        );
+        assert!(matches!(
+            FrameCodec::encode(&message),
+            Err(ProtocolError::IncompatibleVersion)
+        ));
+        assert!(matches!(
+            FrameEncoder::new().encode_into(&message, &mut Vec::new()),
+            Err(ProtocolError::IncompatibleVersion)
+        ));
+    }
+}
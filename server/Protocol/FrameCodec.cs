@@ -27,6 +27,23 @@ public static class FrameCodec
     /// <summary>Target compressed frame size (64 KB)</summary>
     public const int TargetFrameSize = 64 * 1024;
 
+    /// <summary>
+    /// Envelope flags byte bits (the byte that was the v1.0 compressed bool).
+    /// Must be kept in sync with the FLAG_* constants in agent/src/protocol.rs.
+    /// </summary>
+    public const byte FlagCompressed = 0x01;
+
+    /// <summary>An extension area follows the flags byte.</summary>
+    public const byte FlagExtensions = 0x02;
+
+    /// <summary>The frame carries an HMAC tag (not supported by this server).</summary>
+    public const byte FlagAuthenticated = 0x04;
+
+    /// <summary>The payload is encrypted (not supported by this server).</summary>
+    public const byte FlagEncrypted = 0x08;
+
+    private const byte KnownFlags = FlagCompressed | FlagExtensions | FlagAuthenticated | FlagEncrypted;
+
     /// <summary>
     /// Decode a message from a stream.
     /// 
@@ -224,7 +241,21 @@ public static class FrameCodec
         var timestampUtcMs = reader.ReadInt64(); // i64
         var agentId = ReadString(reader); // string
         var platform = (OsType)reader.ReadByte(); // u8
-        var compressed = reader.ReadBoolean(); // bool
+        var flags = reader.ReadByte(); // bit flags
+        if ((flags & ~KnownFlags) != 0)
+        {
+            throw new ProtocolException($"Unknown envelope flags: 0x{flags:X2}");
+        }
+        if ((flags & (FlagAuthenticated | FlagEncrypted)) != 0)
+        {
+            throw new ProtocolException(
+                $"Authenticated or encrypted frames are not supported (flags 0x{flags:X2})");
+        }
+        if ((flags & FlagExtensions) != 0)
+        {
+            SkipExtensions(reader);
+        }
+        var compressed = (flags & FlagCompressed) != 0;
 
         var envelope = new Envelope
         {
@@ -381,6 +412,25 @@ public static class FrameCodec
         };
     }
 
+    /// <summary>
+    /// Skip an extension area: [count:u16 LE] then count entries of [tag:u16 LE][len:u32 LE][value].
+    /// </summary>
+    private static void SkipExtensions(BinaryReader reader)
+    {
+        var count = reader.ReadUInt16();
+        for (var i = 0; i < count; i++)
+        {
+            reader.ReadUInt16(); // tag
+            var length = reader.ReadUInt32();
+            var value = reader.ReadBytes(checked((int)length));
+            if (value.Length != length)
+            {
+                throw new EndOfStreamException(
+                    $"Extension value truncated: expected {length} bytes, got {value.Length}");
+            }
+        }
+    }
+
     private static List<ProcessSample> ReadProcessList(BinaryReader reader)
     {
         var count = reader.ReadUInt64();
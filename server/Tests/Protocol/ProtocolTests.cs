@@ -6,6 +6,7 @@
 /// - Message type conversion
 /// - Encode/decode round-trips for all message types
 /// - Compression handling
+/// - Envelope flags and extension areas
 /// - Frame size validation
 /// </summary>
 
@@ -353,6 +354,71 @@ public class ProtocolTests
         Assert.Throws<FrameTooLargeException>(() => FrameCodec.Encode(message));
     }
 
+    // Test helper: Heartbeat frame with the envelope flags byte replaced and an optional
+    // extension area inserted after it, re-framed with a fresh CRC32
+    private static byte[] HeartbeatFrameWithFlags(byte flags, byte[] extensionArea)
+    {
+        var message = new Message
+        {
+            Envelope = new Envelope
+            {
+                Version = ProtocolVersion.Current,
+                MessageType = MessageType.Heartbeat,
+                MessageId = TestMessageId(7),
+                TimestampUtcMs = 1703174400000,
+                AgentId = "agent-flags",
+                Platform = OsType.Linux,
+                Compressed = false
+            },
+            Payload = new MessagePayload.Heartbeat()
+        };
+        var body = FrameCodec.Encode(message)[4..^4].ToList();
+        var flagsOffset = 2 + 1 + 16 + 8 + 8 + "agent-flags".Length + 1;
+        body[flagsOffset] = flags;
+        body.InsertRange(flagsOffset + 1, extensionArea);
+
+        var bodyBytes = body.ToArray();
+        var frame = new byte[4 + bodyBytes.Length + 4];
+        System.Buffers.Binary.BinaryPrimitives.WriteUInt32BigEndian(frame.AsSpan(0, 4), (uint)bodyBytes.Length);
+        bodyBytes.CopyTo(frame, 4);
+        System.Buffers.Binary.BinaryPrimitives.WriteUInt32LittleEndian(
+            frame.AsSpan(4 + bodyBytes.Length, 4),
+            Force.Crc32.Crc32Algorithm.Compute(bodyBytes));
+        return frame;
+    }
+
+    [Fact]
+    public async Task FrameCodec_EnvelopeExtensions_AreSkipped()
+    {
+        // One entry: tag 0x0001 (sequence number), 8-byte value
+        byte[] extensionArea =
+        {
+            0x01, 0x00,
+            0x01, 0x00, 0x08, 0x00, 0x00, 0x00,
+            0x2A, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00
+        };
+        var frame = HeartbeatFrameWithFlags(FrameCodec.FlagExtensions, extensionArea);
+
+        using var ms = new MemoryStream(frame);
+        var decoded = await FrameCodec.DecodeAsync(ms);
+
+        Assert.False(decoded.Envelope.Compressed);
+        Assert.Equal("agent-flags", decoded.Envelope.AgentId);
+        Assert.IsType<MessagePayload.Heartbeat>(decoded.Payload);
+    }
+
+    [Theory]
+    [InlineData(FrameCodec.FlagAuthenticated)]
+    [InlineData(FrameCodec.FlagEncrypted)]
+    [InlineData((byte)0x10)]
+    public async Task FrameCodec_UnsupportedEnvelopeFlags_Throw(byte flags)
+    {
+        var frame = HeartbeatFrameWithFlags(flags, Array.Empty<byte>());
+
+        using var ms = new MemoryStream(frame);
+        await Assert.ThrowsAsync<ProtocolException>(() => FrameCodec.DecodeAsync(ms));
+    }
+
     /// <summary>
     /// Cross-language serialization test.
     /// Reads binary snapshot generated by Rust tests and verifies .NET can deserialize it.